@@ -0,0 +1,150 @@
+//! Baud rate detection from the low pulses and the PIO pulse program
+//!
+use rp2040_sandbox::autobaud::{
+    pulse_cycles, pulse_ns, pulse_program, AutobaudState, Detector, BAUD_RATES, LOCK_CONFIDENCE, MIN_PULSE_NS, WINDOW,
+};
+use rp2040_sandbox::pio_sim::Simulator;
+
+
+const RX: u8 = 5;
+const TEXT: &[u8] = b"Hello from the autobaud test, 0123456789!\r\n";
+
+
+/// Widths of the low pulses in nanoseconds of the 8N1 frames
+fn low_pulses(data: &[u8], baud_rate: f64) -> Vec<u32> {
+    let bit_ns = 1e9 / baud_rate;
+    let mut pulses = Vec::new();
+    for &byte in data {
+        // Start bit, data LSB first and the stop bit
+        let bits = (0..10).map(|i| match i {
+            0 => false,
+            9 => true,
+            _ => byte & (1 << (i - 1)) != 0,
+        });
+        let mut run = 0;
+        for bit in bits {
+            if bit {
+                if run > 0 {
+                    pulses.push((run as f64 * bit_ns).round() as u32);
+                }
+                run = 0;
+            } else {
+                run += 1;
+            }
+        }
+    }
+    pulses
+}
+
+/// Push the text until the detector switches the rate
+fn detect(detector: &mut Detector, baud_rate: f64, max_windows: usize) -> Option<u32> {
+    let pulses = low_pulses(TEXT, baud_rate);
+    pulses.iter().cycle().take(max_windows * WINDOW).find_map(|&width| detector.push(width))
+}
+
+
+#[test]
+fn standard_rates() {
+    for &rate in &[9600, 57_600, 115_200, 125_000, 250_000, 420_000, 1_000_000] {
+        let mut detector = Detector::new(&BAUD_RATES);
+        assert_eq!(detect(&mut detector, rate as f64, 2), Some(rate));
+        assert_eq!(detector.state(), AutobaudState::Locked { baud_rate: rate });
+        assert_eq!(detector.baud_rate(), Some(rate));
+        assert!(detector.confidence() >= LOCK_CONFIDENCE);
+    }
+}
+
+#[test]
+fn clock_error() {
+    // 2% off still gives the closest rate
+    let mut detector = Detector::new(&BAUD_RATES);
+    assert_eq!(detect(&mut detector, 250_000.0 * 1.02, 2), Some(250_000));
+    let mut detector = Detector::new(&BAUD_RATES);
+    assert_eq!(detect(&mut detector, 115_200.0 * 0.98, 2), Some(115_200));
+
+    // Nothing standard around 75000
+    let mut detector = Detector::new(&BAUD_RATES);
+    assert_eq!(detect(&mut detector, 75_000.0, 4), None);
+    assert_eq!(detector.state(), AutobaudState::Searching);
+    let estimate = detector.last_estimate().unwrap();
+    assert_eq!((estimate.baud_rate, estimate.confidence), (None, 0));
+    assert!(estimate.measured.abs_diff(75_000) < 100);
+}
+
+#[test]
+fn locking_and_unlocking() {
+    let mut detector = Detector::new(&[115_200, 125_000, 250_000]);
+    let pulses = low_pulses(TEXT, 125_000.0);
+    for &width in pulses.iter().cycle().take(WINDOW) {
+        assert_eq!(detector.push(width), None);
+    }
+    assert_eq!(detector.state(), AutobaudState::Locking { baud_rate: 125_000 });
+    assert!(detector.confidence() >= LOCK_CONFIDENCE);
+    assert_eq!(detect(&mut detector, 125_000.0, 1), Some(125_000));
+
+    // Single bad window is tolerated
+    assert_eq!(detect(&mut detector, 250_000.0, 1), None);
+    assert_eq!(detector.state(), AutobaudState::Locked { baud_rate: 125_000 });
+    assert_eq!(detector.confidence(), 0);
+    assert_eq!(detect(&mut detector, 125_000.0, 1), None);
+    assert_eq!(detector.baud_rate(), Some(125_000));
+
+    // The other side switched the rate
+    assert_eq!(detect(&mut detector, 250_000.0, 3), None);
+    assert_eq!(detector.state(), AutobaudState::Searching);
+    assert_eq!(detect(&mut detector, 250_000.0, 2), Some(250_000));
+    // Close rate from the table
+    assert_eq!(detect(&mut detector, 115_200.0, 5), Some(115_200));
+
+    detector.reset();
+    assert_eq!((detector.state(), detector.last_estimate()), (AutobaudState::Searching, None));
+}
+
+#[test]
+fn noise() {
+    let mut detector = Detector::new(&BAUD_RATES);
+    let mut pulses = low_pulses(TEXT, 115_200.0);
+    // Glitches are ignored
+    for i in (0..pulses.len()).step_by(3) {
+        pulses.insert(i, MIN_PULSE_NS - 1);
+    }
+    let rate = pulses.iter().cycle().take(4 * WINDOW).find_map(|&width| detector.push(width));
+    assert_eq!(rate, Some(115_200));
+
+    // Random widths don't lock
+    let mut detector = Detector::new(&BAUD_RATES);
+    let mut seed = 12345u32;
+    for _ in 0..10 * WINDOW {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        assert_eq!(detector.push(1000 + (seed >> 8) % 100_000), None);
+    }
+    assert_eq!(detector.baud_rate(), None);
+}
+
+#[test]
+fn pulse_program_widths() {
+    let mut sim = Simulator::new(&pulse_program()).in_pin_base(RX).jmp_pin(RX);
+    sim.set_inputs(1 << RX);
+    sim.run(10, |_| {});
+    for &cycles in &[3u32, 10, 11, 100, 1085, 5000] {
+        sim.set_inputs(0);
+        sim.run(cycles, |_| {});
+        sim.set_inputs(1 << RX);
+        sim.run(20, |_| {});
+        let width = pulse_cycles(sim.pull_rx().unwrap());
+        assert!(width.abs_diff(cycles) <= 1, "{cycles} cycles measured as {width}");
+        assert_eq!(sim.pull_rx(), None);
+    }
+}
+
+#[test]
+fn pulse_length_in_nanoseconds() {
+    // 8us bit at 125 MHz: 1000 cycles
+    assert_eq!(pulse_ns(499, 125_000_000), 8000);
+    // Line held low for 10s doesn't wrap to a short pulse
+    assert_eq!(pulse_ns(625_000_000, 125_000_000), u32::MAX);
+    let mut detector = Detector::new(&BAUD_RATES);
+    let mut pulses = low_pulses(TEXT, 125_000.0);
+    pulses.insert(5, pulse_ns(625_000_000, 125_000_000));
+    assert_eq!(pulses.iter().cycle().take(2 * WINDOW).find_map(|&width| detector.push(width)), Some(125_000));
+}
@@ -0,0 +1,118 @@
+//! Ring buffer and the chunks of the interrupt driven UART
+//!
+use rp2040_sandbox::buffered_uart::{Chunk, RingBuffer, RxQueue, MAX_CHUNKS};
+
+
+/// Chunk of the bytes counting from the value
+fn receive<const N: usize>(queue: &mut RxQueue<N>, first: u8, len: u8, time_us: u64, idle: bool) {
+    for byte in first..first + len {
+        queue.push(byte);
+    }
+    queue.finish_chunk(time_us, idle);
+}
+
+
+#[test]
+fn fifo_order_and_wrap_around() {
+    let mut ring: RingBuffer<u8, 4> = RingBuffer::new();
+    assert!(ring.is_empty());
+    for round in 0..3u8 {
+        for i in 0..3 {
+            assert!(ring.push(round * 10 + i));
+        }
+        assert_eq!(ring.len(), 3);
+        for i in 0..3 {
+            assert_eq!(ring.pop(), Some(round * 10 + i));
+        }
+    }
+    assert_eq!(ring.pop(), None);
+}
+
+#[test]
+fn full_buffer_rejects() {
+    let mut ring: RingBuffer<u8, 3> = RingBuffer::new();
+    assert!(ring.push(1) && ring.push(2) && ring.push(3));
+    assert!(ring.is_full());
+    assert!(!ring.push(4));
+    assert_eq!(ring.pop(), Some(1));
+    assert!(ring.push(4));
+    assert_eq!(*ring.back_mut().unwrap(), 4);
+    assert_eq!(*ring.front_mut().unwrap(), 2);
+    ring.clear();
+    assert!(ring.is_empty() && ring.front_mut().is_none());
+}
+
+#[test]
+fn chunks_keep_time_and_idle() {
+    let mut queue: RxQueue<64> = RxQueue::new();
+    receive(&mut queue, 0, 3, 100, false);
+    receive(&mut queue, 3, 2, 200, true);
+    // Nothing received, no chunk
+    queue.finish_chunk(300, true);
+    assert_eq!(queue.len(), 5);
+
+    let mut buffer = [0u8; 16];
+    assert_eq!(queue.read_chunk(&mut buffer), Some(Chunk { time_us: 100, len: 3, idle: false }));
+    assert_eq!(&buffer[..3], &[0, 1, 2]);
+    assert_eq!(queue.read_chunk(&mut buffer), Some(Chunk { time_us: 200, len: 2, idle: true }));
+    assert_eq!(&buffer[..2], &[3, 4]);
+    assert_eq!(queue.read_chunk(&mut buffer), None);
+    assert!(queue.is_empty());
+}
+
+#[test]
+fn partial_read_chunk_is_not_idle() {
+    let mut queue: RxQueue<64> = RxQueue::new();
+    receive(&mut queue, 0, 5, 100, true);
+    let mut buffer = [0u8; 3];
+    assert_eq!(queue.read_chunk(&mut buffer), Some(Chunk { time_us: 100, len: 3, idle: false }));
+    // The gap is after the rest
+    assert_eq!(queue.read_chunk(&mut buffer), Some(Chunk { time_us: 100, len: 2, idle: true }));
+    assert_eq!(&buffer[..2], &[3, 4]);
+}
+
+#[test]
+fn read_spans_several_chunks() {
+    let mut queue: RxQueue<64> = RxQueue::new();
+    receive(&mut queue, 0, 3, 100, false);
+    receive(&mut queue, 3, 4, 200, true);
+    receive(&mut queue, 7, 5, 300, true);
+    let mut buffer = [0u8; 8];
+    assert_eq!(queue.read(&mut buffer), 8);
+    assert_eq!(buffer, [0, 1, 2, 3, 4, 5, 6, 7]);
+    // First byte of the last chunk was taken
+    let mut rest = [0u8; 8];
+    assert_eq!(queue.read_chunk(&mut rest), Some(Chunk { time_us: 300, len: 4, idle: true }));
+    assert_eq!(&rest[..4], &[8, 9, 10, 11]);
+    assert_eq!(queue.read(&mut rest), 0);
+}
+
+#[test]
+fn chunks_merge_when_they_run_out() {
+    let mut queue: RxQueue<256> = RxQueue::new();
+    for i in 0..MAX_CHUNKS as u8 + 2 {
+        receive(&mut queue, i * 2, 2, i as u64 * 100, i % 2 == 1);
+    }
+    let mut buffer = [0u8; 16];
+    for i in 0..MAX_CHUNKS as u64 - 1 {
+        assert_eq!(queue.read_chunk(&mut buffer), Some(Chunk { time_us: i * 100, len: 2, idle: i % 2 == 1 }));
+    }
+    // The last one has the bytes of the 3 newest interrupts and the time and gap of the newest
+    let time_us = (MAX_CHUNKS as u64 + 1) * 100;
+    assert_eq!(queue.read_chunk(&mut buffer), Some(Chunk { time_us, len: 6, idle: true }));
+    assert_eq!(&buffer[..6], &[30, 31, 32, 33, 34, 35]);
+    assert_eq!(queue.read_chunk(&mut buffer), None);
+}
+
+#[test]
+fn full_queue_drops_bytes() {
+    let mut queue: RxQueue<4> = RxQueue::new();
+    assert!((0..4).all(|byte| queue.push(byte)));
+    assert!(!queue.push(4));
+    queue.finish_chunk(100, true);
+    let mut buffer = [0u8; 8];
+    assert_eq!(queue.read_chunk(&mut buffer), Some(Chunk { time_us: 100, len: 4, idle: true }));
+    receive(&mut queue, 10, 2, 200, false);
+    queue.clear();
+    assert_eq!((queue.read_chunk(&mut buffer), queue.len()), (None, 0));
+}
@@ -0,0 +1,20 @@
+//! Helpers shared by the tests
+//!
+//! Fixtures are hex dumps: bytes separated by whitespace, `#` starts a comment line.
+//!
+// Every test crate uses only some of the helpers
+#![allow(dead_code)]
+
+
+/// Bytes of the hex dump, one line per packet
+pub fn lines(dump: &str) -> Vec<Vec<u8>> {
+    dump.lines()
+        .filter(|line| !line.starts_with('#') && !line.trim().is_empty())
+        .map(|line| line.split_whitespace().map(|b| u8::from_str_radix(b, 16).unwrap()).collect())
+        .collect()
+}
+
+/// All bytes of the hex dump
+pub fn bytes(dump: &str) -> Vec<u8> {
+    lines(dump).concat()
+}
@@ -0,0 +1,56 @@
+//! Idle line frame detection of the DMA UART
+//!
+use rp2040_sandbox::dma_uart::{idle_time_us, IdleDetector, Span, RING_SIZE};
+
+
+#[test]
+fn frames_end_after_idle_time() {
+    let mut detector = IdleDetector::new(100);
+    // Bytes keep coming
+    assert_eq!(detector.update(10, 0), None);
+    assert_eq!(detector.update(20, 50), None);
+    assert_eq!(detector.update(20, 120), None);
+    assert_eq!(detector.update(20, 150), Some(Span { start: 0, len: 20, time_us: 50 }));
+    assert!(detector.is_idle());
+    // Nothing new, no frame
+    assert_eq!(detector.update(20, 500), None);
+    assert_eq!(detector.update(45, 600), None);
+    assert_eq!(detector.update(45, 700), Some(Span { start: 20, len: 25, time_us: 600 }));
+}
+
+#[test]
+fn count_wraps_around() {
+    let mut detector = IdleDetector::new(10);
+    let start = u32::MAX - 4;
+    detector.update(start, 0);
+    detector.update(start, 20);
+    assert_eq!(detector.update(5, 30), None);
+    assert_eq!(detector.update(5, 40), Some(Span { start, len: 10, time_us: 30 }));
+}
+
+#[test]
+fn too_long_frame_is_dropped() {
+    let mut detector = IdleDetector::new(10);
+    detector.update(RING_SIZE as u32 + 1, 0);
+    assert_eq!(detector.update(RING_SIZE as u32 + 1, 10), None);
+    assert_eq!(detector.overruns(), 1);
+}
+
+#[test]
+fn copy_across_the_end_of_the_ring() {
+    let mut ring = [0u8; RING_SIZE];
+    for (i, byte) in ring.iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+    let span = Span { start: 3 * RING_SIZE as u32 - 2, len: 4, time_us: 0 };
+    let mut out = [0u8; 8];
+    assert_eq!(span.copy(&ring, &mut out), 4);
+    assert_eq!(&out[..4], &[254, 255, 0, 1]);
+}
+
+#[test]
+fn idle_time() {
+    // 3 characters at 420000 baud
+    assert_eq!(idle_time_us(420_000, 3), 72);
+    assert_eq!(idle_time_us(115_200, 2), 174);
+}
@@ -0,0 +1,30 @@
+//! Refill timing of the interrupt driven audio stream
+//!
+use rp2040_sandbox::i2s::stream::UnderrunStats;
+
+
+#[test]
+fn underrun_counters() {
+    let mut stats = UnderrunStats::new(1024);
+    assert_eq!(stats, UnderrunStats { buffers: 0, underruns: 0, worst_margin: 1024 });
+
+    stats.record(900);
+    stats.record(480);
+    stats.record(700);
+    assert_eq!(stats, UnderrunStats { buffers: 3, underruns: 0, worst_margin: 480 });
+
+    // The other buffer was already sent
+    stats.record(0);
+    stats.record(600);
+    assert_eq!(stats, UnderrunStats { buffers: 5, underruns: 1, worst_margin: 0 });
+}
+
+#[test]
+fn worst_margin_in_microseconds() {
+    let mut stats = UnderrunStats::new(1024);
+    stats.record(480);
+    // 48 kHz stereo
+    assert_eq!(stats.worst_margin_us(96_000), 5000);
+    // 8 slot TDM at 48 kHz
+    assert_eq!(stats.worst_margin_us(384_000), 1250);
+}
@@ -0,0 +1,165 @@
+//! Jeti EX Bus packets, telemetry answers and the baud rate detection
+//!
+use rp2040_sandbox::jeti_exbus::{
+    crc16, crc8, encode, BaudDetector, Keys, Packet, Parser, Responder, Sensor, MASTER, MAX_PACKET, SLAVE,
+};
+
+
+const SENSORS: [Sensor; 2] = [
+    Sensor { label: "VSYS", unit: "V", decimals: 2 },
+    Sensor { label: "Temperature", unit: "C", decimals: 1 },
+];
+
+
+fn packet(header: u8, answer: bool, id: u8, data_id: u8, data: &[u8]) -> Vec<u8> {
+    let mut out = [0u8; MAX_PACKET];
+    let len = encode(header, answer, id, data_id, data, &mut out);
+    out[..len].to_vec()
+}
+
+fn channels(values_us: &[u16]) -> Vec<u8> {
+    let data: Vec<u8> = values_us.iter().flat_map(|us| (us * 8).to_le_bytes()).collect();
+    packet(MASTER, false, 0x10, 0x31, &data)
+}
+
+fn parse(bytes: &[u8]) -> (Parser, Vec<Packet>) {
+    let mut parser = Parser::new();
+    let packets = bytes.iter().filter_map(|&b| parser.push(b)).collect();
+    (parser, packets)
+}
+
+
+#[test]
+fn checksums() {
+    // CRC-16/KERMIT check value
+    assert_eq!(crc16(b"123456789"), 0x2189);
+    // CRC-8/SMBUS check value
+    assert_eq!(crc8(b"123456789"), 0xF4);
+}
+
+#[test]
+fn channel_packet() {
+    let bytes = channels(&[1500, 1100, 1900, 1500, 1000, 2000]);
+    assert_eq!(&bytes[..6], &[0x3E, 0x03, 20, 0x10, 0x31, 12]);
+    let (_, packets) = parse(&bytes);
+    let [Packet::Channels(values)] = packets.as_slice() else { panic!("{packets:?}") };
+    assert_eq!(values.count, 6);
+    assert_eq!(values.get_us(1), Some(1100));
+    assert_eq!(values.get_us(5), Some(2000));
+    assert_eq!(values.get_us(6), None);
+}
+
+#[test]
+fn resync_and_own_answers() {
+    let mut bytes = vec![0x00, 0x3E, 0x55, 0xFF];
+    // Truncated packet, then the answer of another device
+    bytes.extend_from_slice(&channels(&[1500; 8])[..9]);
+    bytes.extend_from_slice(&packet(SLAVE, true, 0x11, 0x3A, &[0x9F, 0x40]));
+    bytes.extend_from_slice(&packet(MASTER, true, 0x12, 0x3A, &[]));
+    bytes.extend_from_slice(&packet(MASTER, true, 0x13, 0x3B, &[0x70]));
+    let (_, packets) = parse(&bytes);
+    assert_eq!(
+        packets,
+        vec![
+            Packet::Telemetry { id: 0x12, answer: true },
+            Packet::Jetibox { id: 0x13, answer: true, keys: Keys(0x70) },
+        ]
+    );
+    let Packet::Jetibox { keys, .. } = packets[1] else { unreachable!() };
+    assert!(keys.left() && !keys.right());
+}
+
+#[test]
+fn telemetry_answers() {
+    let mut responder = Responder::new(0xA409, 0x0001, "RP2040", SENSORS);
+    responder.set_value(0, Some(512));
+    responder.set_value(1, Some(253));
+    let mut out = [0u8; MAX_PACKET];
+    let request = Packet::Telemetry { id: 0x42, answer: true };
+
+    // Data frame: 5.12 as int14 with 2 decimals, 25.3 with 1 decimal
+    let len = responder.handle(&request, &mut out);
+    let frame = [0x9F, 0x4C, 0x09, 0xA4, 0x01, 0x00, 0x00, 0x11, 0x00, 0x42, 0x21, 0xFD, 0x20];
+    let mut expected = frame.to_vec();
+    expected.push(crc8(&frame[1..]));
+    assert_eq!(out[..len].to_vec(), packet(SLAVE, true, 0x42, 0x3A, &expected));
+
+    // Text frames: device name, then the sensors
+    responder.handle(&request, &mut out);
+    assert_eq!(&out[13..15], &[0x00, 6 << 3]);
+    assert_eq!(&out[15..21], b"RP2040");
+    responder.handle(&request, &mut out);
+    responder.handle(&request, &mut out);
+    assert_eq!(&out[13..15], &[0x01, (4 << 3) | 1]);
+    assert_eq!(&out[15..20], b"VSYSV");
+
+    // No answer when the receiver doesn't allow it
+    assert_eq!(responder.handle(&Packet::Telemetry { id: 0x43, answer: false }, &mut out), 0);
+}
+
+#[test]
+fn negative_and_large_values() {
+    let mut responder = Responder::new(0xA409, 0x0001, "RP2040", [Sensor { label: "X", unit: "", decimals: 0 }; 3]);
+    responder.set_value(0, Some(-5));
+    responder.set_value(1, Some(100_000));
+    responder.set_value(2, None);
+    let mut out = [0u8; MAX_PACKET];
+    let len = responder.handle(&Packet::Telemetry { id: 1, answer: true }, &mut out);
+    // int6 with the sign bit, int22
+    assert_eq!(&out[13..len - 3], &[0x10, 0x85, 0x24, 0xA0, 0x86, 0x01]);
+}
+
+#[test]
+fn values_which_dont_fit_are_sent_next() {
+    let mut responder = Responder::new(0xA409, 0x0001, "RP2040", [Sensor { label: "X", unit: "", decimals: 0 }; 10]);
+    for sensor in 0..10 {
+        responder.set_value(sensor, Some(100_000));
+    }
+    let mut out = [0u8; MAX_PACKET];
+    let mut data_frame = || {
+        let len = responder.handle(&Packet::Telemetry { id: 1, answer: true }, &mut out);
+        // Skip the text frame
+        responder.handle(&Packet::Telemetry { id: 1, answer: true }, &mut [0u8; MAX_PACKET]);
+        // 4 bytes of int22 per value
+        out[13..len - 3].chunks(4).map(|value| value[0] >> 4).collect::<Vec<_>>()
+    };
+    assert_eq!(data_frame(), [1, 2, 3, 4, 5, 6, 7]);
+    assert_eq!(data_frame(), [8, 9, 10, 1, 2, 3, 4]);
+    assert_eq!(data_frame(), [5, 6, 7, 8, 9, 10, 1]);
+}
+
+#[test]
+#[should_panic]
+fn too_many_sensors() {
+    Responder::new(0xA409, 0x0001, "RP2040", [Sensor { label: "X", unit: "", decimals: 0 }; 16]);
+}
+
+#[test]
+fn jetibox_screen() {
+    let mut responder = Responder::new(0xA409, 0x0001, "RP2040", SENSORS);
+    responder.set_jetibox("Hello", "a line which is too long");
+    let mut out = [0u8; MAX_PACKET];
+    let len = responder.handle(&Packet::Jetibox { id: 7, answer: true, keys: Keys(0xD0) }, &mut out);
+    assert_eq!(len, 40);
+    assert_eq!(&out[6..38], b"Hello           a line which is ");
+    assert!(responder.keys().up());
+}
+
+#[test]
+fn baud_detection() {
+    let mut detector = BaudDetector::new(0);
+    assert_eq!(detector.baud_rate(), 125_000);
+    assert_eq!(detector.poll(100_000), None);
+    assert_eq!(detector.poll(200_000), Some(250_000));
+    detector.packet(210_000);
+    assert!(detector.is_locked());
+    assert_eq!(detector.poll(400_000), None);
+    // Receiver gone
+    assert_eq!(detector.poll(410_000), Some(125_000));
+    assert!(!detector.is_locked());
+
+    // Packet time read after the poll time doesn't switch the rate
+    detector.packet(500_000);
+    assert_eq!(detector.poll(499_000), None);
+    assert_eq!(detector.baud_rate(), 125_000);
+}
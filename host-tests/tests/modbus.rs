@@ -0,0 +1,166 @@
+//! Modbus RTU slave requests and the frame timing
+//!
+use rp2040_sandbox::modbus::{crc16, frame_timeout_us, Exception, Register, RtuReceiver, Slave, MAX_ADU};
+
+
+#[derive(Default)]
+struct Device {
+    coils: [bool; 10],
+    inputs: [u16; 4],
+    holding: [u16; 4],
+}
+
+static MAP: [Register<Device>; 5] = [
+    Register::coils(0, 10, |d, i| d.coils[i as usize], |d, i, on| d.coils[i as usize] = on),
+    Register::discrete_inputs(100, 3, |_, i| i != 1),
+    Register::input_registers(0, 4, |d, i| d.inputs[i as usize]),
+    Register::holding_registers(0, 2, |d, i| d.holding[i as usize], |d, i, value| {
+        d.holding[i as usize] = value;
+        Ok(())
+    }),
+    // Gap at 2, the limited value at 3
+    Register::holding_registers(3, 1, |d, _| d.holding[3], |d, _, value| {
+        if value > 1000 {
+            return Err(Exception::IllegalDataValue);
+        }
+        d.holding[3] = value;
+        Ok(())
+    }),
+];
+
+/// Frame with the CRC
+fn frame(data: &[u8]) -> Vec<u8> {
+    let mut frame = data.to_vec();
+    frame.extend_from_slice(&crc16(data).to_le_bytes());
+    frame
+}
+
+/// Response without the CRC, after checking it
+fn request(slave: &mut Slave<Device>, device: &mut Device, data: &[u8]) -> Vec<u8> {
+    let mut response = [0u8; MAX_ADU];
+    let len = slave.handle(&frame(data), device, &mut response);
+    if len == 0 {
+        return Vec::new();
+    }
+    assert_eq!(crc16(&response[..len - 2]).to_le_bytes(), response[len - 2..len]);
+    response[..len - 2].to_vec()
+}
+
+
+#[test]
+fn crc() {
+    // Read 10 holding registers from slave 1, the usual example
+    assert_eq!(frame(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x0A]), [0x01, 0x03, 0x00, 0x00, 0x00, 0x0A, 0xC5, 0xCD]);
+}
+
+#[test]
+fn read_requests() {
+    let mut slave = Slave::new(1, &MAP);
+    let mut device = Device { inputs: [0x1234, 2, 3, 0xFFFF], ..Default::default() };
+    device.coils[0] = true;
+    device.coils[9] = true;
+
+    assert_eq!(request(&mut slave, &mut device, &[1, 1, 0, 0, 0, 10]), [1, 1, 2, 0x01, 0x02]);
+    assert_eq!(request(&mut slave, &mut device, &[1, 2, 0, 100, 0, 3]), [1, 2, 1, 0b101]);
+    assert_eq!(request(&mut slave, &mut device, &[1, 4, 0, 0, 0, 4]), [1, 4, 8, 0x12, 0x34, 0, 2, 0, 3, 0xFF, 0xFF]);
+    device.holding = [7, 8, 0, 9];
+    assert_eq!(request(&mut slave, &mut device, &[1, 3, 0, 0, 0, 2]), [1, 3, 4, 0, 7, 0, 8]);
+    assert_eq!(slave.stats().requests, 4);
+}
+
+#[test]
+fn write_requests() {
+    let mut slave = Slave::new(1, &MAP);
+    let mut device = Device::default();
+
+    assert_eq!(request(&mut slave, &mut device, &[1, 5, 0, 3, 0xFF, 0]), [1, 5, 0, 3, 0xFF, 0]);
+    assert!(device.coils[3]);
+    assert_eq!(request(&mut slave, &mut device, &[1, 6, 0, 1, 0x12, 0x34]), [1, 6, 0, 1, 0x12, 0x34]);
+    assert_eq!(device.holding[1], 0x1234);
+
+    // Coils 1-9: 1 0 1 1 0 0 0 0 1
+    assert_eq!(request(&mut slave, &mut device, &[1, 15, 0, 1, 0, 9, 2, 0b0000_1101, 0b1]), [1, 15, 0, 1, 0, 9]);
+    assert_eq!(device.coils, [false, true, false, true, true, false, false, false, false, true]);
+
+    assert_eq!(request(&mut slave, &mut device, &[1, 16, 0, 0, 0, 2, 4, 0, 10, 0, 20]), [1, 16, 0, 0, 0, 2]);
+    assert_eq!(&device.holding[..2], &[10, 20]);
+}
+
+#[test]
+fn exceptions() {
+    let mut slave = Slave::new(1, &MAP);
+    let mut device = Device::default();
+
+    // Unknown function
+    assert_eq!(request(&mut slave, &mut device, &[1, 7]), [1, 0x87, 1]);
+    // Address 2 isn't mapped
+    assert_eq!(request(&mut slave, &mut device, &[1, 3, 0, 0, 0, 4]), [1, 0x83, 2]);
+    assert_eq!(request(&mut slave, &mut device, &[1, 1, 0, 5, 0, 6]), [1, 0x81, 2]);
+    // Input register isn't writable
+    assert_eq!(request(&mut slave, &mut device, &[1, 6, 0, 2, 0, 1]), [1, 0x86, 2]);
+    // Value refused by the map
+    assert_eq!(request(&mut slave, &mut device, &[1, 6, 0, 3, 0x10, 0]), [1, 0x86, 3]);
+    // Coil value other than FF00 or 0000
+    assert_eq!(request(&mut slave, &mut device, &[1, 5, 0, 0, 0, 1]), [1, 0x85, 3]);
+    // Wrong byte count, too many registers
+    assert_eq!(request(&mut slave, &mut device, &[1, 16, 0, 0, 0, 1, 4, 0, 1, 0, 2]), [1, 0x90, 3]);
+    assert_eq!(request(&mut slave, &mut device, &[1, 4, 0, 0, 0, 126]), [1, 0x84, 3]);
+    // Short request
+    assert_eq!(request(&mut slave, &mut device, &[1, 3, 0]), [1, 0x83, 3]);
+
+    // Nothing written when a part of the range is missing
+    assert_eq!(request(&mut slave, &mut device, &[1, 16, 0, 1, 0, 2, 4, 0, 5, 0, 6]), [1, 0x90, 2]);
+    assert_eq!(device.holding, [0; 4]);
+    assert_eq!(slave.stats().exceptions, 10);
+}
+
+#[test]
+fn addressing() {
+    let mut slave = Slave::new(1, &MAP);
+    let mut device = Device::default();
+
+    // Other slave
+    assert!(request(&mut slave, &mut device, &[2, 5, 0, 0, 0xFF, 0]).is_empty());
+    assert!(!device.coils[0]);
+    // Broadcast is executed without the response
+    assert!(request(&mut slave, &mut device, &[0, 5, 0, 0, 0xFF, 0]).is_empty());
+    assert!(device.coils[0]);
+
+    let mut response = [0u8; MAX_ADU];
+    let mut broken = frame(&[1, 5, 0, 1, 0xFF, 0]);
+    broken[3] ^= 1;
+    assert_eq!(slave.handle(&broken, &mut device, &mut response), 0);
+    assert_eq!(slave.handle(&[1, 5], &mut device, &mut response), 0);
+    let stats = slave.stats();
+    assert_eq!((stats.requests, stats.crc_errors, stats.invalid), (1, 1, 1));
+}
+
+#[test]
+fn frame_timing() {
+    // 3.5 characters of 11 bits
+    assert_eq!(frame_timeout_us(9600), 4011);
+    assert_eq!(frame_timeout_us(19200), 2006);
+    assert_eq!(frame_timeout_us(115200), 1750);
+
+    let mut receiver = RtuReceiver::new(19200);
+    let request = frame(&[1, 3, 0, 0, 0, 1]);
+    for (i, &byte) in request.iter().enumerate() {
+        receiver.push(byte, 1000 + 573 * i as u64);
+    }
+    let last = 1000 + 573 * 7;
+    assert_eq!(receiver.poll(last + 2000), None);
+    assert_eq!(receiver.poll(last + 2006), Some(&request[..]));
+    assert_eq!(receiver.poll(last + 5000), None);
+
+    // Frame with the parity error is dropped
+    receiver.push(1, 20_000);
+    receiver.error(20_600);
+    receiver.push(3, 21_200);
+    assert_eq!(receiver.poll(30_000), None);
+    assert_eq!(receiver.errors(), 1);
+
+    // Frame not taken in time is replaced by the next one
+    receiver.push(9, 40_000);
+    receiver.push(1, 43_000);
+    assert_eq!(receiver.poll(46_000), Some(&[1][..]));
+}
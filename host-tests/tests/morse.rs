@@ -0,0 +1,66 @@
+//! Morse encoder
+//!
+use rp2040_sandbox::morse::{encode, Encoder, Speed};
+
+
+#[test]
+fn hello_world_matches_hand_encoded_message() {
+    // Message which was hand encoded in pio_dma
+    // .... . .-.. .-.. --- / .-- --- .-. .-.. -..
+    #[allow(clippy::unusual_byte_groupings)]
+    let expected = [
+        0b10101010_00100010_11101010_00101110,
+        0b10100011_10111011_10000000_10111011,
+        0b10001110_11101110_00101110_10001011,
+        0b10101000_11101010_00000000_00000000,
+    ];
+    let mut buffer = [0u32; 4];
+    let bits = encode(b"HELLO WORLD", &mut buffer).unwrap();
+    assert_eq!(buffer, expected);
+    // Including the final word gap
+    assert_eq!(bits, 111 + 7);
+}
+
+#[test]
+fn lowercase_and_unknown_characters() {
+    let mut a = [0u32; 4];
+    let mut b = [0u32; 4];
+    encode(b"sos", &mut a).unwrap();
+    encode(b"S#O~S", &mut b).unwrap();
+    assert_eq!(a, b);
+}
+
+#[test]
+fn buffer_too_small() {
+    let mut buffer = [0u32; 1];
+    assert_eq!(encode(b"HELLO", &mut buffer), None);
+}
+
+#[test]
+fn slow_speed_repeats_bits() {
+    let speed = Speed::new(125_000_000, 5);
+    assert!(speed.bits_per_unit > 1);
+    assert!((speed.wpm(125_000_000) - 5.0).abs() < 0.01);
+
+    // E with its word gap: 1 + 7 units
+    let mut encoder = Encoder::new(3);
+    let bits: Vec<bool> = std::iter::from_fn(|| encoder.next_bit(b"E")).collect();
+    assert_eq!(bits.len(), 8 * 3);
+    assert!(bits[..3].iter().all(|&b| b));
+    assert!(bits[3..].iter().all(|&b| !b));
+}
+
+#[test]
+#[should_panic(expected = "at least 1 wpm")]
+fn zero_speed() {
+    Speed::new(125_000_000, 0);
+}
+
+#[test]
+fn repeat_continues_without_padding() {
+    let mut encoder = Encoder::new(1);
+    let mut buffer = [0u32; 1];
+    // "E" is 8 bits long
+    assert_eq!(encoder.fill(b"E", &mut buffer, true), 32);
+    assert_eq!(buffer[0], 0x8080_8080);
+}
@@ -0,0 +1,118 @@
+//! Morse decoder fed with recorded edge timestamps
+//!
+//! Fixtures have one edge per line: time in microseconds and the key state after the edge.
+//!
+use rp2040_sandbox::morse::{character, symbol, Decoder, Encoder};
+
+
+/// Parse fixture into (timestamp_us, key_down) edges
+fn edges(fixture: &str) -> Vec<(u64, bool)> {
+    fixture
+        .lines()
+        .filter(|line| !line.starts_with('#') && !line.trim().is_empty())
+        .map(|line| {
+            let mut fields = line.split_whitespace();
+            let time = fields.next().unwrap().parse().unwrap();
+            let level = fields.next().unwrap() == "1";
+            (time, level)
+        })
+        .collect()
+}
+
+/// Decode the edges the same way as the firmware: every edge ends the previous state,
+/// the last character comes from `idle()`.
+fn decode(decoder: &mut Decoder, edges: &[(u64, bool)]) -> String {
+    let mut text = String::new();
+    for pair in edges.windows(2) {
+        let ((start, key_down), (end, _)) = (pair[0], pair[1]);
+        text.extend(decoder.push(key_down, (end - start) as u32).map(char::from));
+    }
+    text.extend(decoder.idle(u32::MAX).map(char::from));
+    text
+}
+
+fn unit_us(wpm: u32) -> u32 {
+    1_200_000 / wpm
+}
+
+
+#[test]
+fn reverse_lookup_of_all_symbols() {
+    for c in b'!'..=b'Z' {
+        if let Some(code) = symbol(c) {
+            assert_eq!(character(code), Some(c));
+        }
+    }
+    assert_eq!(character(b"........"), None);
+}
+
+#[test]
+fn keyer_at_constant_speed() {
+    let mut decoder = Decoder::new(unit_us(20));
+    let text = decode(&mut decoder, &edges(include_str!("fixtures/paris_20wpm.txt")));
+    assert_eq!(text, "PARIS PARIS PARIS ");
+    assert!((decoder.wpm() - 20.0).abs() < 1.0, "{} WPM", decoder.wpm());
+}
+
+#[test]
+fn straight_key_speeding_up() {
+    // Starts with a wrong guess
+    let mut decoder = Decoder::new(unit_us(10));
+    let text = decode(&mut decoder, &edges(include_str!("fixtures/cq_straight_key.txt")));
+    assert_eq!(text, "CQ CQ DE OK1ABC OK1ABC K ");
+    assert!((decoder.wpm() - 19.0).abs() < 2.0, "{} WPM", decoder.wpm());
+}
+
+#[test]
+fn slowing_down() {
+    let mut decoder = Decoder::new(unit_us(25));
+    let text = decode(&mut decoder, &edges(include_str!("fixtures/sos_slowing.txt")));
+    assert_eq!(text, "SOS SOS SOS 73 ");
+    assert!((decoder.wpm() - 11.0).abs() < 1.5, "{} WPM", decoder.wpm());
+}
+
+#[test]
+fn idle_finishes_character_and_word_once() {
+    let unit = unit_us(20);
+    let mut decoder = Decoder::new(unit);
+    assert_eq!(decoder.push(true, unit).count(), 0);
+    assert_eq!(decoder.idle(unit).count(), 0);
+    assert!(decoder.idle(3 * unit).eq(*b"E"));
+    assert_eq!(decoder.idle(4 * unit).count(), 0);
+    assert!(decoder.idle(7 * unit).eq(*b" "));
+    // The edge which finally ends the gap doesn't repeat them
+    assert_eq!(decoder.push(false, 20 * unit).count(), 0);
+}
+
+#[test]
+fn unknown_code() {
+    let unit = unit_us(20);
+    let mut decoder = Decoder::new(unit);
+    for _ in 0..9 {
+        decoder.push(true, unit);
+        decoder.push(false, unit);
+    }
+    assert!(decoder.idle(3 * unit).eq(*b"*"));
+}
+
+#[test]
+fn encoder_output_round_trip() {
+    let message = b"THE QUICK BROWN FOX JUMPS OVER THE LAZY DOG 0123456789";
+    let bits_per_unit = 4;
+    let bit_us = 15_000;
+    let mut encoder = Encoder::new(bits_per_unit);
+    let mut edges = vec![];
+    let mut last = None;
+    let mut time = 0u64;
+    while let Some(level) = encoder.next_bit(message) {
+        if last != Some(level) {
+            edges.push((time, level));
+            last = Some(level);
+        }
+        time += bit_us;
+    }
+    edges.push((time, false));
+    let mut decoder = Decoder::new(bits_per_unit * bit_us as u32);
+    let text = decode(&mut decoder, &edges);
+    assert_eq!(text.trim_end(), core::str::from_utf8(message).unwrap());
+}
@@ -0,0 +1,224 @@
+//! NMEA sentences from the GPS log and the PPS time sync
+//!
+use rp2040_sandbox::gps_clock::{ClockState, GpsClock};
+use rp2040_sandbox::nmea::{date_time, unix_us, Date, Parser, Position, Satellite, Sentence, Time};
+
+
+/// Sentences of the log with CR LF
+fn stream(log: &str) -> Vec<u8> {
+    log.lines().filter(|line| !line.starts_with('#')).flat_map(|line| format!("{line}\r\n").into_bytes()).collect()
+}
+
+fn parse(parser: &mut Parser, bytes: &[u8]) -> Vec<Sentence> {
+    bytes.iter().filter_map(|&b| parser.push(b)).collect()
+}
+
+fn log() -> (Vec<Sentence>, Parser) {
+    let mut parser = Parser::new();
+    let sentences = parse(&mut parser, &stream(include_str!("fixtures/nmea_log.txt")));
+    (sentences, parser)
+}
+
+fn time(hour: u8, minute: u8, second: u8, millis: u16) -> Time {
+    Time { hour, minute, second, millis }
+}
+
+
+#[test]
+fn log_counters() {
+    let (sentences, parser) = log();
+    assert_eq!(sentences.len(), 19);
+    let stats = parser.stats();
+    assert_eq!(stats.sentences, 19);
+    assert_eq!(stats.checksum_errors, 1);
+    // Cut off GGA and the long PUBX
+    assert_eq!(stats.invalid, 2);
+    // TXT, GLL and PGRMZ
+    assert_eq!(stats.unsupported, 3);
+}
+
+#[test]
+fn without_fix() {
+    let (sentences, _) = log();
+    let Sentence::Rmc(rmc) = sentences[0] else { panic!("{:?}", sentences[0]) };
+    assert_eq!(&rmc.talker, b"GP");
+    assert!(!rmc.valid);
+    assert_eq!((rmc.time, rmc.position, rmc.date, rmc.speed_knots), (None, None, None, None));
+
+    let Sentence::Vtg(vtg) = sentences[1] else { panic!() };
+    assert_eq!((vtg.course_true, vtg.speed_kmh), (None, None));
+
+    let Sentence::Gga(gga) = sentences[2] else { panic!() };
+    assert_eq!((gga.quality, gga.satellites, gga.hdop, gga.altitude), (0, Some(0), Some(99.99), None));
+
+    let Sentence::Gsa(gsa) = sentences[3] else { panic!() };
+    assert_eq!((gsa.fix, gsa.satellite_count()), (1, 0));
+
+    let Sentence::Gsv(gsv) = sentences[4] else { panic!() };
+    assert_eq!((gsv.messages, gsv.number, gsv.in_view), (1, 1, 0));
+    assert!(gsv.satellites().is_empty());
+
+    // Time before the position
+    let Sentence::Rmc(rmc) = sentences[5] else { panic!() };
+    assert_eq!(rmc.time, Some(time(23, 59, 58, 0)));
+    assert_eq!(rmc.date, Some(Date { year: 2024, month: 12, day: 31 }));
+    assert_eq!(rmc.position, None);
+}
+
+#[test]
+fn fix() {
+    let (sentences, _) = log();
+    let position = Some(Position { latitude: 481_173_020, longitude: 115_166_743 });
+
+    let Sentence::Rmc(rmc) = sentences[7] else { panic!() };
+    assert!(rmc.valid);
+    assert_eq!(rmc.time, Some(time(23, 59, 59, 0)));
+    assert_eq!(rmc.position, position);
+    assert_eq!(rmc.speed_knots, Some(0.012));
+    assert_eq!(rmc.course, None);
+
+    let Sentence::Vtg(vtg) = sentences[8] else { panic!() };
+    assert_eq!((vtg.speed_knots, vtg.speed_kmh), (Some(0.012), Some(0.022)));
+
+    let Sentence::Gga(gga) = sentences[9] else { panic!() };
+    assert_eq!(gga.position, position);
+    assert_eq!((gga.quality, gga.satellites, gga.hdop), (1, Some(8), Some(0.95)));
+    assert_eq!((gga.altitude, gga.geoid_separation), (Some(545.4), Some(46.9)));
+
+    let Sentence::Gsa(gsa) = sentences[10] else { panic!() };
+    assert!(gsa.automatic);
+    assert_eq!(gsa.fix, 3);
+    assert_eq!(gsa.satellites, [4, 5, 9, 12, 17, 24, 25, 28, 0, 0, 0, 0]);
+    assert_eq!((gsa.pdop, gsa.hdop, gsa.vdop), (Some(1.82), Some(0.95), Some(1.55)));
+}
+
+#[test]
+fn satellites_in_view() {
+    let (sentences, _) = log();
+    let satellites: Vec<Satellite> = sentences[11..14]
+        .iter()
+        .flat_map(|sentence| match sentence {
+            Sentence::Gsv(gsv) => gsv.satellites().to_vec(),
+            _ => panic!("{sentence:?}"),
+        })
+        .collect();
+    assert_eq!(satellites.len(), 11);
+    assert_eq!(satellites[0], Satellite { prn: 4, elevation: Some(64), azimuth: Some(284), snr: Some(40) });
+    // Not tracked
+    assert_eq!(satellites[5], Satellite { prn: 24, elevation: Some(12), azimuth: Some(177), snr: None });
+    assert_eq!(satellites[10].prn, 32);
+    let Sentence::Gsv(last) = sentences[13] else { panic!() };
+    assert_eq!((last.messages, last.number, last.in_view), (3, 3, 11));
+}
+
+#[test]
+fn multi_gnss() {
+    let (sentences, _) = log();
+    // After the cut off GGA
+    let Sentence::Vtg(vtg) = sentences[14] else { panic!() };
+    assert_eq!((vtg.course_true, vtg.speed_kmh), (Some(54.7), Some(10.19)));
+
+    let position = Some(Position { latitude: -338_643_402, longitude: -1_512_085_713 });
+    let Sentence::Rmc(rmc) = sentences[15] else { panic!() };
+    assert_eq!(&rmc.talker, b"GN");
+    assert_eq!(rmc.time, Some(time(12, 35, 19, 500)));
+    assert_eq!(rmc.position, position);
+    assert_eq!(rmc.course, Some(54.7));
+
+    let Sentence::Gga(gga) = sentences[16] else { panic!() };
+    assert_eq!((gga.quality, gga.altitude, gga.position), (2, Some(-12.3), position));
+
+    let Sentence::Gsa(gsa) = sentences[17] else { panic!() };
+    assert_eq!(gsa.satellite_count(), 4);
+
+    // Signal id after the satellites
+    let Sentence::Gsv(gsv) = sentences[18] else { panic!() };
+    assert_eq!(&gsv.talker, b"GL");
+    assert_eq!(gsv.satellites().iter().map(|s| s.prn).collect::<Vec<_>>(), [65, 66]);
+}
+
+#[test]
+fn split_reads_and_noise() {
+    let bytes = stream(include_str!("fixtures/nmea_log.txt"));
+    let (expected, _) = log();
+    // Line noise between the sentences and LF only line ends
+    let mut noisy = b"\x00\xFF garbage\n".to_vec();
+    for &byte in &bytes {
+        if byte != b'\r' {
+            noisy.push(byte);
+        }
+    }
+    let mut parser = Parser::new();
+    let mut sentences = Vec::new();
+    for chunk in noisy.chunks(7) {
+        sentences.extend(parse(&mut parser, chunk));
+    }
+    assert_eq!(sentences, expected);
+
+    // Binary byte inside the sentence
+    let mut parser = Parser::new();
+    assert!(parse(&mut parser, b"$GPGSV,1,1,0\x800*79\r\n").is_empty());
+    assert_eq!(parser.stats().invalid, 1);
+    // Missing checksum
+    assert!(parse(&mut parser, b"$GPGSV,1,1,00\r\n").is_empty());
+    assert_eq!(parser.stats().checksum_errors, 1);
+}
+
+#[test]
+fn dates() {
+    let date = Date { year: 2024, month: 12, day: 31 };
+    assert_eq!(unix_us(&date, &time(23, 59, 59, 0)), 1_735_689_599_000_000);
+    assert_eq!(Date { year: 1970, month: 1, day: 1 }.unix_days(), 0);
+    assert_eq!(Date { year: 2000, month: 3, day: 1 }.unix_days(), 11_017);
+    for days in (0..40_000).step_by(37) {
+        assert_eq!(Date::from_unix_days(days).unix_days(), days);
+    }
+    assert_eq!(date_time(1_735_734_919_500_000), (Date { year: 2025, month: 1, day: 1 }, time(12, 35, 19, 500)));
+}
+
+
+const DATE: Date = Date { year: 2025, month: 1, day: 1 };
+const UTC_US: u64 = 1_735_689_600_000_000;
+
+#[test]
+fn clock_coarse() {
+    let mut clock = GpsClock::new();
+    assert_eq!(clock.state(0), ClockState::NoTime);
+    assert_eq!(clock.utc_us(0), None);
+
+    clock.on_time(&DATE, &time(0, 0, 0, 0), 5_000_000);
+    assert_eq!(clock.state(5_000_000), ClockState::Coarse);
+    assert_eq!(clock.utc_us(5_250_000), Some(UTC_US + 250_000));
+}
+
+#[test]
+fn clock_locked_to_pps() {
+    let mut clock = GpsClock::new();
+    // Timer runs 20 ppm fast, the sentence comes 300ms after the edge
+    let pps = |second: u64| 10_000_000 + second * 1_000_020;
+    for second in 0..10 {
+        clock.on_pps(pps(second));
+        clock.on_time(&DATE, &time(0, 0, second as u8, 0), pps(second) + 300_000);
+    }
+    assert_eq!(clock.state(pps(9) + 300_000), ClockState::Locked);
+    assert_eq!(clock.frequency_error_ppb(), 20_000);
+    assert_eq!(clock.utc_us(pps(9)), Some(UTC_US + 9_000_000));
+    // Half of the Timer second is 500ms of UTC
+    assert_eq!(clock.utc_us(pps(9) + 500_010), Some(UTC_US + 9_500_000));
+
+    // Sentences lost, PPS keeps the time
+    for second in 10..13 {
+        clock.on_pps(pps(second));
+    }
+    assert_eq!(clock.utc_us(pps(12) + 1), Some(UTC_US + 12_000_000));
+
+    // PPS lost, the coarse time doesn't replace the holdover
+    let now = pps(16);
+    assert_eq!(clock.state(now), ClockState::Holdover);
+    clock.on_time(&DATE, &time(0, 0, 16, 0), now + 300_000);
+    assert_eq!(clock.utc_us(now), Some(UTC_US + 16_000_000));
+
+    // Glitch doesn't change the frequency
+    clock.on_pps(now + 500_000);
+    assert_eq!(clock.frequency_error_ppb(), 20_000);
+}
@@ -0,0 +1,396 @@
+//! PIO programs checked on the simulator
+//!
+use pio::{Instruction, InstructionOperands, MovDestination, MovOperation, MovSource, SetDestination};
+use rp2040_sandbox::bist::{loopback_program, Patterns};
+use rp2040_sandbox::i2s::input::{sign_extend, I2sInput, Mode, Slot};
+use rp2040_sandbox::i2s::{i2s_program, left_justified_program, tdm_program, Format, I2sConfig, CYCLES_PER_BIT, SLOT_BITS};
+use rp2040_sandbox::pio_sim::Simulator;
+use rp2040_sandbox::vcd::{period_ps, Signal, VcdWriter};
+use rp_pico::hal::pio::{PinDir, ShiftDirection};
+
+
+const DATA: u8 = 0;
+const BCLK: u8 = 1;
+const LRCLK: u8 = 2;
+const DIN: u8 = 3;
+const I2S_SIGNALS: [Signal; 3] = [Signal::new("DATA", DATA), Signal::new("BCLK", BCLK), Signal::new("LRCLK", LRCLK)];
+
+
+/// Waveform which is saved to `target/<target>/tmp/<name>.vcd` if the test fails
+struct Waveform {
+    name: &'static str,
+    vcd: String,
+}
+
+impl Drop for Waveform {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            let path = format!("{}/{}.vcd", env!("CARGO_TARGET_TMPDIR"), self.name);
+            std::fs::write(&path, &self.vcd).unwrap();
+            eprintln!("Waveform saved to {}", path);
+        }
+    }
+}
+
+/// I2S output on DATA/BCLK/LRCLK, configured the same way as `I2sConfig::init()`
+fn i2s_simulator(divisor: (u16, u8)) -> Simulator {
+    format_simulator(&i2s_program(), divisor)
+}
+
+/// Any of the output programs, configured the same way as `I2sConfig::init()`
+fn format_simulator(program: &pio::Program<32>, divisor: (u16, u8)) -> Simulator {
+    let mut sim = Simulator::new(program)
+        .out_pins(DATA, 1)
+        .side_set_pin_base(BCLK)
+        .out_shift_direction(ShiftDirection::Left)
+        .autopull(true)
+        .pull_threshold(SLOT_BITS as u8)
+        .clock_divisor_fixed_point(divisor.0, divisor.1);
+    sim.set_pindirs([(DATA, PinDir::Output), (BCLK, PinDir::Output), (LRCLK, PinDir::Output)]);
+    sim
+}
+
+/// (LRCLK, DATA) at every rising edge of BCLK, which is where the receiver samples
+fn sample_bits(sim: &mut Simulator, words: &[u32], cycles: u32, waveform: &mut Waveform) -> Vec<(bool, bool)> {
+    let mut vcd = VcdWriter::new(String::new(), "i2s", &I2S_SIGNALS, period_ps(125_000_000)).unwrap();
+    let mut words = words.iter();
+    let mut bits = Vec::new();
+    let mut bclk = sim.pin(BCLK);
+    for _ in 0..cycles {
+        while sim.tx_level() < 4 {
+            match words.next() {
+                Some(&w) => sim.push_tx(w),
+                None => break,
+            };
+        }
+        sim.step();
+        vcd.sample(sim.pins()).unwrap();
+        if sim.pin(BCLK) && !bclk {
+            bits.push((sim.pin(LRCLK), sim.pin(DATA)));
+        }
+        bclk = sim.pin(BCLK);
+    }
+    waveform.vcd = vcd.finish().unwrap();
+    bits
+}
+
+/// Word of the 32 bits starting at the index
+fn word(bits: &[(bool, bool)], from: usize) -> u32 {
+    bits[from..from + 32].iter().fold(0u32, |acc, b| (acc << 1) | b.1 as u32)
+}
+
+#[test]
+fn i2s_lrclk_changes_one_bclk_before_msb() {
+    let mut sim = i2s_simulator((1, 0));
+    // MSB and LSB set in both channels
+    let words = [0x8000_0001u32; 8];
+    let mut waveform = Waveform { name: "i2s_lrclk", vcd: String::new() };
+    let bits = sample_bits(&mut sim, &words, 4 * 64 * CYCLES_PER_BIT, &mut waveform);
+
+    let mut edges = 0;
+    for i in 1..bits.len() - 2 {
+        if bits[i].0 != bits[i - 1].0 {
+            edges += 1;
+            // LSB of the previous channel is sent after LRCLK changes, then MSB of the new one
+            assert!(bits[i].1, "LSB at bit {}", i);
+            assert!(bits[i + 1].1, "MSB at bit {}", i + 1);
+            assert!(!bits[i + 2].1, "MSB-1 at bit {}", i + 2);
+        }
+    }
+    assert!(edges >= 6);
+}
+
+#[test]
+fn i2s_sends_msb_first() {
+    let mut sim = i2s_simulator((1, 0));
+    let words = [0xA5A5_0F0F, 0x1234_5678, 0xA5A5_0F0F, 0x1234_5678];
+    let mut waveform = Waveform { name: "i2s_msb_first", vcd: String::new() };
+    let bits = sample_bits(&mut sim, &words, 3 * 64 * CYCLES_PER_BIT, &mut waveform);
+
+    // Left channel MSB is the second bit with LRCLK low, the first one is the LSB of the right channel
+    let start = bits.windows(2).position(|w| w[0].0 && !w[1].0).unwrap() + 2;
+    assert_eq!(word(&bits, start), 0xA5A5_0F0F);
+    assert_eq!(word(&bits, start + 32), 0x1234_5678);
+}
+
+#[test]
+fn left_justified_lrclk_changes_with_msb() {
+    let mut sim = format_simulator(&left_justified_program(), (1, 0));
+    let words = [0xA5A5_0F0F, 0x1234_5678, 0xA5A5_0F0F, 0x1234_5678, 0xA5A5_0F0F, 0x1234_5678];
+    let mut waveform = Waveform { name: "left_justified", vcd: String::new() };
+    let bits = sample_bits(&mut sim, &words, 5 * 64 * CYCLES_PER_BIT, &mut waveform);
+
+    // Left channel (LRCLK high) from the first bit, whole slot at the same level
+    let start = bits.windows(2).position(|w| !w[0].0 && w[1].0).unwrap() + 1;
+    assert!(bits[start..start + 32].iter().all(|b| b.0));
+    assert!(bits[start + 32..start + 64].iter().all(|b| !b.0));
+    assert_eq!(word(&bits, start), 0xA5A5_0F0F);
+    assert_eq!(word(&bits, start + 32), 0x1234_5678);
+    assert_eq!(word(&bits, start + 64), 0xA5A5_0F0F);
+}
+
+#[test]
+fn right_justified_sample_ends_with_the_slot() {
+    let right = I2sConfig::new(Format::RightJustified, 48_000, 24);
+    let left = I2sConfig::new(Format::LeftJustified, 48_000, 24);
+    assert_eq!(right.program_name(), left.program_name());
+    assert_eq!(left.pack(0x12_3456), 0x1234_5600);
+
+    let mut sim = format_simulator(&right.program(), (1, 0));
+    let words = [right.pack(0x12_3456), right.pack(-0x12_3456)].repeat(3);
+    let mut waveform = Waveform { name: "right_justified", vcd: String::new() };
+    let bits = sample_bits(&mut sim, &words, 5 * 64 * CYCLES_PER_BIT, &mut waveform);
+
+    let start = bits.windows(2).position(|w| !w[0].0 && w[1].0).unwrap() + 1;
+    // Zero and sign bits before the MSB, the LSB just before LRCLK changes
+    assert_eq!(word(&bits, start), 0x0012_3456);
+    assert!(bits[start..start + 8].iter().all(|b| !b.1));
+    assert_eq!(word(&bits, start + 32) as i32, -0x12_3456);
+    assert!(bits[start + 32..start + 40].iter().all(|b| b.1));
+    assert!(bits[start + 31].0 && !bits[start + 32].0);
+}
+
+#[test]
+fn tdm_frame_sync_before_first_slot() {
+    let slots = 4u8;
+    let mut sim = format_simulator(&tdm_program(), (1, 0));
+    // Same preload as `I2sConfig::init()`
+    sim.exec_instruction(Instruction {
+        operands: InstructionOperands::SET { destination: SetDestination::X, data: slots - 1 },
+        delay: 0,
+        side_set: Some(0),
+    });
+    sim.exec_instruction(Instruction {
+        operands: InstructionOperands::MOV { destination: MovDestination::ISR, op: MovOperation::None, source: MovSource::X },
+        delay: 0,
+        side_set: Some(0),
+    });
+    let frame = [0x1111_1111, 0x2222_2222, 0x3333_3333, 0x8000_0001];
+    let words = frame.repeat(4);
+    let mut waveform = Waveform { name: "tdm", vcd: String::new() };
+    let bits = sample_bits(&mut sim, &words, 3 * 4 * 32 * CYCLES_PER_BIT, &mut waveform);
+
+    // One BCLK wide pulse per frame, together with the LSB of the last slot
+    let syncs: Vec<usize> = (0..bits.len()).filter(|&i| bits[i].0).collect();
+    assert!(syncs.len() >= 2);
+    for pair in syncs.windows(2) {
+        assert_eq!(pair[1] - pair[0], slots as usize * 32);
+    }
+    let sync = syncs[0];
+    assert!(bits[sync].1);
+    for (slot, &expected) in frame.iter().enumerate() {
+        assert_eq!(word(&bits, sync + 1 + slot * 32), expected, "slot {}", slot);
+    }
+    // The first frame starts without the sync pulse
+    assert_eq!(word(&bits, 0), frame[0]);
+}
+
+#[test]
+fn bclk_follows_clock_divisor() {
+    let sys_clock_hz = 125_000_000;
+    let config = I2sConfig::new(Format::I2s, 48_000, 32);
+    let mut sim = i2s_simulator(config.clock_divisor(sys_clock_hz).unwrap());
+
+    // 10ms
+    let mut edges = 0u32;
+    let mut bclk = false;
+    for _ in 0..sys_clock_hz / 100 {
+        if sim.tx_level() < 4 {
+            sim.push_tx(0);
+        }
+        sim.step();
+        if sim.pin(BCLK) && !bclk {
+            edges += 1;
+        }
+        bclk = sim.pin(BCLK);
+    }
+    let expected = config.bclk_hz() / 100;
+    assert!(edges.abs_diff(expected) <= expected / 1000, "{} BCLK edges, expected {}", edges, expected);
+}
+
+#[test]
+fn clock_divisor_range() {
+    let sys_clock_hz = 125_000_000;
+    // 61.44 MHz PIO clock
+    assert_eq!(I2sConfig::new(Format::Tdm { slots: 8 }, 48_000, 32).clock_divisor(sys_clock_hz), Some((2, 9)));
+    // 245.76 MHz PIO clock, faster than the system clock
+    assert_eq!(I2sConfig::new(Format::Tdm { slots: 32 }, 48_000, 32).clock_divisor(sys_clock_hz), None);
+    assert_eq!(I2sConfig::new(Format::I2s, 125_000_000 / 320, 32).clock_divisor(sys_clock_hz), Some((1, 0)));
+    // Below 125 MHz / 65536
+    assert_eq!(I2sConfig::new(Format::I2s, 5, 32).clock_divisor(sys_clock_hz), None);
+}
+
+/// I2S input, configured the same way as `I2sInput::init()`
+fn input_simulator(input: &I2sInput) -> Simulator {
+    let sim = Simulator::new(&input.program())
+        .in_pin_base(DIN)
+        .side_set_pin_base(BCLK)
+        .in_shift_direction(ShiftDirection::Left)
+        .autopush(true)
+        .push_threshold(SLOT_BITS as u8);
+    let mut sim = match input.mode {
+        Mode::Receive => sim,
+        Mode::FullDuplex => sim
+            .out_pins(DATA, 1)
+            .out_shift_direction(ShiftDirection::Left)
+            .autopull(true)
+            .pull_threshold(SLOT_BITS as u8),
+    };
+    sim.set_pindirs([(DATA, PinDir::Output), (BCLK, PinDir::Output), (LRCLK, PinDir::Output)]);
+    sim.exec_instruction(Instruction {
+        operands: InstructionOperands::SET { destination: SetDestination::Y, data: 30 },
+        delay: 0,
+        side_set: Some(0b01),
+    });
+    sim
+}
+
+/// Microphone which sends the words on DIN, changing the data at the falling edges of BCLK.
+/// Returns the received words and (in full duplex) the words sampled from DATA at the rising edges.
+fn receive(sim: &mut Simulator, mic: &[u32], out: &[u32], cycles: u32) -> (Vec<u32>, Vec<u32>) {
+    let bit = |words: &[u32], index: usize| words.get(index / 32).map_or(0, |w| w >> (31 - index % 32) & 1);
+    let mut out = out.iter();
+    let mut received = Vec::new();
+    let mut sent_bits = Vec::new();
+    let mut falling = 0;
+    let mut bclk = sim.pin(BCLK);
+    let mut lrclk = sim.pin(LRCLK);
+    for _ in 0..cycles {
+        while sim.tx_level() < 4 {
+            match out.next() {
+                Some(&w) => sim.push_tx(w),
+                None => break,
+            };
+        }
+        sim.step();
+        if bclk && !sim.pin(BCLK) {
+            falling += 1;
+            sim.set_inputs(bit(mic, falling - 1) << DIN);
+            // LRCLK changes together with the LSB, one BCLK before the MSB
+            if sim.pin(LRCLK) != lrclk {
+                assert_eq!(falling % 32, 0, "LRCLK changed at bit {}", falling - 1);
+            }
+            lrclk = sim.pin(LRCLK);
+        } else if !bclk && sim.pin(BCLK) {
+            sent_bits.push(sim.pin(DATA) as u32);
+        }
+        bclk = sim.pin(BCLK);
+        while let Some(w) = sim.pull_rx() {
+            received.push(w);
+        }
+    }
+    let sent = sent_bits.chunks_exact(32).map(|c| c.iter().fold(0, |acc, b| (acc << 1) | b)).collect();
+    (received, sent)
+}
+
+#[test]
+fn input_receives_left_channel_first() {
+    let input = I2sInput::new(I2sConfig::new(Format::I2s, 48_000, 24), Mode::Receive);
+    let mut sim = input_simulator(&input);
+    let mic = [0x1234_5600, 0xEDCB_AA00, 0x8000_0100, 0x7FFF_FF00, 0xA5A5_A500, 0x0000_0100];
+    let (received, _) = receive(&mut sim, &mic, &[], 3 * 64 * CYCLES_PER_BIT + 10);
+    assert_eq!(received, mic);
+
+    let mut left = [0i32; 3];
+    assert_eq!(input.mono(&received, Slot::Left, &mut left), 3);
+    assert_eq!(left, [0x12_3456, -0x80_0000 + 1, -0x5A_5A5B]);
+    let mut right = [0i32; 4];
+    assert_eq!(input.mono(&received, Slot::Right, &mut right), 3);
+    assert_eq!(right[..3], [-0x12_3456, 0x7F_FFFF, 1]);
+    assert_eq!(sign_extend(0xFFFF_C000, 18), -1);
+}
+
+#[test]
+fn duplex_sends_and_receives_the_same_slots() {
+    let input = I2sInput::new(I2sConfig::new(Format::I2s, 48_000, 32), Mode::FullDuplex);
+    let mut sim = input_simulator(&input);
+    let mic = [0x0102_0304, 0x1112_1314, 0x2122_2324, 0x3132_3334];
+    let out = [0xA0A1_A2A3, 0xB0B1_B2B3, 0xC0C1_C2C3, 0xD0D1_D2D3];
+    let (received, sent) = receive(&mut sim, &mic, &out, 2 * 64 * CYCLES_PER_BIT + 10);
+    assert_eq!(received, mic);
+    assert_eq!(sent, out);
+}
+
+#[test]
+fn blink_period_includes_delays() {
+    // Program from pio_basic
+    let program = pio_proc::pio_asm!(
+        "
+        .wrap_target
+            set pins, 1 [31]
+            set pins, 0 [30]
+        .wrap
+    "
+    )
+    .program;
+    let mut sim = Simulator::new(&program).set_pins(0, 1).clock_divisor_fixed_point(2, 128);
+    sim.set_pindirs([(0, PinDir::Output)]);
+
+    let mut rising = Vec::new();
+    let mut level = false;
+    for _ in 0..1000 {
+        sim.step();
+        if sim.pin(0) && !level {
+            rising.push(sim.clock());
+        }
+        level = sim.pin(0);
+    }
+    // 32 + 31 cycles of the state machine, 2.5 system clocks each
+    assert!(rising.len() > 3);
+    for w in rising.windows(3) {
+        assert!(w[1] - w[0] == 157 || w[1] - w[0] == 158);
+        assert_eq!(w[2] - w[0], 315);
+    }
+}
+
+#[test]
+fn loopback_with_autopull_and_autopush() {
+    // Program from pio_dma
+    let program = pio_proc::pio_asm!(
+        ".wrap_target",
+        "    out x, 1",
+        "    mov pins, x",
+        "    in x, 1 [13]",
+        ".wrap"
+    )
+    .program;
+    let mut sim = Simulator::new(&program).out_pins(25, 1).autopull(true).autopush(true);
+    sim.set_pindirs([(25, PinDir::Output)]);
+
+    let message = [0xdead_beef, 0x0123_4567];
+    for &w in &message {
+        assert!(sim.push_tx(w));
+    }
+    let mut received = Vec::new();
+    for _ in 0..2000 {
+        sim.step();
+        if let Some(w) = sim.pull_rx() {
+            received.push(w);
+        }
+    }
+    assert_eq!(received, message);
+    // Nothing more to send
+    assert!(sim.is_stalled());
+    assert_eq!(sim.pc(), 0);
+}
+
+#[test]
+fn bist_loopback_two_cycles_per_word() {
+    let mut sim = Simulator::new(&loopback_program()).autopull(true).autopush(true);
+    let sent: Vec<u32> = Patterns::new(1).take(4).collect();
+    for &w in &sent {
+        assert!(sim.push_tx(w));
+    }
+    let mut received = Vec::new();
+    let mut cycles = 0;
+    while received.len() < sent.len() {
+        sim.step();
+        cycles += 1;
+        while let Some(w) = sim.pull_rx() {
+            received.push(w);
+        }
+        assert!(cycles < 100);
+    }
+    assert_eq!(received, sent);
+    assert_eq!(cycles, 2 * sent.len());
+}
@@ -0,0 +1,125 @@
+//! PIO UART frames and the TX program connected to the RX program on the simulator
+//!
+// Binary literals are grouped by the fields of the frame
+#![allow(clippy::unusual_byte_groupings)]
+
+use pio::{Instruction, InstructionOperands, SetDestination};
+use rp2040_sandbox::pio_sim::Simulator;
+use rp2040_sandbox::pio_uart::{clock_divisor, uart_rx_program, uart_tx_program, Frame, CYCLES_PER_BIT};
+use rp_pico::hal::fugit::RateExtU32;
+use rp_pico::hal::pio::{PinDir, ShiftDirection};
+use rp_pico::hal::uart::{DataBits, Parity, ReadErrorType, StopBits, UartConfig};
+
+
+const TX: u8 = 8;
+const RX: u8 = 9;
+
+
+fn config(data_bits: DataBits, parity: Option<Parity>, stop_bits: StopBits) -> UartConfig {
+    UartConfig::new(115_200.Hz(), data_bits, parity, stop_bits)
+}
+
+fn transmitter() -> Simulator {
+    let mut sim = Simulator::new(&uart_tx_program())
+        .out_pins(TX, 1)
+        .set_pins(TX, 1)
+        .out_shift_direction(ShiftDirection::Right);
+    sim.set_pindirs([(TX, PinDir::Output)]);
+    sim.exec_instruction(Instruction {
+        operands: InstructionOperands::SET { destination: SetDestination::PINS, data: 1 },
+        delay: 0,
+        side_set: None,
+    });
+    sim
+}
+
+fn receiver(frame: &Frame) -> Simulator {
+    let mut sim = Simulator::new(&uart_rx_program())
+        .in_pin_base(RX)
+        .jmp_pin(RX)
+        .in_shift_direction(ShiftDirection::Right);
+    sim.set_inputs(1 << RX);
+    assert!(sim.push_tx(frame.rx_bits() as u32 - 2));
+    sim
+}
+
+/// Send the bytes from TX to RX and return the decoded results
+fn loopback(config: &UartConfig, data: &[u8]) -> Vec<Result<u8, ReadErrorType>> {
+    let frame = Frame::new(config);
+    let mut tx = transmitter();
+    let mut rx = receiver(&frame);
+    let mut received = Vec::new();
+    let mut pending = data.iter();
+    let frame_cycles = 13 * CYCLES_PER_BIT as usize;
+    for _ in 0..(data.len() + 2) * frame_cycles {
+        if tx.tx_level() < 4 {
+            if let Some(&byte) = pending.next() {
+                tx.push_tx(frame.encode(byte));
+            }
+        }
+        tx.step();
+        rx.set_inputs((tx.pin(TX) as u32) << RX);
+        rx.step();
+        while let Some(word) = rx.pull_rx() {
+            received.push(frame.decode(word));
+        }
+    }
+    received
+}
+
+
+#[test]
+fn frame_encoding() {
+    // 8N1: start, 0x55 LSB first, stop
+    let frame = Frame::new(&config(DataBits::Eight, None, StopBits::One));
+    assert_eq!(frame.encode(0x55), 9 | 0b1_0101_0101_0 << 4);
+    // 8E2 (SBUS): 0x01 has odd number of ones, parity is 1
+    let frame = Frame::new(&config(DataBits::Eight, Some(Parity::Even), StopBits::Two));
+    assert_eq!(frame.encode(0x01), 11 | 0b11_1_0000_0001_0 << 4);
+    // 7O1
+    let frame = Frame::new(&config(DataBits::Seven, Some(Parity::Odd), StopBits::One));
+    assert_eq!(frame.encode(0x03), 9 | 0b1_1_000_0011_0 << 4);
+}
+
+#[test]
+fn loopback_8n1() {
+    let data: Vec<u8> = (0..=255).step_by(7).collect();
+    let received = loopback(&config(DataBits::Eight, None, StopBits::One), &data);
+    let received: Vec<u8> = received.into_iter().map(Result::unwrap).collect();
+    assert_eq!(received, data);
+}
+
+#[test]
+fn loopback_sbus_8e2() {
+    let data = [0x0f, 0xe0, 0x03, 0x1f, 0x58, 0xc0, 0x00];
+    let received = loopback(&config(DataBits::Eight, Some(Parity::Even), StopBits::Two), &data);
+    let received: Vec<u8> = received.into_iter().map(Result::unwrap).collect();
+    assert_eq!(received, data);
+}
+
+#[test]
+fn loopback_7o1() {
+    let data = b"Hello";
+    let received = loopback(&config(DataBits::Seven, Some(Parity::Odd), StopBits::One), data);
+    let received: Vec<u8> = received.into_iter().map(Result::unwrap).collect();
+    assert_eq!(received, data);
+}
+
+#[test]
+fn parity_framing_and_break() {
+    let frame = Frame::new(&config(DataBits::Eight, Some(Parity::Even), StopBits::One));
+    // RX word: data, parity and stop bit at the top of the word
+    let word = |frame_bits: u32| frame_bits << (32 - 10);
+    assert_eq!(frame.decode(word(0b1_1_0000_0001)).unwrap(), 0x01);
+    assert!(matches!(frame.decode(word(0b1_0_0000_0001)), Err(ReadErrorType::Parity)));
+    assert!(matches!(frame.decode(word(0b0_1_0000_0001)), Err(ReadErrorType::Framing)));
+    assert!(matches!(frame.decode(0), Err(ReadErrorType::Break)));
+}
+
+#[test]
+fn baud_rate_divisor() {
+    // 125MHz / (115200 * 8) = 135.63
+    assert_eq!(clock_divisor(125_000_000, 115_200), (135, 162));
+    // SBUS: 125MHz / 800kHz = 156.25
+    assert_eq!(clock_divisor(125_000_000, 100_000), (156, 64));
+}
@@ -0,0 +1,97 @@
+//! Quadrature encoder program, velocity and button debouncing
+//!
+use rp2040_sandbox::pio_sim::Simulator;
+use rp2040_sandbox::quadrature::{quadrature_program, Button, CYCLES_PER_SAMPLE, ButtonEvent, Velocity};
+use rp_pico::hal::pio::ShiftDirection;
+
+
+const PIN_A: u8 = 10;
+// Gray code (B, A) when B leads A
+const FORWARD: [(bool, bool); 4] = [(false, false), (true, false), (true, true), (false, true)];
+
+
+fn encoder() -> Simulator {
+    Simulator::new(&quadrature_program())
+        .in_pin_base(PIN_A)
+        .in_shift_direction(ShiftDirection::Left)
+        .out_shift_direction(ShiftDirection::Right)
+}
+
+/// Set the pins, run a few cycles and return the last pushed position
+fn sample(sim: &mut Simulator, (b, a): (bool, bool), cycles: u32) -> i32 {
+    sim.set_inputs((a as u32) << PIN_A | (b as u32) << (PIN_A + 1));
+    let mut last = None;
+    for _ in 0..cycles {
+        sim.step();
+        while let Some(position) = sim.pull_rx() {
+            last = Some(position as i32);
+        }
+    }
+    last.unwrap()
+}
+
+
+#[test]
+fn counts_every_transition() {
+    let mut sim = encoder();
+    assert_eq!(sample(&mut sim, FORWARD[0], 20), 0);
+    for step in 1..=10 {
+        assert_eq!(sample(&mut sim, FORWARD[step % 4], 20), step as i32);
+    }
+    for step in (0..10).rev() {
+        assert_eq!(sample(&mut sim, FORWARD[step % 4], 20), step as i32);
+    }
+    // Below zero
+    assert_eq!(sample(&mut sim, FORWARD[3], 20), -1);
+}
+
+#[test]
+fn fast_transitions_are_not_missed() {
+    let mut sim = encoder();
+    // Every state is held for CYCLES_PER_SAMPLE only
+    for step in 1..=1_000 {
+        sample(&mut sim, FORWARD[step % 4], CYCLES_PER_SAMPLE);
+    }
+    assert_eq!(sample(&mut sim, FORWARD[0], 20), 1_000);
+}
+
+#[test]
+fn invalid_transition_is_ignored() {
+    let mut sim = encoder();
+    sample(&mut sim, FORWARD[0], 20);
+    // Both phases changed at once
+    assert_eq!(sample(&mut sim, FORWARD[2], 20), 0);
+    assert_eq!(sample(&mut sim, FORWARD[3], 20), 1);
+}
+
+#[test]
+fn velocity_over_window() {
+    let mut velocity = Velocity::new(100_000);
+    assert_eq!(velocity.update(0, 0), 0.0);
+    assert_eq!(velocity.update(10, 50_000), 0.0);
+    assert_eq!(velocity.update(20, 100_000), 200.0);
+    assert_eq!(velocity.update(10, 150_000), 200.0);
+    assert_eq!(velocity.update(0, 200_000), -200.0);
+}
+
+#[test]
+fn button_debounce_click_and_long_press() {
+    let mut button = Button::new(5_000, 500_000);
+    // Bounces
+    assert_eq!(button.update(true, 0), None);
+    assert_eq!(button.update(false, 1_000), None);
+    assert_eq!(button.update(true, 2_000), None);
+    assert_eq!(button.update(true, 6_000), None);
+    assert_eq!(button.update(true, 7_000), Some(ButtonEvent::Pressed));
+    assert_eq!(button.update(false, 100_000), None);
+    assert_eq!(button.update(false, 106_000), Some(ButtonEvent::Click));
+
+    assert_eq!(button.update(true, 200_000), None);
+    assert_eq!(button.update(true, 205_000), Some(ButtonEvent::Pressed));
+    assert_eq!(button.update(true, 704_000), None);
+    assert_eq!(button.update(true, 705_000), Some(ButtonEvent::LongPress));
+    assert_eq!(button.update(true, 800_000), None);
+    assert_eq!(button.update(false, 900_000), None);
+    assert_eq!(button.update(false, 905_000), None);
+    assert!(!button.is_pressed());
+}
@@ -0,0 +1,100 @@
+//! SBUS, CRSF and iBus decoders on the recorded byte streams
+//!
+mod common;
+
+use rp2040_sandbox::rc::crsf::{crc8, CrsfDecoder};
+use rp2040_sandbox::rc::ibus::IbusDecoder;
+use rp2040_sandbox::rc::sbus::SbusDecoder;
+use rp2040_sandbox::rc::{Decoder, RcFrame};
+
+
+fn decode(decoder: &mut impl Decoder, bytes: &[u8]) -> Vec<RcFrame> {
+    bytes.iter().filter_map(|&b| decoder.push(b)).collect()
+}
+
+
+#[test]
+fn sbus_stream() {
+    let mut decoder = SbusDecoder::new();
+    let frames = decode(&mut decoder, &common::bytes(include_str!("fixtures/sbus_stream.txt")));
+    assert_eq!(frames.len(), 4);
+
+    assert_eq!(frames[0].count, 18);
+    assert_eq!(frames[0].channel(0), Some(1500));
+    assert_eq!(frames[0].channel(1), Some(988));
+    assert_eq!(frames[0].channel(2), Some(2011));
+    assert_eq!(frames[0].channel(16), Some(2012));
+    assert_eq!(frames[0].channel(17), Some(988));
+    assert!(!frames[0].failsafe);
+    assert_eq!(frames[0].link_quality, Some(100));
+
+    // 1000 + 20 * i ticks
+    assert_eq!(frames[1].channel(15), Some(1692));
+    assert_eq!(frames[1].link_quality, Some(96));
+
+    assert!(frames[2].failsafe);
+    assert_eq!(frames[2].channel(2), Some(988));
+    assert_eq!(frames[2].link_quality, Some(93));
+
+    // SBUS2 frame
+    assert_eq!(frames[3].channel(17), Some(2012));
+}
+
+#[test]
+fn sbus_split_reads() {
+    let stream = common::bytes(include_str!("fixtures/sbus_stream.txt"));
+    let mut decoder = SbusDecoder::new();
+    // Gap in the middle of the frame drops it
+    assert!(decode(&mut decoder, &stream[..12]).is_empty());
+    decoder.reset();
+    assert_eq!(decode(&mut decoder, &stream[25..]).len(), 3);
+}
+
+#[test]
+fn crsf_stream() {
+    let mut decoder = CrsfDecoder::new();
+    let frames = decode(&mut decoder, &common::bytes(include_str!("fixtures/crsf_stream.txt")));
+    assert_eq!(frames.len(), 2);
+    assert_eq!(decoder.crc_errors(), 1);
+
+    assert_eq!(frames[0].count, 16);
+    assert_eq!(&frames[0].channels[..6], &[1500, 988, 2011, 1500, 1817, 1193]);
+    assert_eq!(frames[0].link_quality, Some(100));
+    assert!(!frames[0].failsafe);
+
+    assert!(frames[1].failsafe);
+    assert_eq!(frames[1].link_quality, Some(0));
+    let link = decoder.link_statistics().unwrap();
+    assert_eq!(link.uplink_rssi, [-120, -120]);
+    assert_eq!(link.uplink_snr, -5);
+}
+
+#[test]
+fn crsf_channels_without_link_statistics() {
+    let stream = common::bytes(include_str!("fixtures/crsf_stream.txt"));
+    let mut decoder = CrsfDecoder::new();
+    // Second frame only
+    let frames = decode(&mut decoder, &stream[14..40]);
+    assert_eq!(frames.len(), 1);
+    assert_eq!(frames[0].link_quality, None);
+    assert!(!frames[0].failsafe);
+}
+
+#[test]
+fn crsf_crc() {
+    // CRC-8/DVB-S2 check value
+    assert_eq!(crc8(b"123456789"), 0xBC);
+}
+
+#[test]
+fn ibus_stream() {
+    let mut decoder = IbusDecoder::new();
+    let frames = decode(&mut decoder, &common::bytes(include_str!("fixtures/ibus_stream.txt")));
+    assert_eq!(frames.len(), 2);
+    assert_eq!(decoder.checksum_errors(), 1);
+    assert_eq!(frames[0].count, 14);
+    assert_eq!(&frames[0].channels[..4], &[1500, 1000, 2000, 1500]);
+    assert_eq!(frames[1].channel(13), Some(1513));
+    assert_eq!(frames[1].channel(14), None);
+    assert_eq!(frames[1].link_quality, None);
+}
@@ -0,0 +1,116 @@
+//! Line editing and the command dispatch of the shell
+//!
+use core::fmt::Write;
+use rp2040_sandbox::shell::{ArgError, Args, Command, Shell};
+
+
+#[derive(Default)]
+struct Context {
+    led: bool,
+    servo: u16,
+}
+
+fn led(context: &mut Context, args: &Args, _out: &mut dyn Write) -> Result<(), ArgError> {
+    context.led = args.get(0)?;
+    Ok(())
+}
+
+fn servo(context: &mut Context, args: &Args, out: &mut dyn Write) -> Result<(), ArgError> {
+    context.servo = args.get(0)?;
+    write!(out, "ok\r\n").unwrap();
+    Ok(())
+}
+
+static COMMANDS: [Command<Context>; 3] = [
+    Command { name: "led", help: "led on|off", run: led },
+    Command { name: "servo", help: "servo <us>", run: servo },
+    Command { name: "serial", help: "serial", run: |_, _, _| Ok(()) },
+];
+
+/// Type the text, return the output
+fn type_text(shell: &mut Shell<Context>, context: &mut Context, text: &[u8]) -> String {
+    let mut out = String::new();
+    for &byte in text {
+        shell.push(byte, context, &mut out).unwrap();
+    }
+    out
+}
+
+
+#[test]
+fn typed_arguments() {
+    let args = Args::new("  1500 -3 on text  ");
+    assert_eq!(args.len(), 4);
+    assert_eq!(args.get::<u16>(0), Ok(1500));
+    assert_eq!(args.get::<i32>(1), Ok(-3));
+    assert_eq!(args.get::<bool>(2), Ok(true));
+    assert_eq!(args.get::<&str>(3), Ok("text"));
+    assert_eq!(args.get::<u8>(0), Err(ArgError::Invalid { index: 0, expected: "0-255" }));
+    assert_eq!(args.get::<u8>(4), Err(ArgError::Missing { index: 4 }));
+    assert_eq!(args.get_or::<f32>(4, 0.5), Ok(0.5));
+}
+
+#[test]
+fn run_commands() {
+    let mut shell = Shell::new(&COMMANDS);
+    let mut context = Context::default();
+    let out = type_text(&mut shell, &mut context, b"servo 1200\r\n");
+    assert_eq!(context.servo, 1200);
+    assert_eq!(out, "servo 1200\r\nok\r\n> ");
+
+    type_text(&mut shell, &mut context, b"led on\r");
+    assert!(context.led);
+
+    let out = type_text(&mut shell, &mut context, b"led maybe\r");
+    assert!(out.contains("Argument 1 should be on or off\r\nUsage: led on|off"));
+    let out = type_text(&mut shell, &mut context, b"servo\r");
+    assert!(out.contains("Missing argument 1"));
+    let out = type_text(&mut shell, &mut context, b"blink\r");
+    assert!(out.contains("Unknown command: blink"));
+    let out = type_text(&mut shell, &mut context, b"help\r");
+    assert!(out.contains("servo    servo <us>"));
+}
+
+#[test]
+fn backspace_and_ctrl_c() {
+    let mut shell = Shell::new(&COMMANDS);
+    let mut context = Context::default();
+    let out = type_text(&mut shell, &mut context, b"servo 19\x7F00\r");
+    assert!(out.contains("9\x08 \x0800"));
+    assert_eq!(context.servo, 100);
+
+    type_text(&mut shell, &mut context, b"servo 5\x03servo 7\r");
+    assert_eq!(context.servo, 7);
+}
+
+#[test]
+fn history() {
+    let mut shell = Shell::new(&COMMANDS);
+    let mut context = Context::default();
+    type_text(&mut shell, &mut context, b"servo 1000\rservo 2000\r");
+
+    // Up twice, then down once
+    let out = type_text(&mut shell, &mut context, b"\x1B[A\x1B[A\x1B[B");
+    assert!(out.ends_with("\r\x1B[K> servo 2000"));
+    type_text(&mut shell, &mut context, b"\x1B[A\r");
+    assert_eq!(context.servo, 1000);
+
+    // Down to the empty line
+    let out = type_text(&mut shell, &mut context, b"\x1B[A\x1B[B");
+    assert!(out.ends_with("\r\x1B[K> "));
+}
+
+#[test]
+fn tab_completion() {
+    let mut shell = Shell::new(&COMMANDS);
+    let mut context = Context::default();
+    let out = type_text(&mut shell, &mut context, b"l\t");
+    assert_eq!(out, "led ");
+    type_text(&mut shell, &mut context, b"on\r");
+    assert!(context.led);
+
+    let out = type_text(&mut shell, &mut context, b"se\t");
+    assert_eq!(out, "se\r\nservo  serial\r\n> se");
+    type_text(&mut shell, &mut context, b"rv\t1600\r");
+    assert_eq!(context.servo, 1600);
+}
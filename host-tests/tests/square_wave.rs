@@ -0,0 +1,81 @@
+//! Square wave timing checked on the simulator
+//!
+use rp2040_sandbox::pio_sim::Simulator;
+use rp2040_sandbox::square_wave::{square_wave_program, FrequencyError, Timing};
+use rp_pico::hal::pio::{PinDir, ShiftDirection};
+
+
+/// Number of state machine cycles in the high and low part of the second period
+fn measure(timing: &Timing) -> (u64, u64) {
+    let mut sim = Simulator::new(&square_wave_program())
+        .set_pins(0, 1)
+        .out_shift_direction(ShiftDirection::Right);
+    sim.set_pindirs([(0, PinDir::Output)]);
+    sim.push_tx(timing.word());
+
+    let mut edges = Vec::new();
+    let mut level = false;
+    while edges.len() < 5 {
+        sim.step();
+        if sim.pin(0) != level {
+            edges.push(sim.clock());
+            level = sim.pin(0);
+        }
+    }
+    (edges[3] - edges[2], edges[4] - edges[3])
+}
+
+#[test]
+fn counters_match_period_and_duty() {
+    let timing = Timing::new(1_000_000, 1000.0, 0.25).unwrap();
+    assert_eq!(timing.divisor, 1);
+    assert_eq!(measure(&timing), (250, 750));
+}
+
+#[test]
+fn period_is_repeated_without_new_setting() {
+    let timing = Timing { divisor: 1, high: 0, low: 0 };
+    // The shortest possible period
+    assert_eq!(measure(&timing), (3, 5));
+    assert_eq!(timing.cycles(), 8);
+}
+
+#[test]
+fn low_frequency_uses_divisor() {
+    let timing = Timing::new(125_000_000, 1.0, 0.5).unwrap();
+    assert!(timing.divisor > 900);
+    assert!((timing.frequency(125_000_000) - 1.0).abs() < 1e-3);
+    assert!((timing.duty() - 0.5).abs() < 1e-3);
+}
+
+#[test]
+fn long_part_fits_counter() {
+    // LED on for a quarter at 0.5 Hz, as in the pio_square_wave example
+    let timing = Timing::new(125_000_000, 0.5, 0.25).unwrap();
+    let (high, low) = measure(&timing);
+    assert_eq!(high + low, timing.cycles() as u64);
+    let period = (high + low) as f64 * timing.divisor as f64 / 125e6;
+    assert!((period - 2.0).abs() < 1e-4, "period {period}s");
+    assert!((high as f64 / (high + low) as f64 - 0.25).abs() < 1e-4);
+}
+
+#[test]
+fn extreme_duty() {
+    for &freq in &[0.05, 0.5, 100.0, 10_000.0] {
+        for &duty in &[0.0, 0.01, 0.25, 0.75, 0.99, 1.0] {
+            let timing = Timing::new(125_000_000, freq, duty).unwrap();
+            let error = (timing.frequency(125_000_000) - freq).abs() / freq;
+            assert!(error < 1e-3, "{freq}Hz, duty {duty}: {timing:?}");
+            assert!((timing.duty() - duty).abs() < 1e-3, "{freq}Hz, duty {duty}: {timing:?}");
+        }
+    }
+}
+
+#[test]
+fn out_of_range() {
+    assert_eq!(Timing::new(125_000_000, 20e6, 0.5), Err(FrequencyError::TooHigh));
+    assert_eq!(Timing::new(125_000_000, 0.001, 0.5), Err(FrequencyError::TooLow));
+    // Whole period in the high counter
+    assert!(Timing::new(125_000_000, 0.02, 0.5).is_ok());
+    assert_eq!(Timing::new(125_000_000, 0.02, 1.0), Err(FrequencyError::TooLow));
+}
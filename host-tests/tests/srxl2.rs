@@ -0,0 +1,153 @@
+//! SRXL2 parser and device on the recorded bus session
+//!
+mod common;
+
+use rp2040_sandbox::srxl2::{
+    channel_to_us, crc16, encode_telemetry, Command, Device, Handshake, Packet, Parser, State, FLIGHT_CONTROLLER_ID,
+    MAX_PACKET, RECEIVER_ID,
+};
+
+
+const SESSION: &str = include_str!("fixtures/srxl2_session.txt");
+const UID: u32 = 0x5250_3230;
+
+
+fn parse(bytes: &[u8]) -> (Parser, Vec<Packet>) {
+    let mut parser = Parser::new();
+    let packets = bytes.iter().filter_map(|&b| parser.push(b)).collect();
+    (parser, packets)
+}
+
+
+#[test]
+fn crc() {
+    // CRC-16/XMODEM check value
+    assert_eq!(crc16(b"123456789"), 0x31C3);
+}
+
+#[test]
+fn session_packets() {
+    let bytes = common::bytes(SESSION);
+    let (parser, packets) = parse(&bytes);
+    assert_eq!(packets.len(), 7);
+    let stats = parser.stats();
+    assert_eq!(stats.packets, 7);
+    assert_eq!(stats.crc_errors, 1);
+
+    let Packet::Handshake(handshake) = packets[0] else { panic!("{:?}", packets[0]) };
+    assert_eq!(handshake.src, RECEIVER_ID);
+    assert_eq!(handshake.dest, FLIGHT_CONTROLLER_ID);
+    assert_eq!(handshake.uid, 0x1A2B_3C4D);
+
+    let Packet::Control(control) = packets[3] else { panic!("{:?}", packets[3]) };
+    assert_eq!(control.command, Command::Channels);
+    assert_eq!(control.reply_id, FLIGHT_CONTROLLER_ID);
+    assert_eq!(control.rssi, -42);
+    assert_eq!(control.frame_losses, 3);
+    assert_eq!(control.channels.mask, 0x3F);
+    assert_eq!(control.channels.get(1), Some(0x2AA0));
+    assert_eq!(control.channels.get(6), None);
+
+    assert_eq!(packets[4], Packet::Other { packet_type: 0x41 });
+
+    let Packet::Control(failsafe) = packets[6] else { panic!("{:?}", packets[6]) };
+    assert_eq!(failsafe.command, Command::Failsafe);
+    assert_eq!(failsafe.channels.get(2), Some(0x1000));
+}
+
+#[test]
+fn resync_after_garbage() {
+    let packets = common::lines(SESSION);
+    // Half of a packet, then two complete ones
+    let mut bytes = packets[3][..10].to_vec();
+    bytes.extend_from_slice(&packets[0]);
+    bytes.extend_from_slice(&packets[2]);
+    let (parser, parsed) = parse(&bytes);
+    assert!(matches!(parsed.as_slice(), [Packet::Handshake(_), Packet::Handshake(_)]));
+    assert_eq!(parser.stats().crc_errors, 1);
+}
+
+#[test]
+fn handshake_and_telemetry() {
+    let packets = common::lines(SESSION);
+    let mut device = Device::new(FLIGHT_CONTROLLER_ID, UID);
+    let mut parser = Parser::new();
+    let mut reply = [0u8; MAX_PACKET];
+    let mut replies = Vec::new();
+    device.set_telemetry([0x7E, 0, 0xFF, 0xFF, 0x01, 0xF4, 0x7F, 0xFF, 0, 0, 0, 0, 0, 0, 0, 0]);
+    for (i, packet) in packets.iter().enumerate() {
+        for &byte in packet {
+            if let Some(packet) = parser.push(byte) {
+                let len = device.handle(&packet, i as u64 * 10_000, &mut reply);
+                replies.push((i, reply[..len].to_vec()));
+            }
+        }
+        if i == 2 {
+            assert_eq!(device.state(), State::Running { master: RECEIVER_ID });
+            assert_eq!(device.baud_rate(), 400_000);
+        }
+    }
+    // Answer to the handshake is the one recorded on the bus
+    assert_eq!(replies[0], (0, packets[1].clone()));
+    // Own handshake comes back on the half-duplex line and is ignored
+    assert!(replies[1].1.is_empty());
+    // Telemetry in the slot of the flight controller only
+    let mut expected = [0u8; MAX_PACKET];
+    let len = encode_telemetry(RECEIVER_ID, &[0x7E, 0, 0xFF, 0xFF, 0x01, 0xF4, 0x7F, 0xFF, 0, 0, 0, 0, 0, 0, 0, 0], &mut expected);
+    assert_eq!(replies[3], (3, expected[..len].to_vec()));
+    assert!(replies[5].1.is_empty());
+    assert!(replies[6].1.is_empty());
+}
+
+#[test]
+fn channels_and_failsafe() {
+    let packets = common::lines(SESSION);
+    let mut device = Device::new(FLIGHT_CONTROLLER_ID, UID);
+    let mut parser = Parser::new();
+    let mut reply = [0u8; MAX_PACKET];
+    assert!(device.is_failsafe(0));
+    let mut feed = |device: &mut Device, line: usize, time_us: u64| {
+        for &byte in &packets[line] {
+            if let Some(packet) = parser.push(byte) {
+                device.handle(&packet, time_us, &mut reply);
+            }
+        }
+    };
+
+    feed(&mut device, 3, 1_000);
+    assert!(!device.is_failsafe(20_000));
+    // Timer read before the packet was timestamped
+    assert!(!device.is_failsafe(500));
+    assert_eq!(device.rssi(), -42);
+    assert_eq!(device.channels().get(0).map(channel_to_us), Some(1500));
+    // Partial update keeps the other channels
+    feed(&mut device, 6, 12_000);
+    assert_eq!(device.channels().get(2), Some(0xA000));
+    assert_eq!(device.channels().get(1), Some(0x2AA0));
+    // No data for too long
+    assert!(device.is_failsafe(200_000));
+    feed(&mut device, 8, 210_000);
+    assert!(device.is_failsafe(210_000));
+    assert_eq!(device.frame_losses(), 57);
+}
+
+#[test]
+fn unprompted_handshake() {
+    let mut device = Device::new(FLIGHT_CONTROLLER_ID, UID);
+    let mut reply = [0u8; MAX_PACKET];
+    assert_eq!(device.poll(0, &mut reply), 0);
+    assert_eq!(device.poll(40_000, &mut reply), 0);
+    // Older time doesn't count as the timeout
+    assert_eq!(device.poll(39_000, &mut reply), 0);
+    let len = device.poll(50_000, &mut reply);
+    let (_, packets) = parse(&reply[..len]);
+    let expected = Handshake { src: FLIGHT_CONTROLLER_ID, dest: RECEIVER_ID, priority: 10, baud_supported: 1, info: 0, uid: UID };
+    assert_eq!(packets, vec![Packet::Handshake(expected)]);
+}
+
+#[test]
+fn channel_pulse_width() {
+    assert_eq!(channel_to_us(0), 903);
+    assert_eq!(channel_to_us(0x8000), 1500);
+    assert_eq!(channel_to_us(0xFFFF), 2097);
+}
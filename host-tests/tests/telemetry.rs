@@ -0,0 +1,141 @@
+//! Telemetry frames: COBS, CRC and the round trip through the encoder and decoder
+//!
+mod common;
+
+use rp2040_sandbox::telemetry::{cobs_decode, cobs_encode, crc16, Decoder, Encoder, Frame, Record, Value, MAX_FRAME, MAX_RECORD};
+
+
+fn decode(decoder: &mut Decoder, bytes: &[u8]) -> Vec<Frame> {
+    bytes.iter().filter_map(|&b| decoder.push(b)).collect()
+}
+
+fn encode(encoder: &mut Encoder, records: &[Record]) -> Vec<u8> {
+    let mut stream = Vec::new();
+    for record in records {
+        let mut frame = [0u8; MAX_FRAME];
+        let len = encoder.encode(record, &mut frame);
+        stream.extend_from_slice(&frame[..len]);
+    }
+    stream
+}
+
+fn records() -> Vec<Record> {
+    vec![
+        Record::Counter { value: 0 },
+        Record::Analog { channel: 3, raw: 0x0100 },
+        Record::Status { uptime_ms: 123_456, vsys_mv: 5020, temperature_cdeg: -1250 },
+        Record::other(0x20, &[0; MAX_RECORD]).unwrap(),
+        Record::other(0x21, &[]).unwrap(),
+    ]
+}
+
+
+#[test]
+fn crc() {
+    // CRC-16/CCITT-FALSE check value
+    assert_eq!(crc16(b"123456789"), 0x29B1);
+}
+
+#[test]
+fn cobs() {
+    let cases: [&[u8]; 5] = [&[], &[0], &[0, 0], &[0x11, 0x22, 0, 0x33], &[0x11, 0x22, 0x33, 0x44]];
+    let expected: [&[u8]; 5] = [&[1], &[1, 1], &[1, 1, 1], &[3, 0x11, 0x22, 2, 0x33], &[5, 0x11, 0x22, 0x33, 0x44]];
+    for (data, expected) in cases.iter().zip(expected) {
+        let mut encoded = [0u8; 8];
+        let len = cobs_encode(data, &mut encoded);
+        assert_eq!(&encoded[..len], expected);
+        let mut decoded = [0u8; 8];
+        assert_eq!(cobs_decode(expected, &mut decoded), Some(data.len()));
+        assert_eq!(&decoded[..data.len()], *data);
+    }
+
+    // 254 non-zero bytes fill the whole group
+    let data: Vec<u8> = (1..=254).collect();
+    let mut encoded = [0u8; 260];
+    let len = cobs_encode(&data, &mut encoded);
+    assert_eq!(encoded[0], 0xFF);
+    assert!(!encoded[..len].contains(&0));
+    let mut decoded = [0u8; 260];
+    assert_eq!(cobs_decode(&encoded[..len], &mut decoded), Some(254));
+    assert_eq!(&decoded[..254], &data[..]);
+
+    // Zero inside and the group longer than the data
+    assert_eq!(cobs_decode(&[3, 1, 0], &mut decoded), None);
+    assert_eq!(cobs_decode(&[5, 1], &mut decoded), None);
+}
+
+#[test]
+fn round_trip() {
+    let mut encoder = Encoder::new();
+    let stream = encode(&mut encoder, &records());
+    let mut decoder = Decoder::new();
+    let frames = decode(&mut decoder, &stream);
+    assert_eq!(frames.iter().map(|f| f.record).collect::<Vec<_>>(), records());
+    assert_eq!(frames.iter().map(|f| f.seq).collect::<Vec<_>>(), [0, 1, 2, 3, 4]);
+    assert_eq!(stream.iter().filter(|&&b| b == 0).count(), 5);
+    assert_eq!(decoder.stats().frames, 5);
+}
+
+#[test]
+fn corrupted_frames() {
+    let mut encoder = Encoder::new();
+    let stream = encode(&mut encoder, &records());
+    let mut decoder = Decoder::new();
+    // Every single bit error is found, the other frames are decoded
+    for i in 0..stream.len() {
+        for bit in 0..8 {
+            let mut corrupted = stream.clone();
+            corrupted[i] ^= 1 << bit;
+            decoder.reset();
+            let frames = decode(&mut decoder, &corrupted);
+            assert!(frames.len() >= 3, "byte {i} bit {bit}");
+            assert!(frames.iter().all(|f| records()[f.seq as usize] == f.record), "byte {i} bit {bit}");
+        }
+    }
+}
+
+#[test]
+fn stream() {
+    let mut decoder = Decoder::new();
+    let frames = decode(&mut decoder, &common::bytes(include_str!("fixtures/telemetry_stream.txt")));
+    assert_eq!(frames.len(), 4);
+    assert_eq!(frames[0], Frame { seq: 0, record: Record::Counter { value: 0 } });
+    assert_eq!(frames[1].record, Record::Status { uptime_ms: 1000, vsys_mv: 4980, temperature_cdeg: 2512 });
+    assert_eq!(frames[2], Frame { seq: 5, record: Record::Counter { value: 256 } });
+    assert_eq!(frames[3].record, Record::other(0x20, &[1, 0, 2, 0, 3]).unwrap());
+
+    let stats = decoder.stats();
+    assert_eq!(stats.frames, 4);
+    assert_eq!(stats.crc_errors, 1);
+    assert_eq!(stats.invalid, 1);
+    // seq 2 to 4
+    assert_eq!(stats.lost, 3);
+}
+
+#[test]
+fn sequence_numbers() {
+    let mut encoder = Encoder::new();
+    let record = Record::Counter { value: 1 };
+    let stream: Vec<Vec<u8>> = (0..5).map(|_| encode(&mut encoder, &[record])).collect();
+    let mut decoder = Decoder::new();
+    decode(&mut decoder, &[stream[0].clone(), stream[3].clone(), stream[4].clone()].concat());
+    assert_eq!(decoder.stats().lost, 2);
+    // Restart of the sender
+    decode(&mut decoder, &stream[0]);
+    assert_eq!(decoder.stats().lost, 2);
+
+    // Frame longer than the buffer is dropped
+    decode(&mut decoder, &[0x55; MAX_FRAME + 1]);
+    decode(&mut decoder, &[0]);
+    assert_eq!(decoder.stats().invalid, 1);
+}
+
+#[test]
+fn fields() {
+    let mut fields = Vec::new();
+    Record::Status { uptime_ms: 10, vsys_mv: 5000, temperature_cdeg: -5 }.fields(|name, value| fields.push(format!("{name}={value}")));
+    Record::other(0x30, &[0xAB, 0x01]).unwrap().fields(|name, value| fields.push(format!("{name}={value}")));
+    assert_eq!(fields, ["uptime_ms=10", "vsys_mv=5000", "temperature_cdeg=-5", "id=48", "data=ab01"]);
+    assert_eq!(Value::U8(7).to_string(), "7");
+    assert!(Record::other(1, &[]).is_none());
+}
@@ -0,0 +1,45 @@
+//! VCD export
+//!
+use rp2040_sandbox::vcd::{period_ps, write_samples, Signal, VcdWriter};
+
+
+#[test]
+fn captured_samples_only_write_changes() {
+    let signals = [Signal::new("clk", 1), Signal::new("data", 4)];
+    let samples = [0b00000, 0b00010, 0b10010, 0b10010, 0b10000];
+    let vcd = write_samples(String::new(), "gpio", &signals, period_ps(1_000_000), &samples).unwrap();
+
+    let expected = "\
+$version rp2040-sandbox $end
+$timescale 1ps $end
+$scope module gpio $end
+$var wire 1 ! clk $end
+$var wire 1 \" data $end
+$upscope $end
+$enddefinitions $end
+#0
+$dumpvars
+0!
+0\"
+$end
+#1000000
+1!
+#2000000
+1\"
+#4000000
+0!
+#5000000
+";
+    assert_eq!(vcd, expected);
+}
+
+#[test]
+fn other_pins_are_ignored() {
+    let signals = [Signal::new("led", 25)];
+    let mut vcd = VcdWriter::new(String::new(), "pio", &signals, period_ps(125_000_000)).unwrap();
+    vcd.sample(0).unwrap();
+    vcd.sample(0xff).unwrap();
+    vcd.sample(1 << 25).unwrap();
+    let vcd = vcd.finish().unwrap();
+    assert!(vcd.ends_with("$end\n#16000\n1!\n#24000\n"));
+}
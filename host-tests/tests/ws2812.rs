@@ -0,0 +1,101 @@
+//! WS2812 encoding, effects and the bit timing on the simulator
+//!
+use rp2040_sandbox::pio_sim::Simulator;
+use rp2040_sandbox::ws2812::effects::Effect;
+use rp2040_sandbox::ws2812::{clock_divisor, encode, ws2812_program, Color, LedType, CYCLES_PER_BIT};
+use rp_pico::hal::pio::{PinDir, ShiftDirection};
+
+
+const DATA: u8 = 22;
+
+
+#[test]
+fn grb_order_at_full_brightness() {
+    assert_eq!(encode(Color::rgb(255, 0, 0), 255, LedType::Rgb), 0x00ff_0000);
+    assert_eq!(encode(Color::rgb(0, 255, 0), 255, LedType::Rgb), 0xff00_0000);
+    assert_eq!(encode(Color::rgb(0, 0, 255), 255, LedType::Rgb), 0x0000_ff00);
+    assert_eq!(encode(Color::rgbw(0, 0, 0, 255), 255, LedType::Rgbw), 0x0000_00ff);
+    // White is ignored by RGB LEDs
+    assert_eq!(encode(Color::rgbw(0, 0, 0, 255), 255, LedType::Rgb), 0);
+}
+
+#[test]
+fn brightness_and_gamma() {
+    let half = encode(Color::rgb(255, 255, 255), 128, LedType::Rgb);
+    // 0.5 ^ 2.8 = 14%
+    assert_eq!(half >> 24, 37);
+    assert_eq!(encode(Color::rgb(255, 255, 255), 0, LedType::Rgb), 0);
+    // Gamma keeps the dark colors dark
+    assert_eq!(encode(Color::rgb(20, 0, 0), 255, LedType::Rgb), 0);
+}
+
+#[test]
+fn hsv_primaries() {
+    assert_eq!(Color::hsv(0, 255, 255), Color::rgb(255, 0, 0));
+    assert_eq!(Color::hsv(0, 0, 100), Color::rgb(100, 100, 100));
+    let green = Color::hsv(86, 255, 255);
+    assert!(green.g == 255 && green.r < 10 && green.b < 10, "{:?}", green);
+    let blue = Color::hsv(172, 255, 255);
+    assert!(blue.b == 255 && blue.r < 10 && blue.g < 10, "{:?}", blue);
+}
+
+#[test]
+fn effects_depend_only_on_time() {
+    let mut frame = [Color::BLACK; 8];
+    let chase = Effect::Chase { color: Color::rgb(1, 2, 3), spacing: 4, step_ms: 100 };
+    chase.render(250_000, &mut frame);
+    let lit: Vec<usize> = (0..8).filter(|&i| frame[i] != Color::BLACK).collect();
+    assert_eq!(lit, [2, 6]);
+
+    let fade = Effect::Fade { from: Color::rgb(0, 0, 0), to: Color::rgb(200, 0, 0), period_ms: 1_000 };
+    fade.render(0, &mut frame);
+    assert_eq!(frame[0], Color::BLACK);
+    fade.render(500_000, &mut frame);
+    assert_eq!(frame[7], Color::rgb(200, 0, 0));
+    fade.render(1_000_000, &mut frame);
+    assert_eq!(frame[3], Color::BLACK);
+
+    let rainbow = Effect::Rainbow { period_ms: 1_000 };
+    rainbow.render(0, &mut frame);
+    assert_eq!(frame[0], Color::hsv(0, 255, 255));
+    assert_eq!(frame[4], Color::hsv(128, 255, 255));
+}
+
+#[test]
+fn bit_timing_on_simulator() {
+    let mut sim = Simulator::new(&ws2812_program())
+        .side_set_pin_base(DATA)
+        .out_shift_direction(ShiftDirection::Left)
+        .autopull(true)
+        .pull_threshold(24);
+    sim.set_pindirs([(DATA, PinDir::Output)]);
+    // 1, 0, 1, 1, 0, 1, 0, 1, ...
+    let word = 0xb5a3_c100;
+    assert!(sim.push_tx(word));
+
+    // Length of every high pulse
+    let mut pulses = Vec::new();
+    let mut high = 0;
+    for _ in 0..24 * CYCLES_PER_BIT + 20 {
+        sim.step();
+        if sim.pin(DATA) {
+            high += 1;
+        } else if high > 0 {
+            pulses.push(high);
+            high = 0;
+        }
+    }
+    assert_eq!(pulses.len(), 24);
+    for (i, &pulse) in pulses.iter().enumerate() {
+        let bit = word >> (31 - i) & 1;
+        assert_eq!(pulse, if bit == 1 { 7 } else { 2 }, "bit {}", i);
+    }
+    assert!(sim.is_stalled());
+    assert!(!sim.pin(DATA));
+}
+
+#[test]
+fn divisor_for_800khz() {
+    // 125MHz / 8MHz = 15.625
+    assert_eq!(clock_divisor(125_000_000), (15, 160));
+}
@@ -0,0 +1,275 @@
+//! Automatic baud rate detection
+//!
+//! A PIO state machine measures every low pulse on the RX pin in system clock cycles. It runs
+//! in parallel with the UART on the same pin, the input of a GPIO is seen by all peripherals
+//! whatever its function. A low pulse starts with the start bit (or a 0 data bit) and ends
+//! with the first 1 bit, so it is always a whole number of bits. High pulses are not used,
+//! the gap between the frames can have any length.
+//!
+//! `Detector` collects the pulses in windows. The shortest pulses of the window give the bit
+//! time, the closest rate from the table is picked and the confidence is the part of the pulses
+//! which are a whole number of these bits. The state machine locks after a few good windows
+//! in a row and unlocks when the pulses don't fit the rate any more, e.g. after the other side
+//! switched to another rate.
+//!
+//! The shortest pulse has to be a single bit, which is true for most of the traffic. Data with
+//! long runs of zeros only (e.g. 0x00 or 0x80 bytes) looks like a slower rate.
+//!
+use rp_pico::hal::pio::{Buffers, PIOBuilder, PIOExt, Running, Rx, StateMachine, StateMachineIndex, UninitStateMachine};
+
+use crate::pio_manager::{PioError, PioManager};
+
+
+/// Standard and RC protocol rates
+pub const BAUD_RATES: [u32; 14] = [
+    4800, 9600, 19_200, 38_400, 57_600, 100_000, 115_200, 125_000, 230_400, 250_000, 420_000, 460_800, 921_600, 1_000_000,
+];
+/// Pulses in the single estimate
+pub const WINDOW: usize = 32;
+/// Shorter pulses are noise (half of the bit at 1 Mbaud)
+pub const MIN_PULSE_NS: u32 = 500;
+/// Measured rate further than this from the table rate doesn't match it
+const TOLERANCE_PERCENT: u64 = 3;
+/// Longest low pulse of a frame: start bit, 8 data bits and parity
+const MAX_RUN_BITS: u32 = 10;
+/// Confidence in percent required for the lock
+pub const LOCK_CONFIDENCE: u8 = 90;
+/// Locked rate is kept while the confidence is at least this
+pub const UNLOCK_CONFIDENCE: u8 = 70;
+/// Good windows in a row before the lock
+const LOCK_WINDOWS: u8 = 2;
+/// Bad windows in a row before the unlock
+const UNLOCK_WINDOWS: u8 = 3;
+/// PIO cycles of the program outside of the counting loop
+const PULSE_OVERHEAD_CYCLES: u32 = 2;
+
+
+/// Result of the single window
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Estimate {
+    /// Rate measured from the shortest pulses
+    pub measured: u32,
+    /// Closest rate from the table, None if none is within the tolerance
+    pub baud_rate: Option<u32>,
+    /// Pulses which are a whole number of bits, in percent. 0 without the baud rate.
+    pub confidence: u8,
+}
+
+impl Estimate {
+    /// Pulse widths in nanoseconds, glitches already removed
+    pub fn new(rates: &[u32], pulses: &[u32]) -> Option<Self> {
+        let shortest = *pulses.iter().min()?;
+        // Average of the single bits
+        let (sum, count) = pulses
+            .iter()
+            .filter(|&&width| width < shortest + shortest / 2)
+            .fold((0u64, 0u64), |(sum, count), &width| (sum + width as u64, count + 1));
+        let bit_ns = sum / count;
+        let measured = (1_000_000_000 + bit_ns / 2) / bit_ns;
+
+        let baud_rate = rates
+            .iter()
+            .copied()
+            .min_by_key(|&rate| rate.abs_diff(measured as u32))
+            .filter(|&rate| (rate as u64).abs_diff(measured) * 100 <= rate as u64 * TOLERANCE_PERCENT);
+        let confidence = match baud_rate {
+            Some(_) => {
+                let fit = pulses
+                    .iter()
+                    .filter(|&&width| {
+                        let bits = (width as u64 + bit_ns / 2) / bit_ns;
+                        // Within a quarter of the bit
+                        bits <= MAX_RUN_BITS as u64 && (width as u64).abs_diff(bits * bit_ns) * 4 <= bit_ns
+                    })
+                    .count();
+                (fit * 100 / pulses.len()) as u8
+            }
+            None => 0,
+        };
+        Some(Self { measured: measured as u32, baud_rate, confidence })
+    }
+}
+
+
+/// State of the detector
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum AutobaudState {
+    /// No rate fits the pulses
+    Searching,
+    /// The rate fits, waiting for more windows
+    Locking { baud_rate: u32 },
+    /// The UART should use this rate
+    Locked { baud_rate: u32 },
+}
+
+
+/// Finds the baud rate from the widths of the low pulses
+#[derive(Clone, Copy, Debug)]
+pub struct Detector {
+    rates: &'static [u32],
+    pulses: [u32; WINDOW],
+    len: usize,
+    state: AutobaudState,
+    /// Good windows while locking, bad windows while locked
+    windows: u8,
+    last: Option<Estimate>,
+}
+
+impl Detector {
+    ///   * rates - allowed rates, e.g. `&BAUD_RATES` or only the rates of the protocol
+    pub const fn new(rates: &'static [u32]) -> Self {
+        Self { rates, pulses: [0; WINDOW], len: 0, state: AutobaudState::Searching, windows: 0, last: None }
+    }
+
+    /// Add the width of the low pulse in nanoseconds.
+    /// Returns the new baud rate when the UART has to be switched.
+    pub fn push(&mut self, width_ns: u32) -> Option<u32> {
+        if width_ns < MIN_PULSE_NS {
+            return None;
+        }
+        self.pulses[self.len] = width_ns;
+        self.len += 1;
+        if self.len < WINDOW {
+            return None;
+        }
+        self.len = 0;
+        let estimate = Estimate::new(self.rates, &self.pulses)?;
+        self.last = Some(estimate);
+        self.update(estimate)
+    }
+
+    fn update(&mut self, estimate: Estimate) -> Option<u32> {
+        let good = |confidence| estimate.baud_rate.filter(|_| estimate.confidence >= confidence);
+        match self.state {
+            AutobaudState::Locked { baud_rate } => {
+                if good(UNLOCK_CONFIDENCE) == Some(baud_rate) {
+                    self.windows = 0;
+                } else {
+                    self.windows += 1;
+                    if self.windows >= UNLOCK_WINDOWS {
+                        self.state = AutobaudState::Searching;
+                        self.windows = 0;
+                    }
+                }
+                None
+            }
+            AutobaudState::Searching | AutobaudState::Locking { .. } => {
+                let Some(rate) = good(LOCK_CONFIDENCE) else {
+                    self.state = AutobaudState::Searching;
+                    return None;
+                };
+                self.windows = match self.state {
+                    AutobaudState::Locking { baud_rate } if baud_rate == rate => self.windows + 1,
+                    _ => 1,
+                };
+                if self.windows >= LOCK_WINDOWS {
+                    self.state = AutobaudState::Locked { baud_rate: rate };
+                    self.windows = 0;
+                    Some(rate)
+                } else {
+                    self.state = AutobaudState::Locking { baud_rate: rate };
+                    None
+                }
+            }
+        }
+    }
+
+    pub fn state(&self) -> AutobaudState {
+        self.state
+    }
+
+    /// Locked baud rate
+    pub fn baud_rate(&self) -> Option<u32> {
+        match self.state {
+            AutobaudState::Locked { baud_rate } => Some(baud_rate),
+            _ => None,
+        }
+    }
+
+    /// Confidence of the last window in percent, 0 if it found another rate than the state
+    pub fn confidence(&self) -> u8 {
+        let rate = match self.state {
+            AutobaudState::Searching => return 0,
+            AutobaudState::Locking { baud_rate } | AutobaudState::Locked { baud_rate } => baud_rate,
+        };
+        match self.last {
+            Some(estimate) if estimate.baud_rate == Some(rate) => estimate.confidence,
+            _ => 0,
+        }
+    }
+
+    /// Result of the last complete window
+    pub fn last_estimate(&self) -> Option<Estimate> {
+        self.last
+    }
+
+    /// Start searching again
+    pub fn reset(&mut self) {
+        *self = Self::new(self.rates);
+    }
+}
+
+
+/// Widths of the low pulses on the pin, measured by PIO
+pub struct PulseCapture<P: PIOExt, SM: StateMachineIndex> {
+    _sm: StateMachine<(P, SM), Running>,
+    rx: Rx<(P, SM)>,
+    sys_clock_hz: u32,
+}
+
+impl<P: PIOExt, SM: StateMachineIndex> PulseCapture<P, SM> {
+    /// Install the program and start measuring
+    ///   * pin - RX pin, it can stay in the UART function
+    pub fn new(
+        pio: &mut PioManager<P>,
+        sm: UninitStateMachine<(P, SM)>,
+        pin: u8,
+        sys_clock_hz: u32,
+    ) -> Result<Self, PioError> {
+        let installed = pio.install("autobaud_pulse", &pulse_program())?;
+        let (sm, rx, _tx) = PIOBuilder::from_program(installed)
+            .in_pin_base(pin)
+            .jmp_pin(pin)
+            .buffers(Buffers::OnlyRx)
+            .build(sm);
+        Ok(Self { _sm: sm.start(), rx, sys_clock_hz })
+    }
+
+    /// Width of the next pulse in nanoseconds. Pulses are lost when the FIFO is full,
+    /// so read them often at the high rates.
+    pub fn read(&mut self) -> Option<u32> {
+        Some(pulse_ns(self.rx.read()?, self.sys_clock_hz))
+    }
+}
+
+
+/// Length of the pulse in PIO cycles from the word pushed by `pulse_program`
+pub const fn pulse_cycles(word: u32) -> u32 {
+    word.saturating_mul(2).saturating_add(PULSE_OVERHEAD_CYCLES)
+}
+
+/// Length of the pulse in nanoseconds from the word pushed by `pulse_program`.
+/// Pulses over ~4.29s (e.g. a break) give `u32::MAX`.
+pub fn pulse_ns(word: u32, sys_clock_hz: u32) -> u32 {
+    let ns = pulse_cycles(word) as u64 * 1_000_000_000 / sys_clock_hz as u64;
+    u32::try_from(ns).unwrap_or(u32::MAX)
+}
+
+/// Counts the low pulses on the jmp pin (also the in pin base) and pushes every count,
+/// without blocking when the FIFO is full. Use without autopush.
+pub fn pulse_program() -> pio::Program<32> {
+    pio_proc::pio_asm!("
+        .wrap_target
+            wait 1 pin 0
+            wait 0 pin 0
+            mov x, ~null
+        low:
+            ; 2 cycles per loop
+            jmp pin done
+            jmp x-- low
+        done:
+            mov isr, ~x
+            push noblock
+        .wrap
+    ").program
+}
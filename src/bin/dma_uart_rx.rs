@@ -0,0 +1,96 @@
+//! UART reception by DMA with the idle line frame detection
+//!
+//! CRSF / ExpressLRS receiver on GPIO5 (UART1 RX) at 420000 baud. The DMA (channel 0) fills
+//! the ring buffer, the main loop gets the complete frames after the idle line and decodes
+//! them with the `rc::crsf` decoder.
+//!
+#![no_std]
+#![no_main]
+
+use rp_pico as bsp;
+use bsp::hal::{
+    clocks::{init_clocks_and_plls, Clock}, dma::DMAExt, fugit::RateExtU32, pac, sio::Sio, uart::{DataBits, StopBits, UartConfig, UartPeripheral}, watchdog::Watchdog, Timer
+};
+use cortex_m::singleton;
+use cortex_m_rt::entry;
+use defmt::*;
+use defmt_rtt as _;
+use panic_probe as _;
+use rp2040_sandbox::dma_uart::{idle_time_us, DmaUart, RxRing};
+use rp2040_sandbox::rc::{crsf::CrsfDecoder, Decoder};
+
+
+const BAUD_RATE: u32 = 420_000;
+/// Print the channels this often
+const PRINT_US: u64 = 500_000;
+
+
+#[entry]
+fn main() -> ! {
+    info!("Program start");
+    let mut pac = pac::Peripherals::take().unwrap();
+    let mut watchdog = Watchdog::new(pac.WATCHDOG);
+    let sio = Sio::new(pac.SIO);
+
+    // External high-speed crystal on the pico board is 12Mhz
+    let external_xtal_freq_hz = 12_000_000u32;
+    let clocks = init_clocks_and_plls(
+        external_xtal_freq_hz,
+        pac.XOSC,
+        pac.CLOCKS,
+        pac.PLL_SYS,
+        pac.PLL_USB,
+        &mut pac.RESETS,
+        &mut watchdog,
+    )
+    .ok()
+    .unwrap();
+
+    let pins = bsp::Pins::new(
+        pac.IO_BANK0,
+        pac.PADS_BANK0,
+        sio.gpio_bank0,
+        &mut pac.RESETS,
+    );
+
+    let timer = Timer::new(pac.TIMER, &mut pac.RESETS, &clocks);
+    let dma = pac.DMA.split(&mut pac.RESETS);
+
+    let uart_pins = (pins.gpio4.into_function(), pins.gpio5.into_function());
+    let uart = UartPeripheral::new(pac.UART1, uart_pins, &mut pac.RESETS)
+        .enable(
+            UartConfig::new(BAUD_RATE.Hz(), DataBits::Eight, None, StopBits::One),
+            clocks.peripheral_clock.freq(),
+        )
+        .unwrap();
+
+    let ring = singleton!(: RxRing = RxRing::new()).unwrap();
+    let mut uart = DmaUart::new(uart, dma.ch0, ring, idle_time_us(BAUD_RATE, 3));
+
+    let mut decoder = CrsfDecoder::new();
+    let mut last_frame = None;
+    let mut last_print = 0;
+    loop {
+        let now = timer.get_counter().ticks();
+        uart.poll(now, |frame, _time_us| {
+            // Every frame starts at the beginning
+            decoder.reset();
+            for &byte in frame {
+                if let Some(rc_frame) = decoder.push(byte) {
+                    last_frame = Some(rc_frame);
+                }
+            }
+        });
+
+        if now - last_print > PRINT_US {
+            last_print = now;
+            if let Some(frame) = last_frame {
+                info!("LQ: {}, ch: {}", frame.link_quality, &frame.channels[..frame.count]);
+            }
+            let stats = uart.stats();
+            info!("{} frames, {} CRC errors, {}", stats.frames, decoder.crc_errors(), stats);
+        }
+    }
+}
+
+// End of file
@@ -0,0 +1,167 @@
+//! GPS logger with the PPS time sync
+//!
+//! GPS receiver (e.g. u-blox NEO-6M) TX on GPIO5 (UART1 RX) at 9600 baud, the PPS output
+//! on GPIO3. The NMEA sentences are received in the UART interrupt (`buffered_uart`) and
+//! parsed with `nmea::Parser`. The PPS edges are timestamped in the GPIO interrupt and,
+//! together with the RMC time, lock `GpsClock` to UTC.
+//!
+//! Every second the position and the UTC of the `Timer` are printed. Without the PPS wire
+//! the time is still available, with the accuracy of the sentence reception.
+//!
+#![no_std]
+#![no_main]
+
+use rp_pico as bsp;
+use bsp::hal::{
+    clocks::{init_clocks_and_plls, Clock}, fugit::RateExtU32, gpio::{bank0::{Gpio3, Gpio4, Gpio5}, FunctionSioInput, FunctionUart, Interrupt, Pin, PullDown}, pac::{self, interrupt}, sio::Sio, uart::{DataBits, StopBits, UartConfig, UartPeripheral}, watchdog::Watchdog, Timer
+};
+use core::cell::RefCell;
+use cortex_m_rt::entry;
+use critical_section::Mutex;
+use defmt::*;
+use defmt_rtt as _;
+use panic_probe as _;
+use rp2040_sandbox::buffered_uart::BufferedUart;
+use rp2040_sandbox::gps_clock::GpsClock;
+use rp2040_sandbox::nmea::{date_time, Parser, Sentence};
+
+
+const BAUD_RATE: u32 = 9600;
+/// Print the state this often
+const PRINT_US: u64 = 1_000_000;
+
+type UartPins = (Pin<Gpio4, FunctionUart, PullDown>, Pin<Gpio5, FunctionUart, PullDown>);
+type GpsUart = BufferedUart<pac::UART1, UartPins, 512, 16>;
+
+/// PPS input handled in the interrupt
+struct Pps {
+    pin: Pin<Gpio3, FunctionSioInput, PullDown>,
+    timer: Timer,
+    /// Timer value of the edge not taken by the main loop
+    edge: Option<u64>,
+}
+
+static UART: Mutex<RefCell<Option<GpsUart>>> = Mutex::new(RefCell::new(None));
+static PPS: Mutex<RefCell<Option<Pps>>> = Mutex::new(RefCell::new(None));
+
+
+#[entry]
+fn main() -> ! {
+    info!("Program start");
+    let mut pac = pac::Peripherals::take().unwrap();
+    let mut watchdog = Watchdog::new(pac.WATCHDOG);
+    let sio = Sio::new(pac.SIO);
+
+    // External high-speed crystal on the pico board is 12Mhz
+    let external_xtal_freq_hz = 12_000_000u32;
+    let clocks = init_clocks_and_plls(
+        external_xtal_freq_hz,
+        pac.XOSC,
+        pac.CLOCKS,
+        pac.PLL_SYS,
+        pac.PLL_USB,
+        &mut pac.RESETS,
+        &mut watchdog,
+    )
+    .ok()
+    .unwrap();
+
+    let pins = bsp::Pins::new(
+        pac.IO_BANK0,
+        pac.PADS_BANK0,
+        sio.gpio_bank0,
+        &mut pac.RESETS,
+    );
+
+    let timer = Timer::new(pac.TIMER, &mut pac.RESETS, &clocks);
+
+    let uart_pins = (pins.gpio4.into_function(), pins.gpio5.into_function());
+    let uart = UartPeripheral::new(pac.UART1, uart_pins, &mut pac.RESETS)
+        .enable(
+            UartConfig::new(BAUD_RATE.Hz(), DataBits::Eight, None, StopBits::One),
+            clocks.peripheral_clock.freq(),
+        )
+        .unwrap();
+    critical_section::with(|cs| UART.borrow_ref_mut(cs).replace(BufferedUart::new(uart, timer)));
+
+    let pin = pins.gpio3.into_pull_down_input();
+    pin.set_interrupt_enabled(Interrupt::EdgeHigh, true);
+    critical_section::with(|cs| PPS.borrow_ref_mut(cs).replace(Pps { pin, timer, edge: None }));
+    unsafe {
+        pac::NVIC::unmask(pac::Interrupt::UART1_IRQ);
+        pac::NVIC::unmask(pac::Interrupt::IO_IRQ_BANK0);
+    }
+
+    let mut parser = Parser::new();
+    let mut clock = GpsClock::new();
+    let mut last_fix = None;
+    let mut buffer = [0u8; 64];
+    let mut last_print = 0;
+    loop {
+        let pps = critical_section::with(|cs| PPS.borrow_ref_mut(cs).as_mut().and_then(|pps| pps.edge.take()));
+        if let Some(edge) = pps {
+            clock.on_pps(edge);
+        }
+
+        while let Some(chunk) = critical_section::with(|cs| UART.borrow_ref_mut(cs).as_mut().unwrap().read_chunk(&mut buffer)) {
+            for &byte in &buffer[..chunk.len] {
+                match parser.push(byte) {
+                    Some(Sentence::Rmc(rmc)) => {
+                        if let (Some(date), Some(time)) = (rmc.date, rmc.time) {
+                            clock.on_time(&date, &time, chunk.time_us);
+                        }
+                    }
+                    Some(Sentence::Gga(gga)) if gga.quality > 0 => last_fix = Some(gga),
+                    _ => {}
+                }
+            }
+        }
+
+        let now = timer.get_counter().ticks();
+        if now - last_print > PRINT_US {
+            last_print = now;
+            if let Some(utc) = clock.utc_us(now) {
+                let (date, time) = date_time(utc);
+                info!(
+                    "{=u16}-{=u8:02}-{=u8:02} {=u8:02}:{=u8:02}:{=u8:02}.{=u16:03} {} ({} ppb)",
+                    date.year, date.month, date.day, time.hour, time.minute, time.second, time.millis,
+                    clock.state(now), clock.frequency_error_ppb(),
+                );
+            }
+            if let Some(fix) = last_fix.take() {
+                if let Some(position) = fix.position {
+                    info!(
+                        "lat: {=i32}, lon: {=i32} (1e-7 deg), alt: {}m, sats: {}",
+                        position.latitude, position.longitude, fix.altitude, fix.satellites,
+                    );
+                }
+            }
+            let stats = parser.stats();
+            if stats.checksum_errors > 0 || stats.invalid > 0 {
+                warn!("{}", stats);
+            }
+        }
+    }
+}
+
+
+#[interrupt]
+fn UART1_IRQ() {
+    critical_section::with(|cs| {
+        if let Some(uart) = UART.borrow_ref_mut(cs).as_mut() {
+            uart.on_interrupt();
+        }
+    });
+}
+
+#[interrupt]
+fn IO_IRQ_BANK0() {
+    critical_section::with(|cs| {
+        if let Some(pps) = PPS.borrow_ref_mut(cs).as_mut() {
+            pps.edge = Some(pps.timer.get_counter().ticks());
+            pps.pin.clear_interrupt(Interrupt::EdgeHigh);
+        }
+    });
+}
+
+// End of file
@@ -1,49 +1,85 @@
 //! Implementation of I2S protocol for sending PCM data
 //!
 //! Look at the code to find which 3 pins should be connected.
+//! Other formats (left/right-justified, TDM) can be selected with `FORMAT`,
+//! and the optional MCLK output with `MCLK_SOURCE`.
+//! Buffers are refilled from the DMA interrupt, potentiometer on GPIO26 sets the tone frequency.
+//! Late refills (underruns) are reported every second.
 //!
 #![no_std]
 #![no_main]
 
 use bsp::hal::{
-    dma::{double_buffer, DMAExt}, gpio::FunctionPio0, pac, pio::{Buffers, PIOBuilder, PIOExt, PinDir, ShiftDirection}, sio::Sio
+    adc::AdcPin, clocks::{init_clocks_and_plls, Clock}, dma::{Channel, DMAExt, CH0, CH1}, gpio::{FunctionClock, FunctionPio0}, pac::{self, interrupt, PIO0}, pio::{Tx, SM0, SM1}, sio::Sio, watchdog::Watchdog, Adc
 };
+use core::cell::RefCell;
 use cortex_m::singleton;
 use cortex_m_rt::entry;
+use critical_section::Mutex;
 use defmt::*;
 use defmt_rtt as _;
+use embedded_hal::adc::OneShot;
 use panic_probe as _;
 use rp_pico as bsp;
 #[allow(unused_imports)]
 use num_traits::float::Float;
+use rp2040_sandbox::i2s::{mclk::{MclkPlan, MclkSource}, stream::{I2sStream, Refill, UnderrunPolicy}, Format, I2sConfig};
 use rp2040_sandbox::oscillator::{Oscillator, Square};
+use rp2040_sandbox::pio_manager::PioManager;
 
 
 // Sound sample rate
 const SAMPLE_RATE: u32 = 48_000;
 // Bits per channel
-const NUM_BITS: u32 = 32;
-// System clock
-const RP2040_CLOCK_HZ: u32 = 125_000_000;
-// Number of cycles required for sending single sample
-const CYCLES_PER_SAMPLE: u32 = 5;
-// I2S bit clock
-const I2S_PIO_CLOCK_HZ: u32 = SAMPLE_RATE * 2 * NUM_BITS * CYCLES_PER_SAMPLE;
-/// int + (frac/256)
-const I2S_PIO_CLOCKDIV_INT: u16 = (RP2040_CLOCK_HZ / I2S_PIO_CLOCK_HZ) as u16;
-const I2S_PIO_CLOCKDIV_FRAC: u8 = 0u8;
+const NUM_BITS: u8 = 32;
+// Frame format. Use e.g. Format::Tdm { slots: 4 } for multi-channel codecs
+const FORMAT: Format = Format::I2s;
+const I2S_CONFIG: I2sConfig = I2sConfig::new(FORMAT, SAMPLE_RATE, NUM_BITS);
+// Master clock for codecs which need it: GPIO16 (Pio) or GPIO21 (Gpout0). None for 3 pins only.
+const MCLK_SOURCE: Option<MclkSource> = Some(MclkSource::Pio);
+// MCLK as multiple of the sample rate
+const MCLK_FS: u32 = 256;
 
 // How many sample can be put into DMA buffer. (Mono)
 const DMA_BUFFER_SIZE: usize = 16;
+// Number of slots in a single frame
+const NUM_SLOTS: usize = FORMAT.slots();
+const BUFFER_LEN: usize = DMA_BUFFER_SIZE * NUM_SLOTS;
+// What is sent when the buffer is not refilled in time
+const UNDERRUN_POLICY: UnderrunPolicy = UnderrunPolicy::Silence;
+// Number of main loop iterations (100ms) between underrun reports
+const REPORT_LOOPS: u32 = 10;
+// ADC change which retunes the tone. A new oscillator starts from zero phase, which clicks.
+const POT_HYSTERESIS: u16 = 16;
+
+type Stream = I2sStream<Channel<CH0>, Channel<CH1>, Tx<(PIO0, SM0)>, Tone, BUFFER_LEN>;
+// Refilled from DMA_IRQ_0
+static STREAM: Mutex<RefCell<Option<Stream>>> = Mutex::new(RefCell::new(None));
 
 #[entry]
 fn main() -> ! {
     info!("Program start");
     info!("SAMPLE_RATE: {=u32}", SAMPLE_RATE);
-    info!("I2S_PIO_CLOCKDIV_INT: {=u16}", I2S_PIO_CLOCKDIV_INT);
     let mut peripherals = pac::Peripherals::take().unwrap();
+    let core = pac::CorePeripherals::take().unwrap();
+    let mut watchdog = Watchdog::new(peripherals.WATCHDOG);
     let sio = Sio::new(peripherals.SIO);
 
+    // External high-speed crystal on the pico board is 12Mhz
+    let external_xtal_freq_hz = 12_000_000u32;
+    let mut clocks = init_clocks_and_plls(
+        external_xtal_freq_hz,
+        peripherals.XOSC,
+        peripherals.CLOCKS,
+        peripherals.PLL_SYS,
+        peripherals.PLL_USB,
+        &mut peripherals.RESETS,
+        &mut watchdog,
+    )
+    .ok()
+    .unwrap();
+    let sys_clock_hz = clocks.system_clock.freq().to_Hz();
+
     let pins = bsp::Pins::new(
         peripherals.IO_BANK0,
         peripherals.PADS_BANK0,
@@ -53,73 +89,116 @@ fn main() -> ! {
 
     let data_out_pin = pins.gpio13.into_function::<FunctionPio0>();
     let bclk_pin = pins.gpio14.into_function::<FunctionPio0>();
-    let lrclk_pin = pins.gpio15.into_function::<FunctionPio0>();
-
-    let (mut pio, sm0, _, _, _) = peripherals.PIO0.split(&mut peripherals.RESETS);
-    // Install a program in instruction memory.
-    let program = pio_proc::pio_asm!(" 
-        .side_set 2
-                    ;                  /----LRCLK
-                    ;                  |/---BCLK    
-        .wrap_target    
-            set y, 30 [2]       side 0b01
-        loopLch:
-            out pins, 1 [1]     side 0b00; MSB -> LSB
-            jmp y-- loopLch [2] side 0b01
-            out pins, 1 [1]     side 0b10; LSB
-            set y, 30 [2]       side 0b11
-        loopRch:
-            out pins, 1 [1]     side 0b10; MSB -> LSB
-            jmp y-- loopRch [2] side 0b11
-            out pins, 1 [1]     side 0b00; LSB
-        .wrap
-    ").program;
-    let installed = pio.install(&program).unwrap();
-    // Configure a state machine to use the program.
-    let (mut sm0, _rx, tx) = PIOBuilder::from_program(installed)
-        .out_pins(data_out_pin.id().num, 1)
-        .side_set_pin_base(bclk_pin.id().num)
-        .out_shift_direction(ShiftDirection::Left) // I2S MSB first
-        .autopull(true)
-        .pull_threshold(32u8) // Bit-depth: 32bit
-        .buffers(Buffers::OnlyTx)
-        .clock_divisor_fixed_point(I2S_PIO_CLOCKDIV_INT, I2S_PIO_CLOCKDIV_FRAC)
-        .build(sm0);
-
-    // The GPIO pin needs to be configured as an output.
-    sm0.set_pindirs([
-        (data_out_pin.id().num, PinDir::Output),
-        (bclk_pin.id().num, PinDir::Output),
-        (lrclk_pin.id().num, PinDir::Output),
-    ]);
-    sm0.start(); 
+    // LRCLK (frame sync for TDM) has to be the pin next to BCLK
+    let _lrclk_pin = pins.gpio15.into_function::<FunctionPio0>();
+
+    // MCLK has to be locked to BCLK, which dictates the I2S clock divisor
+    let mclk_plan = MCLK_SOURCE.map(|source| MclkPlan::new(&I2S_CONFIG, MCLK_FS, source, sys_clock_hz).unwrap());
+    let (clockdiv_int, clockdiv_frac) = match &mclk_plan {
+        Some(plan) => plan.i2s_divisor,
+        None => I2S_CONFIG.clock_divisor(sys_clock_hz).unwrap(),
+    };
+    info!("I2S_PIO_CLOCKDIV: {=u16} + {=u8}/256", clockdiv_int, clockdiv_frac);
+    if let Some(plan) = &mclk_plan {
+        info!("MCLK: {=f32} Hz, jitter: {=f32} ns", plan.mclk_hz, plan.mclk_jitter_ns);
+        info!("BCLK jitter: {=f32} ns", plan.bclk_jitter_ns);
+        info!("Sample rate: {=f32} Hz, error: {=f32} ppm", plan.sample_rate, plan.error_ppm);
+    }
+
+    let mut pio0 = PioManager::new(peripherals.PIO0, &mut peripherals.RESETS);
+    // Install the program and configure a state machine to use it.
+    let sm0 = pio0.claim::<SM0>().unwrap();
+    let (sm0, tx) = I2S_CONFIG
+        .init(&mut pio0, sm0, data_out_pin.id().num, bclk_pin.id().num, (clockdiv_int, clockdiv_frac))
+        .unwrap();
+    match mclk_plan {
+        Some(plan @ MclkPlan { source: MclkSource::Pio, .. }) => {
+            let mclk_pin = pins.gpio16.into_function::<FunctionPio0>();
+            let sm1 = pio0.claim::<SM1>().unwrap();
+            let sm1 = plan.init_pio(&mut pio0, sm1, mclk_pin.id().num).unwrap();
+            // Start both state machines with clock dividers in phase
+            sm0.with(sm1).sync().start();
+        }
+        Some(plan @ MclkPlan { source: MclkSource::Gpout0, .. }) => {
+            let _mclk_pin = pins.gpio21.into_function::<FunctionClock>();
+            plan.init_gpout0(&mut clocks.gpio_output0_clock, &clocks.system_clock).unwrap();
+            sm0.start();
+        }
+        None => {
+            sm0.start();
+        }
+    }
 
     //=============================DMA===============================
     let dma_channels = peripherals.DMA.split(&mut peripherals.RESETS);
-    // Static buffers. 2* BUFFER_SIZE for stereo
-    let i2s_tx_buf1 = singleton!(: [u32; DMA_BUFFER_SIZE*2] = [12345; DMA_BUFFER_SIZE*2]).unwrap(); //static
-    let i2s_tx_buf2 = singleton!(: [u32; DMA_BUFFER_SIZE*2] = [123; DMA_BUFFER_SIZE*2]).unwrap(); //static
-    let i2s_dma_config =
-        double_buffer::Config::new((dma_channels.ch0, dma_channels.ch1), i2s_tx_buf1, tx);
-    let i2s_tx_transfer = i2s_dma_config.start(); 
-    let mut i2s_tx_transfer = i2s_tx_transfer.read_next(i2s_tx_buf2);
-
-    let mut square = Square::new(220.0, SAMPLE_RATE);
+    // Static buffers. NUM_SLOTS * BUFFER_SIZE, 2 slots for stereo
+    let i2s_tx_buf1 = singleton!(: [u32; BUFFER_LEN] = [0; BUFFER_LEN]).unwrap(); //static
+    let i2s_tx_buf2 = singleton!(: [u32; BUFFER_LEN] = [0; BUFFER_LEN]).unwrap(); //static
+    let tone = Tone { square: Square::new(220.0, SAMPLE_RATE) };
+    let mut stream = I2sStream::start((dma_channels.ch0, dma_channels.ch1), (i2s_tx_buf1, i2s_tx_buf2), tx, tone);
+    stream.set_underrun_policy(UNDERRUN_POLICY);
+    critical_section::with(|cs| STREAM.borrow_ref_mut(cs).replace(stream));
+    // Safety: the handler only touches STREAM, which is already initialized
+    unsafe { pac::NVIC::unmask(pac::Interrupt::DMA_IRQ_0) };
+
+    // The main loop is free for other work. Here the potentiometer sets the tone frequency.
+    let mut delay = cortex_m::delay::Delay::new(core.SYST, sys_clock_hz);
+    let mut adc = Adc::new(peripherals.ADC, &mut peripherals.RESETS);
+    let mut pot_pin = AdcPin::new(pins.gpio26);
+    let mut loops = 0;
+    let mut last_v: Option<u16> = None;
     loop {
-        if i2s_tx_transfer.is_done() {
-            let (next_tx_buf, next_tx_transfer) = i2s_tx_transfer.wait();
-
-            for (i, e) in next_tx_buf.iter_mut().enumerate() {
-                if i % 2 == 0 {
-                    // Left channel only
-                    let sample: u32 = square.next_sample();
-                    *e = sample / 100;
+        let v: u16 = adc.read(&mut pot_pin).unwrap();
+        // Only when the pot was moved, not on every ADC noise
+        let retune = last_v.is_none_or(|last| v.abs_diff(last) > POT_HYSTERESIS);
+        if retune {
+            last_v = Some(v);
+        }
+        let freq = 110.0 + 770.0 * v as f32 / 4096.0;
+        let stats = critical_section::with(|cs| {
+            STREAM.borrow_ref_mut(cs).as_mut().map(|stream| {
+                if retune {
+                    stream.source_mut().square = Square::new(freq, SAMPLE_RATE);
                 }
-            }
+                stream.stats()
+            })
+        });
+
+        loops += 1;
+        if let (Some(stats), true) = (stats, loops >= REPORT_LOOPS) {
+            let word_rate = SAMPLE_RATE * NUM_SLOTS as u32;
+            info!("{}, worst margin: {=u32}us", stats, stats.worst_margin_us(word_rate));
+            loops = 0;
+        }
+        delay.delay_ms(100);
+    }
+}
+
 
-            i2s_tx_transfer = next_tx_transfer.read_next(next_tx_buf);
+/// Square wave on the first slot of every frame
+struct Tone {
+    square: Square,
+}
+
+impl Refill for Tone {
+    fn refill(&mut self, buffer: &mut [u32]) {
+        for (i, e) in buffer.iter_mut().enumerate() {
+            if i % NUM_SLOTS == 0 {
+                // Left channel only
+                let sample: u32 = self.square.next_sample();
+                *e = sample / 100;
+            }
         }
     }
 }
 
+#[interrupt]
+fn DMA_IRQ_0() {
+    critical_section::with(|cs| {
+        if let Some(stream) = STREAM.borrow_ref_mut(cs).as_mut() {
+            stream.on_interrupt();
+        }
+    });
+}
+
 // End of file
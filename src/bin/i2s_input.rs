@@ -0,0 +1,114 @@
+//! Capture audio from I2S MEMS microphone (INMP441, SPH0645)
+//!
+//! Pins:
+//!   * GPIO12 - microphone data (SD)
+//!   * GPIO14 - BCLK (SCK)
+//!   * GPIO15 - LRCLK (WS)
+//!
+//! Microphone L/R pin selects the slot. Peak level is printed over defmt.
+//!
+#![no_std]
+#![no_main]
+
+use bsp::hal::{
+    clocks::{init_clocks_and_plls, Clock}, dma::{double_buffer, DMAExt}, gpio::FunctionPio0, pac, pio::SM0, sio::Sio, watchdog::Watchdog
+};
+use cortex_m::singleton;
+use cortex_m_rt::entry;
+use defmt::*;
+use defmt_rtt as _;
+use panic_probe as _;
+use rp_pico as bsp;
+use rp2040_sandbox::i2s::{input::{I2sInput, Mode, Slot}, Format, I2sConfig};
+use rp2040_sandbox::pio_manager::PioManager;
+
+
+// Sound sample rate
+const SAMPLE_RATE: u32 = 48_000;
+// Microphone resolution. INMP441: 24 bits, SPH0645: 18 bits
+const NUM_BITS: u8 = 24;
+const I2S_INPUT: I2sInput = I2sInput::new(I2sConfig::new(Format::I2s, SAMPLE_RATE, NUM_BITS), Mode::Receive);
+// Microphone with L/R pin connected to GND sends left slot
+const MIC_SLOT: Slot = Slot::Left;
+
+// How many sample can be put into DMA buffer. (Mono)
+const DMA_BUFFER_SIZE: usize = 16;
+// Number of buffers between level reports
+const REPORT_BUFFERS: u32 = SAMPLE_RATE / DMA_BUFFER_SIZE as u32 / 2;
+
+#[entry]
+fn main() -> ! {
+    info!("Program start");
+    let mut pac = pac::Peripherals::take().unwrap();
+    let mut watchdog = Watchdog::new(pac.WATCHDOG);
+    let sio = Sio::new(pac.SIO);
+
+    // External high-speed crystal on the pico board is 12Mhz
+    let external_xtal_freq_hz = 12_000_000u32;
+    let clocks = init_clocks_and_plls(
+        external_xtal_freq_hz,
+        pac.XOSC,
+        pac.CLOCKS,
+        pac.PLL_SYS,
+        pac.PLL_USB,
+        &mut pac.RESETS,
+        &mut watchdog,
+    )
+    .ok()
+    .unwrap();
+
+    let pins = bsp::Pins::new(
+        pac.IO_BANK0,
+        pac.PADS_BANK0,
+        sio.gpio_bank0,
+        &mut pac.RESETS,
+    );
+
+    // INMP441 releases the data line after the last bit, keep it at a defined level
+    let data_in_pin = pins.gpio12.into_pull_down_input().into_function::<FunctionPio0>();
+    let bclk_pin = pins.gpio14.into_function::<FunctionPio0>();
+    let _lrclk_pin = pins.gpio15.into_function::<FunctionPio0>();
+
+    let mut pio0 = PioManager::new(pac.PIO0, &mut pac.RESETS);
+    let sm0 = pio0.claim::<SM0>().unwrap();
+    let divisor = I2S_INPUT.config.clock_divisor(clocks.system_clock.freq().to_Hz()).unwrap();
+    let (sm0, rx, _tx) = I2S_INPUT
+        .init(&mut pio0, sm0, data_in_pin.id().num, None, bclk_pin.id().num, divisor)
+        .unwrap();
+    sm0.start();
+
+    //=============================DMA===============================
+    let dma_channels = pac.DMA.split(&mut pac.RESETS);
+    // Static buffers. 2* BUFFER_SIZE for stereo
+    let i2s_rx_buf1 = singleton!(: [u32; DMA_BUFFER_SIZE*2] = [0; DMA_BUFFER_SIZE*2]).unwrap();
+    let i2s_rx_buf2 = singleton!(: [u32; DMA_BUFFER_SIZE*2] = [0; DMA_BUFFER_SIZE*2]).unwrap();
+    let i2s_dma_config =
+        double_buffer::Config::new((dma_channels.ch0, dma_channels.ch1), rx, i2s_rx_buf1);
+    let i2s_rx_transfer = i2s_dma_config.start();
+    let mut i2s_rx_transfer = i2s_rx_transfer.write_next(i2s_rx_buf2);
+
+    let mut samples = [0i32; DMA_BUFFER_SIZE];
+    let mut peak = 0u32;
+    let mut buffers = 0;
+    loop {
+        if i2s_rx_transfer.is_done() {
+            let (next_rx_buf, next_rx_transfer) = i2s_rx_transfer.wait();
+
+            let count = I2S_INPUT.mono(next_rx_buf, MIC_SLOT, &mut samples);
+            for s in &samples[..count] {
+                peak = peak.max(s.unsigned_abs());
+            }
+
+            i2s_rx_transfer = next_rx_transfer.write_next(next_rx_buf);
+
+            buffers += 1;
+            if buffers >= REPORT_BUFFERS {
+                info!("Peak: {=u32}", peak);
+                peak = 0;
+                buffers = 0;
+            }
+        }
+    }
+}
+
+// End of file
@@ -0,0 +1,122 @@
+//! Full duplex I2S: microphone or codec ADC in, DAC out
+//!
+//! Single state machine drives BCLK/LRCLK for both directions. Every received frame is passed
+//! through `effect` and sent back, which is the skeleton of an effects pedal.
+//!
+//! Pins:
+//!   * GPIO12 - data in
+//!   * GPIO13 - data out
+//!   * GPIO14 - BCLK
+//!   * GPIO15 - LRCLK
+//!
+#![no_std]
+#![no_main]
+
+use bsp::hal::{
+    clocks::{init_clocks_and_plls, Clock}, dma::{double_buffer, DMAExt}, gpio::FunctionPio0, pac, pio::SM0, sio::Sio, watchdog::Watchdog
+};
+use cortex_m::singleton;
+use cortex_m_rt::entry;
+use defmt::*;
+use defmt_rtt as _;
+use panic_probe as _;
+use rp_pico as bsp;
+use rp2040_sandbox::i2s::{input::{I2sInput, Mode}, Format, I2sConfig};
+use rp2040_sandbox::pio_manager::PioManager;
+
+
+// Sound sample rate
+const SAMPLE_RATE: u32 = 48_000;
+// Bits per channel
+const NUM_BITS: u8 = 24;
+const I2S_CONFIG: I2sConfig = I2sConfig::new(Format::I2s, SAMPLE_RATE, NUM_BITS);
+const I2S_DUPLEX: I2sInput = I2sInput::new(I2S_CONFIG, Mode::FullDuplex);
+// Output gain
+const GAIN: i32 = 2;
+
+// How many sample can be put into DMA buffer. (Mono)
+const DMA_BUFFER_SIZE: usize = 16;
+
+
+fn effect(sample: i32) -> i32 {
+    let max = (1 << (NUM_BITS - 1)) - 1;
+    (sample * GAIN).clamp(-max, max)
+}
+
+#[entry]
+fn main() -> ! {
+    info!("Program start");
+    let mut pac = pac::Peripherals::take().unwrap();
+    let mut watchdog = Watchdog::new(pac.WATCHDOG);
+    let sio = Sio::new(pac.SIO);
+
+    // External high-speed crystal on the pico board is 12Mhz
+    let external_xtal_freq_hz = 12_000_000u32;
+    let clocks = init_clocks_and_plls(
+        external_xtal_freq_hz,
+        pac.XOSC,
+        pac.CLOCKS,
+        pac.PLL_SYS,
+        pac.PLL_USB,
+        &mut pac.RESETS,
+        &mut watchdog,
+    )
+    .ok()
+    .unwrap();
+
+    let pins = bsp::Pins::new(
+        pac.IO_BANK0,
+        pac.PADS_BANK0,
+        sio.gpio_bank0,
+        &mut pac.RESETS,
+    );
+
+    let data_in_pin = pins.gpio12.into_pull_down_input().into_function::<FunctionPio0>();
+    let data_out_pin = pins.gpio13.into_function::<FunctionPio0>();
+    let bclk_pin = pins.gpio14.into_function::<FunctionPio0>();
+    let _lrclk_pin = pins.gpio15.into_function::<FunctionPio0>();
+
+    let mut pio0 = PioManager::new(pac.PIO0, &mut pac.RESETS);
+    let sm0 = pio0.claim::<SM0>().unwrap();
+    let divisor = I2S_CONFIG.clock_divisor(clocks.system_clock.freq().to_Hz()).unwrap();
+    let (sm0, rx, tx) = I2S_DUPLEX
+        .init(
+            &mut pio0,
+            sm0,
+            data_in_pin.id().num,
+            Some(data_out_pin.id().num),
+            bclk_pin.id().num,
+            divisor,
+        )
+        .unwrap();
+
+    //=============================DMA===============================
+    let dma_channels = pac.DMA.split(&mut pac.RESETS);
+    // Static buffers. 2* BUFFER_SIZE for stereo
+    let i2s_tx_buf1 = singleton!(: [u32; DMA_BUFFER_SIZE*2] = [0; DMA_BUFFER_SIZE*2]).unwrap();
+    let i2s_tx_buf2 = singleton!(: [u32; DMA_BUFFER_SIZE*2] = [0; DMA_BUFFER_SIZE*2]).unwrap();
+    let i2s_rx_buf1 = singleton!(: [u32; DMA_BUFFER_SIZE*2] = [0; DMA_BUFFER_SIZE*2]).unwrap();
+    let i2s_rx_buf2 = singleton!(: [u32; DMA_BUFFER_SIZE*2] = [0; DMA_BUFFER_SIZE*2]).unwrap();
+    let i2s_tx_transfer =
+        double_buffer::Config::new((dma_channels.ch0, dma_channels.ch1), i2s_tx_buf1, tx).start();
+    let mut i2s_tx_transfer = i2s_tx_transfer.read_next(i2s_tx_buf2);
+    let i2s_rx_transfer =
+        double_buffer::Config::new((dma_channels.ch2, dma_channels.ch3), rx, i2s_rx_buf1).start();
+    let mut i2s_rx_transfer = i2s_rx_transfer.write_next(i2s_rx_buf2);
+    sm0.start();
+
+    loop {
+        // Both directions run from the same clock, so the buffers complete together
+        let (rx_buf, next_rx_transfer) = i2s_rx_transfer.wait();
+        let (tx_buf, next_tx_transfer) = i2s_tx_transfer.wait();
+
+        for (out, &word) in tx_buf.iter_mut().zip(rx_buf.iter()) {
+            *out = I2S_CONFIG.pack(effect(I2S_DUPLEX.sample(word)));
+        }
+
+        i2s_tx_transfer = next_tx_transfer.read_next(tx_buf);
+        i2s_rx_transfer = next_rx_transfer.write_next(rx_buf);
+    }
+}
+
+// End of file
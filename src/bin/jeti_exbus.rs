@@ -0,0 +1,209 @@
+//! Jeti EX Bus receiver and telemetry
+//!
+//! EX Bus is half-duplex: connect GPIO4 (UART1 TX) and GPIO5 (UART1 RX) together to the
+//! EX Bus port of the receiver. TX drives the line only while we answer.
+//!
+//! The baud rate (125000 or 250000) is found automatically. Channels are printed over defmt,
+//! VSYS voltage and the chip temperature are sent as the telemetry. The JetiBox shows
+//! the same values, up/down keys switch to the channels screen.
+//!
+#![no_std]
+#![no_main]
+
+use rp_pico as bsp;
+use bsp::hal::{
+    adc::AdcPin, clocks::{init_clocks_and_plls, Clock}, fugit::RateExtU32, pac, sio::Sio, uart::{DataBits, StopBits, UartConfig, UartPeripheral}, watchdog::Watchdog, Adc, Timer
+};
+use core::fmt::Write;
+use cortex_m_rt::entry;
+use defmt::*;
+use defmt_rtt as _;
+use embedded_hal::adc::OneShot;
+use panic_probe as _;
+use rp2040_sandbox::buffered_uart::set_tx_enabled;
+use rp2040_sandbox::jeti_exbus::{BaudDetector, Keys, Packet, Parser, Responder, Sensor, MAX_PACKET};
+
+
+/// UART TX pin
+const TX_PIN: usize = 4;
+/// Free to use manufacturer ID
+const MANUFACTURER_ID: u16 = 0xA409;
+const DEVICE_ID: u16 = 0x0001;
+const SENSORS: [Sensor; 2] = [
+    Sensor { label: "VSYS", unit: "V", decimals: 2 },
+    Sensor { label: "Temperature", unit: "C", decimals: 1 },
+];
+/// Silence on the line which ends the packet
+const PACKET_GAP_US: u64 = 500;
+/// Measure and print this often
+const UPDATE_US: u64 = 500_000;
+
+
+#[entry]
+fn main() -> ! {
+    info!("Program start");
+    let mut pac = pac::Peripherals::take().unwrap();
+    let mut watchdog = Watchdog::new(pac.WATCHDOG);
+    let sio = Sio::new(pac.SIO);
+
+    // External high-speed crystal on the pico board is 12Mhz
+    let external_xtal_freq_hz = 12_000_000u32;
+    let clocks = init_clocks_and_plls(
+        external_xtal_freq_hz,
+        pac.XOSC,
+        pac.CLOCKS,
+        pac.PLL_SYS,
+        pac.PLL_USB,
+        &mut pac.RESETS,
+        &mut watchdog,
+    )
+    .ok()
+    .unwrap();
+
+    let pins = bsp::Pins::new(
+        pac.IO_BANK0,
+        pac.PADS_BANK0,
+        sio.gpio_bank0,
+        &mut pac.RESETS,
+    );
+
+    let timer = Timer::new(pac.TIMER, &mut pac.RESETS, &clocks);
+
+    // VSYS / 3 is on ADC3
+    let mut adc = Adc::new(pac.ADC, &mut pac.RESETS);
+    let mut vsys_pin = AdcPin::new(pins.voltage_monitor.into_floating_input());
+    let mut temp_sensor = adc.take_temp_sensor().unwrap();
+
+    let uart_pins = (pins.gpio4.into_function(), pins.gpio5.into_function());
+    let mut detector = BaudDetector::new(timer.get_counter().ticks());
+    let mut uart = UartPeripheral::new(pac.UART1, uart_pins, &mut pac.RESETS)
+        .enable(
+            UartConfig::new(detector.baud_rate().Hz(), DataBits::Eight, None, StopBits::One),
+            clocks.peripheral_clock.freq(),
+        )
+        .unwrap();
+    set_tx_enabled(TX_PIN, false);
+
+    let mut parser = Parser::new();
+    let mut responder = Responder::new(MANUFACTURER_ID, DEVICE_ID, "RP2040", SENSORS);
+    let mut answer = [0u8; MAX_PACKET];
+    let mut buffer = [0u8; 32];
+    let mut echo = [0u8; 32];
+    let mut channels_screen = false;
+    let mut last_keys = Keys::NONE;
+    let mut channels = [0u16; 4];
+    let mut last_byte = 0;
+    let mut last_update = 0;
+    loop {
+        let now = timer.get_counter().ticks();
+        match uart.read_raw(&mut buffer) {
+            Ok(count) => {
+                last_byte = now;
+                for &byte in &buffer[..count] {
+                    let Some(packet) = parser.push(byte) else { continue };
+                    detector.packet(now);
+                    if let Packet::Channels(values) = packet {
+                        for (i, value) in channels.iter_mut().enumerate() {
+                            *value = values.get_us(i).unwrap_or(0);
+                        }
+                    }
+                    let len = responder.handle(&packet, &mut answer);
+                    if len > 0 {
+                        set_tx_enabled(TX_PIN, true);
+                        uart.write_full_blocking(&answer[..len]);
+                        while uart.uart_is_busy() {}
+                        set_tx_enabled(TX_PIN, false);
+                        // Drop our own bytes from the RX FIFO
+                        while uart.read_raw(&mut echo).is_ok() {}
+                    }
+                    if let Packet::Jetibox { .. } = packet {
+                        // Switch once per press, the receiver repeats the keys while they are held
+                        let keys = responder.keys();
+                        if (keys.up() && !last_keys.up()) || (keys.down() && !last_keys.down()) {
+                            channels_screen = !channels_screen;
+                        }
+                        last_keys = keys;
+                    }
+                }
+            }
+            Err(nb::Error::Other(error)) => debug!("UART {}", Debug2Format(&error.err_type)),
+            Err(nb::Error::WouldBlock) => {
+                if now - last_byte > PACKET_GAP_US {
+                    parser.reset();
+                }
+            }
+        }
+
+        if let Some(baud_rate) = detector.poll(now) {
+            info!("Trying {} baud", baud_rate);
+            parser.reset();
+            uart = uart
+                .disable()
+                .enable(
+                    UartConfig::new(baud_rate.Hz(), DataBits::Eight, None, StopBits::One),
+                    clocks.peripheral_clock.freq(),
+                )
+                .unwrap();
+        }
+
+        if now - last_update > UPDATE_US {
+            last_update = now;
+            let vsys: u16 = adc.read(&mut vsys_pin).unwrap();
+            let centivolts = vsys as i32 * 3 * 330 / 4096;
+            let raw: u16 = adc.read(&mut temp_sensor).unwrap();
+            // T = 27 - (V - 0.706) / 0.001721, in 0.1 C
+            let millivolts = raw as i32 * 3300 / 4096;
+            let decicelsius = 270 - (millivolts - 706) * 10_000 / 1721;
+            responder.set_value(0, Some(centivolts));
+            responder.set_value(1, Some(decicelsius));
+
+            let mut line1 = Line::new();
+            let mut line2 = Line::new();
+            if channels_screen {
+                core::write!(line1, "{} {}", channels[0], channels[1]).unwrap();
+                core::write!(line2, "{} {}", channels[2], channels[3]).unwrap();
+            } else {
+                core::write!(line1, "VSYS {}.{:02}V", centivolts / 100, centivolts % 100).unwrap();
+                // -0.5C has no integer part to carry the sign
+                let sign = if decicelsius < 0 { "-" } else { "" };
+                let abs = decicelsius.unsigned_abs();
+                core::write!(line2, "Temp {}{}.{}C", sign, abs / 10, abs % 10).unwrap();
+            }
+            responder.set_jetibox(line1.as_str(), line2.as_str());
+
+            info!(
+                "{} baud, locked: {}, ch: {} {} {} {}",
+                detector.baud_rate(),
+                detector.is_locked(),
+                channels[0], channels[1], channels[2], channels[3],
+            );
+        }
+    }
+}
+
+/// One line of the JetiBox screen
+struct Line {
+    buffer: [u8; 16],
+    len: usize,
+}
+
+impl Line {
+    fn new() -> Self {
+        Self { buffer: [b' '; 16], len: 0 }
+    }
+
+    fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.buffer[..self.len]).unwrap_or("")
+    }
+}
+
+impl Write for Line {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let len = s.len().min(self.buffer.len() - self.len);
+        self.buffer[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+// End of file
@@ -0,0 +1,180 @@
+//! Modbus RTU slave on RS-485
+//!
+//! MAX485 style transceiver on UART1: DI on GPIO4 (TX), RO on GPIO5 (RX), DE and /RE
+//! together on GPIO6. 19200 baud, 8E1, slave address 1.
+//!
+//!   * coil 0 - onboard LED, coil 1 - GPIO7 output
+//!   * discrete input 0 - button on GPIO8 (to GND)
+//!   * input registers 0-2 - ADC0-2 raw (GPIO26-28), 3 - VSYS in mV, 4 - temperature in 0.01 C
+//!   * holding register 0 - PWM duty on GPIO15 in 0.1% (0-1000), 1-4 - free for the master
+//!
+#![no_std]
+#![no_main]
+
+use rp_pico as bsp;
+use bsp::hal::{
+    adc::{AdcPin, TempSense}, clocks::{init_clocks_and_plls, Clock}, fugit::RateExtU32, gpio::{bank0::{Gpio25, Gpio26, Gpio27, Gpio28, Gpio29, Gpio7, Gpio8}, FunctionNull, FunctionSioInput, FunctionSioOutput, Pin, PullDown, PullUp}, pac, pwm::{FreeRunning, Pwm7, Slice, Slices}, sio::Sio, uart::{DataBits, Parity, StopBits, UartConfig, UartPeripheral}, watchdog::Watchdog, Adc, Timer
+};
+use cortex_m_rt::entry;
+use defmt::*;
+use defmt_rtt as _;
+use embedded_hal::{adc::OneShot, digital::v2::{InputPin, OutputPin, StatefulOutputPin}, PwmPin};
+use panic_probe as _;
+use rp2040_sandbox::modbus::{Exception, ModbusRtu, Register, Slave};
+
+
+const BAUD_RATE: u32 = 19_200;
+const SLAVE_ADDRESS: u8 = 1;
+/// PWM period of 1000 counts
+const PWM_TOP: u16 = 999;
+
+
+type AdcInput<I> = AdcPin<Pin<I, FunctionNull, PullDown>>;
+
+
+/// Peripherals and values behind the registers
+struct Board {
+    led: Pin<Gpio25, FunctionSioOutput, PullDown>,
+    output: Pin<Gpio7, FunctionSioOutput, PullDown>,
+    button: Pin<Gpio8, FunctionSioInput, PullUp>,
+    adc: Adc,
+    adc_pins: (AdcInput<Gpio26>, AdcInput<Gpio27>, AdcInput<Gpio28>, AdcInput<Gpio29>),
+    temp_sensor: TempSense,
+    pwm: Slice<Pwm7, FreeRunning>,
+    duty: u16,
+    memory: [u16; 4],
+}
+
+impl Board {
+    fn read_adc(&mut self, input: u16) -> u16 {
+        let pins = &mut self.adc_pins;
+        match input {
+            0 => self.adc.read(&mut pins.0).unwrap(),
+            1 => self.adc.read(&mut pins.1).unwrap(),
+            2 => self.adc.read(&mut pins.2).unwrap(),
+            // VSYS / 3
+            3 => {
+                let vsys: u16 = self.adc.read(&mut pins.3).unwrap();
+                (vsys as u32 * 3 * 3300 / 4096) as u16
+            }
+            _ => {
+                // T = 27 - (V - 0.706) / 0.001721
+                let raw: u16 = self.adc.read(&mut self.temp_sensor).unwrap();
+                let millivolts = raw as i32 * 3300 / 4096;
+                (2700 - (millivolts - 706) * 100_000 / 1721) as i16 as u16
+            }
+        }
+    }
+}
+
+
+static MAP: [Register<Board>; 6] = [
+    Register::coils(0, 1, |board, _| board.led.is_set_high().unwrap(), |board, _, on| set_pin(&mut board.led, on)),
+    Register::coils(1, 1, |board, _| board.output.is_set_high().unwrap(), |board, _, on| set_pin(&mut board.output, on)),
+    Register::discrete_inputs(0, 1, |board, _| board.button.is_low().unwrap()),
+    Register::input_registers(0, 5, Board::read_adc),
+    Register::holding_registers(0, 1, |board, _| board.duty, set_duty),
+    Register::holding_registers(1, 4, |board, i| board.memory[i as usize], |board, i, value| {
+        board.memory[i as usize] = value;
+        Ok(())
+    }),
+];
+
+
+fn set_pin(pin: &mut impl OutputPin, on: bool) {
+    if on {
+        let _ = pin.set_high();
+    } else {
+        let _ = pin.set_low();
+    }
+}
+
+fn set_duty(board: &mut Board, _offset: u16, duty: u16) -> Result<(), Exception> {
+    if duty > 1000 {
+        return Err(Exception::IllegalDataValue);
+    }
+    board.duty = duty;
+    board.pwm.channel_b.set_duty(duty);
+    Ok(())
+}
+
+
+#[entry]
+fn main() -> ! {
+    info!("Program start");
+    let mut pac = pac::Peripherals::take().unwrap();
+    let mut watchdog = Watchdog::new(pac.WATCHDOG);
+    let sio = Sio::new(pac.SIO);
+
+    // External high-speed crystal on the pico board is 12Mhz
+    let external_xtal_freq_hz = 12_000_000u32;
+    let clocks = init_clocks_and_plls(
+        external_xtal_freq_hz,
+        pac.XOSC,
+        pac.CLOCKS,
+        pac.PLL_SYS,
+        pac.PLL_USB,
+        &mut pac.RESETS,
+        &mut watchdog,
+    )
+    .ok()
+    .unwrap();
+
+    let pins = bsp::Pins::new(
+        pac.IO_BANK0,
+        pac.PADS_BANK0,
+        sio.gpio_bank0,
+        &mut pac.RESETS,
+    );
+
+    let timer = Timer::new(pac.TIMER, &mut pac.RESETS, &clocks);
+
+    let uart_pins = (pins.gpio4.into_function(), pins.gpio5.into_function());
+    let uart = UartPeripheral::new(pac.UART1, uart_pins, &mut pac.RESETS)
+        .enable(
+            UartConfig::new(BAUD_RATE.Hz(), DataBits::Eight, Some(Parity::Even), StopBits::One),
+            clocks.peripheral_clock.freq(),
+        )
+        .unwrap();
+    let mut modbus = ModbusRtu::new(uart, timer, pins.gpio6.into_push_pull_output(), BAUD_RATE);
+
+    let pwm_slices = Slices::new(pac.PWM, &mut pac.RESETS);
+    let mut pwm = pwm_slices.pwm7;
+    pwm.set_top(PWM_TOP);
+    pwm.channel_b.set_duty(0);
+    pwm.channel_b.output_to(pins.gpio15);
+    pwm.enable();
+
+    let mut adc = Adc::new(pac.ADC, &mut pac.RESETS);
+    let temp_sensor = adc.take_temp_sensor().unwrap();
+    let mut board = Board {
+        led: pins.led.into_push_pull_output(),
+        output: pins.gpio7.into_push_pull_output(),
+        button: pins.gpio8.into_pull_up_input(),
+        adc,
+        adc_pins: (
+            AdcPin::new(pins.gpio26),
+            AdcPin::new(pins.gpio27),
+            AdcPin::new(pins.gpio28),
+            AdcPin::new(pins.voltage_monitor),
+        ),
+        temp_sensor,
+        pwm,
+        duty: 0,
+        memory: [0; 4],
+    };
+
+    let mut slave = Slave::new(SLAVE_ADDRESS, &MAP);
+    let mut last_stats = slave.stats();
+    loop {
+        modbus.poll(&mut slave, &mut board);
+
+        let stats = slave.stats();
+        if stats != last_stats {
+            last_stats = stats;
+            info!("{}, receive errors: {}", stats, modbus.receive_errors());
+        }
+    }
+}
+
+// End of file
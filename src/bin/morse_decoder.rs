@@ -0,0 +1,186 @@
+//! Morse code decoder
+//!
+//! The inverse of `pio_dma`: a straight key (or a photodiode with a comparator) pulls GPIO15
+//! low while the key is down. Edges are timestamped by the `Timer` in the GPIO interrupt,
+//! the main loop decodes them with the adaptive `morse::Decoder`.
+//!
+//! Words are printed over defmt. The 16x2 LCD (connected as in the `lcd` example) shows
+//! the estimated speed and the last 16 characters.
+//!
+#![no_std]
+#![no_main]
+
+use bsp::hal::{
+    clocks::{init_clocks_and_plls, Clock}, gpio::{bank0::Gpio15, FunctionSioInput, Interrupt, Pin, PullUp}, pac::{self, interrupt}, sio::Sio, watchdog::Watchdog, Timer
+};
+use core::cell::RefCell;
+use cortex_m::delay::Delay;
+use cortex_m_rt::entry;
+use critical_section::Mutex;
+use defmt::*;
+use defmt_rtt as _;
+use embedded_hal::digital::v2::InputPin;
+use panic_probe as _;
+use rp_pico as bsp;
+use rp2040_sandbox::lcd::Lcd1602;
+use rp2040_sandbox::morse::{Decoded, Decoder};
+
+
+// Expected speed at the start, the decoder follows the real one
+const WPM: u32 = 15;
+// Edges closer than this to the previous one are contact bounce
+const DEBOUNCE_US: u64 = 5_000;
+// Edges waiting for the main loop
+const EDGE_QUEUE: usize = 32;
+const LCD_COLUMNS: usize = 16;
+const MAX_WORD: usize = 32;
+
+type KeyPin = Pin<Gpio15, FunctionSioInput, PullUp>;
+
+/// Key input handled in the interrupt
+struct Key {
+    pin: KeyPin,
+    timer: Timer,
+    last_edge: u64,
+    // (timestamp_us, key_down)
+    edges: [(u64, bool); EDGE_QUEUE],
+    head: usize,
+    len: usize,
+}
+
+static KEY: Mutex<RefCell<Option<Key>>> = Mutex::new(RefCell::new(None));
+
+
+#[entry]
+fn main() -> ! {
+    info!("Program start");
+    let mut pac = pac::Peripherals::take().unwrap();
+    let core = pac::CorePeripherals::take().unwrap();
+    let mut watchdog = Watchdog::new(pac.WATCHDOG);
+    let sio = Sio::new(pac.SIO);
+
+    // External high-speed crystal on the pico board is 12Mhz
+    let external_xtal_freq_hz = 12_000_000u32;
+    let clocks = init_clocks_and_plls(
+        external_xtal_freq_hz,
+        pac.XOSC,
+        pac.CLOCKS,
+        pac.PLL_SYS,
+        pac.PLL_USB,
+        &mut pac.RESETS,
+        &mut watchdog,
+    )
+    .ok()
+    .unwrap();
+
+    let pins = bsp::Pins::new(
+        pac.IO_BANK0,
+        pac.PADS_BANK0,
+        sio.gpio_bank0,
+        &mut pac.RESETS,
+    );
+
+    let timer = Timer::new(pac.TIMER, &mut pac.RESETS, &clocks);
+
+    let rs = pins.gpio16.into_push_pull_output().into_pull_type().into_dyn_pin();
+    let e = pins.gpio17.into_push_pull_output().into_pull_type().into_dyn_pin();
+    let d4 = pins.gpio18.into_push_pull_output().into_pull_type().into_dyn_pin();
+    let d5 = pins.gpio19.into_push_pull_output().into_pull_type().into_dyn_pin();
+    let d6 = pins.gpio20.into_push_pull_output().into_pull_type().into_dyn_pin();
+    let d7 = pins.gpio21.into_push_pull_output().into_pull_type().into_dyn_pin();
+    let delay = Delay::new(core.SYST, clocks.system_clock.freq().to_Hz());
+    let mut lcd = Lcd1602::init(delay, rs, e, d4, d5, d6, d7);
+    lcd.setup();
+
+    let pin: KeyPin = pins.gpio15.reconfigure();
+    pin.set_interrupt_enabled(Interrupt::EdgeLow, true);
+    pin.set_interrupt_enabled(Interrupt::EdgeHigh, true);
+    let key = Key { pin, timer, last_edge: 0, edges: [(0, false); EDGE_QUEUE], head: 0, len: 0 };
+    critical_section::with(|cs| KEY.borrow_ref_mut(cs).replace(key));
+    unsafe {
+        pac::NVIC::unmask(pac::Interrupt::IO_IRQ_BANK0);
+    }
+
+    let mut decoder = Decoder::new(1_200_000 / WPM);
+    let mut key_down = false;
+    let mut since = timer.get_counter().ticks();
+    let mut screen = [b' '; LCD_COLUMNS];
+    let mut word = [0u8; MAX_WORD];
+    let mut word_len = 0;
+    loop {
+        let mut edges = [(0, false); EDGE_QUEUE];
+        let count = critical_section::with(|cs| {
+            let mut key = KEY.borrow_ref_mut(cs);
+            let key = key.as_mut().unwrap();
+            for (i, edge) in edges.iter_mut().take(key.len).enumerate() {
+                *edge = key.edges[(key.head + i) % EDGE_QUEUE];
+            }
+            let count = key.len;
+            key.head = (key.head + count) % EDGE_QUEUE;
+            key.len = 0;
+            count
+        });
+
+        let mut changed = false;
+        let mut decode = |decoded: Decoded| {
+            for c in decoded {
+                screen.copy_within(1.., 0);
+                screen[LCD_COLUMNS - 1] = c;
+                changed = true;
+                if c == b' ' {
+                    info!("{=[u8]:a}", &word[..word_len]);
+                    word_len = 0;
+                } else if word_len < MAX_WORD {
+                    word[word_len] = c;
+                    word_len += 1;
+                }
+            }
+        };
+        for &(time, level) in &edges[..count] {
+            // Bounces can leave two edges with the same level
+            if level != key_down {
+                decode(decoder.push(key_down, (time - since) as u32));
+                key_down = level;
+                since = time;
+            }
+        }
+        if !key_down {
+            let idle = timer.get_counter().ticks() - since;
+            decode(decoder.idle(idle.min(u32::MAX as u64) as u32));
+        }
+
+        if changed {
+            let wpm = (decoder.wpm() + 0.5) as u32;
+            let status = [b'0' + (wpm / 10 % 10) as u8, b'0' + (wpm % 10) as u8];
+            lcd.set_cursor(0, 0);
+            lcd.print(core::str::from_utf8(&status).unwrap());
+            lcd.print(" WPM");
+            lcd.set_cursor(0, 1);
+            lcd.print(core::str::from_utf8(&screen).unwrap_or(""));
+        }
+        lcd.delay.delay_ms(10);
+    }
+}
+
+
+#[interrupt]
+fn IO_IRQ_BANK0() {
+    critical_section::with(|cs| {
+        if let Some(key) = KEY.borrow_ref_mut(cs).as_mut() {
+            let now = key.timer.get_counter().ticks();
+            key.pin.clear_interrupt(Interrupt::EdgeLow);
+            key.pin.clear_interrupt(Interrupt::EdgeHigh);
+            if now - key.last_edge < DEBOUNCE_US || key.len == EDGE_QUEUE {
+                return;
+            }
+            key.last_edge = now;
+            // Key pulls the input low
+            let level = key.pin.is_low().unwrap();
+            let tail = (key.head + key.len) % EDGE_QUEUE;
+            key.edges[tail] = (now, level);
+            key.len += 1;
+        }
+    });
+}
+
+// End of file
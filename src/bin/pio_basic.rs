@@ -2,18 +2,20 @@
 //!
 //! This program will switch on/off defined pin, with the frequency based on
 //! Pico clock, clock_div and numer of cycles (Minimal ~29Hz)
+//! See `pio_square_wave` for frequency set at runtime and without this limit.
 //!
 #![no_std]
 #![no_main]
 
 use bsp::hal::{
-    clocks::{init_clocks_and_plls, Clock}, gpio::FunctionPio0, pac, pio::{PIOBuilder, PIOExt, PinDir}, sio::Sio, watchdog::Watchdog
+    clocks::{init_clocks_and_plls, Clock}, gpio::FunctionPio0, pac, pio::{PIOBuilder, PinDir, SM0}, sio::Sio, watchdog::Watchdog
 };
 use cortex_m_rt::entry;
 use defmt::*;
 use defmt_rtt as _;
 use panic_probe as _;
 use rp_pico as bsp;
+use rp2040_sandbox::pio_manager::PioManager;
 
 
 const I2S_PIO_CLOCKDIV_INT: u16 = u16::MAX;
@@ -59,7 +61,7 @@ fn main() -> ! {
     // let led_pin = pins.led.into_function::<FunctionPio0>();
     let led_pin = pins.gpio16.into_function::<FunctionPio0>();
 
-    let (mut pio, sm0, _, _, _) = pac.PIO0.split(&mut pac.RESETS);
+    let mut pio0 = PioManager::new(pac.PIO0, &mut pac.RESETS);
     // Install a program in instruction memory.
     let program = pio_proc::pio_asm!(
         " 
@@ -70,7 +72,8 @@ fn main() -> ! {
     "
     )
     .program;
-    let installed = pio.install(&program).unwrap();
+    let installed = pio0.install("blink", &program).unwrap();
+    let sm0 = pio0.claim::<SM0>().unwrap();
     // Configure a state machine to use the program.
     let (mut sm0, _rx, _tx) = PIOBuilder::from_program(installed)
         .set_pins(led_pin.id().num, 1)
@@ -1,8 +1,14 @@
 //! This example shows how to read from and write to PIO using DMA.
 //!
 //! If a LED is connected to that pin, like on a Pico board, it will continously output "HELLO
-//! WORLD" in morse code. The example also tries to read the data back. If reading the data fails,
-//! the message will only be shown once, and then the LED remains dark.
+//! WORLD" in morse code. Every line received over UART0 (GPIO0 TX, GPIO1 RX, 115200 baud)
+//! replaces the message. Text is encoded by the `morse` module into DMA buffers, which are
+//! refilled while the previous ones are sent.
+//!
+//! The PIO program also sends every bit back through RX FIFO. The received data is compared with
+//! the sent data and mismatches are reported over defmt.
+//!
+//! At boot the loopback self-test (`bist` module) checks PIO1 and DMA channels 4 and 5.
 //!
 //! See the `Cargo.toml` file for Copyright and licence details.
 #![no_std]
@@ -10,19 +16,54 @@
 
 use rp_pico as bsp;
 use bsp::hal::{
-    dma::{double_buffer, single_buffer, DMAExt}, gpio::{FunctionPio0, Pin}, pac, pio::{PIOBuilder, PIOExt, PinDir}, sio::Sio
+    clocks::{init_clocks_and_plls, Clock}, dma::{double_buffer, DMAExt}, fugit::RateExtU32, gpio::{FunctionPio0, Pin}, pac, pio::{PIOBuilder, PinDir, SM0}, sio::Sio, uart::{DataBits, StopBits, UartConfig, UartPeripheral}, watchdog::Watchdog, Timer
 };
 use cortex_m::singleton;
 use cortex_m_rt::entry;
+use defmt::*;
 use panic_probe as _;
 use defmt_rtt as _;
+use rp2040_sandbox::bist::Loopback;
+use rp2040_sandbox::morse::{morse_program, Encoder, Speed};
+use rp2040_sandbox::pio_manager::PioManager;
+
+
+// Morse speed in words per minute
+const WPM: u32 = 12;
+const UART_BAUD_RATE: u32 = 115_200;
+// Longest message
+const MAX_MESSAGE: usize = 64;
+// Words in a single DMA buffer
+const BUFFER_WORDS: usize = 4;
+// Number of sent buffers kept for the comparison with received data
+const SENT_HISTORY: usize = 4;
+// Self-test: words in a single round and number of rounds
+const BIST_WORDS: usize = 256;
+const BIST_ROUNDS: u32 = 16;
 
 
 #[entry]
 fn main() -> ! {
+    info!("Program start");
     let mut pac = pac::Peripherals::take().unwrap();
-
+    let mut watchdog = Watchdog::new(pac.WATCHDOG);
     let sio = Sio::new(pac.SIO);
+
+    // External high-speed crystal on the pico board is 12Mhz
+    let external_xtal_freq_hz = 12_000_000u32;
+    let clocks = init_clocks_and_plls(
+        external_xtal_freq_hz,
+        pac.XOSC,
+        pac.CLOCKS,
+        pac.PLL_SYS,
+        pac.PLL_USB,
+        &mut pac.RESETS,
+        &mut watchdog,
+    )
+    .ok()
+    .unwrap();
+    let sys_clock_hz = clocks.system_clock.freq().to_Hz();
+
     let pins = bsp::Pins::new(
         pac.IO_BANK0,
         pac.PADS_BANK0,
@@ -30,37 +71,34 @@ fn main() -> ! {
         &mut pac.RESETS,
     );
 
+    let dma = pac.DMA.split(&mut pac.RESETS);
+    let timer = Timer::new(pac.TIMER, &mut pac.RESETS, &clocks);
+
+    // Self-test of the resources not used below
+    let mut pio1 = PioManager::new(pac.PIO1, &mut pac.RESETS);
+    let bist_tx = singleton!(: [u32; BIST_WORDS] = [0; BIST_WORDS]).unwrap();
+    let bist_rx = singleton!(: [u32; BIST_WORDS] = [0; BIST_WORDS]).unwrap();
+    let sm = pio1.claim::<SM0>().unwrap();
+    let mut bist = Loopback::new(&mut pio1, sm, dma.ch4, dma.ch5, bist_tx, bist_rx).unwrap();
+    if let Err(error) = bist.run(&timer, timer.get_counter_low(), BIST_ROUNDS) {
+        error!("Self-test failed: {}", error);
+    }
+
     // configure LED pin for Pio0.
     let led: Pin<_, FunctionPio0, _> = pins.led.into_function();
     // PIN id for use inside of PIO
     let led_pin_id = led.id().num;
 
-    // HELLO WORLD in morse code:
-    // .... . .-.. .-.. --- / .-- --- .-. .-.. -..
-    #[allow(clippy::unusual_byte_groupings)]
-    let message = [
-        0b10101010_00100010_11101010_00101110,
-        0b10100011_10111011_10000000_10111011,
-        0b10001110_11101110_00101110_10001011,
-        0b10101000_11101010_00000000_00000000,
-    ];
-
-    // Define a PIO program which reads data from the TX FIFO bit by bit, configures the LED
-    // according to the data, and then writes the data back to the RX FIFO.
-    let program = pio_proc::pio_asm!(
-        ".wrap_target",
-        "    out x, 1",
-        "    mov pins, x",
-        "    in x, 1 [13]",
-        ".wrap"
-    );
+    let speed = Speed::new(sys_clock_hz, WPM);
+    info!("{=f32} WPM, {=u32} bits per unit", speed.wpm(sys_clock_hz), speed.bits_per_unit);
 
     // Initialize and start PIO
-    let (mut pio, sm0, _, _, _) = pac.PIO0.split(&mut pac.RESETS);
-    let installed = pio.install(&program.program).unwrap();
+    let mut pio0 = PioManager::new(pac.PIO0, &mut pac.RESETS);
+    let installed = pio0.install("morse", &morse_program()).unwrap();
+    let sm0 = pio0.claim::<SM0>().unwrap();
     let (mut sm, rx, tx) = PIOBuilder::from_program(installed)
         .out_pins(led_pin_id, 1)
-        .clock_divisor_fixed_point(0, 0) // as slow as possible (0 is interpreted as 65536)
+        .clock_divisor_fixed_point(speed.divisor.0, speed.divisor.1)
         .autopull(true)
         .autopush(true)
         .build(sm0);
@@ -68,27 +106,43 @@ fn main() -> ! {
     sm.set_pindirs([(led_pin_id, PinDir::Output)]);
     sm.start();
 
-    let dma = pac.DMA.split(&mut pac.RESETS);
+    let uart_pins = (
+        // UART TX
+        pins.gpio0.into_function(),
+        // UART RX
+        pins.gpio1.into_function(),
+    );
+    let uart = UartPeripheral::new(pac.UART0, uart_pins, &mut pac.RESETS)
+        .enable(
+            UartConfig::new(UART_BAUD_RATE.Hz(), DataBits::Eight, None, StopBits::One),
+            clocks.peripheral_clock.freq(),
+        )
+        .unwrap();
 
-    // Transfer a single message via DMA.
-    let tx_buf = singleton!(: [u32; 4] = message).unwrap();
-    let rx_buf = singleton!(: [u32; 4] = [0; 4]).unwrap();
-    let tx_transfer = single_buffer::Config::new(dma.ch0, tx_buf, tx).start();
-    let rx_transfer = single_buffer::Config::new(dma.ch1, rx, rx_buf).start();
-    let (ch0, tx_buf, tx) = tx_transfer.wait();
-    let (ch1, rx, rx_buf) = rx_transfer.wait();
-    for i in 0..rx_buf.len() {
-        if rx_buf[i] != tx_buf[i] {
-            // The data did not match, abort.
-            #[allow(clippy::empty_loop)]
-            loop {}
-        }
+    let mut message = [0u8; MAX_MESSAGE];
+    let mut message_len = 11;
+    message[..message_len].copy_from_slice(b"HELLO WORLD");
+    let mut line = [0u8; MAX_MESSAGE];
+    let mut line_len = 0;
+    let mut encoder = Encoder::new(speed.bits_per_unit);
+
+    // Sent buffers, to compare them with the data read back
+    let mut sent = [[0u32; BUFFER_WORDS]; SENT_HISTORY];
+    let mut tx_count = 0;
+    let mut rx_count = 0;
+
+    let tx_buf = singleton!(: [u32; BUFFER_WORDS] = [0; BUFFER_WORDS]).unwrap();
+    let tx_buf2 = singleton!(: [u32; BUFFER_WORDS] = [0; BUFFER_WORDS]).unwrap();
+    let rx_buf = singleton!(: [u32; BUFFER_WORDS] = [0; BUFFER_WORDS]).unwrap();
+    let rx_buf2 = singleton!(: [u32; BUFFER_WORDS] = [0; BUFFER_WORDS]).unwrap();
+    for buf in [&mut *tx_buf, &mut *tx_buf2] {
+        encoder.fill(&message[..message_len], buf, true);
+        sent[tx_count % SENT_HISTORY] = *buf;
+        tx_count += 1;
     }
 
     // Chain some buffers together for continuous transfers
-    let tx_buf2 = singleton!(: [u32; 4] = message).unwrap();
-    let rx_buf2 = singleton!(: [u32; 4] = [0; 4]).unwrap();
-    let tx_transfer = double_buffer::Config::new((ch0, ch1), tx_buf, tx).start();
+    let tx_transfer = double_buffer::Config::new((dma.ch0, dma.ch1), tx_buf, tx).start();
     let mut tx_transfer = tx_transfer.read_next(tx_buf2);
     let rx_transfer = double_buffer::Config::new((dma.ch2, dma.ch3), rx, rx_buf).start();
     let mut rx_transfer = rx_transfer.write_next(rx_buf2);
@@ -96,18 +150,37 @@ fn main() -> ! {
         // When a transfer is done we immediately enqueue the buffers again.
         if tx_transfer.is_done() {
             let (tx_buf, next_tx_transfer) = tx_transfer.wait();
+            encoder.fill(&message[..message_len], tx_buf, true);
+            sent[tx_count % SENT_HISTORY] = *tx_buf;
+            tx_count += 1;
             tx_transfer = next_tx_transfer.read_next(tx_buf);
         }
         if rx_transfer.is_done() {
             let (rx_buf, next_rx_transfer) = rx_transfer.wait();
-            for i in 0..rx_buf.len() {
-                if rx_buf[i] != message[i] {
-                    // The data did not match, abort.
-                    #[allow(clippy::empty_loop)]
-                    loop {}
-                }
+            if *rx_buf != sent[rx_count % SENT_HISTORY] {
+                warn!("Data read back from PIO doesn't match");
             }
+            rx_count += 1;
             rx_transfer = next_rx_transfer.write_next(rx_buf);
         }
+
+        // New message
+        let mut bytes = [0u8; 16];
+        if let Ok(count) = uart.read_raw(&mut bytes) {
+            for &b in &bytes[..count] {
+                if b == b'\r' || b == b'\n' {
+                    if line_len > 0 {
+                        message[..line_len].copy_from_slice(&line[..line_len]);
+                        message_len = line_len;
+                        line_len = 0;
+                        encoder.reset();
+                        info!("Message: {=[u8]:a}", &message[..message_len]);
+                    }
+                } else if line_len < MAX_MESSAGE {
+                    line[line_len] = b;
+                    line_len += 1;
+                }
+            }
+        }
     }
-}
\ No newline at end of file
+}
@@ -4,19 +4,24 @@
 //! It will demonstrate
 //! * Usage of side pins
 //! * Using DMA to access FIFO queue os PIO processor
+//! * Refilling DMA buffers from the DMA interrupt
 //!
 #![no_std]
 #![no_main]
 
 use bsp::hal::{
-    clocks::{init_clocks_and_plls, Clock}, dma::{double_buffer, DMAExt}, gpio::FunctionPio0, pac, pio::{Buffers, PIOBuilder, PIOExt, PinDir, ShiftDirection}, sio::Sio, watchdog::Watchdog
+    clocks::{init_clocks_and_plls, Clock}, dma::{Channel, DMAExt, CH0, CH1}, gpio::FunctionPio0, pac::{self, interrupt, PIO0}, pio::{Buffers, PIOBuilder, PinDir, ShiftDirection, Tx, SM0}, sio::Sio, watchdog::Watchdog
 };
+use core::cell::RefCell;
 use cortex_m::singleton;
 use cortex_m_rt::entry;
+use critical_section::Mutex;
 use defmt::*;
 use defmt_rtt as _;
 use panic_probe as _;
 use rp_pico as bsp;
+use rp2040_sandbox::i2s::stream::{I2sStream, Refill};
+use rp2040_sandbox::pio_manager::PioManager;
 
 
 const I2S_PIO_CLOCKDIV_INT: u16 = 400;
@@ -24,6 +29,9 @@ const I2S_PIO_CLOCKDIV_FRAC: u8 = 0u8;
 const WORD_SIZE: u8 = 32;
 const DMA_BUFFER_SIZE: usize = 16;
 
+type Stream = I2sStream<Channel<CH0>, Channel<CH1>, Tx<(PIO0, SM0)>, Pattern, {DMA_BUFFER_SIZE*2}>;
+static STREAM: Mutex<RefCell<Option<Stream>>> = Mutex::new(RefCell::new(None));
+
 
 #[entry]
 fn main() -> ! {
@@ -85,8 +93,9 @@ fn main() -> ! {
     "
     )
     .program;
-    let (mut pio, sm0, _, _, _) = pac.PIO0.split(&mut pac.RESETS);
-    let installed = pio.install(&program).unwrap();
+    let mut pio0 = PioManager::new(pac.PIO0, &mut pac.RESETS);
+    let installed = pio0.install("i2s", &program).unwrap();
+    let sm0 = pio0.claim::<SM0>().unwrap();
     // Configure a state machine to use the program.
     let (mut sm, _rx, tx) = PIOBuilder::from_program(installed)
         .out_pins(data_out_pin.id().num, 1)
@@ -111,27 +120,41 @@ fn main() -> ! {
     let dma_channels = pac.DMA.split(&mut pac.RESETS);
     let i2s_tx_buf1 = singleton!(: [u32; DMA_BUFFER_SIZE*2] = [12345; DMA_BUFFER_SIZE*2]).unwrap(); 
     let i2s_tx_buf2 = singleton!(: [u32; DMA_BUFFER_SIZE*2] = [123; DMA_BUFFER_SIZE*2]).unwrap(); 
-    let i2s_dma_config =
-        double_buffer::Config::new((dma_channels.ch0, dma_channels.ch1), i2s_tx_buf1, tx);
-    let i2s_tx_transfer = i2s_dma_config.start(); 
-    let mut i2s_tx_transfer = i2s_tx_transfer.read_next(i2s_tx_buf2);
+    // Buffers are refilled from DMA_IRQ_0
+    let stream = I2sStream::start((dma_channels.ch0, dma_channels.ch1), (i2s_tx_buf1, i2s_tx_buf2), tx, Pattern);
+    critical_section::with(|cs| STREAM.borrow_ref_mut(cs).replace(stream));
+    // Safety: the handler only touches STREAM, which is already initialized
+    unsafe { pac::NVIC::unmask(pac::Interrupt::DMA_IRQ_0) };
 
 
     info!("Clock {=u32}", clocks.system_clock.freq().to_Hz());
     loop {
-        if i2s_tx_transfer.is_done() {
-            let (next_tx_buf, next_tx_transfer) = i2s_tx_transfer.wait();
-
-            for (i, e) in next_tx_buf.iter_mut().enumerate() {
-                if i % 2 == 0 {
-                    // Left channel
-                    *e = 0xff00ff00;
-                } else {
-                    *e = 0xff00ff00;
-                }
-            }
+        cortex_m::asm::wfi();
+    }
+}
+
 
-            i2s_tx_transfer = next_tx_transfer.read_next(next_tx_buf);
+/// The same word in both channels
+struct Pattern;
+
+impl Refill for Pattern {
+    fn refill(&mut self, buffer: &mut [u32]) {
+        for (i, e) in buffer.iter_mut().enumerate() {
+            if i % 2 == 0 {
+                // Left channel
+                *e = 0xff00ff00;
+            } else {
+                *e = 0xff00ff00;
+            }
         }
     }
-}
\ No newline at end of file
+}
+
+#[interrupt]
+fn DMA_IRQ_0() {
+    critical_section::with(|cs| {
+        if let Some(stream) = STREAM.borrow_ref_mut(cs).as_mut() {
+            stream.on_interrupt();
+        }
+    });
+}
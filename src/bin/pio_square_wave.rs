@@ -0,0 +1,85 @@
+//! Square wave generator on PIO with runtime frequency control
+//!
+//! The LED blinks with frequency set by the potentiometer (GPIO26), from 0.5Hz to 20Hz.
+//! The state machine is never stopped, the new frequency is used from the next period.
+//! Unlike `pio_basic` the frequency can go far below 29Hz (see `square_wave` module).
+//!
+#![no_std]
+#![no_main]
+
+use bsp::hal::{
+    adc::AdcPin, clocks::{init_clocks_and_plls, Clock}, gpio::FunctionPio0, pac, pio::SM0, sio::Sio, watchdog::Watchdog, Adc
+};
+use cortex_m_rt::entry;
+use defmt::*;
+use defmt_rtt as _;
+use embedded_hal::adc::OneShot;
+use panic_probe as _;
+use rp_pico as bsp;
+#[allow(unused_imports)]
+use num_traits::float::Float;
+use rp2040_sandbox::pio_manager::PioManager;
+use rp2040_sandbox::square_wave::{SquareWave, Timing};
+
+
+// Frequency range in Hz
+const MIN_FREQ: f32 = 0.5;
+const MAX_FREQ: f32 = 20.0;
+// Part of the period with LED on
+const DUTY: f32 = 0.25;
+
+
+#[entry]
+fn main() -> ! {
+    info!("Program start");
+    let mut pac = pac::Peripherals::take().unwrap();
+    let core = pac::CorePeripherals::take().unwrap();
+    let mut watchdog = Watchdog::new(pac.WATCHDOG);
+    let sio = Sio::new(pac.SIO);
+
+    // External high-speed crystal on the pico board is 12Mhz
+    let external_xtal_freq_hz = 12_000_000u32;
+    let clocks = init_clocks_and_plls(
+        external_xtal_freq_hz,
+        pac.XOSC,
+        pac.CLOCKS,
+        pac.PLL_SYS,
+        pac.PLL_USB,
+        &mut pac.RESETS,
+        &mut watchdog,
+    )
+    .ok()
+    .unwrap();
+    let sys_clock_hz = clocks.system_clock.freq().to_Hz();
+
+    let mut delay = cortex_m::delay::Delay::new(core.SYST, sys_clock_hz);
+
+    let pins = bsp::Pins::new(
+        pac.IO_BANK0,
+        pac.PADS_BANK0,
+        sio.gpio_bank0,
+        &mut pac.RESETS,
+    );
+
+    let led_pin = pins.led.into_function::<FunctionPio0>();
+    let mut pio0 = PioManager::new(pac.PIO0, &mut pac.RESETS);
+    let sm0 = pio0.claim::<SM0>().unwrap();
+    let timing = Timing::new(sys_clock_hz, MIN_FREQ, DUTY).unwrap();
+    let mut square_wave = SquareWave::start(&mut pio0, sm0, led_pin.id().num, timing).unwrap();
+
+    let mut adc = Adc::new(pac.ADC, &mut pac.RESETS);
+    let mut pot_pin = AdcPin::new(pins.gpio26);
+    loop {
+        let v: u16 = adc.read(&mut pot_pin).unwrap();
+        // Logarithmic scale
+        let freq = MIN_FREQ * (MAX_FREQ / MIN_FREQ).powf(v as f32 / 4096.0);
+        let timing = Timing::new(sys_clock_hz, freq, DUTY).unwrap();
+        if timing != square_wave.timing() {
+            square_wave.retune(timing);
+            info!("Frequency: {=f32} Hz, divisor: {=u16}", timing.frequency(sys_clock_hz), timing.divisor);
+        }
+        delay.delay_ms(200);
+    }
+}
+
+// End of file
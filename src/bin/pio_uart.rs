@@ -0,0 +1,100 @@
+//! Extra serial port on PIO
+//!
+//! PIO UART on GPIO2 (TX) and GPIO3 (RX). It sends a counter every second and prints
+//! the received bytes over defmt, like `uart_tx` and `uart_rx` do with the hardware UARTs.
+//! Connect TX to RX to test it without another board.
+//!
+//! For an SBUS receiver use 100000 baud, 8E2 and `INVERTED = true`.
+//!
+#![no_std]
+#![no_main]
+
+use rp_pico as bsp;
+use bsp::hal::{
+    clocks::{init_clocks_and_plls, Clock}, fugit::RateExtU32, gpio::{FunctionPio0, Pin}, pac, pio::{SM0, SM1}, sio::Sio, uart::{DataBits, StopBits, UartConfig}, watchdog::Watchdog
+};
+use core::fmt::Write;
+use cortex_m_rt::entry;
+use defmt::*;
+use defmt_rtt as _;
+use panic_probe as _;
+use rp2040_sandbox::pio_manager::PioManager;
+use rp2040_sandbox::pio_uart::PioUart;
+
+
+const BAUD_RATE: u32 = 115_200;
+const INVERTED: bool = false;
+
+
+#[entry]
+fn main() -> ! {
+    info!("Program start");
+    let mut pac = pac::Peripherals::take().unwrap();
+    let core = pac::CorePeripherals::take().unwrap();
+    let mut watchdog = Watchdog::new(pac.WATCHDOG);
+    let sio = Sio::new(pac.SIO);
+
+    // External high-speed crystal on the pico board is 12Mhz
+    let external_xtal_freq_hz = 12_000_000u32;
+    let clocks = init_clocks_and_plls(
+        external_xtal_freq_hz,
+        pac.XOSC,
+        pac.CLOCKS,
+        pac.PLL_SYS,
+        pac.PLL_USB,
+        &mut pac.RESETS,
+        &mut watchdog,
+    )
+    .ok()
+    .unwrap();
+    let sys_clock_hz = clocks.system_clock.freq().to_Hz();
+
+    let mut delay = cortex_m::delay::Delay::new(core.SYST, sys_clock_hz);
+
+    let pins = bsp::Pins::new(
+        pac.IO_BANK0,
+        pac.PADS_BANK0,
+        sio.gpio_bank0,
+        &mut pac.RESETS,
+    );
+
+    // configure UART pins for Pio0.
+    let tx_pin: Pin<_, FunctionPio0, _> = pins.gpio2.into_function();
+    let rx_pin: Pin<_, FunctionPio0, _> = pins.gpio3.into_function();
+
+    let mut pio0 = PioManager::new(pac.PIO0, &mut pac.RESETS);
+    let sm0 = pio0.claim::<SM0>().unwrap();
+    let sm1 = pio0.claim::<SM1>().unwrap();
+    let config = UartConfig::new(BAUD_RATE.Hz(), DataBits::Eight, None, StopBits::One);
+    let mut uart = PioUart::new(
+        &mut pio0,
+        sm0,
+        sm1,
+        tx_pin.id().num,
+        rx_pin.id().num,
+        &config,
+        INVERTED,
+        sys_clock_hz,
+    )
+    .unwrap();
+
+    uart.write_full_blocking(b"PIO UART example\r\n");
+
+    let mut value = 0u32;
+    let mut buffer = [0u8; 32];
+    loop {
+        writeln!(uart, "value: {value:02}\r").unwrap();
+        value += 1;
+
+        for _ in 0..100 {
+            match uart.read_raw(&mut buffer) {
+                Ok(count) => info!("{=[u8]:a}", &buffer[..count]),
+                Err(nb::Error::Other(error)) => warn!("{}", Debug2Format(&error.err_type)),
+                Err(nb::Error::WouldBlock) => {}
+            }
+            delay.delay_ms(10);
+        }
+    }
+}
+
+// End of file
@@ -0,0 +1,129 @@
+//! RC receiver on UART1
+//!
+//! Receiver output connected to GPIO5 (UART1 RX). Set `PROTOCOL` to the one of the receiver:
+//! SBUS (inverted line, inverted back by the GPIO), CRSF / ExpressLRS or iBus.
+//! Channels, failsafe and link quality are printed over defmt.
+//!
+#![no_std]
+#![no_main]
+
+use rp_pico as bsp;
+use bsp::hal::{
+    clocks::{init_clocks_and_plls, Clock}, fugit::RateExtU32, pac, sio::Sio, uart::{DataBits, Parity, StopBits, UartConfig, UartPeripheral}, watchdog::Watchdog, Timer
+};
+use cortex_m_rt::entry;
+use defmt::*;
+use defmt_rtt as _;
+use panic_probe as _;
+use rp2040_sandbox::rc::{crsf::CrsfDecoder, ibus::IbusDecoder, sbus::SbusDecoder, Decoder};
+
+
+#[allow(dead_code)]
+enum Protocol {
+    Sbus,
+    Crsf,
+    Ibus,
+}
+
+const PROTOCOL: Protocol = Protocol::Sbus;
+/// UART RX pin
+const RX_PIN: usize = 5;
+/// Silence on the line which ends the frame
+const FRAME_GAP_US: u64 = 1_000;
+/// Without a frame for this long the receiver is gone
+const TIMEOUT_US: u64 = 100_000;
+/// Print the channels this often
+const PRINT_US: u64 = 200_000;
+
+
+#[entry]
+fn main() -> ! {
+    info!("Program start");
+    let mut pac = pac::Peripherals::take().unwrap();
+    let mut watchdog = Watchdog::new(pac.WATCHDOG);
+    let sio = Sio::new(pac.SIO);
+
+    // External high-speed crystal on the pico board is 12Mhz
+    let external_xtal_freq_hz = 12_000_000u32;
+    let clocks = init_clocks_and_plls(
+        external_xtal_freq_hz,
+        pac.XOSC,
+        pac.CLOCKS,
+        pac.PLL_SYS,
+        pac.PLL_USB,
+        &mut pac.RESETS,
+        &mut watchdog,
+    )
+    .ok()
+    .unwrap();
+
+    let pins = bsp::Pins::new(
+        pac.IO_BANK0,
+        pac.PADS_BANK0,
+        sio.gpio_bank0,
+        &mut pac.RESETS,
+    );
+
+    let timer = Timer::new(pac.TIMER, &mut pac.RESETS, &clocks);
+
+    let mut sbus = SbusDecoder::new();
+    let mut crsf = CrsfDecoder::new();
+    let mut ibus = IbusDecoder::new();
+    let (config, decoder): (UartConfig, &mut dyn Decoder) = match PROTOCOL {
+        Protocol::Sbus => (UartConfig::new(100_000.Hz(), DataBits::Eight, Some(Parity::Even), StopBits::Two), &mut sbus),
+        Protocol::Crsf => (UartConfig::new(420_000.Hz(), DataBits::Eight, None, StopBits::One), &mut crsf),
+        Protocol::Ibus => (UartConfig::new(115_200.Hz(), DataBits::Eight, None, StopBits::One), &mut ibus),
+    };
+
+    let uart_pins = (pins.gpio4.into_function(), pins.gpio5.into_function());
+    let uart = UartPeripheral::new(pac.UART1, uart_pins, &mut pac.RESETS)
+        .enable(config, clocks.peripheral_clock.freq())
+        .unwrap();
+    if let Protocol::Sbus = PROTOCOL {
+        // Safety: only the input override of the RX pin is changed
+        let io = unsafe { &*pac::IO_BANK0::ptr() };
+        io.gpio[RX_PIN].gpio_ctrl.modify(|_, w| w.inover().invert());
+    }
+
+    let mut buffer = [0u8; 32];
+    let mut last_byte = 0;
+    let mut last_frame = None;
+    let mut last_print = 0;
+    let mut frames = 0u32;
+    loop {
+        let now = timer.get_counter().ticks();
+        match uart.read_raw(&mut buffer) {
+            Ok(count) => {
+                last_byte = now;
+                for &byte in &buffer[..count] {
+                    if let Some(frame) = decoder.push(byte) {
+                        last_frame = Some((frame, now));
+                        frames += 1;
+                    }
+                }
+            }
+            Err(nb::Error::Other(error)) => debug!("UART {}", Debug2Format(&error.err_type)),
+            Err(nb::Error::WouldBlock) => {
+                if now - last_byte > FRAME_GAP_US {
+                    decoder.reset();
+                }
+            }
+        }
+
+        if now - last_print > PRINT_US {
+            last_print = now;
+            match last_frame {
+                Some((frame, time)) if now - time < TIMEOUT_US => info!(
+                    "{} frames, failsafe: {}, LQ: {}, ch: {}",
+                    frames,
+                    frame.failsafe,
+                    frame.link_quality,
+                    &frame.channels[..frame.count],
+                ),
+                _ => warn!("No receiver"),
+            }
+        }
+    }
+}
+
+// End of file
@@ -0,0 +1,131 @@
+//! Rotary encoder menu
+//!
+//! Encoder phases on GPIO10 (A) and GPIO11 (B), push-button on GPIO12, all to ground
+//! with internal pull-ups. Transitions are counted by PIO (`quadrature` module).
+//!
+//! Turning the knob moves through the menu on the 16x2 LCD (connected as in the `lcd` example),
+//! click selects the item and long press returns to the first one. Position and speed are
+//! printed over defmt.
+//!
+#![no_std]
+#![no_main]
+
+use rp_pico as bsp;
+use bsp::hal::{
+    clocks::{init_clocks_and_plls, Clock}, gpio::{FunctionPio0, Pin, PullUp}, pac, pio::SM0, sio::Sio, watchdog::Watchdog, Timer
+};
+use cortex_m::delay::Delay;
+use cortex_m_rt::entry;
+use defmt::*;
+use defmt_rtt as _;
+use embedded_hal::digital::v2::InputPin;
+use panic_probe as _;
+use rp2040_sandbox::lcd::Lcd1602;
+use rp2040_sandbox::pio_manager::PioManager;
+use rp2040_sandbox::quadrature::{Button, ButtonEvent, QuadratureEncoder, Velocity};
+
+
+const MENU: [&str; 5] = ["Start", "Speed", "Brightness", "Settings", "About"];
+// Transitions per detent of the encoder
+const TRANSITIONS_PER_DETENT: i32 = 4;
+// Mechanical encoders don't go faster than this, it filters the contact noise
+const MAX_STEP_RATE: u32 = 10_000;
+const DEBOUNCE_US: u64 = 10_000;
+const LONG_PRESS_US: u64 = 1_000_000;
+const VELOCITY_WINDOW_US: u64 = 200_000;
+const LOOP_MS: u32 = 5;
+
+
+#[entry]
+fn main() -> ! {
+    info!("Program start");
+    let mut pac = pac::Peripherals::take().unwrap();
+    let core = pac::CorePeripherals::take().unwrap();
+    let mut watchdog = Watchdog::new(pac.WATCHDOG);
+    let sio = Sio::new(pac.SIO);
+
+    // External high-speed crystal on the pico board is 12Mhz
+    let external_xtal_freq_hz = 12_000_000u32;
+    let clocks = init_clocks_and_plls(
+        external_xtal_freq_hz,
+        pac.XOSC,
+        pac.CLOCKS,
+        pac.PLL_SYS,
+        pac.PLL_USB,
+        &mut pac.RESETS,
+        &mut watchdog,
+    )
+    .ok()
+    .unwrap();
+    let sys_clock_hz = clocks.system_clock.freq().to_Hz();
+
+    let pins = bsp::Pins::new(
+        pac.IO_BANK0,
+        pac.PADS_BANK0,
+        sio.gpio_bank0,
+        &mut pac.RESETS,
+    );
+
+    let timer = Timer::new(pac.TIMER, &mut pac.RESETS, &clocks);
+
+    let rs = pins.gpio16.into_push_pull_output().into_pull_type().into_dyn_pin();
+    let e = pins.gpio17.into_push_pull_output().into_pull_type().into_dyn_pin();
+    let d4 = pins.gpio18.into_push_pull_output().into_pull_type().into_dyn_pin();
+    let d5 = pins.gpio19.into_push_pull_output().into_pull_type().into_dyn_pin();
+    let d6 = pins.gpio20.into_push_pull_output().into_pull_type().into_dyn_pin();
+    let d7 = pins.gpio21.into_push_pull_output().into_pull_type().into_dyn_pin();
+    let delay = Delay::new(core.SYST, sys_clock_hz);
+    let mut lcd = Lcd1602::init(delay, rs, e, d4, d5, d6, d7);
+    lcd.setup();
+
+    // configure encoder pins for Pio0.
+    let pin_a: Pin<_, FunctionPio0, PullUp> = pins.gpio10.reconfigure();
+    let _pin_b: Pin<_, FunctionPio0, PullUp> = pins.gpio11.reconfigure();
+    let button_pin = pins.gpio12.into_pull_up_input();
+
+    let mut pio0 = PioManager::new(pac.PIO0, &mut pac.RESETS);
+    let sm0 = pio0.claim::<SM0>().unwrap();
+    let mut encoder = QuadratureEncoder::new(&mut pio0, sm0, pin_a.id().num, MAX_STEP_RATE, sys_clock_hz).unwrap();
+    let mut velocity = Velocity::new(VELOCITY_WINDOW_US);
+    let mut button = Button::new(DEBOUNCE_US, LONG_PRESS_US);
+
+    // Detent which is shown as the first item
+    let mut origin = 0;
+    let mut shown = None;
+    let mut last_position = 0;
+    loop {
+        let now = timer.get_counter().ticks();
+        let position = encoder.position();
+        let speed = velocity.update(position, now);
+        if position != last_position {
+            info!("Position {=i32}, {=f32} transitions/s", position, speed);
+            last_position = position;
+        }
+
+        let detent = position.div_euclid(TRANSITIONS_PER_DETENT);
+        let item = (detent - origin).rem_euclid(MENU.len() as i32) as usize;
+        match button.update(button_pin.is_low().unwrap(), now) {
+            Some(ButtonEvent::Click) => {
+                info!("Selected {=str}", MENU[item]);
+                lcd.set_cursor(0, 1);
+                lcd.print("selected        ");
+            }
+            Some(ButtonEvent::LongPress) => {
+                origin = detent;
+                shown = None;
+            }
+            _ => {}
+        }
+
+        let item = (detent - origin).rem_euclid(MENU.len() as i32) as usize;
+        if shown != Some(item) {
+            shown = Some(item);
+            lcd.clear();
+            lcd.print("> ");
+            lcd.print(MENU[item]);
+        }
+        lcd.delay.delay_ms(LOOP_MS);
+    }
+}
+
+// End of file
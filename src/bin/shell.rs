@@ -0,0 +1,206 @@
+//! Command shell on UART0 (GPIO0 TX, GPIO1 RX) at 115200 baud
+//!
+//! Connect a USB-serial adapter and open it in a terminal (e.g. `picocom -b 115200 /dev/ttyUSB0`).
+//!
+//!   * led on|off|toggle - onboard LED
+//!   * servo <us> - servo pulse on GPIO15 (500-2500us)
+//!   * adc read <n> - ADC input 0-3 (GPIO26-28, VSYS/3)
+//!   * tone <hz> - square wave on GPIO13 (0 to stop)
+//!   * reboot
+//!
+#![no_std]
+#![no_main]
+
+use rp_pico as bsp;
+use bsp::hal::{
+    adc::AdcPin, clocks::{init_clocks_and_plls, Clock}, fugit::RateExtU32, gpio::{bank0::{Gpio25, Gpio26, Gpio27, Gpio28, Gpio29}, FunctionNull, FunctionSioOutput, Pin, PullDown}, pac, pwm::{FreeRunning, Pwm6, Pwm7, Slice, Slices}, sio::Sio, uart::{DataBits, StopBits, UartConfig, UartPeripheral}, watchdog::Watchdog, Adc
+};
+use core::fmt::Write;
+use cortex_m_rt::entry;
+use defmt::*;
+use defmt_rtt as _;
+use embedded_hal::{adc::OneShot, digital::v2::{OutputPin, StatefulOutputPin}, PwmPin};
+use panic_probe as _;
+use rp2040_sandbox::shell::{ArgError, Args, Command, Shell};
+
+
+/// PWM counter runs at 1MHz (125MHz / 125)
+const PWM_DIV: u8 = 125;
+/// Servo period 20ms
+const SERVO_TOP: u16 = 19_999;
+
+
+type AdcInput<I> = AdcPin<Pin<I, FunctionNull, PullDown>>;
+
+
+/// Peripherals driven by the commands
+struct Board {
+    led: Pin<Gpio25, FunctionSioOutput, PullDown>,
+    servo: Slice<Pwm7, FreeRunning>,
+    tone: Slice<Pwm6, FreeRunning>,
+    adc: Adc,
+    adc_pins: (AdcInput<Gpio26>, AdcInput<Gpio27>, AdcInput<Gpio28>, AdcInput<Gpio29>),
+}
+
+
+const COMMANDS: [Command<Board>; 5] = [
+    Command { name: "led", help: "led on|off|toggle", run: led },
+    Command { name: "servo", help: "servo <500-2500 us>", run: servo },
+    Command { name: "adc", help: "adc read <0-3>", run: adc },
+    Command { name: "tone", help: "tone <hz>, 0 stops it", run: tone },
+    Command { name: "reboot", help: "reboot", run: reboot },
+];
+
+
+fn led(board: &mut Board, args: &Args, out: &mut dyn Write) -> Result<(), ArgError> {
+    match args.get::<&str>(0)? {
+        "toggle" => {
+            if board.led.is_set_high().unwrap() {
+                board.led.set_low().unwrap();
+            } else {
+                board.led.set_high().unwrap();
+            }
+        }
+        _ => {
+            if args.get::<bool>(0)? {
+                board.led.set_high().unwrap();
+            } else {
+                board.led.set_low().unwrap();
+            }
+        }
+    }
+    let state = if board.led.is_set_high().unwrap() { "on" } else { "off" };
+    let _ = core::write!(out, "LED {state}\r\n");
+    Ok(())
+}
+
+fn servo(board: &mut Board, args: &Args, out: &mut dyn Write) -> Result<(), ArgError> {
+    let pulse: u16 = args.get(0)?;
+    if !(500..=2500).contains(&pulse) {
+        return Err(ArgError::Invalid { index: 0, expected: "500-2500" });
+    }
+    board.servo.channel_b.set_duty(pulse);
+    let _ = core::write!(out, "Servo {pulse}us\r\n");
+    Ok(())
+}
+
+fn adc(board: &mut Board, args: &Args, out: &mut dyn Write) -> Result<(), ArgError> {
+    if args.get::<&str>(0)? != "read" {
+        return Err(ArgError::Usage);
+    }
+    let input: u8 = args.get(1)?;
+    let pins = &mut board.adc_pins;
+    let value: u16 = match input {
+        0 => board.adc.read(&mut pins.0).unwrap(),
+        1 => board.adc.read(&mut pins.1).unwrap(),
+        2 => board.adc.read(&mut pins.2).unwrap(),
+        3 => board.adc.read(&mut pins.3).unwrap(),
+        _ => return Err(ArgError::Invalid { index: 1, expected: "0-3" }),
+    };
+    let mv = value as u32 * 3300 / 4096;
+    let _ = core::write!(out, "ADC{input}: {value} ({mv}mV)\r\n");
+    Ok(())
+}
+
+fn tone(board: &mut Board, args: &Args, out: &mut dyn Write) -> Result<(), ArgError> {
+    let freq: u32 = args.get(0)?;
+    if freq == 0 {
+        board.tone.channel_b.set_duty(0);
+        let _ = core::write!(out, "Tone off\r\n");
+        return Ok(());
+    }
+    // 16-bit counter at 1MHz
+    if !(16..=20_000).contains(&freq) {
+        return Err(ArgError::Invalid { index: 0, expected: "16-20000 or 0" });
+    }
+    let top = (1_000_000 / freq - 1) as u16;
+    board.tone.set_top(top);
+    board.tone.channel_b.set_duty(top / 2);
+    let _ = core::write!(out, "Tone {}Hz\r\n", 1_000_000 / (top as u32 + 1));
+    Ok(())
+}
+
+fn reboot(_board: &mut Board, _args: &Args, out: &mut dyn Write) -> Result<(), ArgError> {
+    let _ = core::write!(out, "Rebooting\r\n");
+    // Let the message leave the FIFO
+    cortex_m::asm::delay(1_000_000);
+    cortex_m::peripheral::SCB::sys_reset();
+}
+
+
+#[entry]
+fn main() -> ! {
+    info!("Program start");
+    let mut pac = pac::Peripherals::take().unwrap();
+    let mut watchdog = Watchdog::new(pac.WATCHDOG);
+    let sio = Sio::new(pac.SIO);
+
+    // External high-speed crystal on the pico board is 12Mhz
+    let external_xtal_freq_hz = 12_000_000u32;
+    let clocks = init_clocks_and_plls(
+        external_xtal_freq_hz,
+        pac.XOSC,
+        pac.CLOCKS,
+        pac.PLL_SYS,
+        pac.PLL_USB,
+        &mut pac.RESETS,
+        &mut watchdog,
+    )
+    .ok()
+    .unwrap();
+
+    let pins = bsp::Pins::new(
+        pac.IO_BANK0,
+        pac.PADS_BANK0,
+        sio.gpio_bank0,
+        &mut pac.RESETS,
+    );
+
+    let uart_pins = (pins.gpio0.into_function(), pins.gpio1.into_function());
+    let mut uart = UartPeripheral::new(pac.UART0, uart_pins, &mut pac.RESETS)
+        .enable(
+            UartConfig::new(115200.Hz(), DataBits::Eight, None, StopBits::One),
+            clocks.peripheral_clock.freq(),
+        )
+        .unwrap();
+
+    let pwm_slices = Slices::new(pac.PWM, &mut pac.RESETS);
+    let mut servo = pwm_slices.pwm7;
+    servo.set_div_int(PWM_DIV);
+    servo.set_top(SERVO_TOP);
+    servo.channel_b.set_duty(1500);
+    servo.channel_b.output_to(pins.gpio15);
+    servo.enable();
+    let mut tone = pwm_slices.pwm6;
+    tone.set_div_int(PWM_DIV);
+    tone.channel_b.set_duty(0);
+    tone.channel_b.output_to(pins.gpio13);
+    tone.enable();
+
+    let mut board = Board {
+        led: pins.led.into_push_pull_output(),
+        servo,
+        tone,
+        adc: Adc::new(pac.ADC, &mut pac.RESETS),
+        adc_pins: (
+            AdcPin::new(pins.gpio26),
+            AdcPin::new(pins.gpio27),
+            AdcPin::new(pins.gpio28),
+            AdcPin::new(pins.voltage_monitor),
+        ),
+    };
+
+    let mut shell = Shell::new(&COMMANDS);
+    uart.write_full_blocking(b"\r\nRP2040 shell, type help\r\n");
+    shell.prompt(&mut uart).unwrap();
+    let mut buffer = [0u8; 16];
+    loop {
+        if let Ok(count) = uart.read_raw(&mut buffer) {
+            for &byte in &buffer[..count] {
+                shell.push(byte, &mut board, &mut uart).unwrap();
+            }
+        }
+    }
+}
+
+// End of file
@@ -0,0 +1,163 @@
+//! # UART with the automatic baud rate
+//! Receives on UART1 (GPIO5) at the rate of the other side, without recompiling.
+//!
+//! PIO0 measures the low pulses on the RX pin (`autobaud`) while the UART receives from it.
+//! When the detector locks to a rate, the UART is switched to it. Received bytes are printed
+//! in hex with the state of the detector.
+//!
+//! Try it with a USB-serial adapter at 115200, then switch the terminal to 250000.
+//!
+
+#![no_std]
+#![no_main]
+
+use bsp::hal::fugit::RateExtU32;
+use bsp::hal::{
+    clocks,
+    gpio::{bank0::{Gpio4, Gpio5}, FunctionUart, Pin, PullDown},
+    pac::{self, interrupt},
+    pio::SM0,
+    sio::Sio,
+    uart::{DataBits, StopBits, UartConfig, UartPeripheral},
+    watchdog::Watchdog,
+    Clock, Timer,
+};
+use core::cell::RefCell;
+use cortex_m_rt::entry;
+use critical_section::Mutex;
+use defmt::{info, warn};
+use defmt_rtt as _;
+use panic_probe as _;
+use rp2040_sandbox::autobaud::{Detector, PulseCapture, BAUD_RATES};
+use rp2040_sandbox::buffered_uart::BufferedUart;
+use rp2040_sandbox::pio_manager::PioManager;
+use rp_pico as bsp;
+
+/// External high-speed crystal on the Raspberry Pi Pico board is 12 MHz. Adjust
+/// if your board has a different frequency
+const XTAL_FREQ_HZ: u32 = 12_000_000u32;
+
+/// Until the detector locks
+const DEFAULT_RATE: u32 = 115_200;
+/// UART RX pin
+const RX_PIN: u8 = 5;
+/// Print the state this often
+const PRINT_US: u64 = 1_000_000;
+
+type UartPins = (Pin<Gpio4, FunctionUart, PullDown>, Pin<Gpio5, FunctionUart, PullDown>);
+type AutoUart = BufferedUart<pac::UART1, UartPins, 256, 16>;
+
+/// UART shared with the interrupt
+static UART: Mutex<RefCell<Option<AutoUart>>> = Mutex::new(RefCell::new(None));
+
+/// Entry point to our bare-metal application.
+///
+/// The function configures the RP2040 peripherals, then follows the baud rate and prints
+/// the received bytes in an infinite loop.
+#[entry]
+fn main() -> ! {
+    let mut pac = pac::Peripherals::take().unwrap();
+
+    // Set up the watchdog driver - needed by the clock setup code
+    let mut watchdog = Watchdog::new(pac.WATCHDOG);
+
+    // Configure the clocks
+    let clocks = clocks::init_clocks_and_plls(
+        XTAL_FREQ_HZ,
+        pac.XOSC,
+        pac.CLOCKS,
+        pac.PLL_SYS,
+        pac.PLL_USB,
+        &mut pac.RESETS,
+        &mut watchdog,
+    )
+    .ok()
+    .unwrap();
+    let sys_clock_hz = clocks.system_clock.freq().to_Hz();
+
+    // The single-cycle I/O block controls our GPIO pins
+    let sio = Sio::new(pac.SIO);
+
+    // Set the pins to their default state
+    let pins = bsp::Pins::new(
+        pac.IO_BANK0,
+        pac.PADS_BANK0,
+        sio.gpio_bank0,
+        &mut pac.RESETS,
+    );
+
+    let timer = Timer::new(pac.TIMER, &mut pac.RESETS, &clocks);
+
+    let uart_pins = (
+        // UART TX
+        pins.gpio4.into_function(),
+        // UART RX, also measured by PIO
+        pins.gpio5.into_function(),
+    );
+    let uart = UartPeripheral::new(pac.UART1, uart_pins, &mut pac.RESETS)
+        .enable(
+            UartConfig::new(DEFAULT_RATE.Hz(), DataBits::Eight, None, StopBits::One),
+            clocks.peripheral_clock.freq(),
+        )
+        .unwrap();
+    critical_section::with(|cs| UART.borrow_ref_mut(cs).replace(BufferedUart::new(uart, timer)));
+    unsafe {
+        pac::NVIC::unmask(pac::Interrupt::UART1_IRQ);
+    }
+
+    let mut pio0 = PioManager::new(pac.PIO0, &mut pac.RESETS);
+    let sm0 = pio0.claim::<SM0>().unwrap();
+    let mut capture = PulseCapture::new(&mut pio0, sm0, RX_PIN, sys_clock_hz).unwrap();
+    let mut detector = Detector::new(&BAUD_RATES);
+
+    let mut buffer = [0u8; 16];
+    let mut last_print = 0;
+    loop {
+        while let Some(width_ns) = capture.read() {
+            let Some(baud_rate) = detector.push(width_ns) else { continue };
+            info!("Locked to {} baud", baud_rate);
+            critical_section::with(|cs| {
+                let mut slot = UART.borrow_ref_mut(cs);
+                let (uart, timer) = slot.take().unwrap().free();
+                let uart = uart
+                    .disable()
+                    .enable(
+                        UartConfig::new(baud_rate.Hz(), DataBits::Eight, None, StopBits::One),
+                        clocks.peripheral_clock.freq(),
+                    )
+                    .unwrap();
+                slot.replace(BufferedUart::new(uart, timer));
+            });
+        }
+
+        while let Some(chunk) = with_uart(|uart| uart.read_chunk(&mut buffer)) {
+            // Bytes at the wrong rate are garbage
+            if detector.baud_rate().is_some() {
+                info!("{=[u8]:02x}", buffer[..chunk.len]);
+            }
+        }
+
+        let now = timer.get_counter().ticks();
+        if now - last_print > PRINT_US {
+            last_print = now;
+            info!("{}, confidence: {}%, last: {}", detector.state(), detector.confidence(), detector.last_estimate());
+            let stats = with_uart(|uart| uart.stats());
+            if stats.overrun > 0 || stats.framing > 0 || stats.dropped > 0 {
+                warn!("{}", stats);
+            }
+        }
+    }
+}
+
+fn with_uart<R>(f: impl FnOnce(&mut AutoUart) -> R) -> R {
+    critical_section::with(|cs| f(UART.borrow_ref_mut(cs).as_mut().unwrap()))
+}
+
+#[interrupt]
+fn UART1_IRQ() {
+    critical_section::with(|cs| {
+        if let Some(uart) = UART.borrow_ref_mut(cs).as_mut() {
+            uart.on_interrupt();
+        }
+    });
+}
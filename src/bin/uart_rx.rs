@@ -1,5 +1,15 @@
 //! # UART Example
-//! Send samo data on UART interface to test another board
+//! Spektrum SRXL2 receiver on UART1.
+//!
+//! SRXL2 is half-duplex: connect GPIO4 (TX) and GPIO5 (RX) together to the signal line of the
+//! receiver. TX drives the line only while we send, so it doesn't block the receiver.
+//!
+//! The board works as a flight controller (device ID 0x30). It answers the handshake, switches
+//! to the baud rate chosen by the receiver, prints the channels and sends the VSYS voltage
+//! as the telemetry when the receiver gives it the slot.
+//!
+//! Bytes are received in the UART interrupt (`buffered_uart`), the idle line after
+//! the chunk marks the end of the packet.
 //!
 
 #![no_std]
@@ -7,39 +17,57 @@
 
 use bsp::hal::fugit::RateExtU32;
 use bsp::hal::{
-    clocks, pac,
+    adc::AdcPin,
+    clocks,
+    gpio::{bank0::{Gpio4, Gpio5}, FunctionUart, Pin, PullDown},
+    pac::{self, interrupt},
     sio::Sio,
     uart::{DataBits, StopBits, UartConfig, UartPeripheral},
     watchdog::Watchdog,
-    Clock,
+    Adc, Clock, Timer,
 };
+use core::cell::RefCell;
 use cortex_m_rt::entry;
-use defmt::info;
+use critical_section::Mutex;
+use defmt::{info, warn, Debug2Format};
 use defmt_rtt as _;
+use embedded_hal::adc::OneShot;
 use panic_probe as _;
+use rp2040_sandbox::buffered_uart::{set_tx_enabled, BufferedUart};
+use rp2040_sandbox::srxl2::{self, channel_to_us, Device, Parser, FLIGHT_CONTROLLER_ID, MAX_PACKET};
 use rp_pico as bsp;
 
 /// External high-speed crystal on the Raspberry Pi Pico board is 12 MHz. Adjust
 /// if your board has a different frequency
 const XTAL_FREQ_HZ: u32 = 12_000_000u32;
 
-// Spektrum SRXL2
+// Spektrum SRXL2. For Jeti EX bus see the `jeti_exbus` example, for any other device
+// the `uart_autobaud` example which finds the rate by itself.
 const BOUND_RATE: u32 = 115_200;
-// Jeti EX bus 
-// const BOUND_RATE: u32 = 125_000;
-// const BOUND_RATE: u32 = 250_000;
+
+/// UART TX pin
+const TX_PIN: usize = 4;
+/// Should be unique for every board on the bus
+const UID: u32 = 0x5250_3230;
+/// Print the channels this often
+const PRINT_US: u64 = 500_000;
+
+type UartPins = (Pin<Gpio4, FunctionUart, PullDown>, Pin<Gpio5, FunctionUart, PullDown>);
+type SrxlUart = BufferedUart<pac::UART1, UartPins, 256, 128>;
+
+/// UART shared with the interrupt
+static UART: Mutex<RefCell<Option<SrxlUart>>> = Mutex::new(RefCell::new(None));
 
 /// Entry point to our bare-metal application.
 ///
 /// The `#[rp2040_hal::entry]` macro ensures the Cortex-M start-up code calls this function
 /// as soon as all global variables and the spinlock are initialised.
 ///
-/// The function configures the RP2040 peripherals, then reads from the UART in
+/// The function configures the RP2040 peripherals, then handles the SRXL2 packets in
 /// an infinite loop.
 #[entry]
 fn main() -> ! {
     let mut pac = pac::Peripherals::take().unwrap();
-    let core = pac::CorePeripherals::take().unwrap();
 
     // Set up the watchdog driver - needed by the clock setup code
     let mut watchdog = Watchdog::new(pac.WATCHDOG);
@@ -68,6 +96,12 @@ fn main() -> ! {
         &mut pac.RESETS,
     );
 
+    let timer = Timer::new(pac.TIMER, &mut pac.RESETS, &clocks);
+
+    // VSYS / 3 is on ADC3
+    let mut adc = Adc::new(pac.ADC, &mut pac.RESETS);
+    let mut vsys_pin = AdcPin::new(pins.voltage_monitor.into_floating_input());
+
     let uart_pins = (
         // UART TX
         pins.gpio4.into_function(),
@@ -80,14 +114,109 @@ fn main() -> ! {
             clocks.peripheral_clock.freq(),
         )
         .unwrap();
+    set_tx_enabled(TX_PIN, false);
+    critical_section::with(|cs| UART.borrow_ref_mut(cs).replace(BufferedUart::new(uart, timer)));
+    unsafe {
+        pac::NVIC::unmask(pac::Interrupt::UART1_IRQ);
+    }
 
-    let mut delay = cortex_m::delay::Delay::new(core.SYST, clocks.system_clock.freq().to_Hz());
-    let mut buffer = [0u8; 100];
+    let mut parser = Parser::new();
+    let mut device = Device::new(FLIGHT_CONTROLLER_ID, UID);
+    let mut baud_rate = BOUND_RATE;
+    let mut reply = [0u8; MAX_PACKET];
+    let mut buffer = [0u8; 64];
+    let mut last_print = 0;
     loop {
-        if uart.read_raw(&mut buffer).ok().is_some() {
-            info!("{:x}", &buffer);
+        while let Some(chunk) = with_uart(|uart| uart.read_chunk(&mut buffer)) {
+            for &byte in &buffer[..chunk.len] {
+                let Some(packet) = parser.push(byte) else { continue };
+                let len = device.handle(&packet, chunk.time_us, &mut reply);
+                // The reply slot starts right after the packet from the receiver
+                send(&reply[..len]);
+            }
+            if chunk.idle {
+                // Gap on the line, the next byte starts a new packet
+                parser.reset();
+            }
+        }
+
+        // After the chunks, so it isn't older than their timestamps
+        let now = timer.get_counter().ticks();
+        let len = device.poll(now, &mut reply);
+        send(&reply[..len]);
+
+        if device.baud_rate() != baud_rate {
+            baud_rate = device.baud_rate();
+            info!("Switching to {} baud", baud_rate);
+            critical_section::with(|cs| {
+                let mut slot = UART.borrow_ref_mut(cs);
+                let (uart, timer) = slot.take().unwrap().free();
+                let uart = uart
+                    .disable()
+                    .enable(
+                        UartConfig::new(baud_rate.Hz(), DataBits::Eight, None, StopBits::One),
+                        clocks.peripheral_clock.freq(),
+                    )
+                    .unwrap();
+                slot.replace(BufferedUart::new(uart, timer));
+            });
         }
-        
-        delay.delay_ms(5);
+
+        if now - last_print > PRINT_US {
+            last_print = now;
+            let vsys: u16 = adc.read(&mut vsys_pin).unwrap();
+            device.set_telemetry(rpm_telemetry(vsys as u32 * 3 * 3300 / 4096 / 10));
+
+            let channels = device.channels();
+            let us = |ch| channels.get(ch).map(channel_to_us).unwrap_or(0);
+            info!(
+                "{} rssi: {}, losses: {}, failsafe: {}, ch: {} {} {} {} {} {}",
+                Debug2Format(&device.state()),
+                device.rssi(),
+                device.frame_losses(),
+                device.is_failsafe(now),
+                us(0), us(1), us(2), us(3), us(4), us(5),
+            );
+            let stats = parser.stats();
+            let uart_stats = with_uart(|uart| uart.stats());
+            if stats.crc_errors > 0 || uart_stats.overrun > 0 || uart_stats.framing > 0 || uart_stats.dropped > 0 {
+                warn!("{} packets, {} CRC errors, {}", stats.packets, stats.crc_errors, uart_stats);
+            }
+        }
+    }
+}
+
+/// RPM sensor (0x7E) with only the voltage in 0.01V
+fn rpm_telemetry(volts: u32) -> [u8; srxl2::TELEMETRY_PAYLOAD] {
+    let mut payload = [0u8; srxl2::TELEMETRY_PAYLOAD];
+    payload[0] = 0x7E;
+    // No RPM and temperature
+    payload[2..4].copy_from_slice(&0xFFFFu16.to_be_bytes());
+    payload[4..6].copy_from_slice(&(volts as u16).to_be_bytes());
+    payload[6..8].copy_from_slice(&0x7FFFu16.to_be_bytes());
+    payload
+}
+
+/// Send the packet and release the line when it is done
+fn send(packet: &[u8]) {
+    if packet.is_empty() {
+        return;
     }
+    set_tx_enabled(TX_PIN, true);
+    with_uart(|uart| uart.write(packet));
+    while !with_uart(|uart| uart.is_tx_idle()) {}
+    set_tx_enabled(TX_PIN, false);
+}
+
+fn with_uart<R>(f: impl FnOnce(&mut SrxlUart) -> R) -> R {
+    critical_section::with(|cs| f(UART.borrow_ref_mut(cs).as_mut().unwrap()))
+}
+
+#[interrupt]
+fn UART1_IRQ() {
+    critical_section::with(|cs| {
+        if let Some(uart) = UART.borrow_ref_mut(cs).as_mut() {
+            uart.on_interrupt();
+        }
+    });
 }
@@ -1,19 +1,29 @@
 //! # UART Example
-//! Send samo data on UART interface to test another board
+//! Send telemetry frames on UART interface to test another board
+//!
+//! The records are framed with COBS and CRC-16 (see `rp2040_sandbox::telemetry`).
+//! Decode them on the computer with the USB-serial adapter:
+//!
+//! ```sh
+//! stty -F /dev/ttyUSB0 9600 raw
+//! cd tools/telemetry-decode
+//! cargo run -- /dev/ttyUSB0
+//! ```
 //!
 
 #![no_std]
 #![no_main]
 
 use bsp::hal::{
-    clocks, pac, sio::Sio, uart::{DataBits, StopBits, UartConfig, UartPeripheral}, watchdog::Watchdog, Clock
+    adc::AdcPin, clocks, pac, sio::Sio, uart::{DataBits, StopBits, UartConfig, UartPeripheral}, watchdog::Watchdog, Adc, Clock, Timer
 };
 use cortex_m_rt::entry;
 use defmt_rtt as _;
 use panic_probe as _;
 use rp_pico as bsp;
-use core::fmt::Write;
 use bsp::hal::fugit::RateExtU32;
+use embedded_hal::adc::OneShot;
+use rp2040_sandbox::telemetry::{Encoder, Record, MAX_FRAME};
 
 
 /// External high-speed crystal on the Raspberry Pi Pico board is 12 MHz. Adjust
@@ -25,8 +35,8 @@ const XTAL_FREQ_HZ: u32 = 12_000_000u32;
 /// The `#[rp2040_hal::entry]` macro ensures the Cortex-M start-up code calls this function
 /// as soon as all global variables and the spinlock are initialised.
 ///
-/// The function configures the RP2040 peripherals, then writes to the UART in
-/// an infinite loop.
+/// The function configures the RP2040 peripherals, then writes the counter and
+/// the status records to the UART in an infinite loop.
 #[entry]
 fn main() -> ! {
     // Grab our singleton objects
@@ -68,19 +78,42 @@ fn main() -> ! {
         // UART RX (characters received by RP2040) on pin 2 (GPIO1)
         pins.gpio1.into_function(),
     );
-    let mut uart = UartPeripheral::new(pac.UART0, uart_pins, &mut pac.RESETS)
+    let uart = UartPeripheral::new(pac.UART0, uart_pins, &mut pac.RESETS)
         .enable(
             UartConfig::new(9600.Hz(), DataBits::Eight, None, StopBits::One),
             clocks.peripheral_clock.freq(),
         )
         .unwrap();
 
-    uart.write_full_blocking(b"UART example\r\n");
+    let timer = Timer::new(pac.TIMER, &mut pac.RESETS, &clocks);
+
+    // VSYS / 3 is on ADC3
+    let mut adc = Adc::new(pac.ADC, &mut pac.RESETS);
+    let mut vsys_pin = AdcPin::new(pins.voltage_monitor.into_floating_input());
+    let mut temp_sensor = adc.take_temp_sensor().unwrap();
 
+    let mut encoder = Encoder::new();
+    let mut frame = [0u8; MAX_FRAME];
     let mut value = 0u32;
     loop {
-        writeln!(uart, "value: {value:02}\r").unwrap();
-        delay.delay_ms(1000);
+        let len = encoder.encode(&Record::Counter { value }, &mut frame);
+        uart.write_full_blocking(&frame[..len]);
+
+        if value.is_multiple_of(10) {
+            let vsys: u16 = adc.read(&mut vsys_pin).unwrap();
+            let raw: u16 = adc.read(&mut temp_sensor).unwrap();
+            // T = 27 - (V - 0.706) / 0.001721, in 0.01 C
+            let millivolts = raw as i32 * 3300 / 4096;
+            let status = Record::Status {
+                uptime_ms: (timer.get_counter().ticks() / 1000) as u32,
+                vsys_mv: (vsys as u32 * 3 * 3300 / 4096) as u16,
+                temperature_cdeg: (2700 - (millivolts - 706) * 100_000 / 1721) as i16,
+            };
+            let len = encoder.encode(&status, &mut frame);
+            uart.write_full_blocking(&frame[..len]);
+        }
+
+        delay.delay_ms(100);
         value += 1
     }
 }
\ No newline at end of file
@@ -0,0 +1,103 @@
+//! WS2812 (NeoPixel) LED strip
+//!
+//! Strip with data on GPIO22 shows the effects one after another. The potentiometer
+//! on GPIO26 sets the global brightness.
+//!
+//! The frame is sent by DMA from the `ws2812` module, effects are rendered from the `Timer`.
+//!
+#![no_std]
+#![no_main]
+
+use rp_pico as bsp;
+use bsp::hal::{
+    adc::AdcPin, clocks::{init_clocks_and_plls, Clock}, dma::DMAExt, gpio::{FunctionPio0, Pin}, pac, pio::SM0, sio::Sio, watchdog::Watchdog, Adc, Timer
+};
+use cortex_m::singleton;
+use cortex_m_rt::entry;
+use defmt::*;
+use defmt_rtt as _;
+use embedded_hal::adc::OneShot;
+use panic_probe as _;
+use rp2040_sandbox::pio_manager::PioManager;
+use rp2040_sandbox::ws2812::{effects::Effect, Color, LedType, Ws2812};
+
+
+// Number of LEDs on the strip
+const LEDS: usize = 16;
+// SK6812 RGBW strips need LedType::Rgbw
+const LED_TYPE: LedType = LedType::Rgb;
+const EFFECT_DURATION_US: u64 = 10_000_000;
+const FRAME_US: u64 = 10_000;
+const EFFECTS: [Effect; 3] = [
+    Effect::Rainbow { period_ms: 3_000 },
+    Effect::Chase { color: Color::rgb(255, 80, 0), spacing: 4, step_ms: 120 },
+    Effect::Fade { from: Color::rgb(0, 0, 255), to: Color::rgb(255, 0, 40), period_ms: 4_000 },
+];
+
+
+#[entry]
+fn main() -> ! {
+    info!("Program start");
+    let mut pac = pac::Peripherals::take().unwrap();
+    let mut watchdog = Watchdog::new(pac.WATCHDOG);
+    let sio = Sio::new(pac.SIO);
+
+    // External high-speed crystal on the pico board is 12Mhz
+    let external_xtal_freq_hz = 12_000_000u32;
+    let clocks = init_clocks_and_plls(
+        external_xtal_freq_hz,
+        pac.XOSC,
+        pac.CLOCKS,
+        pac.PLL_SYS,
+        pac.PLL_USB,
+        &mut pac.RESETS,
+        &mut watchdog,
+    )
+    .ok()
+    .unwrap();
+
+    let pins = bsp::Pins::new(
+        pac.IO_BANK0,
+        pac.PADS_BANK0,
+        sio.gpio_bank0,
+        &mut pac.RESETS,
+    );
+
+    let timer = Timer::new(pac.TIMER, &mut pac.RESETS, &clocks);
+    let mut adc = Adc::new(pac.ADC, &mut pac.RESETS);
+    let mut pot_pin = AdcPin::new(pins.gpio26);
+
+    let data: Pin<_, FunctionPio0, _> = pins.gpio22.into_function();
+    let data_pin_id = data.id().num;
+    let dma = pac.DMA.split(&mut pac.RESETS);
+    let buffer = singleton!(: [u32; LEDS] = [0; LEDS]).unwrap();
+    let mut pio0 = PioManager::new(pac.PIO0, &mut pac.RESETS);
+    let sm0 = pio0.claim::<SM0>().unwrap();
+    let sys_clock_hz = clocks.system_clock.freq().to_Hz();
+    let mut strip = Ws2812::new(&mut pio0, sm0, dma.ch0, data_pin_id, LED_TYPE, sys_clock_hz, buffer, timer).unwrap();
+
+    let mut frame = [Color::BLACK; LEDS];
+    let start = timer.get_counter().ticks();
+    let mut next_frame = start;
+    let mut current = usize::MAX;
+    loop {
+        let now = timer.get_counter().ticks();
+        if now < next_frame {
+            continue;
+        }
+        next_frame += FRAME_US;
+
+        let elapsed = now - start;
+        let index = (elapsed / EFFECT_DURATION_US) as usize % EFFECTS.len();
+        if index != current {
+            current = index;
+            info!("{}", EFFECTS[index]);
+        }
+        let v: u16 = adc.read(&mut pot_pin).unwrap();
+        strip.set_brightness((v >> 4) as u8);
+        EFFECTS[index].render(elapsed % EFFECT_DURATION_US, &mut frame);
+        strip.write(frame);
+    }
+}
+
+// End of file
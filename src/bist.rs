@@ -0,0 +1,240 @@
+//! Built-in self-test of PIO and DMA
+//!
+//! Random words are sent by DMA to the TX FIFO of the state machine, which returns them
+//! through RX FIFO to the second DMA channel. The received buffer is compared with the sent one.
+//! Any PIO block, state machine and pair of DMA channels can be checked, e.g. at boot:
+//!
+//! ```ignore
+//! let mut bist = Loopback::new(&mut pio1, sm, dma.ch4, dma.ch5, tx_buf, rx_buf)?;
+//! bist.run(&timer, 0x1234_5678, 16)?;
+//! let (sm, ch4, ch5, tx_buf, rx_buf) = bist.free();
+//! ```
+//!
+//! Results are reported over defmt and returned as `Result`.
+//!
+use rp_pico::hal::{
+    dma::{single_buffer, SingleChannel},
+    pac,
+    pio::{PIOBuilder, PIOExt, Rx, StateMachine, StateMachineIndex, Stopped, Tx, UninitStateMachine},
+    Timer,
+};
+
+use crate::pio_manager::{PioError, PioManager};
+
+
+/// Number of mismatched words reported over defmt in a single round
+const MAX_REPORTED: u32 = 8;
+/// Time allowed for a single round, in addition to 1us per word
+const TIMEOUT_US: u64 = 1_000;
+
+
+/// Why the self-test failed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum BistError {
+    /// Received data doesn't match. The first wrong word and the number of wrong words.
+    Mismatch { round: u32, index: u32, expected: u32, got: u32, errors: u32 },
+    /// DMA didn't finish in time. Number of words still expected by the channels.
+    Timeout { round: u32, tx_remaining: u32, rx_remaining: u32 },
+}
+
+
+/// Result of the successful test
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct BistReport {
+    /// Number of words sent (and received)
+    pub words: u32,
+    /// Time of all transfers
+    pub elapsed_us: u32,
+}
+
+impl BistReport {
+    /// Words per second
+    pub fn throughput(&self) -> u32 {
+        (self.words as u64 * 1_000_000 / self.elapsed_us.max(1) as u64) as u32
+    }
+}
+
+
+/// Pseudo random patterns (xorshift32)
+#[derive(Clone, Copy, Debug)]
+pub struct Patterns(u32);
+
+impl Patterns {
+    /// Seed 0 is replaced by 1, xorshift would return only zeros
+    pub const fn new(seed: u32) -> Self {
+        Self(if seed == 0 { 1 } else { seed })
+    }
+}
+
+impl Iterator for Patterns {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.0 = x;
+        Some(x)
+    }
+}
+
+
+/// FIFO, DMA channel and buffer of one direction
+type Path<FIFO, CH, const N: usize> = (FIFO, CH, &'static mut [u32; N]);
+
+/// Loopback test on a single state machine and two DMA channels
+pub struct Loopback<P: PIOExt, SM: StateMachineIndex, TXC: SingleChannel, RXC: SingleChannel, const N: usize> {
+    // Resources are moved into the transfers during the test
+    sm: Option<StateMachine<(P, SM), Stopped>>,
+    tx: Option<Path<Tx<(P, SM)>, TXC, N>>,
+    rx: Option<Path<Rx<(P, SM)>, RXC, N>>,
+}
+
+impl<P, SM, TXC, RXC, const N: usize> Loopback<P, SM, TXC, RXC, N>
+where
+    P: PIOExt,
+    SM: StateMachineIndex,
+    TXC: SingleChannel,
+    RXC: SingleChannel,
+{
+    /// Install the loopback program and configure the state machine
+    ///   * tx_ch - DMA channel writing to TX FIFO
+    ///   * rx_ch - DMA channel reading RX FIFO
+    ///   * tx_buf, rx_buf - words sent in a single round
+    pub fn new(
+        pio: &mut PioManager<P>,
+        sm: UninitStateMachine<(P, SM)>,
+        tx_ch: TXC,
+        rx_ch: RXC,
+        tx_buf: &'static mut [u32; N],
+        rx_buf: &'static mut [u32; N],
+    ) -> Result<Self, PioError> {
+        let installed = pio.install("loopback", &loopback_program())?;
+        let (sm, rx, tx) = PIOBuilder::from_program(installed)
+            .autopull(true)
+            .autopush(true)
+            .build(sm);
+        Ok(Self { sm: Some(sm), tx: Some((tx, tx_ch, tx_buf)), rx: Some((rx, rx_ch, rx_buf)) })
+    }
+
+    /// Send the given number of buffers with random data
+    ///   * timer - measures throughput and timeouts
+    ///   * seed - start of the random patterns, the same seed repeats the test
+    pub fn run(&mut self, timer: &Timer, seed: u32, rounds: u32) -> Result<BistReport, BistError> {
+        let mut patterns = Patterns::new(seed);
+        let mut elapsed_us = 0;
+        for round in 0..rounds {
+            let (tx, tx_ch, tx_buf) = self.tx.take().unwrap();
+            let (rx, rx_ch, rx_buf) = self.rx.take().unwrap();
+            tx_buf.iter_mut().zip(&mut patterns).for_each(|(word, pattern)| *word = pattern);
+            rx_buf.fill(!0);
+
+            let tx_id = tx_ch.id();
+            let rx_id = rx_ch.id();
+            let mut sm = self.sm.take().unwrap();
+            sm.clear_fifos();
+            let sm = sm.start();
+            let start = timer.get_counter().ticks();
+            let rx_transfer = single_buffer::Config::new(rx_ch, rx, rx_buf).start();
+            let tx_transfer = single_buffer::Config::new(tx_ch, tx_buf, tx).start();
+            let deadline = start + TIMEOUT_US + N as u64;
+            while !(tx_transfer.is_done() && rx_transfer.is_done()) && timer.get_counter().ticks() < deadline {}
+            let end = timer.get_counter().ticks();
+            elapsed_us += (end - start) as u32;
+
+            let timeout = !(tx_transfer.is_done() && rx_transfer.is_done());
+            let tx_remaining = abort(tx_id);
+            let rx_remaining = abort(rx_id);
+            let (tx_ch, tx_buf, tx) = tx_transfer.wait();
+            let (rx_ch, rx, rx_buf) = rx_transfer.wait();
+            self.sm = Some(sm.stop());
+
+            let result = if timeout {
+                defmt::error!(
+                    "BIST round {=u32}: timeout, {=u32} words not sent, {=u32} not received",
+                    round,
+                    tx_remaining,
+                    rx_remaining
+                );
+                Err(BistError::Timeout { round, tx_remaining, rx_remaining })
+            } else {
+                compare(round, &tx_buf[..], &rx_buf[..])
+            };
+            self.tx = Some((tx, tx_ch, tx_buf));
+            self.rx = Some((rx, rx_ch, rx_buf));
+            result?;
+        }
+
+        let report = BistReport { words: rounds * N as u32, elapsed_us };
+        defmt::info!(
+            "BIST PIO{=u8} SM{=u8}: {=u32} words OK in {=u32}us, {=u32} words/s",
+            P::id() as u8,
+            SM::id() as u8,
+            report.words,
+            report.elapsed_us,
+            report.throughput()
+        );
+        Ok(report)
+    }
+
+    /// Release the resources
+    #[allow(clippy::type_complexity)]
+    pub fn free(self) -> (UninitStateMachine<(P, SM)>, TXC, RXC, &'static mut [u32; N], &'static mut [u32; N]) {
+        let (tx, tx_ch, tx_buf) = self.tx.unwrap();
+        let (rx, rx_ch, rx_buf) = self.rx.unwrap();
+        let (sm, _program) = self.sm.unwrap().uninit(rx, tx);
+        (sm, tx_ch, rx_ch, tx_buf, rx_buf)
+    }
+}
+
+
+/// Loopback through OSR and ISR. Use with autopull and autopush, 2 cycles per word.
+pub fn loopback_program() -> pio::Program<32> {
+    pio_proc::pio_asm!(
+        ".wrap_target",
+        "    out x, 32",
+        "    in x, 32",
+        ".wrap"
+    )
+    .program
+}
+
+
+/// Compare the buffers and report the first mismatches
+fn compare(round: u32, sent: &[u32], received: &[u32]) -> Result<(), BistError> {
+    let mut first = None;
+    let mut errors = 0;
+    for (index, (&expected, &got)) in sent.iter().zip(received).enumerate() {
+        if expected != got {
+            if errors < MAX_REPORTED {
+                defmt::error!(
+                    "BIST round {=u32}: word {=usize} expected {=u32:#010x}, got {=u32:#010x}",
+                    round,
+                    index,
+                    expected,
+                    got
+                );
+            }
+            first.get_or_insert((index as u32, expected, got));
+            errors += 1;
+        }
+    }
+    match first {
+        Some((index, expected, got)) => Err(BistError::Mismatch { round, index, expected, got, errors }),
+        None => Ok(()),
+    }
+}
+
+/// Stop the channel if it is still running and return the number of words it didn't transfer
+fn abort(channel: u8) -> u32 {
+    // Safety: only the channel owned by the transfer is accessed
+    let dma = unsafe { &*pac::DMA::ptr() };
+    let ch = &dma.ch[channel as usize];
+    if !ch.ch_ctrl_trig.read().busy().bit_is_set() {
+        return 0;
+    }
+    dma.chan_abort.write(|w| unsafe { w.bits(1 << channel) });
+    while dma.chan_abort.read().bits() & (1 << channel) != 0 {}
+    ch.ch_trans_count.read().bits()
+}
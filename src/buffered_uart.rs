@@ -0,0 +1,368 @@
+//! Interrupt driven UART with ring buffers
+//!
+//! The UART interrupt moves the received bytes from the 32 byte hardware FIFO to the RX ring
+//! buffer and refills the TX FIFO from the TX ring buffer, so nothing is lost while the main
+//! loop is busy.
+//!
+//! Received bytes are grouped into chunks, one per interrupt, with the time of the interrupt.
+//! The chunk which ended with the receive timeout (the line was idle for 32 bit periods) is
+//! marked as `idle`, so the protocol parsers can find the gaps between the frames.
+//! The bytes and their chunks are kept in `RxQueue`, which doesn't need the hardware.
+//!
+//! On a half-duplex bus TX and RX are connected together, `set_tx_enabled` releases the TX pin
+//! while the other side sends.
+//!
+//! The driver is shared with the interrupt handler in a `Mutex<RefCell<Option<...>>>`:
+//!
+//! ```ignore
+//! #[interrupt]
+//! fn UART1_IRQ() {
+//!     critical_section::with(|cs| {
+//!         if let Some(uart) = UART.borrow_ref_mut(cs).as_mut() {
+//!             uart.on_interrupt();
+//!         }
+//!     });
+//! }
+//! ```
+//!
+use rp_pico::hal::{
+    pac::{self, uart0::RegisterBlock},
+    uart::{Enabled, ReadErrorType, UartDevice, UartPeripheral, ValidUartPinout},
+    Timer,
+};
+
+
+/// Chunks remembered in the driver. When they run out the bytes are added to the last one.
+pub const MAX_CHUNKS: usize = 16;
+
+
+/// Fixed size FIFO queue
+#[derive(Clone, Debug)]
+pub struct RingBuffer<T: Copy + Default, const N: usize> {
+    buffer: [T; N],
+    head: usize,
+    len: usize,
+}
+
+impl<T: Copy + Default, const N: usize> RingBuffer<T, N> {
+    pub fn new() -> Self {
+        Self { buffer: [T::default(); N], head: 0, len: 0 }
+    }
+
+    /// Add the item at the end. Returns false when the buffer is full.
+    pub fn push(&mut self, item: T) -> bool {
+        if self.len == N {
+            return false;
+        }
+        self.buffer[(self.head + self.len) % N] = item;
+        self.len += 1;
+        true
+    }
+
+    /// Take the oldest item
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        let item = self.buffer[self.head];
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        Some(item)
+    }
+
+    /// Oldest item
+    pub fn front_mut(&mut self) -> Option<&mut T> {
+        (self.len > 0).then(|| &mut self.buffer[self.head])
+    }
+
+    /// Newest item
+    pub fn back_mut(&mut self) -> Option<&mut T> {
+        (self.len > 0).then(|| &mut self.buffer[(self.head + self.len - 1) % N])
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+}
+
+impl<T: Copy + Default, const N: usize> Default for RingBuffer<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+/// Bytes received in one interrupt
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub struct Chunk {
+    /// Time of the interrupt from `Timer::get_counter()`
+    pub time_us: u64,
+    pub len: usize,
+    /// The line was idle after the last byte
+    pub idle: bool,
+}
+
+
+/// Received bytes grouped into chunks, the part of the driver which doesn't use the hardware
+#[derive(Clone, Debug)]
+pub struct RxQueue<const N: usize> {
+    bytes: RingBuffer<u8, N>,
+    chunks: RingBuffer<Chunk, MAX_CHUNKS>,
+    /// Bytes pushed since the last chunk was finished
+    pending: usize,
+}
+
+impl<const N: usize> RxQueue<N> {
+    pub fn new() -> Self {
+        Self { bytes: RingBuffer::new(), chunks: RingBuffer::new(), pending: 0 }
+    }
+
+    /// Add the received byte to the current chunk. Returns false if the buffer is full.
+    pub fn push(&mut self, byte: u8) -> bool {
+        let stored = self.bytes.push(byte);
+        self.pending += stored as usize;
+        stored
+    }
+
+    /// End the current chunk, e.g. at the end of the interrupt
+    ///   * time_us - time of the last byte
+    ///   * idle - the line was idle after it
+    pub fn finish_chunk(&mut self, time_us: u64, idle: bool) {
+        if self.pending == 0 {
+            return;
+        }
+        let chunk = Chunk { time_us, len: self.pending, idle };
+        self.pending = 0;
+        if !self.chunks.push(chunk) {
+            if let Some(last) = self.chunks.back_mut() {
+                *last = Chunk { len: last.len + chunk.len, ..chunk };
+            }
+        }
+    }
+
+    /// Take the received bytes, returns their number
+    pub fn read(&mut self, buffer: &mut [u8]) -> usize {
+        let mut count = 0;
+        while count < buffer.len() {
+            let Some(byte) = self.bytes.pop() else { break };
+            buffer[count] = byte;
+            count += 1;
+        }
+        self.consume_chunks(count);
+        count
+    }
+
+    /// Take the oldest chunk. When the buffer is shorter, the rest stays for the next call
+    /// (and the returned chunk is not `idle`).
+    pub fn read_chunk(&mut self, buffer: &mut [u8]) -> Option<Chunk> {
+        let chunk = *self.chunks.front_mut()?;
+        let len = chunk.len.min(buffer.len());
+        for byte in buffer[..len].iter_mut() {
+            *byte = self.bytes.pop().unwrap_or(0);
+        }
+        self.consume_chunks(len);
+        Some(Chunk { len, idle: chunk.idle && len == chunk.len, ..chunk })
+    }
+
+    /// Bytes waiting in the buffer
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// Forget everything received
+    pub fn clear(&mut self) {
+        self.bytes.clear();
+        self.chunks.clear();
+        self.pending = 0;
+    }
+
+    /// Remove the bytes which were read from the chunks
+    fn consume_chunks(&mut self, mut count: usize) {
+        while count > 0 {
+            let Some(chunk) = self.chunks.front_mut() else { break };
+            if chunk.len > count {
+                chunk.len -= count;
+                break;
+            }
+            count -= chunk.len;
+            self.chunks.pop();
+        }
+    }
+}
+
+impl<const N: usize> Default for RxQueue<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+/// Counters of the driver
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub struct UartStats {
+    pub rx_bytes: u32,
+    pub tx_bytes: u32,
+    /// Hardware FIFO was full, bytes were lost
+    pub overrun: u32,
+    pub framing: u32,
+    pub parity: u32,
+    pub breaks: u32,
+    /// RX ring buffer was full, bytes were lost
+    pub dropped: u32,
+}
+
+
+/// UART with the RX and TX ring buffers filled by the interrupt
+pub struct BufferedUart<D: UartDevice, P: ValidUartPinout<D>, const RX: usize, const TX: usize> {
+    uart: UartPeripheral<Enabled, D, P>,
+    timer: Timer,
+    rx: RxQueue<RX>,
+    tx: RingBuffer<u8, TX>,
+    stats: UartStats,
+}
+
+impl<D: UartDevice, P: ValidUartPinout<D>, const RX: usize, const TX: usize> BufferedUart<D, P, RX, TX> {
+    /// Enable the RX interrupt of the UART. The interrupt has to be unmasked in the NVIC
+    /// after the driver is moved to the place where the handler finds it.
+    pub fn new(mut uart: UartPeripheral<Enabled, D, P>, timer: Timer) -> Self {
+        uart.enable_rx_interrupt();
+        Self {
+            uart,
+            timer,
+            rx: RxQueue::new(),
+            tx: RingBuffer::new(),
+            stats: UartStats::default(),
+        }
+    }
+
+    /// Call it from the UART interrupt handler
+    pub fn on_interrupt(&mut self) {
+        // Receive timeout is cleared by emptying the FIFO, so check it first
+        let idle = registers::<D>().uartmis.read().rtmis().bit_is_set();
+        let mut buffer = [0u8; 32];
+        let mut received = 0;
+        loop {
+            let bytes = match self.uart.read_raw(&mut buffer) {
+                Ok(count) => &buffer[..count],
+                Err(nb::Error::Other(error)) => {
+                    match error.err_type {
+                        ReadErrorType::Overrun => self.stats.overrun += 1,
+                        ReadErrorType::Break => self.stats.breaks += 1,
+                        ReadErrorType::Parity => self.stats.parity += 1,
+                        ReadErrorType::Framing => self.stats.framing += 1,
+                    }
+                    error.discarded
+                }
+                Err(nb::Error::WouldBlock) => break,
+            };
+            for &byte in bytes {
+                if self.rx.push(byte) {
+                    received += 1;
+                } else {
+                    self.stats.dropped += 1;
+                }
+            }
+        }
+        if received > 0 {
+            self.stats.rx_bytes += received as u32;
+            self.rx.finish_chunk(self.timer.get_counter().ticks(), idle);
+        }
+        self.fill_tx_fifo();
+    }
+
+    /// Take the received bytes, returns their number
+    pub fn read(&mut self, buffer: &mut [u8]) -> usize {
+        self.rx.read(buffer)
+    }
+
+    /// Take the oldest chunk. When the buffer is shorter, the rest stays for the next call
+    /// (and the returned chunk is not `idle`).
+    pub fn read_chunk(&mut self, buffer: &mut [u8]) -> Option<Chunk> {
+        self.rx.read_chunk(buffer)
+    }
+
+    /// Queue the bytes for sending, returns how many fit into the TX buffer
+    pub fn write(&mut self, data: &[u8]) -> usize {
+        let count = data.iter().take_while(|&&byte| self.tx.push(byte)).count();
+        self.fill_tx_fifo();
+        count
+    }
+
+    /// True when everything was sent, including the last bit
+    pub fn is_tx_idle(&self) -> bool {
+        self.tx.is_empty() && !self.uart.uart_is_busy()
+    }
+
+    /// Bytes waiting in the RX buffer
+    pub fn rx_len(&self) -> usize {
+        self.rx.len()
+    }
+
+    pub fn stats(&self) -> UartStats {
+        self.stats
+    }
+
+    /// Forget everything received
+    pub fn clear_rx(&mut self) {
+        self.rx.clear();
+    }
+
+    pub fn free(mut self) -> (UartPeripheral<Enabled, D, P>, Timer) {
+        self.uart.disable_rx_interrupt();
+        self.uart.disable_tx_interrupt();
+        (self.uart, self.timer)
+    }
+
+    /// Move bytes from the TX buffer to the FIFO, the interrupt continues when it gets empty
+    fn fill_tx_fifo(&mut self) {
+        while self.uart.uart_is_writable() {
+            let Some(byte) = self.tx.pop() else { break };
+            // Can't block, the FIFO has space
+            let _ = self.uart.write_raw(&[byte]);
+            self.stats.tx_bytes += 1;
+        }
+        if self.tx.is_empty() {
+            self.uart.disable_tx_interrupt();
+        } else {
+            self.uart.enable_tx_interrupt();
+        }
+    }
+}
+
+
+/// Drive the TX pin only while we send, so the other side of the half-duplex bus can use the line.
+/// The pin stays in the UART function, only its output enable is overridden.
+///   * tx_pin - GPIO number of the UART TX pin
+pub fn set_tx_enabled(tx_pin: usize, enabled: bool) {
+    // Safety: only the output enable of the TX pin is changed
+    let io = unsafe { &*pac::IO_BANK0::ptr() };
+    io.gpio[tx_pin].gpio_ctrl.modify(|_, w| if enabled { w.oeover().normal() } else { w.oeover().disable() });
+}
+
+
+/// Registers of the UART for the flags the HAL doesn't expose
+fn registers<D: UartDevice>() -> &'static RegisterBlock {
+    // Safety: only the read-only interrupt status is used
+    unsafe {
+        match D::ID {
+            0 => &*pac::UART0::ptr(),
+            _ => &*pac::UART1::ptr(),
+        }
+    }
+}
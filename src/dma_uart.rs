@@ -0,0 +1,262 @@
+//! UART reception by DMA into a ring buffer
+//!
+//! The DMA channel copies every received byte from the UART to a 256 byte ring buffer
+//! (the DMA wraps the write address), so the CPU doesn't touch the single bytes.
+//! `poll` finds the frames: when the number of received bytes doesn't change for the
+//! idle time, the bytes since the last frame are one frame.
+//!
+//! The receive timeout interrupt of the UART can't be used for it, because the DMA keeps
+//! the RX FIFO empty and the timeout only comes with data in the FIFO. So call `poll` often,
+//! from the main loop or from a `Timer` alarm, at least once per idle time.
+//!
+//! Frames have to be shorter than the ring buffer, and the gap between them longer than
+//! the idle time. Parity, framing and break errors are counted from the UART status.
+//!
+use rp_pico::hal::{
+    dma::SingleChannel,
+    pac::{self, uart0::RegisterBlock},
+    uart::{Enabled, UartDevice, UartPeripheral, ValidUartPinout},
+};
+
+
+/// Size of the ring buffer
+pub const RING_SIZE: usize = 256;
+/// log2 of the ring size for the DMA
+const RING_BITS: u8 = 8;
+/// Transfers of the DMA before it has to be restarted
+const TRANSFERS: u32 = u32::MAX;
+
+
+/// Ring buffer aligned to its size, as the DMA requires
+#[repr(C, align(256))]
+pub struct RxRing(pub [u8; RING_SIZE]);
+
+impl RxRing {
+    pub const fn new() -> Self {
+        Self([0; RING_SIZE])
+    }
+}
+
+impl Default for RxRing {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+/// Time of the given number of characters (10 bits each), the usual idle time is 2 or 3
+pub fn idle_time_us(baud_rate: u32, chars: u32) -> u64 {
+    (chars as u64 * 10 * 1_000_000).div_ceil(baud_rate as u64)
+}
+
+
+/// Position of the frame in the ring buffer
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Span {
+    /// Number of bytes received before the frame
+    pub start: u32,
+    pub len: usize,
+    /// When the last byte was seen
+    pub time_us: u64,
+}
+
+impl Span {
+    /// Copy the frame from the ring buffer, return the number of bytes
+    pub fn copy(&self, ring: &[u8; RING_SIZE], out: &mut [u8]) -> usize {
+        let len = self.len.min(out.len());
+        for (i, byte) in out[..len].iter_mut().enumerate() {
+            *byte = ring[(self.start as usize + i) % RING_SIZE];
+        }
+        len
+    }
+}
+
+
+/// Finds the frames from the number of the received bytes and the time
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct IdleDetector {
+    idle_us: u64,
+    /// Bytes already given out as frames
+    consumed: u32,
+    last_count: u32,
+    last_change_us: u64,
+    overruns: u32,
+}
+
+impl IdleDetector {
+    pub const fn new(idle_us: u64) -> Self {
+        Self { idle_us, consumed: 0, last_count: 0, last_change_us: 0, overruns: 0 }
+    }
+
+    /// Returns the frame when the line is idle after it
+    ///   * received - number of bytes written by the DMA, it may wrap around
+    pub fn update(&mut self, received: u32, now_us: u64) -> Option<Span> {
+        if received != self.last_count {
+            self.last_count = received;
+            self.last_change_us = now_us;
+            return None;
+        }
+        let pending = received.wrapping_sub(self.consumed);
+        if pending == 0 || now_us - self.last_change_us < self.idle_us {
+            return None;
+        }
+        let start = self.consumed;
+        self.consumed = received;
+        if pending as usize > RING_SIZE {
+            // Start of the frame was overwritten
+            self.overruns += 1;
+            return None;
+        }
+        Some(Span { start, len: pending as usize, time_us: self.last_change_us })
+    }
+
+    /// True when the line is idle and all the bytes were given out
+    pub fn is_idle(&self) -> bool {
+        self.consumed == self.last_count
+    }
+
+    /// Frames lost because they didn't fit into the ring buffer
+    pub fn overruns(&self) -> u32 {
+        self.overruns
+    }
+}
+
+
+/// Counters of the driver
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub struct DmaUartStats {
+    pub frames: u32,
+    /// Frames longer than the ring buffer
+    pub overruns: u32,
+    pub framing: u32,
+    pub parity: u32,
+    pub breaks: u32,
+}
+
+
+/// UART with the reception by DMA
+pub struct DmaUart<D: UartDevice, P: ValidUartPinout<D>, CH: SingleChannel> {
+    uart: UartPeripheral<Enabled, D, P>,
+    ch: CH,
+    ring: &'static mut RxRing,
+    detector: IdleDetector,
+    /// Bytes received before the last start of the DMA
+    base: u32,
+    stats: DmaUartStats,
+}
+
+impl<D: UartDevice, P: ValidUartPinout<D>, CH: SingleChannel> DmaUart<D, P, CH> {
+    /// Start the reception
+    ///   * idle_us - gap which ends the frame, see `idle_time_us`
+    pub fn new(uart: UartPeripheral<Enabled, D, P>, ch: CH, ring: &'static mut RxRing, idle_us: u64) -> Self {
+        let mut dma_uart = Self { uart, ch, ring, detector: IdleDetector::new(idle_us), base: 0, stats: DmaUartStats::default() };
+        registers::<D>().uartdmacr.modify(|_, w| w.rxdmae().set_bit());
+        dma_uart.start(0);
+        dma_uart
+    }
+
+    /// Look for the complete frames and give them to the callback with the time of their last byte.
+    /// Returns the number of frames.
+    pub fn poll(&mut self, now_us: u64, mut on_frame: impl FnMut(&[u8], u64)) -> usize {
+        self.count_errors();
+        let mut frames = 0;
+        let received = self.received();
+        if let Some(span) = self.detector.update(received, now_us) {
+            let mut frame = [0u8; RING_SIZE];
+            let len = span.copy(&self.ring.0, &mut frame);
+            self.stats.frames += 1;
+            frames += 1;
+            on_frame(&frame[..len], span.time_us);
+        }
+        self.stats.overruns = self.detector.overruns();
+        // Restart the DMA long before it stops, while nothing is coming
+        if self.remaining() < TRANSFERS / 2 && self.detector.is_idle() {
+            self.abort();
+            // Bytes which came in the meantime are kept, the count continues
+            self.start(self.received());
+        }
+        frames
+    }
+
+    pub fn stats(&self) -> DmaUartStats {
+        self.stats
+    }
+
+    /// Stop the DMA and return the resources
+    pub fn free(mut self) -> (UartPeripheral<Enabled, D, P>, CH, &'static mut RxRing) {
+        self.abort();
+        registers::<D>().uartdmacr.modify(|_, w| w.rxdmae().clear_bit());
+        (self.uart, self.ch, self.ring)
+    }
+
+    /// Number of bytes written by the DMA since the start, wraps around
+    fn received(&self) -> u32 {
+        self.base.wrapping_add(TRANSFERS - self.remaining())
+    }
+
+    fn remaining(&self) -> u32 {
+        dma_channel(self.ch.id()).ch_trans_count.read().bits()
+    }
+
+    /// Start the DMA at the position in the ring buffer for the given byte count
+    fn start(&mut self, received: u32) {
+        self.base = received;
+        let id = self.ch.id();
+        let ch = dma_channel(id);
+        let write_addr = self.ring.0.as_ptr() as u32 + received % RING_SIZE as u32;
+        let read_addr = &registers::<D>().uartdr as *const _ as u32;
+        ch.ch_read_addr.write(|w| unsafe { w.bits(read_addr) });
+        ch.ch_write_addr.write(|w| unsafe { w.bits(write_addr) });
+        ch.ch_trans_count.write(|w| unsafe { w.bits(TRANSFERS) });
+        ch.ch_ctrl_trig.write(|w| unsafe {
+            w.data_size().size_byte()
+                .incr_read().clear_bit()
+                .incr_write().set_bit()
+                .ring_size().bits(RING_BITS)
+                .ring_sel().set_bit()
+                .chain_to().bits(id)
+                .treq_sel().bits(D::rx_dreq())
+                .irq_quiet().set_bit()
+                .en().set_bit()
+        });
+    }
+
+    fn abort(&mut self) {
+        // Safety: only the channel owned by the driver is accessed
+        let dma = unsafe { &*pac::DMA::ptr() };
+        let id = self.ch.id();
+        dma.chan_abort.write(|w| unsafe { w.bits(1 << id) });
+        while dma.chan_abort.read().bits() & (1 << id) != 0 {}
+    }
+
+    /// Errors of the received bytes, the DMA reads only the data
+    fn count_errors(&mut self) {
+        let uart = registers::<D>();
+        let status = uart.uartrsr.read();
+        if status.bits() & 0x0F == 0 {
+            return;
+        }
+        self.stats.framing += status.fe().bit_is_set() as u32;
+        self.stats.parity += status.pe().bit_is_set() as u32;
+        self.stats.breaks += status.be().bit_is_set() as u32;
+        // Any write clears the flags
+        uart.uartrsr.write(|w| unsafe { w.bits(0) });
+    }
+}
+
+
+/// Registers of the UART for the DMA settings the HAL doesn't expose
+fn registers<D: UartDevice>() -> &'static RegisterBlock {
+    // Safety: only the DMA enable and the error status are changed
+    unsafe {
+        match D::ID {
+            0 => &*pac::UART0::ptr(),
+            _ => &*pac::UART1::ptr(),
+        }
+    }
+}
+
+fn dma_channel(id: u8) -> &'static pac::dma::CH {
+    // Safety: only the channel owned by the driver is accessed
+    unsafe { &(*pac::DMA::ptr()).ch[id as usize] }
+}
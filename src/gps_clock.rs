@@ -0,0 +1,141 @@
+//! GPS time for the `Timer` timestamps
+//!
+//! The PPS (pulse per second) output of the GPS receiver rises at the start of every UTC
+//! second, within tens of nanoseconds. Its edges are captured with the `Timer` (e.g. in the
+//! GPIO interrupt) and the time sentence which follows (RMC) names the second of the last edge.
+//!
+//! The clock measures the length of the GPS second in the Timer microseconds, so it corrects
+//! the frequency error of the crystal too, and converts any Timer value to UTC:
+//!
+//!   * Coarse - time from the sentences only, the reception delay makes it ~10-100ms late
+//!   * Locked - PPS with the time, a few microseconds
+//!   * Holdover - PPS lost, the time runs on with the last measured frequency
+//!
+//! The Timer itself keeps counting, only the conversion is disciplined.
+//!
+use crate::nmea::{unix_us, Date, Time};
+
+
+/// PPS interval further than this from 1s (1000 ppm) is a glitch or a lost pulse
+const MAX_ERROR_US: u64 = 1000;
+/// Time sentence comes in this time after its PPS edge
+const MAX_DELAY_US: u64 = 1_000_000;
+/// Without PPS for this long the clock is in holdover
+const HOLDOVER_US: u64 = 2_500_000;
+/// Weight of the new interval in the frequency estimate (1/8)
+const FILTER_SHIFT: u32 = 3;
+/// Length of the second in Timer nanoseconds for the crystal without error
+const NOMINAL_SECOND_NS: u64 = 1_000_000_000;
+
+
+/// Quality of the time
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum ClockState {
+    NoTime,
+    Coarse,
+    Locked,
+    Holdover,
+}
+
+
+/// Converts the Timer values to UTC
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct GpsClock {
+    /// Timer value of the last PPS edge
+    last_pps_us: Option<u64>,
+    /// UTC (microseconds since 1970) at the reference Timer value
+    reference_utc_us: Option<u64>,
+    reference_us: u64,
+    /// The reference is a PPS edge
+    locked: bool,
+    /// Measured GPS second in Timer nanoseconds
+    second_ns: u64,
+    /// PPS intervals in the frequency estimate
+    intervals: u32,
+}
+
+impl GpsClock {
+    pub const fn new() -> Self {
+        Self {
+            last_pps_us: None,
+            reference_utc_us: None,
+            reference_us: 0,
+            locked: false,
+            second_ns: NOMINAL_SECOND_NS,
+            intervals: 0,
+        }
+    }
+
+    /// Rising edge of the PPS at the Timer value
+    pub fn on_pps(&mut self, timer_us: u64) {
+        if let Some(last) = self.last_pps_us {
+            let interval = timer_us - last;
+            if interval.abs_diff(1_000_000) < MAX_ERROR_US {
+                let interval_ns = interval * 1000;
+                // The first interval replaces the nominal value
+                self.second_ns = if self.intervals == 0 {
+                    interval_ns
+                } else {
+                    (self.second_ns * ((1 << FILTER_SHIFT) - 1) + interval_ns) >> FILTER_SHIFT
+                };
+                self.intervals += 1;
+            }
+        }
+        self.last_pps_us = Some(timer_us);
+
+        // The edge is the start of the next second, whether its sentence comes or not
+        if let (true, Some(utc)) = (self.locked, self.reference_utc_us) {
+            let seconds = ((timer_us - self.reference_us) * 1000 + self.second_ns / 2) / self.second_ns;
+            self.reference_utc_us = Some(utc + seconds * 1_000_000);
+            self.reference_us = timer_us;
+        }
+    }
+
+    /// Date and time from the sentence (RMC) received at the Timer value
+    pub fn on_time(&mut self, date: &Date, time: &Time, received_us: u64) {
+        let utc = unix_us(date, time);
+        match self.last_pps_us {
+            // The sentence names the second of the last edge
+            Some(pps) if time.millis == 0 && received_us >= pps && received_us - pps < MAX_DELAY_US => {
+                self.reference_utc_us = Some(utc);
+                self.reference_us = pps;
+                self.locked = true;
+            }
+            // Holdover is still better than the sentence time
+            _ if self.locked => {}
+            _ => {
+                self.reference_utc_us = Some(utc);
+                self.reference_us = received_us;
+                self.locked = false;
+            }
+        }
+    }
+
+    /// UTC in microseconds since 1970 of the Timer value
+    pub fn utc_us(&self, timer_us: u64) -> Option<u64> {
+        let reference = self.reference_utc_us?;
+        let elapsed_us = timer_us as i64 - self.reference_us as i64;
+        let corrected = elapsed_us as i128 * NOMINAL_SECOND_NS as i128 / self.second_ns as i128;
+        Some((reference as i128 + corrected) as u64)
+    }
+
+    pub fn state(&self, now_us: u64) -> ClockState {
+        match (self.reference_utc_us, self.locked, self.last_pps_us) {
+            (None, _, _) => ClockState::NoTime,
+            (Some(_), false, _) => ClockState::Coarse,
+            (Some(_), true, Some(pps)) if now_us - pps < HOLDOVER_US => ClockState::Locked,
+            (Some(_), true, _) => ClockState::Holdover,
+        }
+    }
+
+    /// Frequency error of the Timer in parts per billion, positive when it runs fast
+    pub fn frequency_error_ppb(&self) -> i32 {
+        self.second_ns as i32 - NOMINAL_SECOND_NS as i32
+    }
+}
+
+impl Default for GpsClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
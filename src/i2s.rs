@@ -0,0 +1,246 @@
+//! Serial audio formats on PIO
+//!
+//! Family of PIO programs for sending PCM data to codecs and DACs (see `input` for receiving):
+//!   * Philips I2S
+//!   * Left-justified and right-justified
+//!   * TDM / DSP mode A with 1..=32 slots (4 and 8 are the usual ones)
+//!
+//! All programs share the same conventions, so they can be driven by the same DMA pipeline:
+//!   * every slot is 32 bits long and is sent MSB first
+//!   * one bit takes 5 PIO cycles (2 with BCLK low, 3 with BCLK high)
+//!   * 2 side-set pins: BCLK on the base pin and LRCLK (frame sync for TDM) on the next one
+//!   * TX FIFO is fed with one 32 bit word per slot (autopull)
+//!
+pub mod input;
+pub mod mclk;
+pub mod stream;
+
+use pio::{Instruction, InstructionOperands, MovDestination, MovOperation, MovSource, SetDestination};
+use rp_pico::hal::pio::{
+    Buffers, PIOBuilder, PIOExt, PinDir, ShiftDirection, StateMachine, StateMachineIndex, Stopped, Tx,
+    UninitStateMachine,
+};
+
+use crate::pio_manager::{PioError, PioManager};
+
+
+/// Number of bits in a single slot
+pub const SLOT_BITS: u32 = 32;
+/// Number of PIO cycles required for sending a single bit
+pub const CYCLES_PER_BIT: u32 = 5;
+
+
+/// Frame format
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Philips I2S. LRCLK is low for the left channel and changes one BCLK before the MSB.
+    I2s,
+    /// LRCLK is high for the left channel and changes together with the MSB.
+    LeftJustified,
+    /// Framing of the left-justified format, but the sample is aligned to the end of the slot.
+    RightJustified,
+    /// TDM (DSP mode A). One BCLK wide frame sync pulse just before the MSB of the first slot.
+    Tdm { slots: u8 },
+}
+
+impl Format {
+    /// Number of slots (channels) in a single frame
+    pub const fn slots(&self) -> usize {
+        match self {
+            Format::Tdm { slots } => *slots as usize,
+            _ => 2,
+        }
+    }
+}
+
+
+/// Configuration common to all formats
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct I2sConfig {
+    pub format: Format,
+    pub sample_rate: u32,
+    pub sample_bits: u8,
+}
+
+impl I2sConfig {
+    /// Create new configuration
+    ///   * format - frame format
+    ///   * sample_rate - Number of frames/s
+    ///   * sample_bits - Number of significant bits in the sample (up to 32)
+    pub const fn new(format: Format, sample_rate: u32, sample_bits: u8) -> Self {
+        assert!(sample_bits > 0 && sample_bits as u32 <= SLOT_BITS);
+        if let Format::Tdm { slots } = format {
+            assert!(slots > 0 && slots <= 32);
+        }
+        Self { format, sample_rate, sample_bits }
+    }
+
+    /// Bit clock frequency
+    pub const fn bclk_hz(&self) -> u32 {
+        self.sample_rate * self.format.slots() as u32 * SLOT_BITS
+    }
+
+    /// Frequency required by the PIO state machine
+    pub const fn pio_clock_hz(&self) -> u32 {
+        self.bclk_hz() * CYCLES_PER_BIT
+    }
+
+    /// PIO clock divisor as int + (frac/256), rounded to the nearest value.
+    /// None when the PIO clock would have to be faster than the system clock (or too slow).
+    pub const fn clock_divisor(&self, sys_clock_hz: u32) -> Option<(u16, u8)> {
+        let pio_clock_hz = self.pio_clock_hz() as u64;
+        let div = (sys_clock_hz as u64 * 256 + pio_clock_hz / 2) / pio_clock_hz;
+        // Integer part 0 means 65536 for the hardware
+        if div < 256 || div > 0xff_ffff {
+            return None;
+        }
+        Some(((div >> 8) as u16, (div & 0xff) as u8))
+    }
+
+    /// Put the sample at the right position inside of the 32 bit slot
+    pub const fn pack(&self, sample: i32) -> u32 {
+        match self.format {
+            Format::RightJustified => sample as u32,
+            _ => (sample as u32) << (SLOT_BITS - self.sample_bits as u32),
+        }
+    }
+
+    /// Name of the program in `PioManager`
+    pub const fn program_name(&self) -> &'static str {
+        match self.format {
+            Format::I2s => "i2s",
+            Format::LeftJustified | Format::RightJustified => "left_justified",
+            Format::Tdm { .. } => "tdm",
+        }
+    }
+
+    /// PIO program implementing this format
+    pub fn program(&self) -> pio::Program<32> {
+        match self.format {
+            Format::I2s => i2s_program(),
+            Format::LeftJustified | Format::RightJustified => left_justified_program(),
+            Format::Tdm { .. } => tdm_program(),
+        }
+    }
+
+    /// Install the program and configure the state machine.
+    ///
+    /// The state machine is returned stopped, so it can be started together with other ones.
+    ///   * data_pin - serial data output
+    ///   * clock_pin_base - BCLK. LRCLK (or frame sync) has to be the next pin.
+    ///   * divisor - PIO clock divisor, from `clock_divisor()` or `MclkPlan`
+    #[allow(clippy::type_complexity)]
+    pub fn init<P: PIOExt, SM: StateMachineIndex>(
+        &self,
+        pio: &mut PioManager<P>,
+        sm: UninitStateMachine<(P, SM)>,
+        data_pin: u8,
+        clock_pin_base: u8,
+        divisor: (u16, u8),
+    ) -> Result<(StateMachine<(P, SM), Stopped>, Tx<(P, SM)>), PioError> {
+        let installed = pio.install(self.program_name(), &self.program())?;
+        let (int, frac) = divisor;
+        let (mut sm, _rx, tx) = PIOBuilder::from_program(installed)
+            .out_pins(data_pin, 1)
+            .side_set_pin_base(clock_pin_base)
+            .out_shift_direction(ShiftDirection::Left) // MSB first
+            .autopull(true)
+            .pull_threshold(SLOT_BITS as u8)
+            .buffers(Buffers::OnlyTx)
+            .clock_divisor_fixed_point(int, frac)
+            .build(sm);
+
+        sm.set_pindirs([
+            (data_pin, PinDir::Output),
+            (clock_pin_base, PinDir::Output),
+            (clock_pin_base + 1, PinDir::Output),
+        ]);
+
+        if let Format::Tdm { slots } = self.format {
+            // The TDM program keeps the number of slots in ISR, since it is not used for anything else.
+            sm.exec_instruction(Instruction {
+                operands: InstructionOperands::SET { destination: SetDestination::X, data: slots - 1 },
+                delay: 0,
+                side_set: Some(0),
+            });
+            sm.exec_instruction(Instruction {
+                operands: InstructionOperands::MOV {
+                    destination: MovDestination::ISR,
+                    op: MovOperation::None,
+                    source: MovSource::X,
+                },
+                delay: 0,
+                side_set: Some(0),
+            });
+        }
+
+        Ok((sm, tx))
+    }
+}
+
+
+/// Philips I2S
+pub fn i2s_program() -> pio::Program<32> {
+    pio_proc::pio_asm!("
+        .side_set 2
+                    ;                  /----LRCLK
+                    ;                  |/---BCLK
+        .wrap_target
+            set y, 30 [2]       side 0b01
+        loopLch:
+            out pins, 1 [1]     side 0b00; MSB -> LSB
+            jmp y-- loopLch [2] side 0b01
+            out pins, 1 [1]     side 0b10; LSB
+            set y, 30 [2]       side 0b11
+        loopRch:
+            out pins, 1 [1]     side 0b10; MSB -> LSB
+            jmp y-- loopRch [2] side 0b11
+            out pins, 1 [1]     side 0b00; LSB
+        .wrap
+    ").program
+}
+
+/// Left-justified. Also used for right-justified, which differs only in the sample alignment.
+pub fn left_justified_program() -> pio::Program<32> {
+    pio_proc::pio_asm!("
+        .side_set 2
+                    ;                  /----LRCLK
+                    ;                  |/---BCLK
+        .wrap_target
+            out pins, 1 [1]     side 0b10; MSB
+            set y, 30 [2]       side 0b11
+        loopLch:
+            out pins, 1 [1]     side 0b10; MSB-1 -> LSB
+            jmp y-- loopLch [2] side 0b11
+            out pins, 1 [1]     side 0b00; MSB
+            set y, 30 [2]       side 0b01
+        loopRch:
+            out pins, 1 [1]     side 0b00; MSB-1 -> LSB
+            jmp y-- loopRch [2] side 0b01
+        .wrap
+    ").program
+}
+
+/// TDM (DSP mode A). Number of slots - 1 has to be preloaded into X and ISR.
+pub fn tdm_program() -> pio::Program<32> {
+    pio_proc::pio_asm!("
+        .side_set 2
+                    ;                  /----FSYNC
+                    ;                  |/---BCLK
+        .wrap_target
+        slot:
+            out pins, 1         side 0b00; MSB
+            set y, 29           side 0b00
+            nop [2]             side 0b01
+        loopBits:
+            out pins, 1 [1]     side 0b00; MSB-1 -> LSB+1
+            jmp y-- loopBits [2] side 0b01
+            jmp x-- notLast     side 0b00
+            out pins, 1         side 0b10; LSB of the last slot together with frame sync
+            mov x, isr [2]      side 0b11
+        .wrap
+        notLast:
+            out pins, 1         side 0b00; LSB
+            jmp slot [2]        side 0b01
+    ").program
+}
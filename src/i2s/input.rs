@@ -0,0 +1,204 @@
+//! I2S input capture
+//!
+//! Receives Philips I2S from MEMS microphones like INMP441 or SPH0645. The RP2040 is the master,
+//! so BCLK/LRCLK are generated exactly like in the output programs (5 PIO cycles per bit,
+//! 32 bit slots). Data is sampled one PIO cycle after the rising edge of BCLK.
+//!
+//! In full duplex mode a single state machine both sends and receives, so the output and
+//! the input share BCLK/LRCLK and the sample positions in the DMA buffers match.
+//!
+use pio::{Instruction, InstructionOperands, SetDestination};
+use rp_pico::hal::pio::{
+    Buffers, PIOBuilder, PIOExt, PinDir, Rx, ShiftDirection, StateMachine, StateMachineIndex, Stopped, Tx,
+    UninitStateMachine,
+};
+
+use super::{Format, I2sConfig, SLOT_BITS};
+use crate::pio_manager::{PioError, PioManager};
+
+
+/// Channel of a stereo frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Slot {
+    Left = 0,
+    Right = 1,
+}
+
+/// What the state machine does with the data pins
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    /// Only receive
+    Receive,
+    /// Send and receive at the same time
+    FullDuplex,
+}
+
+
+/// I2S receiver
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct I2sInput {
+    pub config: I2sConfig,
+    pub mode: Mode,
+}
+
+impl I2sInput {
+    /// Create new receiver
+    ///   * config - only `Format::I2s` is supported. `sample_bits` is the resolution
+    ///     of the microphone (24 for INMP441, 18 for SPH0645)
+    ///   * mode - receive only or full duplex
+    pub const fn new(config: I2sConfig, mode: Mode) -> Self {
+        assert!(matches!(config.format, Format::I2s));
+        Self { config, mode }
+    }
+
+    /// Name of the program in `PioManager`
+    pub const fn program_name(&self) -> &'static str {
+        match self.mode {
+            Mode::Receive => "i2s_input",
+            Mode::FullDuplex => "i2s_duplex",
+        }
+    }
+
+    /// PIO program for the selected mode
+    pub fn program(&self) -> pio::Program<32> {
+        match self.mode {
+            Mode::Receive => input_program(),
+            Mode::FullDuplex => duplex_program(),
+        }
+    }
+
+    /// Install the program and configure the state machine.
+    ///
+    /// The state machine is returned stopped. Words in RX FIFO alternate between
+    /// left and right channel, starting with left.
+    ///   * data_in_pin - serial data input
+    ///   * data_out_pin - serial data output, required in full duplex mode
+    ///   * clock_pin_base - BCLK. LRCLK has to be the next pin.
+    ///   * divisor - PIO clock divisor, from `I2sConfig::clock_divisor()` or `MclkPlan`
+    #[allow(clippy::type_complexity)]
+    pub fn init<P: PIOExt, SM: StateMachineIndex>(
+        &self,
+        pio: &mut PioManager<P>,
+        sm: UninitStateMachine<(P, SM)>,
+        data_in_pin: u8,
+        data_out_pin: Option<u8>,
+        clock_pin_base: u8,
+        divisor: (u16, u8),
+    ) -> Result<(StateMachine<(P, SM), Stopped>, Rx<(P, SM)>, Tx<(P, SM)>), PioError> {
+        let installed = pio.install(self.program_name(), &self.program())?;
+        let (int, frac) = divisor;
+        let builder = PIOBuilder::from_program(installed)
+            .in_pin_base(data_in_pin)
+            .side_set_pin_base(clock_pin_base)
+            .in_shift_direction(ShiftDirection::Left) // MSB first
+            .autopush(true)
+            .push_threshold(SLOT_BITS as u8)
+            .clock_divisor_fixed_point(int, frac);
+        let builder = match self.mode {
+            Mode::Receive => builder.buffers(Buffers::OnlyRx),
+            Mode::FullDuplex => builder
+                .out_pins(data_out_pin.expect("Full duplex requires data output pin"), 1)
+                .out_shift_direction(ShiftDirection::Left)
+                .autopull(true)
+                .pull_threshold(SLOT_BITS as u8),
+        };
+        let (mut sm, rx, tx) = builder.build(sm);
+
+        sm.set_pindirs([
+            (data_in_pin, PinDir::Input),
+            (clock_pin_base, PinDir::Output),
+            (clock_pin_base + 1, PinDir::Output),
+        ]);
+        if let (Mode::FullDuplex, Some(pin)) = (self.mode, data_out_pin) {
+            sm.set_pindirs([(pin, PinDir::Output)]);
+        }
+
+        // Bit counter for the first left channel, later it is set by the program itself
+        sm.exec_instruction(Instruction {
+            operands: InstructionOperands::SET { destination: SetDestination::Y, data: 30 },
+            delay: 0,
+            side_set: Some(0b01),
+        });
+
+        Ok((sm, rx, tx))
+    }
+
+    /// Convert received word into sample. Microphones send the sample MSB aligned,
+    /// so this drops the unused bits and extends the sign.
+    pub const fn sample(&self, word: u32) -> i32 {
+        sign_extend(word, self.config.sample_bits)
+    }
+
+    /// Extract single channel from the stereo buffer
+    ///
+    /// Returns number of samples written to `out`.
+    pub fn mono(&self, frames: &[u32], slot: Slot, out: &mut [i32]) -> usize {
+        let mut count = 0;
+        for (o, &word) in out.iter_mut().zip(frames.iter().skip(slot as usize).step_by(2)) {
+            *o = self.sample(word);
+            count += 1;
+        }
+        count
+    }
+}
+
+
+/// Sign extend MSB aligned sample with the given number of bits
+pub const fn sign_extend(word: u32, bits: u8) -> i32 {
+    (word as i32) >> (SLOT_BITS - bits as u32)
+}
+
+
+/// Receive only. Y has to be preloaded with 30.
+pub fn input_program() -> pio::Program<32> {
+    pio_proc::pio_asm!("
+        .side_set 2
+                    ;                  /----LRCLK
+                    ;                  |/---BCLK
+        .wrap_target
+        loopLch:
+            nop [1]             side 0b00
+            nop                 side 0b01
+            in pins, 1          side 0b01; MSB -> LSB+1
+            jmp y-- loopLch     side 0b01
+            nop [1]             side 0b10
+            set y, 30           side 0b11
+            in pins, 1 [1]      side 0b11; LSB
+        loopRch:
+            nop [1]             side 0b10
+            nop                 side 0b11
+            in pins, 1          side 0b11; MSB -> LSB+1
+            jmp y-- loopRch     side 0b11
+            nop [1]             side 0b00
+            set y, 30           side 0b01
+            in pins, 1 [1]      side 0b01; LSB
+        .wrap
+    ").program
+}
+
+/// Send and receive. Y has to be preloaded with 30.
+pub fn duplex_program() -> pio::Program<32> {
+    pio_proc::pio_asm!("
+        .side_set 2
+                    ;                  /----LRCLK
+                    ;                  |/---BCLK
+        .wrap_target
+        loopLch:
+            out pins, 1 [1]     side 0b00
+            nop                 side 0b01
+            in pins, 1          side 0b01; MSB -> LSB+1
+            jmp y-- loopLch     side 0b01
+            out pins, 1 [1]     side 0b10
+            set y, 30           side 0b11
+            in pins, 1 [1]      side 0b11; LSB
+        loopRch:
+            out pins, 1 [1]     side 0b10
+            nop                 side 0b11
+            in pins, 1          side 0b11; MSB -> LSB+1
+            jmp y-- loopRch     side 0b11
+            out pins, 1 [1]     side 0b00
+            set y, 30           side 0b01
+            in pins, 1 [1]      side 0b01; LSB
+        .wrap
+    ").program
+}
@@ -0,0 +1,163 @@
+//! Master clock (MCLK) for codecs
+//!
+//! MCLK is generated either by the GPOUT0 clock (GPIO21) or by a second PIO state machine.
+//! Both BCLK and MCLK are fractional divisions of the system clock. The divisors are chosen so
+//! that the number of system clock cycles per frame is exactly the same for both of them,
+//! which keeps MCLK locked to BCLK/LRCLK at the cost of a small sample rate error.
+//!
+#[allow(unused_imports)]
+use num_traits::float::Float;
+use rp_pico::hal::{
+    clocks::{Clock, ClockError, GpioOutput0Clock, StoppableClock, SystemClock},
+    fugit::RateExtU32,
+    pac,
+    pio::{PIOBuilder, PIOExt, PinDir, StateMachine, StateMachineIndex, Stopped, UninitStateMachine},
+};
+
+use super::{I2sConfig, CYCLES_PER_BIT, SLOT_BITS};
+use crate::pio_manager::{PioError, PioManager};
+
+
+/// Number of cycles of the MCLK state machine per MCLK period
+const PIO_CYCLES_PER_MCLK: u32 = 2;
+/// Largest PIO divisor (int + frac/256) as 1/256 fraction
+const MAX_PIO_DIVISOR: u32 = 65_536 * 256;
+/// Largest GPOUT divisor (24 bit int + frac/256) as 1/256 fraction
+const MAX_GPOUT_DIVISOR: u32 = u32::MAX;
+
+
+/// Where MCLK is generated
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum MclkSource {
+    /// GPOUT0 clock on GPIO21
+    Gpout0,
+    /// Additional PIO state machine
+    Pio,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum MclkError {
+    /// There are no divisors which can lock MCLK to BCLK at this sample rate
+    OutOfRange,
+}
+
+
+/// Divisors and resulting clock quality
+#[derive(Clone, Copy, Debug, defmt::Format)]
+pub struct MclkPlan {
+    pub source: MclkSource,
+    /// Divisor of the I2S state machine, int + (frac/256)
+    pub i2s_divisor: (u16, u8),
+    /// Divisor of the MCLK generator as 1/256 fraction
+    pub mclk_divisor: u32,
+    /// Real sample rate
+    pub sample_rate: f32,
+    /// Real MCLK frequency
+    pub mclk_hz: f32,
+    /// Sample rate error in ppm
+    pub error_ppm: f32,
+    /// Peak to peak jitter of BCLK edges caused by the fractional divisor
+    pub bclk_jitter_ns: f32,
+    /// Peak to peak jitter of MCLK edges caused by the fractional divisor
+    pub mclk_jitter_ns: f32,
+}
+
+impl MclkPlan {
+    /// Find divisors for I2S and MCLK
+    ///   * config - I2S configuration
+    ///   * mclk_fs - MCLK as multiple of the sample rate (usually 256)
+    ///   * source - MCLK generator
+    ///   * sys_clock_hz - System clock
+    pub fn new(config: &I2sConfig, mclk_fs: u32, source: MclkSource, sys_clock_hz: u32) -> Result<Self, MclkError> {
+        let (cycles_per_mclk, max_divisor) = match source {
+            MclkSource::Gpout0 => (1, MAX_GPOUT_DIVISOR),
+            MclkSource::Pio => (PIO_CYCLES_PER_MCLK, MAX_PIO_DIVISOR),
+        };
+        // Generator cycles per frame
+        let i2s_cycles = config.format.slots() as u32 * SLOT_BITS * CYCLES_PER_BIT;
+        let mclk_cycles = mclk_fs * cycles_per_mclk;
+        // Lock: i2s_divisor * i2s_cycles == mclk_divisor * mclk_cycles
+        let g = gcd(i2s_cycles, mclk_cycles);
+        let step = (mclk_cycles / g) as u64;
+        let mclk_step = (i2s_cycles / g) as u64;
+
+        let ideal = (sys_clock_hz as u64 * 256) as f64 / (config.sample_rate as f64 * i2s_cycles as f64);
+        let n = (ideal / step as f64).round() as u64;
+        let candidates = [n.saturating_sub(1), n, n + 1];
+        let n = candidates
+            .iter()
+            .copied()
+            .filter(|&n| {
+                let i2s_div = n * step;
+                let mclk_div = n * mclk_step;
+                (256..MAX_PIO_DIVISOR as u64).contains(&i2s_div) && (256..=max_divisor as u64).contains(&mclk_div)
+            })
+            .min_by(|&a, &b| {
+                let error = |n: u64| ((n * step) as f64 - ideal).abs();
+                error(a).total_cmp(&error(b))
+            })
+            .ok_or(MclkError::OutOfRange)?;
+
+        let i2s_div = (n * step) as u32;
+        let mclk_div = (n * mclk_step) as u32;
+        let sys = sys_clock_hz as f32;
+        let sample_rate = sys * 256.0 / (i2s_div as f32 * i2s_cycles as f32);
+        let jitter = |div: u32| if div.is_multiple_of(256) { 0.0 } else { 1e9 / sys };
+
+        Ok(Self {
+            source,
+            i2s_divisor: ((i2s_div >> 8) as u16, (i2s_div & 0xff) as u8),
+            mclk_divisor: mclk_div,
+            sample_rate,
+            mclk_hz: sys * 256.0 / (mclk_div as f32 * cycles_per_mclk as f32),
+            error_ppm: (sample_rate / config.sample_rate as f32 - 1.0) * 1e6,
+            bclk_jitter_ns: jitter(i2s_div),
+            mclk_jitter_ns: jitter(mclk_div),
+        })
+    }
+
+    /// Install the MCLK program and configure the state machine.
+    ///
+    /// Start it together with the I2S state machine, e.g. `i2s_sm.with(mclk_sm).sync().start()`,
+    /// so both clock dividers are in phase.
+    pub fn init_pio<P: PIOExt, SM: StateMachineIndex>(
+        &self,
+        pio: &mut PioManager<P>,
+        sm: UninitStateMachine<(P, SM)>,
+        mclk_pin: u8,
+    ) -> Result<StateMachine<(P, SM), Stopped>, PioError> {
+        let installed = pio.install("mclk", &mclk_program())?;
+        let (mut sm, _rx, _tx) = PIOBuilder::from_program(installed)
+            .set_pins(mclk_pin, 1)
+            .clock_divisor_fixed_point((self.mclk_divisor >> 8) as u16, (self.mclk_divisor & 0xff) as u8)
+            .build(sm);
+        sm.set_pindirs([(mclk_pin, PinDir::Output)]);
+        Ok(sm)
+    }
+
+    /// Start MCLK on GPOUT0. GPIO21 has to be switched to `FunctionClock`.
+    pub fn init_gpout0(&self, gpout: &mut GpioOutput0Clock, sys: &SystemClock) -> Result<(), ClockError> {
+        gpout.configure_clock(sys, (self.mclk_hz as u32).Hz())?;
+        // The HAL computes the divisor from the frequency. Overwrite it with the exact value.
+        // Safety: GPOUT0 divisor is only accessed by GpioOutput0Clock, which we borrow mutably.
+        unsafe { (*pac::CLOCKS::ptr()).clk_gpout0_div.write(|w| w.bits(self.mclk_divisor)) };
+        gpout.enable();
+        Ok(())
+    }
+}
+
+
+/// Square wave with 2 cycles per period
+pub fn mclk_program() -> pio::Program<32> {
+    pio_proc::pio_asm!("
+        .wrap_target
+            set pins, 1
+            set pins, 0
+        .wrap
+    ").program
+}
+
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
@@ -0,0 +1,187 @@
+//! Interrupt driven audio stream
+//!
+//! Double buffered DMA transfer to the PIO TX FIFO, which refills the buffers from
+//! the DMA_IRQ_0 handler instead of polling `is_done()` in the main loop.
+//!
+//! Usage:
+//!   * keep the stream in a `static Mutex<RefCell<Option<I2sStream<..>>>>`
+//!   * call `on_interrupt()` from `DMA_IRQ_0`
+//!   * unmask `DMA_IRQ_0` in NVIC
+//!
+//! Every refill is checked against its deadline, which is the end of the other buffer.
+//! The margin is read directly from the transfer counter of the running DMA channel,
+//! so it is measured in words. Late refills are counted in `UnderrunStats`.
+//!
+use rp_pico::hal::{
+    dma::{
+        double_buffer::{Config, ReadNext, Transfer},
+        EndlessWriteTarget, SingleChannel, WriteTarget,
+    },
+    pac,
+};
+
+
+/// Source of the audio data
+pub trait Refill {
+    /// Fill the buffer which was just sent. Called from the interrupt handler,
+    /// so it has to be done before the other buffer is sent.
+    fn refill(&mut self, buffer: &mut [u32]);
+}
+
+/// Static DMA buffer
+pub type Buffer<const N: usize> = &'static mut [u32; N];
+
+/// What to do when the interrupt comes after the other buffer was already sent.
+///
+/// By then both buffers were sent and the output has stalled. The gap can't be avoided,
+/// the policy only selects what is played after it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum UnderrunPolicy {
+    /// Refill the buffer anyway, the output restarts when it is ready
+    Refill,
+    /// Restart at once with the old content of the buffer (already played once)
+    Repeat,
+    /// Restart at once with zeros
+    Silence,
+}
+
+/// Refill timing
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct UnderrunStats {
+    /// Number of refilled buffers
+    pub buffers: u32,
+    /// Buffers which were not ready before the other one was sent
+    pub underruns: u32,
+    /// Smallest number of words left in the other buffer after a refill
+    pub worst_margin: u32,
+}
+
+impl UnderrunStats {
+    /// Nothing refilled yet
+    ///   * buffer_len - words in a single buffer, the largest possible margin
+    pub const fn new(buffer_len: u32) -> Self {
+        Self { buffers: 0, underruns: 0, worst_margin: buffer_len }
+    }
+
+    /// Count the refilled buffer
+    ///   * margin - words left in the other buffer, 0 if it was already sent
+    pub fn record(&mut self, margin: u32) {
+        self.buffers += 1;
+        if margin == 0 {
+            self.underruns += 1;
+        }
+        self.worst_margin = self.worst_margin.min(margin);
+    }
+
+    /// Worst margin in microseconds
+    ///   * word_rate - words/s, which is sample rate * number of slots
+    pub const fn worst_margin_us(&self, word_rate: u32) -> u32 {
+        (self.worst_margin as u64 * 1_000_000 / word_rate as u64) as u32
+    }
+}
+
+
+/// Audio stream fed from the DMA interrupt
+pub struct I2sStream<CH1, CH2, TO, R, const N: usize>
+where
+    CH1: SingleChannel,
+    CH2: SingleChannel,
+    TO: WriteTarget<TransmittedWord = u32> + EndlessWriteTarget,
+{
+    transfer: Option<Transfer<CH1, CH2, Buffer<N>, TO, ReadNext<Buffer<N>>>>,
+    source: R,
+    // DMA channel ids and index of the one which is sending
+    channels: [u8; 2],
+    active: usize,
+    policy: UnderrunPolicy,
+    stats: UnderrunStats,
+}
+
+impl<CH1, CH2, TO, R, const N: usize> I2sStream<CH1, CH2, TO, R, N>
+where
+    CH1: SingleChannel,
+    CH2: SingleChannel,
+    TO: WriteTarget<TransmittedWord = u32> + EndlessWriteTarget,
+    R: Refill,
+{
+    /// Fill both buffers and start streaming
+    ///   * ch - DMA channels. Their DMA_IRQ_0 signal is enabled here.
+    ///   * buffers - two static buffers
+    ///   * to - PIO TX FIFO
+    ///   * source - audio data
+    pub fn start(mut ch: (CH1, CH2), buffers: (Buffer<N>, Buffer<N>), to: TO, mut source: R) -> Self {
+        let (buf1, buf2) = buffers;
+        source.refill(buf1);
+        source.refill(buf2);
+        ch.0.enable_irq0();
+        ch.1.enable_irq0();
+        let channels = [ch.0.id(), ch.1.id()];
+        let transfer = Config::new(ch, buf1, to).start().read_next(buf2);
+        Self {
+            transfer: Some(transfer),
+            source,
+            channels,
+            active: 0,
+            policy: UnderrunPolicy::Refill,
+            stats: UnderrunStats::new(N as u32),
+        }
+    }
+
+    /// Handle DMA_IRQ_0.
+    ///
+    /// Returns false if the interrupt was raised by another DMA channel.
+    pub fn on_interrupt(&mut self) -> bool {
+        let Some(mut transfer) = self.transfer.take() else {
+            return false;
+        };
+        if !transfer.check_irq0() {
+            self.transfer = Some(transfer);
+            return false;
+        }
+        // The active buffer is done and the other one is already being sent
+        let (buf, next_transfer) = transfer.wait();
+        self.active ^= 1;
+        let channel = self.channels[self.active];
+        if remaining(channel).is_some() {
+            self.source.refill(buf);
+        } else {
+            match self.policy {
+                UnderrunPolicy::Refill => self.source.refill(buf),
+                UnderrunPolicy::Repeat => (),
+                UnderrunPolicy::Silence => buf.fill(0),
+            }
+        }
+
+        // If the other buffer is already sent, read_next() restarts the stream with this one
+        self.stats.record(remaining(channel).unwrap_or(0));
+        self.transfer = Some(next_transfer.read_next(buf));
+        true
+    }
+
+    /// Select what is sent when the interrupt comes too late. Default is `UnderrunPolicy::Refill`.
+    pub fn set_underrun_policy(&mut self, policy: UnderrunPolicy) {
+        self.policy = policy;
+    }
+
+    /// Refill timing since the start
+    pub fn stats(&self) -> UnderrunStats {
+        self.stats
+    }
+
+    /// Access the data source, e.g. to change the parameters from the main loop
+    pub fn source_mut(&mut self) -> &mut R {
+        &mut self.source
+    }
+}
+
+
+/// Number of words the DMA channel still has to transfer. None if it is not running.
+fn remaining(channel: u8) -> Option<u32> {
+    // Safety: only reads the status registers of the channel owned by the transfer
+    let ch = unsafe { &(*pac::DMA::ptr()).ch[channel as usize] };
+    if ch.ch_ctrl_trig.read().busy().bit_is_set() {
+        Some(ch.ch_trans_count.read().bits())
+    } else {
+        None
+    }
+}
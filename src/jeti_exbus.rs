@@ -0,0 +1,456 @@
+//! Jeti EX Bus
+//!
+//! Half-duplex bus (125000 or 250000 baud, 8N1) where the receiver sends the channel values
+//! and asks the devices for the telemetry and JetiBox screens. Every packet is:
+//!
+//!   header, answer flag, length of the whole packet, packet ID, data ID, data length, data, CRC-16
+//!
+//! The header is 0x3E from the receiver and 0x3B from the devices. The CRC is CRC-16/KERMIT
+//! (reflected CCITT, initial value 0) sent LSB first. The device has to answer the request
+//! with the same packet ID within 4ms, and only when the answer flag is 0x01.
+//!
+//! The telemetry inside the EX Bus packet uses the Jeti EX format: the text frames describe
+//! the device and the sensors, the data frames carry the values.
+//!
+//! Nothing here touches the hardware, so the protocol can be tested on the host.
+//!
+/// Header of the packets from the receiver
+pub const MASTER: u8 = 0x3E;
+/// Header of the packets from the devices
+pub const SLAVE: u8 = 0x3B;
+/// Longest packet on the bus
+pub const MAX_PACKET: usize = 64;
+/// Channels in the packet
+pub const MAX_CHANNELS: usize = 24;
+/// Characters of the JetiBox screen, two lines of 16
+pub const JETIBOX_TEXT: usize = 32;
+/// Baud rates used by the receivers
+pub const BAUD_RATES: [u32; 2] = [125_000, 250_000];
+
+const ANSWER: u8 = 0x01;
+const CHANNELS: u8 = 0x31;
+const TELEMETRY: u8 = 0x3A;
+const JETIBOX: u8 = 0x3B;
+/// Header and CRC
+const MIN_PACKET: usize = 8;
+/// First byte of the EX frame
+const EX_HEADER: u8 = 0x9F;
+const EX_TEXT: u8 = 0x00;
+const EX_DATA: u8 = 0x40;
+/// Longest EX frame which fits into the packet
+const MAX_EX_FRAME: usize = 40;
+/// Without a valid packet for this long the other baud rate is tried
+const BAUD_SWITCH_US: u64 = 200_000;
+
+
+/// CRC-16/KERMIT
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &byte in data {
+        let mut x = byte ^ crc as u8;
+        x ^= x << 4;
+        crc = ((x as u16) << 8 | crc >> 8) ^ (x >> 4) as u16 ^ (x as u16) << 3;
+    }
+    crc
+}
+
+/// CRC-8 (polynomial 0x07) of the EX frames
+pub fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+
+/// Channel values in 1/8 us
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Channels {
+    pub count: usize,
+    pub values: [u16; MAX_CHANNELS],
+}
+
+impl Channels {
+    /// Servo pulse of the channel (0 is the first one)
+    pub fn get_us(&self, channel: usize) -> Option<u16> {
+        (channel < self.count).then(|| self.values[channel] / 8)
+    }
+}
+
+
+/// Keys of the JetiBox. The receiver sends them active low in the upper nibble.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Keys(pub u8);
+
+impl Keys {
+    pub const NONE: Keys = Keys(0xF0);
+
+    pub fn right(&self) -> bool {
+        self.0 & 0x10 == 0
+    }
+
+    pub fn up(&self) -> bool {
+        self.0 & 0x20 == 0
+    }
+
+    pub fn down(&self) -> bool {
+        self.0 & 0x40 == 0
+    }
+
+    pub fn left(&self) -> bool {
+        self.0 & 0x80 == 0
+    }
+}
+
+
+/// Decoded packet from the receiver
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Packet {
+    Channels(Channels),
+    /// The device can answer only when `answer` is set
+    Telemetry { id: u8, answer: bool },
+    Jetibox { id: u8, answer: bool, keys: Keys },
+}
+
+impl Packet {
+    /// Decode the packet with the valid CRC
+    fn decode(packet: &[u8]) -> Option<Packet> {
+        let answer = packet[1] == ANSWER;
+        let id = packet[3];
+        let data = &packet[6..packet.len() - 2];
+        if data.len() != packet[5] as usize {
+            return None;
+        }
+        match packet[4] {
+            CHANNELS if data.len().is_multiple_of(2) && data.len() <= 2 * MAX_CHANNELS => {
+                let mut channels = Channels { count: data.len() / 2, values: [0; MAX_CHANNELS] };
+                for (value, pair) in channels.values.iter_mut().zip(data.chunks_exact(2)) {
+                    *value = u16::from_le_bytes([pair[0], pair[1]]);
+                }
+                Some(Packet::Channels(channels))
+            }
+            TELEMETRY => Some(Packet::Telemetry { id, answer }),
+            JETIBOX => Some(Packet::Jetibox { id, answer, keys: Keys(data.first().copied().unwrap_or(Keys::NONE.0)) }),
+            _ => None,
+        }
+    }
+}
+
+
+/// Write the packet with the header and CRC, return its length
+pub fn encode(header: u8, answer: bool, id: u8, data_id: u8, data: &[u8], out: &mut [u8]) -> usize {
+    let len = data.len() + MIN_PACKET;
+    out[0] = header;
+    out[1] = if answer { ANSWER } else { 0x03 };
+    out[2] = len as u8;
+    out[3] = id;
+    out[4] = data_id;
+    out[5] = data.len() as u8;
+    out[6..len - 2].copy_from_slice(data);
+    let crc = crc16(&out[..len - 2]);
+    out[len - 2..len].copy_from_slice(&crc.to_le_bytes());
+    len
+}
+
+
+/// Statistics of the parser
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub struct ParserStats {
+    pub packets: u32,
+    pub crc_errors: u32,
+    /// Bytes skipped while looking for the start of the packet
+    pub skipped: u32,
+}
+
+
+/// Finds the packets of the receiver in the byte stream. Packets of the devices
+/// (including our own answers on the half-duplex line) are skipped.
+#[derive(Clone, Debug)]
+pub struct Parser {
+    buffer: [u8; MAX_PACKET],
+    len: usize,
+    stats: ParserStats,
+}
+
+impl Parser {
+    pub const fn new() -> Self {
+        Self { buffer: [0; MAX_PACKET], len: 0, stats: ParserStats { packets: 0, crc_errors: 0, skipped: 0 } }
+    }
+
+    /// Add the received byte and return the packet when it is complete
+    pub fn push(&mut self, byte: u8) -> Option<Packet> {
+        self.buffer[self.len] = byte;
+        self.len += 1;
+        while self.len > 0 {
+            if !matches!(self.buffer[0], MASTER | SLAVE) {
+                self.resync();
+                continue;
+            }
+            if self.len < 3 {
+                return None;
+            }
+            let expected = self.buffer[2] as usize;
+            if !(MIN_PACKET..=MAX_PACKET).contains(&expected) {
+                self.resync();
+                continue;
+            }
+            if self.len < expected {
+                return None;
+            }
+            let crc = u16::from_le_bytes([self.buffer[expected - 2], self.buffer[expected - 1]]);
+            if crc != crc16(&self.buffer[..expected - 2]) {
+                self.stats.crc_errors += 1;
+                self.resync();
+                continue;
+            }
+            let packet = match self.buffer[0] {
+                MASTER => Packet::decode(&self.buffer[..expected]),
+                _ => None,
+            };
+            // Bytes after the packet are kept, they can be the start of the next one
+            self.buffer.copy_within(expected..self.len, 0);
+            self.len -= expected;
+            if packet.is_some() {
+                self.stats.packets += 1;
+                return packet;
+            }
+        }
+        None
+    }
+
+    /// Forget the partial packet, e.g. after a gap on the line
+    pub fn reset(&mut self) {
+        self.len = 0;
+    }
+
+    pub fn stats(&self) -> ParserStats {
+        self.stats
+    }
+
+    /// Drop the first byte and look for the next header in the rest
+    fn resync(&mut self) {
+        let rest = self.buffer[1..self.len].iter().position(|&b| matches!(b, MASTER | SLAVE));
+        match rest {
+            Some(start) => {
+                self.stats.skipped += start as u32 + 1;
+                self.buffer.copy_within(start + 1..self.len, 0);
+                self.len -= start + 1;
+            }
+            None => {
+                self.stats.skipped += self.len as u32;
+                self.len = 0;
+            }
+        }
+    }
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+/// Telemetry value shown by the transmitter
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Sensor {
+    pub label: &'static str,
+    pub unit: &'static str,
+    /// Value 1234 with 2 decimals is shown as 12.34
+    pub decimals: u8,
+}
+
+
+/// Write the value as the smallest EX type it fits in, return the number of bytes
+///   * id - sensor ID, 1 - 15
+fn encode_value(id: u8, value: i32, decimals: u8, out: &mut [u8]) -> usize {
+    let magnitude = value.unsigned_abs();
+    let sign = (value < 0) as u32;
+    let decimals = (decimals & 0x03) as u32;
+    // Data type and the number of value bits
+    let (data_type, bits) = match magnitude {
+        0..=0x1F => (0, 5),
+        0x20..=0x1FFF => (1, 13),
+        0x2000..=0x1F_FFFF => (4, 21),
+        _ => (8, 29),
+    };
+    let bytes = (bits + 3) / 8;
+    let word = sign << (bits + 2) | decimals << bits | magnitude.min((1 << bits) - 1);
+    out[0] = id << 4 | data_type;
+    out[1..=bytes].copy_from_slice(&word.to_le_bytes()[..bytes]);
+    bytes + 1
+}
+
+/// Write the EX frame header, the content and the CRC, return the length
+fn encode_ex_frame(frame_type: u8, manufacturer_id: u16, device_id: u16, content: &[u8], out: &mut [u8]) -> usize {
+    let len = content.len() + 8;
+    out[0] = EX_HEADER;
+    out[1] = frame_type | (len - 2) as u8;
+    out[2..4].copy_from_slice(&manufacturer_id.to_le_bytes());
+    out[4..6].copy_from_slice(&device_id.to_le_bytes());
+    out[6] = 0;
+    out[7..len - 1].copy_from_slice(content);
+    out[len - 1] = crc8(&out[1..len - 1]);
+    len
+}
+
+
+/// Answers the telemetry and JetiBox requests of the receiver
+#[derive(Clone, Debug)]
+pub struct Responder<const N: usize> {
+    manufacturer_id: u16,
+    device_id: u16,
+    name: &'static str,
+    sensors: [Sensor; N],
+    values: [Option<i32>; N],
+    jetibox: [u8; JETIBOX_TEXT],
+    keys: Keys,
+    /// Telemetry answers sent, every other one is a text frame
+    answers: usize,
+    /// First sensor of the next data frame, the values which don't fit are sent in the next one
+    next_value: usize,
+}
+
+impl<const N: usize> Responder<N> {
+    /// Device with up to 15 sensors
+    ///   * manufacturer_id, device_id - identify the device in the transmitter, 0xA400 - 0xA41F are free to use
+    ///   * name - shown by the transmitter, up to 15 characters
+    pub fn new(manufacturer_id: u16, device_id: u16, name: &'static str, sensors: [Sensor; N]) -> Self {
+        // The identifier of the value has only 4 bits, 0 is the device itself
+        assert!(N <= 15);
+        Self {
+            manufacturer_id,
+            device_id,
+            name,
+            sensors,
+            values: [None; N],
+            jetibox: [b' '; JETIBOX_TEXT],
+            keys: Keys::NONE,
+            answers: 0,
+            next_value: 0,
+        }
+    }
+
+    /// Value of the sensor in the next telemetry frame, `None` to not send it
+    pub fn set_value(&mut self, sensor: usize, value: Option<i32>) {
+        self.values[sensor] = value;
+    }
+
+    /// Text of the JetiBox screen, longer lines are cut
+    pub fn set_jetibox(&mut self, line1: &str, line2: &str) {
+        self.jetibox = [b' '; JETIBOX_TEXT];
+        for (i, line) in [line1, line2].iter().enumerate() {
+            let len = line.len().min(JETIBOX_TEXT / 2);
+            self.jetibox[i * 16..i * 16 + len].copy_from_slice(&line.as_bytes()[..len]);
+        }
+    }
+
+    /// Keys pressed on the JetiBox in the last request
+    pub fn keys(&self) -> Keys {
+        self.keys
+    }
+
+    /// Handle the packet from the receiver. Returns the length of the answer, 0 if there is nothing to send.
+    pub fn handle(&mut self, packet: &Packet, out: &mut [u8]) -> usize {
+        match *packet {
+            Packet::Telemetry { id, answer: true } => {
+                let mut frame = [0; MAX_EX_FRAME];
+                let count = N + 1;
+                let len = if self.answers % 2 == 1 {
+                    self.encode_text((self.answers / 2) % count, &mut frame)
+                } else {
+                    self.encode_data(&mut frame)
+                };
+                self.answers += 1;
+                encode(SLAVE, true, id, TELEMETRY, &frame[..len], out)
+            }
+            Packet::Jetibox { id, answer, keys } => {
+                self.keys = keys;
+                if !answer {
+                    return 0;
+                }
+                encode(SLAVE, true, id, JETIBOX, &self.jetibox, out)
+            }
+            _ => 0,
+        }
+    }
+
+    /// Text frame with the device name (index 0) or the sensor label and unit
+    fn encode_text(&self, index: usize, out: &mut [u8]) -> usize {
+        let (label, unit) = match index {
+            0 => (self.name, ""),
+            _ => (self.sensors[index - 1].label, self.sensors[index - 1].unit),
+        };
+        let label = &label.as_bytes()[..label.len().min(15)];
+        let unit = &unit.as_bytes()[..unit.len().min(7)];
+        let mut content = [0; MAX_EX_FRAME];
+        content[0] = index as u8;
+        content[1] = (label.len() << 3 | unit.len()) as u8;
+        content[2..2 + label.len()].copy_from_slice(label);
+        content[2 + label.len()..2 + label.len() + unit.len()].copy_from_slice(unit);
+        let len = 2 + label.len() + unit.len();
+        encode_ex_frame(EX_TEXT, self.manufacturer_id, self.device_id, &content[..len], out)
+    }
+
+    /// Data frame with the values which fit into it, starting after the last sent one
+    fn encode_data(&mut self, out: &mut [u8]) -> usize {
+        let mut content = [0; MAX_EX_FRAME];
+        let mut len = 0;
+        let start = self.next_value;
+        for i in (start..N).chain(0..start) {
+            let Some(value) = self.values[i] else { continue };
+            if len + 5 > MAX_EX_FRAME - 8 {
+                self.next_value = i;
+                break;
+            }
+            len += encode_value(i as u8 + 1, value, self.sensors[i].decimals, &mut content[len..]);
+        }
+        encode_ex_frame(EX_DATA, self.manufacturer_id, self.device_id, &content[..len], out)
+    }
+}
+
+
+/// Finds the baud rate of the receiver by switching between 125000 and 250000
+/// until the valid packets come.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct BaudDetector {
+    index: usize,
+    last_packet_us: Option<u64>,
+    since_us: u64,
+}
+
+impl BaudDetector {
+    pub const fn new(time_us: u64) -> Self {
+        Self { index: 0, last_packet_us: None, since_us: time_us }
+    }
+
+    pub fn baud_rate(&self) -> u32 {
+        BAUD_RATES[self.index]
+    }
+
+    /// True when the valid packets come at the current baud rate
+    pub fn is_locked(&self) -> bool {
+        self.last_packet_us.is_some()
+    }
+
+    /// Call it for every valid packet
+    pub fn packet(&mut self, time_us: u64) {
+        self.last_packet_us = Some(time_us);
+    }
+
+    /// Returns the new baud rate when the UART has to be switched
+    pub fn poll(&mut self, time_us: u64) -> Option<u32> {
+        let last = self.last_packet_us.unwrap_or(self.since_us).max(self.since_us);
+        if time_us.saturating_sub(last) < BAUD_SWITCH_US {
+            return None;
+        }
+        self.index = (self.index + 1) % BAUD_RATES.len();
+        self.last_packet_us = None;
+        self.since_us = time_us;
+        Some(self.baud_rate())
+    }
+}
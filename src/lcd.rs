@@ -0,0 +1,132 @@
+//! HD44780 compatible 16x2 LCD display in 4 bit mode
+//!
+//! Based on:
+//! https://how2electronics.com/interfacing-16x2-lcd-display-with-raspberry-pi-pico/
+//! Datasheet
+//! https://www.sparkfun.com/datasheets/LCD/HD44780.pdf
+//!
+use cortex_m::delay::Delay;
+use embedded_hal::digital::v2::OutputPin;
+use rp_pico::hal::gpio::{DynPinId, FunctionSioOutput, Pin, PullNone};
+
+
+pub struct Lcd1602 {
+    rs: Pin<DynPinId, FunctionSioOutput, PullNone>,
+    e: Pin<DynPinId, FunctionSioOutput, PullNone>,
+    d4: Pin<DynPinId, FunctionSioOutput, PullNone>,
+    d5: Pin<DynPinId, FunctionSioOutput, PullNone>,
+    d6: Pin<DynPinId, FunctionSioOutput, PullNone>,
+    d7: Pin<DynPinId, FunctionSioOutput, PullNone>,
+    pub delay: Delay,
+}
+
+impl Lcd1602 {
+    pub fn init(
+        delay: Delay,
+        rs: Pin<DynPinId, FunctionSioOutput, PullNone>,
+        e: Pin<DynPinId, FunctionSioOutput, PullNone>,
+        d4: Pin<DynPinId, FunctionSioOutput, PullNone>,
+        d5: Pin<DynPinId, FunctionSioOutput, PullNone>,
+        d6: Pin<DynPinId, FunctionSioOutput, PullNone>,
+        d7: Pin<DynPinId, FunctionSioOutput, PullNone>,
+    ) -> Self {
+        Self {
+            rs,
+            e,
+            d4,
+            d5,
+            d6,
+            d7,
+            delay,
+        }
+    }
+
+    pub fn setup(&mut self) {
+        // Write instructions
+        self.rs.set_low().unwrap();
+        self.send_lcd4(0b0011); // 8 bit
+        self.send_lcd4(0b0011); // 8 bit
+        self.send_lcd4(0b0011); // 8 bit
+        self.send_lcd4(0b0010); // 4 bit
+        self.send_lcd8(0b00101000); // 4 bit,2 lines?,5*8 bots
+        self.send_lcd8(0b00001100); // lcd on, blink off, cursor off.
+        self.send_lcd8(0b00000110); // increment cursor, no display shift
+        self.send_lcd8(0b00000001); // clear screen
+
+        self.delay.delay_ms(2); // clear screen needs a long delay
+    }
+
+    pub fn print(&mut self, text: &str) {
+        // Write data
+        self.rs.set_high().unwrap();
+        for x in text.bytes() {
+            self.send_lcd8(x);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.rs.set_low().unwrap();
+        self.send_lcd8(0b00000001);
+        self.delay.delay_ms(2);
+    }
+
+    /// Move the cursor
+    ///   * col - column 0-15
+    ///   * row - line 0 or 1
+    pub fn set_cursor(&mut self, col: u8, row: u8) {
+        self.rs.set_low().unwrap();
+        self.send_lcd8(0b10000000 | (row & 1) << 6 | (col & 0x3f));
+    }
+
+    fn pulse_e(&mut self) {
+        self.e.set_high().unwrap();
+        self.delay.delay_us(40);
+        self.e.set_low().unwrap();
+        self.delay.delay_us(40);
+    }
+
+    fn send_lcd4(&mut self, bin_num: u8) {
+        self.d4
+            .set_state(((bin_num & 0b00000001) > 0).into())
+            .unwrap();
+        self.d5
+            .set_state(((bin_num & 0b00000010) > 0).into())
+            .unwrap();
+        self.d6
+            .set_state(((bin_num & 0b00000100) > 0).into())
+            .unwrap();
+        self.d7
+            .set_state(((bin_num & 0b00001000) > 0).into())
+            .unwrap();
+        self.pulse_e();
+    }
+
+    fn send_lcd8(&mut self, bin_num: u8) {
+        self.d4
+            .set_state(((bin_num & 0b00010000) > 0).into())
+            .unwrap();
+        self.d5
+            .set_state(((bin_num & 0b00100000) > 0).into())
+            .unwrap();
+        self.d6
+            .set_state(((bin_num & 0b01000000) > 0).into())
+            .unwrap();
+        self.d7
+            .set_state(((bin_num & 0b10000000) > 0).into())
+            .unwrap();
+        self.pulse_e();
+        self.d4
+            .set_state(((bin_num & 0b00000001) > 0).into())
+            .unwrap();
+        self.d5
+            .set_state(((bin_num & 0b00000010) > 0).into())
+            .unwrap();
+        self.d6
+            .set_state(((bin_num & 0b00000100) > 0).into())
+            .unwrap();
+        self.d7
+            .set_state(((bin_num & 0b00001000) > 0).into())
+            .unwrap();
+        self.pulse_e();
+    }
+}
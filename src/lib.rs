@@ -1,3 +1,25 @@
 #![no_std]
 
-pub mod oscillator;
\ No newline at end of file
+pub mod autobaud;
+pub mod bist;
+pub mod buffered_uart;
+pub mod dma_uart;
+pub mod gps_clock;
+pub mod i2s;
+pub mod jeti_exbus;
+pub mod lcd;
+pub mod modbus;
+pub mod morse;
+pub mod nmea;
+pub mod oscillator;
+pub mod pio_manager;
+pub mod pio_sim;
+pub mod pio_uart;
+pub mod quadrature;
+pub mod rc;
+pub mod shell;
+pub mod square_wave;
+pub mod srxl2;
+pub mod telemetry;
+pub mod vcd;
+pub mod ws2812;
\ No newline at end of file
@@ -0,0 +1,467 @@
+//! Modbus RTU slave
+//!
+//! The registers of the device are declared in a table. Each entry covers a block of
+//! addresses and has the functions which read and write the value of the given offset:
+//!
+//! ```ignore
+//! static MAP: [Register<Board>; 3] = [
+//!     Register::coils(0, 1, |board, _| board.led, |board, _, on| board.led = on),
+//!     Register::input_registers(0, 4, |board, i| board.read_adc(i)),
+//!     Register::holding_registers(0, 1, |board, _| board.duty, set_duty),
+//! ];
+//! ```
+//!
+//! `Slave` answers the requests with the function codes 1-6, 15 and 16 from the map.
+//! `RtuReceiver` finds the frames in the byte stream: the frame ends after the silence of
+//! 3.5 characters (t3.5). `ModbusRtu` puts them together on the UART with the Timer
+//! and switches the RS-485 transceiver to transmit (DE/RE pin high) for the response.
+//!
+use embedded_hal::digital::v2::OutputPin;
+use rp_pico::hal::{
+    uart::{Enabled, UartDevice, UartPeripheral, ValidUartPinout},
+    Timer,
+};
+
+
+/// Longest RTU frame (address, PDU, CRC)
+pub const MAX_ADU: usize = 256;
+/// Address of the request for all slaves, they don't respond
+pub const BROADCAST: u8 = 0;
+
+pub const READ_COILS: u8 = 1;
+pub const READ_DISCRETE_INPUTS: u8 = 2;
+pub const READ_HOLDING_REGISTERS: u8 = 3;
+pub const READ_INPUT_REGISTERS: u8 = 4;
+pub const WRITE_SINGLE_COIL: u8 = 5;
+pub const WRITE_SINGLE_REGISTER: u8 = 6;
+pub const WRITE_MULTIPLE_COILS: u8 = 15;
+pub const WRITE_MULTIPLE_REGISTERS: u8 = 16;
+
+
+/// CRC-16/MODBUS, sent low byte first
+pub fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0xFFFF, |crc, &byte| {
+        let mut crc = crc ^ byte as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xA001 } else { crc >> 1 };
+        }
+        crc
+    })
+}
+
+
+/// Silence which ends the frame (3.5 characters of 11 bits), fixed 1750us above 19200 baud
+pub const fn frame_timeout_us(baud_rate: u32) -> u64 {
+    if baud_rate > 19_200 {
+        1750
+    } else {
+        (35 * 11 * 1_000_000u64).div_ceil(10 * baud_rate as u64)
+    }
+}
+
+
+/// Error response of the slave
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+#[repr(u8)]
+pub enum Exception {
+    IllegalFunction = 1,
+    IllegalDataAddress = 2,
+    IllegalDataValue = 3,
+    DeviceFailure = 4,
+}
+
+
+/// Access functions of the register block, they get the offset in the block
+pub enum Access<C> {
+    Coils { read: fn(&mut C, u16) -> bool, write: fn(&mut C, u16, bool) },
+    DiscreteInputs { read: fn(&mut C, u16) -> bool },
+    InputRegisters { read: fn(&mut C, u16) -> u16 },
+    /// The write can refuse the value, e.g. with `Exception::IllegalDataValue`
+    HoldingRegisters { read: fn(&mut C, u16) -> u16, write: fn(&mut C, u16, u16) -> Result<(), Exception> },
+}
+
+
+/// Block of the registers in the map
+pub struct Register<C> {
+    pub address: u16,
+    pub count: u16,
+    pub access: Access<C>,
+}
+
+impl<C> Register<C> {
+    pub const fn coils(address: u16, count: u16, read: fn(&mut C, u16) -> bool, write: fn(&mut C, u16, bool)) -> Self {
+        Self { address, count, access: Access::Coils { read, write } }
+    }
+
+    pub const fn discrete_inputs(address: u16, count: u16, read: fn(&mut C, u16) -> bool) -> Self {
+        Self { address, count, access: Access::DiscreteInputs { read } }
+    }
+
+    pub const fn input_registers(address: u16, count: u16, read: fn(&mut C, u16) -> u16) -> Self {
+        Self { address, count, access: Access::InputRegisters { read } }
+    }
+
+    pub const fn holding_registers(
+        address: u16,
+        count: u16,
+        read: fn(&mut C, u16) -> u16,
+        write: fn(&mut C, u16, u16) -> Result<(), Exception>,
+    ) -> Self {
+        Self { address, count, access: Access::HoldingRegisters { read, write } }
+    }
+
+    /// Offset of the address in the block
+    fn offset(&self, address: u16) -> Option<u16> {
+        address.checked_sub(self.address).filter(|&offset| offset < self.count)
+    }
+}
+
+
+/// Register tables of the requests
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Table {
+    Coils,
+    DiscreteInputs,
+    InputRegisters,
+    HoldingRegisters,
+}
+
+
+/// Counters of the slave
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub struct SlaveStats {
+    /// Requests for this slave, with the broadcasts
+    pub requests: u32,
+    pub exceptions: u32,
+    pub crc_errors: u32,
+    /// Frames too short for the request
+    pub invalid: u32,
+}
+
+
+/// Answers the requests from the register map
+pub struct Slave<C: 'static> {
+    address: u8,
+    map: &'static [Register<C>],
+    stats: SlaveStats,
+}
+
+impl<C> Slave<C> {
+    /// * address - 1 to 247
+    pub const fn new(address: u8, map: &'static [Register<C>]) -> Self {
+        Self { address, map, stats: SlaveStats { requests: 0, exceptions: 0, crc_errors: 0, invalid: 0 } }
+    }
+
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
+    pub fn stats(&self) -> SlaveStats {
+        self.stats
+    }
+
+    /// Execute the request frame (with the CRC), returns the length of the response.
+    /// 0 when there is nothing to send: the frame is broken, for the other slave or broadcast.
+    pub fn handle(&mut self, frame: &[u8], context: &mut C, response: &mut [u8; MAX_ADU]) -> usize {
+        if frame.len() < 4 {
+            self.stats.invalid += 1;
+            return 0;
+        }
+        let (request, crc) = frame.split_at(frame.len() - 2);
+        if crc16(request) != u16::from_le_bytes([crc[0], crc[1]]) {
+            self.stats.crc_errors += 1;
+            return 0;
+        }
+        let address = request[0];
+        if address != self.address && address != BROADCAST {
+            return 0;
+        }
+        self.stats.requests += 1;
+
+        let function = request[1];
+        let len = match self.execute(function, &request[2..], context, &mut response[1..MAX_ADU - 2]) {
+            Ok(len) => len,
+            Err(exception) => {
+                self.stats.exceptions += 1;
+                response[1] = function | 0x80;
+                response[2] = exception as u8;
+                2
+            }
+        };
+        if address == BROADCAST {
+            return 0;
+        }
+        response[0] = address;
+        let crc = crc16(&response[..len + 1]);
+        response[len + 1..len + 3].copy_from_slice(&crc.to_le_bytes());
+        len + 3
+    }
+
+    /// Write the response PDU, returns its length
+    fn execute(&mut self, function: u8, data: &[u8], context: &mut C, out: &mut [u8]) -> Result<usize, Exception> {
+        out[0] = function;
+        match function {
+            READ_COILS | READ_DISCRETE_INPUTS => {
+                let table = if function == READ_COILS { Table::Coils } else { Table::DiscreteInputs };
+                let (start, count) = (u16_at(data, 0)?, u16_at(data, 2)?);
+                check_count(count, 2000)?;
+                let bytes = count.div_ceil(8) as usize;
+                out[1] = bytes as u8;
+                out[2..2 + bytes].fill(0);
+                for i in 0..count {
+                    if self.read_bit(table, address(start, i)?, context)? {
+                        out[2 + i as usize / 8] |= 1 << (i % 8);
+                    }
+                }
+                Ok(2 + bytes)
+            }
+            READ_HOLDING_REGISTERS | READ_INPUT_REGISTERS => {
+                let table = if function == READ_HOLDING_REGISTERS { Table::HoldingRegisters } else { Table::InputRegisters };
+                let (start, count) = (u16_at(data, 0)?, u16_at(data, 2)?);
+                check_count(count, 125)?;
+                out[1] = (count * 2) as u8;
+                for i in 0..count {
+                    let value = self.read_register(table, address(start, i)?, context)?;
+                    let pos = 2 + 2 * i as usize;
+                    out[pos..pos + 2].copy_from_slice(&value.to_be_bytes());
+                }
+                Ok(2 + 2 * count as usize)
+            }
+            WRITE_SINGLE_COIL => {
+                let on = match u16_at(data, 2)? {
+                    0xFF00 => true,
+                    0x0000 => false,
+                    _ => return Err(Exception::IllegalDataValue),
+                };
+                self.write_coil(u16_at(data, 0)?, on, context)?;
+                out[1..5].copy_from_slice(&data[..4]);
+                Ok(5)
+            }
+            WRITE_SINGLE_REGISTER => {
+                self.write_register(u16_at(data, 0)?, u16_at(data, 2)?, context)?;
+                out[1..5].copy_from_slice(&data[..4]);
+                Ok(5)
+            }
+            WRITE_MULTIPLE_COILS => {
+                let (start, count) = (u16_at(data, 0)?, u16_at(data, 2)?);
+                check_count(count, 1968)?;
+                let values = values(data, count.div_ceil(8) as usize)?;
+                // Nothing is written when any address is missing
+                for i in 0..count {
+                    self.find(Table::Coils, address(start, i)?)?;
+                }
+                for i in 0..count {
+                    let on = values[i as usize / 8] & (1 << (i % 8)) != 0;
+                    self.write_coil(start + i, on, context)?;
+                }
+                out[1..5].copy_from_slice(&data[..4]);
+                Ok(5)
+            }
+            WRITE_MULTIPLE_REGISTERS => {
+                let (start, count) = (u16_at(data, 0)?, u16_at(data, 2)?);
+                check_count(count, 123)?;
+                let values = values(data, 2 * count as usize)?;
+                for i in 0..count {
+                    self.find(Table::HoldingRegisters, address(start, i)?)?;
+                }
+                for (i, value) in values.chunks_exact(2).enumerate() {
+                    self.write_register(start + i as u16, u16::from_be_bytes([value[0], value[1]]), context)?;
+                }
+                out[1..5].copy_from_slice(&data[..4]);
+                Ok(5)
+            }
+            _ => Err(Exception::IllegalFunction),
+        }
+    }
+
+    /// Block of the address with the offset in it
+    fn find(&self, table: Table, address: u16) -> Result<(&Register<C>, u16), Exception> {
+        self.map
+            .iter()
+            .filter(|register| register_table(&register.access) == table)
+            .find_map(|register| register.offset(address).map(|offset| (register, offset)))
+            .ok_or(Exception::IllegalDataAddress)
+    }
+
+    fn read_bit(&self, table: Table, address: u16, context: &mut C) -> Result<bool, Exception> {
+        match self.find(table, address)? {
+            (Register { access: Access::Coils { read, .. } | Access::DiscreteInputs { read }, .. }, offset) => Ok(read(context, offset)),
+            _ => Err(Exception::IllegalDataAddress),
+        }
+    }
+
+    fn read_register(&self, table: Table, address: u16, context: &mut C) -> Result<u16, Exception> {
+        match self.find(table, address)? {
+            (Register { access: Access::InputRegisters { read } | Access::HoldingRegisters { read, .. }, .. }, offset) => Ok(read(context, offset)),
+            _ => Err(Exception::IllegalDataAddress),
+        }
+    }
+
+    fn write_coil(&self, address: u16, on: bool, context: &mut C) -> Result<(), Exception> {
+        match self.find(Table::Coils, address)? {
+            (Register { access: Access::Coils { write, .. }, .. }, offset) => {
+                write(context, offset, on);
+                Ok(())
+            }
+            _ => Err(Exception::IllegalDataAddress),
+        }
+    }
+
+    fn write_register(&self, address: u16, value: u16, context: &mut C) -> Result<(), Exception> {
+        match self.find(Table::HoldingRegisters, address)? {
+            (Register { access: Access::HoldingRegisters { write, .. }, .. }, offset) => write(context, offset, value),
+            _ => Err(Exception::IllegalDataAddress),
+        }
+    }
+}
+
+
+fn register_table<C>(access: &Access<C>) -> Table {
+    match access {
+        Access::Coils { .. } => Table::Coils,
+        Access::DiscreteInputs { .. } => Table::DiscreteInputs,
+        Access::InputRegisters { .. } => Table::InputRegisters,
+        Access::HoldingRegisters { .. } => Table::HoldingRegisters,
+    }
+}
+
+fn u16_at(data: &[u8], pos: usize) -> Result<u16, Exception> {
+    data.get(pos..pos + 2).map(|b| u16::from_be_bytes([b[0], b[1]])).ok_or(Exception::IllegalDataValue)
+}
+
+fn check_count(count: u16, max: u16) -> Result<(), Exception> {
+    if (1..=max).contains(&count) {
+        Ok(())
+    } else {
+        Err(Exception::IllegalDataValue)
+    }
+}
+
+/// Address of the i-th item, the range can't go over 0xFFFF
+fn address(start: u16, i: u16) -> Result<u16, Exception> {
+    start.checked_add(i).ok_or(Exception::IllegalDataAddress)
+}
+
+/// Values of the write multiple request after the byte count
+fn values(data: &[u8], bytes: usize) -> Result<&[u8], Exception> {
+    match data.get(4) {
+        Some(&count) if count as usize == bytes && data.len() == 5 + bytes => Ok(&data[5..]),
+        _ => Err(Exception::IllegalDataValue),
+    }
+}
+
+
+/// Collects the bytes into the frames by the t3.5 silence
+#[derive(Clone, Debug)]
+pub struct RtuReceiver {
+    buffer: [u8; MAX_ADU],
+    len: usize,
+    last_us: u64,
+    timeout_us: u64,
+    /// Receive error or overflow in the frame
+    broken: bool,
+    errors: u32,
+}
+
+impl RtuReceiver {
+    pub const fn new(baud_rate: u32) -> Self {
+        Self { buffer: [0; MAX_ADU], len: 0, last_us: 0, timeout_us: frame_timeout_us(baud_rate), broken: false, errors: 0 }
+    }
+
+    /// Add the received byte with the time from `Timer::get_counter()`
+    pub fn push(&mut self, byte: u8, time_us: u64) {
+        // Frame which wasn't taken by `poll` in time
+        if self.len > 0 && time_us - self.last_us >= self.timeout_us {
+            self.len = 0;
+            self.broken = false;
+        }
+        if self.len < MAX_ADU {
+            self.buffer[self.len] = byte;
+            self.len += 1;
+        } else {
+            self.broken = true;
+        }
+        self.last_us = time_us;
+    }
+
+    /// Parity, framing or overrun error, the frame will be dropped
+    pub fn error(&mut self, time_us: u64) {
+        self.broken = true;
+        self.errors += 1;
+        self.last_us = time_us;
+    }
+
+    /// Returns the frame after the t3.5 silence
+    pub fn poll(&mut self, now_us: u64) -> Option<&[u8]> {
+        if (self.len == 0 && !self.broken) || now_us - self.last_us < self.timeout_us {
+            return None;
+        }
+        let len = core::mem::replace(&mut self.len, 0);
+        if core::mem::replace(&mut self.broken, false) {
+            return None;
+        }
+        Some(&self.buffer[..len])
+    }
+
+    /// Frames dropped because of the receive errors
+    pub fn errors(&self) -> u32 {
+        self.errors
+    }
+}
+
+
+/// Modbus RTU on the UART with the RS-485 transceiver.
+/// Call `poll` from the main loop, at least once per t3.5 and before the 32 byte FIFO fills up.
+pub struct ModbusRtu<D: UartDevice, P: ValidUartPinout<D>, DE: OutputPin> {
+    uart: UartPeripheral<Enabled, D, P>,
+    timer: Timer,
+    /// DE and /RE of the transceiver, high while sending
+    de: DE,
+    receiver: RtuReceiver,
+}
+
+impl<D: UartDevice, P: ValidUartPinout<D>, DE: OutputPin> ModbusRtu<D, P, DE> {
+    /// * baud_rate - the one of the UART config, for the frame timing
+    pub fn new(uart: UartPeripheral<Enabled, D, P>, timer: Timer, mut de: DE, baud_rate: u32) -> Self {
+        let _ = de.set_low();
+        Self { uart, timer, de, receiver: RtuReceiver::new(baud_rate) }
+    }
+
+    /// Receive the bytes and answer the complete request
+    pub fn poll<C>(&mut self, slave: &mut Slave<C>, context: &mut C) {
+        let mut buffer = [0u8; 32];
+        loop {
+            let now = self.timer.get_counter().ticks();
+            match self.uart.read_raw(&mut buffer) {
+                Ok(count) => buffer[..count].iter().for_each(|&byte| self.receiver.push(byte, now)),
+                Err(nb::Error::Other(_)) => self.receiver.error(now),
+                Err(nb::Error::WouldBlock) => break,
+            }
+        }
+
+        let now = self.timer.get_counter().ticks();
+        let mut response = [0u8; MAX_ADU];
+        let len = match self.receiver.poll(now) {
+            Some(frame) => slave.handle(frame, context, &mut response),
+            None => return,
+        };
+        if len > 0 {
+            let _ = self.de.set_high();
+            self.uart.write_full_blocking(&response[..len]);
+            // Keep driving the bus until the last stop bit is out
+            while self.uart.uart_is_busy() {}
+            let _ = self.de.set_low();
+            // Echo, when the receiver isn't disabled by /RE
+            while self.uart.read_raw(&mut buffer).is_ok() {}
+        }
+    }
+
+    /// Frames dropped because of the receive errors
+    pub fn receive_errors(&self) -> u32 {
+        self.receiver.errors()
+    }
+
+    pub fn free(self) -> (UartPeripheral<Enabled, D, P>, Timer, DE) {
+        (self.uart, self.timer, self.de)
+    }
+}
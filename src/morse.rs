@@ -0,0 +1,350 @@
+//! Morse code encoder for the PIO DMA stream and decoder of the key timing
+//!
+//! Text is turned into a bitstream where every bit is one state of the output
+//! (1 - key down, 0 - key up). Words are packed MSB first, which is what `morse_program()`
+//! reads with autopull.
+//!
+//! `Decoder` does the opposite for a real key or a photodiode: it gets the length of every
+//! key state and follows the speed of the sender.
+//!
+//! Standard timing in units: dot 1, dash 3, gap inside a character 1,
+//! between characters 3 and between words 7. The unit is 1.2s / WPM (PARIS).
+//!
+/// Number of PIO cycles used by `morse_program()` for a single bit
+pub const PIO_CYCLES_PER_BIT: u32 = 16;
+
+const DOT: u32 = 1;
+const DASH: u32 = 3;
+const ELEMENT_GAP: u32 = 1;
+const CHAR_GAP: u32 = 3;
+const WORD_GAP: u32 = 7;
+/// Longest code handled by the decoder
+const MAX_ELEMENTS: usize = 8;
+/// Decoded character without Morse code
+pub const UNKNOWN: u8 = b'*';
+
+
+/// PIO clock divisor and number of bits for a single unit
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Speed {
+    pub divisor: (u16, u8),
+    pub bits_per_unit: u32,
+}
+
+impl Speed {
+    /// Find the divisor for the given speed. Slow speeds don't fit into the divisor,
+    /// so every unit is sent as several bits.
+    ///   * sys_clock_hz - system clock
+    ///   * wpm - words per minute, at least 1
+    pub fn new(sys_clock_hz: u32, wpm: u32) -> Self {
+        assert!(wpm > 0, "Morse speed has to be at least 1 wpm");
+        let unit_cycles = sys_clock_hz as u64 * 6 / (5 * wpm as u64);
+        let max_bit_cycles = 65_536 * PIO_CYCLES_PER_BIT as u64;
+        let bits_per_unit = unit_cycles.div_ceil(max_bit_cycles).max(1);
+        let cycles_per_bit = PIO_CYCLES_PER_BIT as u64 * bits_per_unit;
+        let div = ((unit_cycles * 256 + cycles_per_bit / 2) / cycles_per_bit).max(256);
+        // 65536 is written as 0
+        Self { divisor: ((div >> 8) as u16, (div & 0xff) as u8), bits_per_unit: bits_per_unit as u32 }
+    }
+
+    /// Real speed after rounding of the divisor
+    pub fn wpm(&self, sys_clock_hz: u32) -> f32 {
+        let int = if self.divisor.0 == 0 { 65_536 } else { self.divisor.0 as u32 };
+        let div = int as f32 + self.divisor.1 as f32 / 256.0;
+        let unit_s = div * (PIO_CYCLES_PER_BIT * self.bits_per_unit) as f32 / sys_clock_hz as f32;
+        1.2 / unit_s
+    }
+}
+
+
+/// Dots and dashes of the character. None for characters without Morse code.
+pub fn symbol(c: u8) -> Option<&'static [u8]> {
+    let code: &[u8] = match c.to_ascii_uppercase() {
+        b'A' => b".-",
+        b'B' => b"-...",
+        b'C' => b"-.-.",
+        b'D' => b"-..",
+        b'E' => b".",
+        b'F' => b"..-.",
+        b'G' => b"--.",
+        b'H' => b"....",
+        b'I' => b"..",
+        b'J' => b".---",
+        b'K' => b"-.-",
+        b'L' => b".-..",
+        b'M' => b"--",
+        b'N' => b"-.",
+        b'O' => b"---",
+        b'P' => b".--.",
+        b'Q' => b"--.-",
+        b'R' => b".-.",
+        b'S' => b"...",
+        b'T' => b"-",
+        b'U' => b"..-",
+        b'V' => b"...-",
+        b'W' => b".--",
+        b'X' => b"-..-",
+        b'Y' => b"-.--",
+        b'Z' => b"--..",
+        b'0' => b"-----",
+        b'1' => b".----",
+        b'2' => b"..---",
+        b'3' => b"...--",
+        b'4' => b"....-",
+        b'5' => b".....",
+        b'6' => b"-....",
+        b'7' => b"--...",
+        b'8' => b"---..",
+        b'9' => b"----.",
+        b'.' => b".-.-.-",
+        b',' => b"--..--",
+        b'?' => b"..--..",
+        b'\'' => b".----.",
+        b'!' => b"-.-.--",
+        b'/' => b"-..-.",
+        b'(' => b"-.--.",
+        b')' => b"-.--.-",
+        b'&' => b".-...",
+        b':' => b"---...",
+        b';' => b"-.-.-.",
+        b'=' => b"-...-",
+        b'+' => b".-.-.",
+        b'-' => b"-....-",
+        b'"' => b".-..-.",
+        b'@' => b".--.-.",
+        _ => return None,
+    };
+    Some(code)
+}
+
+
+/// Character with the given dots and dashes
+pub fn character(code: &[u8]) -> Option<u8> {
+    (b'!'..=b'Z').find(|&c| symbol(c) == Some(code))
+}
+
+
+/// Streaming encoder. It keeps only the position, so the text can be passed in every call.
+#[derive(Clone, Debug)]
+pub struct Encoder {
+    bits_per_unit: u32,
+    pos: usize,
+    element: usize,
+    gap: u32,
+    level: bool,
+    remaining: u32,
+}
+
+impl Encoder {
+    pub const fn new(bits_per_unit: u32) -> Self {
+        Self { bits_per_unit, pos: 0, element: 0, gap: 0, level: false, remaining: 0 }
+    }
+
+    /// Start from the beginning of the text
+    pub fn reset(&mut self) {
+        *self = Self::new(self.bits_per_unit);
+    }
+
+    /// Next bit. The text ends with the word gap, then None is returned.
+    /// Characters without Morse code are skipped.
+    pub fn next_bit(&mut self, text: &[u8]) -> Option<bool> {
+        if self.remaining == 0 {
+            let (level, units) = self.next_element(text)?;
+            self.level = level;
+            self.remaining = units * self.bits_per_unit;
+        }
+        self.remaining -= 1;
+        Some(self.level)
+    }
+
+    /// Fill the buffer with the next bits, MSB first
+    ///   * repeat - start the text again after the end, otherwise the rest is filled with 0
+    ///
+    /// Returns number of bits taken from the text.
+    pub fn fill(&mut self, text: &[u8], buffer: &mut [u32], repeat: bool) -> usize {
+        let mut count = 0;
+        for word in buffer.iter_mut() {
+            *word = 0;
+            for bit in (0..32).rev() {
+                let mut next = self.next_bit(text);
+                if next.is_none() && repeat {
+                    self.reset();
+                    next = self.next_bit(text);
+                }
+                match next {
+                    Some(level) => {
+                        *word |= (level as u32) << bit;
+                        count += 1;
+                    }
+                    None => return count,
+                }
+            }
+        }
+        count
+    }
+
+    /// Next key state and its length in units
+    fn next_element(&mut self, text: &[u8]) -> Option<(bool, u32)> {
+        if self.gap > 0 {
+            let gap = self.gap;
+            self.gap = 0;
+            return Some((false, gap));
+        }
+        while text.get(self.pos).is_some_and(|&c| symbol(c).is_none()) {
+            self.pos += 1;
+        }
+        let code = symbol(*text.get(self.pos)?)?;
+        let mark = code[self.element];
+        self.element += 1;
+        if self.element < code.len() {
+            self.gap = ELEMENT_GAP;
+        } else {
+            // The gap depends on what follows
+            self.element = 0;
+            self.pos += 1;
+            let rest = &text[self.pos..];
+            let next = rest.iter().position(|&c| symbol(c).is_some());
+            let word_end = match next {
+                Some(next) => rest[..next].contains(&b' '),
+                None => true,
+            };
+            self.gap = if word_end { WORD_GAP } else { CHAR_GAP };
+        }
+        Some((true, if mark == b'-' { DASH } else { DOT }))
+    }
+}
+
+
+/// Encode the whole text with 1 bit per unit
+///
+/// Returns number of bits, or None if the buffer is too small.
+pub fn encode(text: &[u8], buffer: &mut [u32]) -> Option<usize> {
+    let mut encoder = Encoder::new(1);
+    let count = encoder.fill(text, buffer, false);
+    if encoder.next_bit(text).is_some() {
+        return None;
+    }
+    Some(count)
+}
+
+
+/// Up to two characters decoded from a single key state
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Decoded {
+    chars: [u8; 2],
+    len: u8,
+    pos: u8,
+}
+
+impl Decoded {
+    fn push(&mut self, c: u8) {
+        self.chars[self.len as usize] = c;
+        self.len += 1;
+    }
+}
+
+impl Iterator for Decoded {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        if self.pos == self.len {
+            return None;
+        }
+        self.pos += 1;
+        Some(self.chars[self.pos as usize - 1])
+    }
+}
+
+
+/// Adaptive decoder of the key timing
+///
+/// Marks shorter than 2 units are dots, longer are dashes. Gaps are split at 2 and 5 units.
+/// Every dot, dash and gap inside a character updates the unit estimate, so the decoder
+/// follows the speed of the sender.
+#[derive(Clone, Debug)]
+pub struct Decoder {
+    unit_us: u32,
+    code: [u8; MAX_ELEMENTS],
+    len: usize,
+    overflow: bool,
+    in_word: bool,
+}
+
+impl Decoder {
+    /// Start with the expected speed. It needs to be within about 2x of the real one.
+    ///   * unit_us - dot length, 1_200_000 / WPM
+    pub const fn new(unit_us: u32) -> Self {
+        Self { unit_us, code: [0; MAX_ELEMENTS], len: 0, overflow: false, in_word: false }
+    }
+
+    /// Estimated dot length
+    pub fn unit_us(&self) -> u32 {
+        self.unit_us
+    }
+
+    /// Estimated speed
+    pub fn wpm(&self) -> f32 {
+        1_200_000.0 / self.unit_us as f32
+    }
+
+    /// Key state has just ended
+    ///   * key_down - state which ended (true for mark, false for gap)
+    ///   * duration_us - how long it lasted
+    ///
+    /// Characters are decoded at the end of the gap which follows them.
+    pub fn push(&mut self, key_down: bool, duration_us: u32) -> Decoded {
+        if key_down {
+            let dash = duration_us >= 2 * self.unit_us;
+            self.adapt(if dash { duration_us / DASH } else { duration_us });
+            if self.len < MAX_ELEMENTS {
+                self.code[self.len] = if dash { b'-' } else { b'.' };
+                self.len += 1;
+            } else {
+                self.overflow = true;
+            }
+            Decoded::default()
+        } else {
+            if duration_us < 2 * self.unit_us {
+                self.adapt(duration_us);
+            }
+            self.idle(duration_us)
+        }
+    }
+
+    /// Key is up for the given time and no edge came yet. Call it periodically
+    /// to get the last character without waiting for the next one.
+    pub fn idle(&mut self, duration_us: u32) -> Decoded {
+        let mut decoded = Decoded::default();
+        if duration_us >= 2 * self.unit_us && (self.len > 0 || self.overflow) {
+            let c = if self.overflow { None } else { character(&self.code[..self.len]) };
+            decoded.push(c.unwrap_or(UNKNOWN));
+            self.len = 0;
+            self.overflow = false;
+            self.in_word = true;
+        }
+        if duration_us >= 5 * self.unit_us && self.in_word {
+            decoded.push(b' ');
+            self.in_word = false;
+        }
+        decoded
+    }
+
+    /// Move the estimate by 1/4 towards the measured unit
+    fn adapt(&mut self, unit_us: u32) {
+        self.unit_us = ((3 * self.unit_us as u64 + unit_us as u64) / 4).max(1) as u32;
+    }
+}
+
+
+/// Sends bits from TX FIFO to the out pin and returns them through RX FIFO.
+/// Use with autopull and autopush, 16 cycles per bit.
+pub fn morse_program() -> pio::Program<32> {
+    pio_proc::pio_asm!(
+        ".wrap_target",
+        "    out x, 1",
+        "    mov pins, x",
+        "    in x, 1 [13]",
+        ".wrap"
+    )
+    .program
+}
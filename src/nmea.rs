@@ -0,0 +1,496 @@
+//! NMEA 0183 parser for the GPS receivers
+//!
+//! Bytes from the UART are pushed one by one, the parser returns the sentence after its
+//! line end. GGA, RMC, GSA, GSV and VTG are decoded, from any talker (GP, GN, GL, GA, ...).
+//! Sentences without the checksum or with the wrong one are dropped, the other types and
+//! the proprietary sentences are counted as unsupported.
+//!
+//! Empty fields (e.g. before the fix) are `None`. The coordinates are in 1e-7 degrees,
+//! negative to the south and west.
+//!
+use core::str;
+
+
+/// Longest sentence from '$' to the checksum, the standard allows 82 characters with CR LF
+const MAX_SENTENCE: usize = 80;
+/// Most fields in the sentence, GSA has 18 with the NMEA 4.1 system id
+const MAX_FIELDS: usize = 24;
+
+
+/// UTC time of day
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, defmt::Format)]
+pub struct Time {
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub millis: u16,
+}
+
+impl Time {
+    /// Milliseconds since midnight
+    pub fn millis_of_day(&self) -> u32 {
+        ((self.hour as u32 * 60 + self.minute as u32) * 60 + self.second as u32) * 1000 + self.millis as u32
+    }
+}
+
+
+/// UTC date
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, defmt::Format)]
+pub struct Date {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+}
+
+impl Date {
+    /// Days since 1970-01-01
+    pub fn unix_days(&self) -> i32 {
+        // Days from civil, H. Hinnant
+        let year = self.year as i32 - (self.month <= 2) as i32;
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let month = self.month as i32;
+        let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + self.day as i32 - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        era * 146_097 + day_of_era - 719_468
+    }
+
+    /// Date of the day since 1970-01-01
+    pub fn from_unix_days(days: i32) -> Self {
+        let days = days + 719_468;
+        let era = days.div_euclid(146_097);
+        let day_of_era = days - era * 146_097;
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let mp = (5 * day_of_year + 2) / 153;
+        let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u8;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+        let year = (year_of_era + era * 400 + (month <= 2) as i32) as u16;
+        Self { year, month, day }
+    }
+}
+
+
+/// Microseconds since 1970-01-01 UTC
+pub fn unix_us(date: &Date, time: &Time) -> u64 {
+    date.unix_days() as u64 * 86_400_000_000 + time.millis_of_day() as u64 * 1000
+}
+
+/// Date and time of the microseconds since 1970-01-01 UTC
+pub fn date_time(unix_us: u64) -> (Date, Time) {
+    let millis = unix_us / 1000;
+    let date = Date::from_unix_days((millis / 86_400_000) as i32);
+    let millis = (millis % 86_400_000) as u32;
+    let time = Time {
+        hour: (millis / 3_600_000) as u8,
+        minute: (millis / 60_000 % 60) as u8,
+        second: (millis / 1000 % 60) as u8,
+        millis: (millis % 1000) as u16,
+    };
+    (date, time)
+}
+
+
+/// Position in 1e-7 degrees
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Position {
+    pub latitude: i32,
+    pub longitude: i32,
+}
+
+
+/// Fix data
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub struct Gga {
+    pub talker: [u8; 2],
+    pub time: Option<Time>,
+    pub position: Option<Position>,
+    /// 0 - no fix, 1 - GPS, 2 - DGPS, 4 - RTK fixed, 5 - RTK float, 6 - estimated
+    pub quality: u8,
+    pub satellites: Option<u8>,
+    pub hdop: Option<f32>,
+    /// Above the mean sea level in meters
+    pub altitude: Option<f32>,
+    /// Geoid above the WGS84 ellipsoid in meters
+    pub geoid_separation: Option<f32>,
+}
+
+
+/// Recommended minimum data
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub struct Rmc {
+    pub talker: [u8; 2],
+    pub time: Option<Time>,
+    /// Status A, the data is valid
+    pub valid: bool,
+    pub position: Option<Position>,
+    pub speed_knots: Option<f32>,
+    /// Course over ground in degrees
+    pub course: Option<f32>,
+    pub date: Option<Date>,
+}
+
+
+/// Satellites used in the fix
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub struct Gsa {
+    pub talker: [u8; 2],
+    /// Automatic 2D/3D selection
+    pub automatic: bool,
+    /// 1 - no fix, 2 - 2D, 3 - 3D
+    pub fix: u8,
+    /// PRNs of the satellites, 0 for the empty fields
+    pub satellites: [u8; 12],
+    pub pdop: Option<f32>,
+    pub hdop: Option<f32>,
+    pub vdop: Option<f32>,
+}
+
+impl Gsa {
+    /// Number of the satellites used in the fix
+    pub fn satellite_count(&self) -> usize {
+        self.satellites.iter().filter(|&&prn| prn != 0).count()
+    }
+}
+
+
+/// Satellite in view
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub struct Satellite {
+    pub prn: u8,
+    /// Degrees
+    pub elevation: Option<u8>,
+    /// Degrees
+    pub azimuth: Option<u16>,
+    /// dB-Hz, None when not tracked
+    pub snr: Option<u8>,
+}
+
+
+/// Satellites in view, up to 4 per sentence
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Gsv {
+    pub talker: [u8; 2],
+    /// Number of GSV sentences in this group
+    pub messages: u8,
+    /// Number of this sentence, from 1
+    pub number: u8,
+    pub in_view: u8,
+    pub satellites: [Satellite; 4],
+    pub count: usize,
+}
+
+impl Gsv {
+    pub fn satellites(&self) -> &[Satellite] {
+        &self.satellites[..self.count]
+    }
+}
+
+
+/// Course and speed over ground
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub struct Vtg {
+    pub talker: [u8; 2],
+    pub course_true: Option<f32>,
+    pub course_magnetic: Option<f32>,
+    pub speed_knots: Option<f32>,
+    pub speed_kmh: Option<f32>,
+}
+
+
+/// Decoded sentence
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub enum Sentence {
+    Gga(Gga),
+    Rmc(Rmc),
+    Gsa(Gsa),
+    Gsv(Gsv),
+    Vtg(Vtg),
+}
+
+
+/// Counters of the parser
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub struct NmeaStats {
+    pub sentences: u32,
+    /// Wrong or missing checksum
+    pub checksum_errors: u32,
+    /// Too long, cut off or with too few fields
+    pub invalid: u32,
+    /// Other sentence types
+    pub unsupported: u32,
+}
+
+
+/// Finds the sentences in the byte stream
+#[derive(Clone, Debug)]
+pub struct Parser {
+    buffer: [u8; MAX_SENTENCE],
+    len: usize,
+    /// After '$', before the line end
+    in_sentence: bool,
+    stats: NmeaStats,
+}
+
+impl Parser {
+    pub const fn new() -> Self {
+        Self {
+            buffer: [0; MAX_SENTENCE],
+            len: 0,
+            in_sentence: false,
+            stats: NmeaStats { sentences: 0, checksum_errors: 0, invalid: 0, unsupported: 0 },
+        }
+    }
+
+    /// Add the received byte, returns the sentence after the CR or LF
+    pub fn push(&mut self, byte: u8) -> Option<Sentence> {
+        match byte {
+            b'$' => {
+                if self.in_sentence {
+                    // The line end was lost
+                    self.stats.invalid += 1;
+                }
+                self.in_sentence = true;
+                self.len = 0;
+                None
+            }
+            b'\r' | b'\n' if self.in_sentence => {
+                self.in_sentence = false;
+                let sentence = self.parse();
+                if sentence.is_some() {
+                    self.stats.sentences += 1;
+                }
+                sentence
+            }
+            _ if self.in_sentence => {
+                if self.len == MAX_SENTENCE || !(0x20..0x7F).contains(&byte) {
+                    self.in_sentence = false;
+                    self.stats.invalid += 1;
+                } else {
+                    self.buffer[self.len] = byte;
+                    self.len += 1;
+                }
+                None
+            }
+            // Between the sentences
+            _ => None,
+        }
+    }
+
+    pub fn stats(&self) -> NmeaStats {
+        self.stats
+    }
+
+    /// Drop the partial sentence
+    pub fn reset(&mut self) {
+        self.in_sentence = false;
+        self.len = 0;
+    }
+
+    fn parse(&mut self) -> Option<Sentence> {
+        let line = &self.buffer[..self.len];
+        let Some(star) = line.iter().rposition(|&b| b == b'*') else {
+            self.stats.checksum_errors += 1;
+            return None;
+        };
+        let (body, checksum) = (&line[..star], &line[star + 1..]);
+        let expected = str::from_utf8(checksum).ok().filter(|s| s.len() == 2).and_then(|s| u8::from_str_radix(s, 16).ok());
+        if expected != Some(body.iter().fold(0, |sum, &b| sum ^ b)) {
+            self.stats.checksum_errors += 1;
+            return None;
+        }
+        // Only printable ASCII is in the buffer
+        let body = str::from_utf8(body).unwrap_or("");
+
+        let mut fields = [""; MAX_FIELDS];
+        let mut count = 0;
+        for field in body.split(',') {
+            if count == MAX_FIELDS {
+                self.stats.invalid += 1;
+                return None;
+            }
+            fields[count] = field;
+            count += 1;
+        }
+        let (address, fields) = (fields[0].as_bytes(), &fields[1..count]);
+        // Proprietary sentences start with P
+        if address.len() != 5 || address[0] == b'P' {
+            self.stats.unsupported += 1;
+            return None;
+        }
+        let talker = [address[0], address[1]];
+        let sentence = match &address[2..] {
+            b"GGA" => parse_gga(talker, fields),
+            b"RMC" => parse_rmc(talker, fields),
+            b"GSA" => parse_gsa(talker, fields),
+            b"GSV" => parse_gsv(talker, fields),
+            b"VTG" => parse_vtg(talker, fields),
+            _ => {
+                self.stats.unsupported += 1;
+                return None;
+            }
+        };
+        if sentence.is_none() {
+            self.stats.invalid += 1;
+        }
+        sentence
+    }
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+fn parse_gga(talker: [u8; 2], fields: &[&str]) -> Option<Sentence> {
+    if fields.len() < 14 {
+        return None;
+    }
+    Some(Sentence::Gga(Gga {
+        talker,
+        time: parse_time(fields[0]),
+        position: parse_position(&fields[1..5]),
+        quality: fields[5].parse().unwrap_or(0),
+        satellites: fields[6].parse().ok(),
+        hdop: fields[7].parse().ok(),
+        altitude: fields[8].parse().ok(),
+        geoid_separation: fields[10].parse().ok(),
+    }))
+}
+
+fn parse_rmc(talker: [u8; 2], fields: &[&str]) -> Option<Sentence> {
+    if fields.len() < 11 {
+        return None;
+    }
+    Some(Sentence::Rmc(Rmc {
+        talker,
+        time: parse_time(fields[0]),
+        valid: fields[1] == "A",
+        position: parse_position(&fields[2..6]),
+        speed_knots: fields[6].parse().ok(),
+        course: fields[7].parse().ok(),
+        date: parse_date(fields[8]),
+    }))
+}
+
+fn parse_gsa(talker: [u8; 2], fields: &[&str]) -> Option<Sentence> {
+    if fields.len() < 17 {
+        return None;
+    }
+    let mut satellites = [0; 12];
+    for (prn, field) in satellites.iter_mut().zip(&fields[2..14]) {
+        *prn = field.parse().unwrap_or(0);
+    }
+    Some(Sentence::Gsa(Gsa {
+        talker,
+        automatic: fields[0] == "A",
+        fix: fields[1].parse().unwrap_or(1),
+        satellites,
+        pdop: fields[14].parse().ok(),
+        hdop: fields[15].parse().ok(),
+        vdop: fields[16].parse().ok(),
+    }))
+}
+
+fn parse_gsv(talker: [u8; 2], fields: &[&str]) -> Option<Sentence> {
+    if fields.len() < 3 {
+        return None;
+    }
+    let mut gsv = Gsv {
+        talker,
+        messages: fields[0].parse().ok()?,
+        number: fields[1].parse().ok()?,
+        in_view: fields[2].parse().unwrap_or(0),
+        satellites: [Satellite::default(); 4],
+        count: 0,
+    };
+    // NMEA 4.1 adds the signal id after the satellites
+    for satellite in fields[3..].chunks_exact(4).take(4) {
+        let Ok(prn) = satellite[0].parse() else { continue };
+        gsv.satellites[gsv.count] = Satellite {
+            prn,
+            elevation: satellite[1].parse().ok(),
+            azimuth: satellite[2].parse().ok(),
+            snr: satellite[3].parse().ok(),
+        };
+        gsv.count += 1;
+    }
+    Some(Sentence::Gsv(gsv))
+}
+
+fn parse_vtg(talker: [u8; 2], fields: &[&str]) -> Option<Sentence> {
+    if fields.len() < 8 {
+        return None;
+    }
+    Some(Sentence::Vtg(Vtg {
+        talker,
+        course_true: fields[0].parse().ok(),
+        course_magnetic: fields[2].parse().ok(),
+        speed_knots: fields[4].parse().ok(),
+        speed_kmh: fields[6].parse().ok(),
+    }))
+}
+
+
+/// hhmmss.sss
+fn parse_time(field: &str) -> Option<Time> {
+    let digits = field.as_bytes();
+    if digits.len() < 6 || !digits[..6].iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    let two = |i: usize| (digits[i] - b'0') * 10 + digits[i + 1] - b'0';
+    let time = Time { hour: two(0), minute: two(2), second: two(4), millis: parse_fraction(&field[6..], 3)? as u16 };
+    (time.hour < 24 && time.minute < 60 && time.second < 61).then_some(time)
+}
+
+/// ddmmyy, the years 80-99 are 1980-1999
+fn parse_date(field: &str) -> Option<Date> {
+    let digits = field.as_bytes();
+    if digits.len() != 6 || !digits.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    let two = |i: usize| (digits[i] - b'0') * 10 + digits[i + 1] - b'0';
+    let year = two(4) as u16;
+    let date = Date { year: if year < 80 { 2000 + year } else { 1900 + year }, month: two(2), day: two(0) };
+    ((1..=12).contains(&date.month) && (1..=31).contains(&date.day)).then_some(date)
+}
+
+/// Latitude, N/S, longitude, E/W
+fn parse_position(fields: &[&str]) -> Option<Position> {
+    let latitude = parse_coordinate(fields[0], fields[1], b'N', b'S')?;
+    let longitude = parse_coordinate(fields[2], fields[3], b'E', b'W')?;
+    Some(Position { latitude, longitude })
+}
+
+/// (d)ddmm.mmmmm in 1e-7 degrees
+fn parse_coordinate(value: &str, hemisphere: &str, positive: u8, negative: u8) -> Option<i32> {
+    let sign = match hemisphere.as_bytes() {
+        [b] if *b == positive => 1,
+        [b] if *b == negative => -1,
+        _ => return None,
+    };
+    let (whole, fraction) = value.split_once('.').unwrap_or((value, ""));
+    if whole.len() < 3 || !whole.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let whole: i64 = whole.parse().ok()?;
+    let (degrees, minutes) = (whole / 100, whole % 100);
+    let minutes_e7 = minutes * 10_000_000 + parse_fraction(fraction, 7)? as i64;
+    if minutes >= 60 {
+        return None;
+    }
+    // Rounded to the nearest 1e-7 degree
+    Some(sign * (degrees * 10_000_000 + (minutes_e7 + 30) / 60) as i32)
+}
+
+/// ".sss" (or the digits only) as the integer with the given number of decimals, extra digits are cut
+fn parse_fraction(field: &str, decimals: usize) -> Option<u32> {
+    let digits = field.strip_prefix('.').unwrap_or(field);
+    if !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let padded = digits.bytes().chain(core::iter::repeat(b'0'));
+    Some(padded.take(decimals).fold(0, |value, digit| value * 10 + (digit - b'0') as u32))
+}
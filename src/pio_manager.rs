@@ -0,0 +1,200 @@
+//! PIO resources shared by several drivers
+//!
+//! Each PIO block has 32 instructions of memory and 4 state machines. The manager owns them,
+//! so drivers (I2S, MCLK, WS2812, ...) can be combined without checking by hand
+//! that their programs fit.
+//!
+//!   * programs are installed under a name. Installing the same code again
+//!     (also under another name) returns the already installed program.
+//!   * state machines are claimed by index. Claiming one twice is an error.
+//!   * programs are never uninstalled, so the shared handles stay valid.
+//!
+use pio::{Program, Wrap};
+use rp_pico::hal::{
+    pac::{self, PIO0, PIO1},
+    pio::{InstalledProgram, PIOExt, StateMachineIndex, UninitStateMachine, PIO, SM0, SM1, SM2, SM3},
+};
+
+
+/// Size of the instruction memory of a single PIO block
+pub const INSTRUCTION_COUNT: u8 = 32;
+/// Number of different programs which can be installed on a single PIO block
+pub const MAX_PROGRAMS: usize = 8;
+
+
+/// Why a resource can't be provided
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum PioError {
+    /// Not enough instruction memory (or no continuous space of the required size)
+    NoSpace { pio: u8, name: &'static str, required: u8, free: u8 },
+    /// Different program was already installed under the same name
+    NameConflict { pio: u8, name: &'static str },
+    /// Program table is full
+    TooManyPrograms { pio: u8, name: &'static str },
+    /// State machine was already claimed
+    StateMachineInUse { pio: u8, sm: u8 },
+}
+
+
+/// Installed program and the data required to compare it with other ones
+struct Entry<P: PIOExt> {
+    name: &'static str,
+    code: [u16; INSTRUCTION_COUNT as usize],
+    len: u8,
+    origin: Option<u8>,
+    wrap: Wrap,
+    side_set: (bool, u8, bool),
+    installed: InstalledProgram<P>,
+}
+
+impl<P: PIOExt> Entry<P> {
+    fn matches(&self, program: &Program<32>) -> bool {
+        self.code[..self.len as usize] == program.code[..]
+            && self.origin == program.origin
+            && self.wrap.source == program.wrap.source
+            && self.wrap.target == program.wrap.target
+            && self.side_set == side_set_key(program)
+    }
+}
+
+
+/// State machines of a single PIO block which were not claimed yet
+pub struct StateMachines<P: PIOExt> {
+    sm0: Option<UninitStateMachine<(P, SM0)>>,
+    sm1: Option<UninitStateMachine<(P, SM1)>>,
+    sm2: Option<UninitStateMachine<(P, SM2)>>,
+    sm3: Option<UninitStateMachine<(P, SM3)>>,
+}
+
+/// State machine index which can be claimed from `PioManager`
+pub trait Claim: StateMachineIndex + Sized {
+    fn take<P: PIOExt>(sms: &mut StateMachines<P>) -> Option<UninitStateMachine<(P, Self)>>;
+    fn is_free<P: PIOExt>(sms: &StateMachines<P>) -> bool;
+}
+
+macro_rules! claim {
+    ($sm:ident, $field:ident) => {
+        impl Claim for $sm {
+            fn take<P: PIOExt>(sms: &mut StateMachines<P>) -> Option<UninitStateMachine<(P, Self)>> {
+                sms.$field.take()
+            }
+
+            fn is_free<P: PIOExt>(sms: &StateMachines<P>) -> bool {
+                sms.$field.is_some()
+            }
+        }
+    };
+}
+
+claim!(SM0, sm0);
+claim!(SM1, sm1);
+claim!(SM2, sm2);
+claim!(SM3, sm3);
+
+
+/// Owner of a single PIO block
+pub struct PioManager<P: PIOExt> {
+    pio: PIO<P>,
+    sms: StateMachines<P>,
+    programs: [Option<Entry<P>>; MAX_PROGRAMS],
+    used: u8,
+}
+
+impl<P: PIOExt> PioManager<P> {
+    /// Reset the PIO block and take all its resources
+    pub fn new(block: P, resets: &mut pac::RESETS) -> Self {
+        let (pio, sm0, sm1, sm2, sm3) = block.split(resets);
+        Self {
+            pio,
+            sms: StateMachines { sm0: Some(sm0), sm1: Some(sm1), sm2: Some(sm2), sm3: Some(sm3) },
+            programs: Default::default(),
+            used: 0,
+        }
+    }
+
+    /// Install the program, or return the already installed copy of it
+    ///   * name - used to find the program and in errors
+    ///   * program - code from `pio_proc::pio_asm!`
+    pub fn install(&mut self, name: &'static str, program: &Program<32>) -> Result<InstalledProgram<P>, PioError> {
+        let pio = P::id() as u8;
+        if let Some(entry) = self.programs.iter().flatten().find(|e| e.name == name) {
+            if !entry.matches(program) {
+                return Err(PioError::NameConflict { pio, name });
+            }
+            // Safety: programs are never uninstalled
+            return Ok(unsafe { entry.installed.share() });
+        }
+        if let Some(entry) = self.programs.iter().flatten().find(|e| e.matches(program)) {
+            // Safety: programs are never uninstalled
+            return Ok(unsafe { entry.installed.share() });
+        }
+
+        let Some(slot) = self.programs.iter_mut().find(|e| e.is_none()) else {
+            return Err(PioError::TooManyPrograms { pio, name });
+        };
+        let len = program.code.len() as u8;
+        let installed = self.pio.install(program).map_err(|_| PioError::NoSpace {
+            pio,
+            name,
+            required: len,
+            free: INSTRUCTION_COUNT - self.used,
+        })?;
+        let mut code = [0; INSTRUCTION_COUNT as usize];
+        code[..len as usize].copy_from_slice(&program.code);
+        // Safety: programs are never uninstalled
+        let shared = unsafe { installed.share() };
+        *slot = Some(Entry {
+            name,
+            code,
+            len,
+            origin: program.origin,
+            wrap: program.wrap,
+            side_set: side_set_key(program),
+            installed,
+        });
+        self.used += len;
+        Ok(shared)
+    }
+
+    /// Take the state machine, e.g. `claim::<SM1>()`
+    pub fn claim<SM: Claim>(&mut self) -> Result<UninitStateMachine<(P, SM)>, PioError> {
+        SM::take(&mut self.sms).ok_or(PioError::StateMachineInUse { pio: P::id() as u8, sm: SM::id() as u8 })
+    }
+
+    /// Number of state machines which were not claimed yet
+    pub fn free_state_machines(&self) -> u8 {
+        [SM0::is_free(&self.sms), SM1::is_free(&self.sms), SM2::is_free(&self.sms), SM3::is_free(&self.sms)]
+            .iter()
+            .filter(|&&free| free)
+            .count() as u8
+    }
+
+    /// Number of unused instructions
+    pub fn free_instructions(&self) -> u8 {
+        INSTRUCTION_COUNT - self.used
+    }
+
+    /// Access to the PIO block, e.g. for the interrupts. Don't install programs here,
+    /// the manager would not know about them.
+    pub fn pio_mut(&mut self) -> &mut PIO<P> {
+        &mut self.pio
+    }
+}
+
+
+/// Both PIO blocks
+pub struct Pios {
+    pub pio0: PioManager<PIO0>,
+    pub pio1: PioManager<PIO1>,
+}
+
+impl Pios {
+    pub fn new(pio0: PIO0, pio1: PIO1, resets: &mut pac::RESETS) -> Self {
+        Self { pio0: PioManager::new(pio0, resets), pio1: PioManager::new(pio1, resets) }
+    }
+}
+
+
+fn side_set_key(program: &Program<32>) -> (bool, u8, bool) {
+    (program.side_set.optional(), program.side_set.bits(), program.side_set.pindirs())
+}
@@ -0,0 +1,667 @@
+//! Cycle accurate simulator of a single PIO state machine
+//!
+//! Runs `pio::Program` produced by `pio_proc::pio_asm!` on the host, so programs can be
+//! checked by tests instead of a scope. The configuration methods have the same names
+//! as in `PIOBuilder`.
+//!
+//! Modeled:
+//!   * all instructions, including `out exec`, `mov exec` and `irq wait`
+//!   * TX/RX FIFOs (4 words, 8 when joined), autopull and autopush with thresholds
+//!   * side-set (optional bit and pindirs), delays, wrap
+//!   * fractional clock divider
+//!
+//! Not modeled: other state machines (IRQ flags are local), input synchronizers
+//! and the exact jitter pattern of the fractional divider.
+//!
+//! Usage:
+//!   * create with `Simulator::new(&program)` and configure like `PIOBuilder`
+//!   * feed TX FIFO with `push_tx()`, drive input pins with `set_inputs()`
+//!   * call `step()` once per system clock cycle and look at `pins()`
+//!
+use pio::{
+    InSource, Instruction, InstructionOperands, JmpCondition, MovDestination, MovOperation, MovSource,
+    OutDestination, SetDestination, SideSet, WaitSource,
+};
+use rp_pico::hal::pio::{Buffers, MovStatusConfig, PinDir, ShiftDirection};
+
+
+/// Depth of a single FIFO
+pub const FIFO_DEPTH: usize = 4;
+
+
+/// Result of a single instruction
+enum Outcome {
+    Next,
+    Jump(u8),
+    Stall,
+}
+
+
+/// FIFO between the state machine and the system
+struct Fifo {
+    buffer: [u32; 2 * FIFO_DEPTH],
+    head: usize,
+    len: usize,
+    capacity: usize,
+}
+
+impl Fifo {
+    fn new(capacity: usize) -> Self {
+        Self { buffer: [0; 2 * FIFO_DEPTH], head: 0, len: 0, capacity }
+    }
+
+    fn push(&mut self, word: u32) -> bool {
+        if self.is_full() {
+            return false;
+        }
+        self.buffer[(self.head + self.len) % self.buffer.len()] = word;
+        self.len += 1;
+        true
+    }
+
+    fn pop(&mut self) -> Option<u32> {
+        if self.len == 0 {
+            return None;
+        }
+        let word = self.buffer[self.head];
+        self.head = (self.head + 1) % self.buffer.len();
+        self.len -= 1;
+        Some(word)
+    }
+
+    fn is_full(&self) -> bool {
+        self.len >= self.capacity
+    }
+}
+
+
+/// Simulated state machine with the program loaded at offset 0
+pub struct Simulator {
+    code: [u16; 32],
+    wrap_source: u8,
+    wrap_target: u8,
+    side_set: SideSet,
+
+    // Configuration
+    divisor: u32,
+    out_base: u8,
+    out_count: u8,
+    set_base: u8,
+    set_count: u8,
+    in_base: u8,
+    side_set_base: u8,
+    jmp_pin: u8,
+    out_shift: ShiftDirection,
+    in_shift: ShiftDirection,
+    autopull: bool,
+    autopush: bool,
+    pull_threshold: u8,
+    push_threshold: u8,
+    mov_status: MovStatusConfig,
+
+    // State
+    pc: u8,
+    x: u32,
+    y: u32,
+    osr: u32,
+    osr_count: u8,
+    isr: u32,
+    isr_count: u8,
+    tx: Fifo,
+    rx: Fifo,
+    pins: u32,
+    pindirs: u32,
+    inputs: u32,
+    irq: u8,
+    irq_wait: bool,
+    exec: Option<u16>,
+    delay: u8,
+    stalled: bool,
+    div_acc: u32,
+    clock: u64,
+}
+
+impl Simulator {
+    /// Load the program. The configuration defaults are the same as in `PIOBuilder`.
+    pub fn new(program: &pio::Program<32>) -> Self {
+        let mut code = [0; 32];
+        code[..program.code.len()].copy_from_slice(&program.code);
+        Self {
+            code,
+            wrap_source: program.wrap.source,
+            wrap_target: program.wrap.target,
+            side_set: program.side_set,
+            divisor: 256,
+            out_base: 0,
+            out_count: 0,
+            set_base: 0,
+            set_count: 5,
+            in_base: 0,
+            side_set_base: 0,
+            jmp_pin: 0,
+            out_shift: ShiftDirection::Left,
+            in_shift: ShiftDirection::Left,
+            autopull: false,
+            autopush: false,
+            pull_threshold: 32,
+            push_threshold: 32,
+            mov_status: MovStatusConfig::Tx(0),
+            pc: 0,
+            x: 0,
+            y: 0,
+            // Both shift registers start empty
+            osr: 0,
+            osr_count: 32,
+            isr: 0,
+            isr_count: 0,
+            tx: Fifo::new(FIFO_DEPTH),
+            rx: Fifo::new(FIFO_DEPTH),
+            pins: 0,
+            pindirs: 0,
+            inputs: 0,
+            irq: 0,
+            irq_wait: false,
+            exec: None,
+            delay: 0,
+            stalled: false,
+            div_acc: 0,
+            clock: 0,
+        }
+    }
+
+    /// Pins driven by `out` and `mov pins`
+    pub fn out_pins(mut self, base: u8, count: u8) -> Self {
+        self.out_base = base;
+        self.out_count = count;
+        self
+    }
+
+    /// Pins driven by `set`
+    pub fn set_pins(mut self, base: u8, count: u8) -> Self {
+        self.set_base = base;
+        self.set_count = count;
+        self
+    }
+
+    /// First pin read by `in pins` and `wait pin`
+    pub fn in_pin_base(mut self, base: u8) -> Self {
+        self.in_base = base;
+        self
+    }
+
+    /// First side-set pin
+    pub fn side_set_pin_base(mut self, base: u8) -> Self {
+        self.side_set_base = base;
+        self
+    }
+
+    /// Pin tested by `jmp pin`
+    pub fn jmp_pin(mut self, pin: u8) -> Self {
+        self.jmp_pin = pin;
+        self
+    }
+
+    pub fn out_shift_direction(mut self, direction: ShiftDirection) -> Self {
+        self.out_shift = direction;
+        self
+    }
+
+    pub fn in_shift_direction(mut self, direction: ShiftDirection) -> Self {
+        self.in_shift = direction;
+        self
+    }
+
+    pub fn autopull(mut self, enabled: bool) -> Self {
+        self.autopull = enabled;
+        self
+    }
+
+    pub fn autopush(mut self, enabled: bool) -> Self {
+        self.autopush = enabled;
+        self
+    }
+
+    /// Number of bits shifted out of OSR before autopull. 0 means 32.
+    pub fn pull_threshold(mut self, threshold: u8) -> Self {
+        self.pull_threshold = if threshold == 0 { 32 } else { threshold };
+        self
+    }
+
+    /// Number of bits shifted into ISR before autopush. 0 means 32.
+    pub fn push_threshold(mut self, threshold: u8) -> Self {
+        self.push_threshold = if threshold == 0 { 32 } else { threshold };
+        self
+    }
+
+    /// Clock divisor as int + (frac/256). 0 is interpreted as 65536.
+    pub fn clock_divisor_fixed_point(mut self, int: u16, frac: u8) -> Self {
+        let int = if int == 0 { 65536 } else { int as u32 };
+        self.divisor = (int << 8) | frac as u32;
+        self
+    }
+
+    /// Join the FIFOs
+    pub fn buffers(mut self, buffers: Buffers) -> Self {
+        let (tx, rx) = match buffers {
+            Buffers::RxTx => (FIFO_DEPTH, FIFO_DEPTH),
+            Buffers::OnlyTx => (2 * FIFO_DEPTH, 0),
+            Buffers::OnlyRx => (0, 2 * FIFO_DEPTH),
+        };
+        self.tx = Fifo::new(tx);
+        self.rx = Fifo::new(rx);
+        self
+    }
+
+    /// Condition for `mov x, status`
+    pub fn set_mov_status_config(mut self, mov_status: MovStatusConfig) -> Self {
+        self.mov_status = mov_status;
+        self
+    }
+
+    /// Set direction of the pins, like `StateMachine::set_pindirs()`
+    pub fn set_pindirs(&mut self, dirs: impl IntoIterator<Item = (u8, PinDir)>) {
+        for (pin, dir) in dirs {
+            let mask = 1 << (pin % 32);
+            match dir {
+                PinDir::Input => self.pindirs &= !mask,
+                PinDir::Output => self.pindirs |= mask,
+            }
+        }
+    }
+
+    /// Execute single instruction immediately, like `StateMachine::exec_instruction()`.
+    /// Stalls and delays are ignored.
+    pub fn exec_instruction(&mut self, instruction: Instruction) {
+        let word = instruction.encode(self.side_set);
+        self.execute_word(word, true);
+        self.delay = 0;
+    }
+
+    /// Put the word into TX FIFO. Returns false if it is full.
+    pub fn push_tx(&mut self, word: u32) -> bool {
+        self.tx.push(word)
+    }
+
+    /// Take the word from RX FIFO
+    pub fn pull_rx(&mut self) -> Option<u32> {
+        self.rx.pop()
+    }
+
+    pub fn tx_level(&self) -> usize {
+        self.tx.len
+    }
+
+    pub fn rx_level(&self) -> usize {
+        self.rx.len
+    }
+
+    /// Levels of the external signals on the input pins
+    pub fn set_inputs(&mut self, inputs: u32) {
+        self.inputs = inputs;
+    }
+
+    /// Levels set by the state machine on all 32 pins (also on pins configured as inputs)
+    pub fn pins(&self) -> u32 {
+        self.pins
+    }
+
+    /// Level set by the state machine on a single pin
+    pub fn pin(&self, pin: u8) -> bool {
+        self.pins & (1 << pin) != 0
+    }
+
+    pub fn pindirs(&self) -> u32 {
+        self.pindirs
+    }
+
+    pub fn pc(&self) -> u8 {
+        self.pc
+    }
+
+    pub fn x(&self) -> u32 {
+        self.x
+    }
+
+    pub fn y(&self) -> u32 {
+        self.y
+    }
+
+    /// IRQ flags 0-7
+    pub fn irq_flags(&self) -> u8 {
+        self.irq
+    }
+
+    pub fn clear_irq(&mut self, index: u8) {
+        self.irq &= !(1 << index);
+    }
+
+    /// True if the last instruction is waiting for FIFO, pin or IRQ
+    pub fn is_stalled(&self) -> bool {
+        self.stalled
+    }
+
+    /// Number of system clock cycles since the start
+    pub fn clock(&self) -> u64 {
+        self.clock
+    }
+
+    /// Advance by one system clock cycle
+    pub fn step(&mut self) {
+        self.clock += 1;
+        self.div_acc += 256;
+        if self.div_acc < self.divisor {
+            return;
+        }
+        self.div_acc -= self.divisor;
+
+        if self.delay > 0 {
+            self.delay -= 1;
+            return;
+        }
+        match self.exec.take() {
+            Some(word) => self.execute_word(word, true),
+            None => self.execute_word(self.code[self.pc as usize], false),
+        }
+    }
+
+    /// Run for the given number of system clock cycles and pass pins after every cycle
+    pub fn run(&mut self, cycles: u32, mut on_cycle: impl FnMut(u32)) {
+        for _ in 0..cycles {
+            self.step();
+            on_cycle(self.pins);
+        }
+    }
+
+    fn execute_word(&mut self, word: u16, is_exec: bool) {
+        let instruction = Instruction::decode(word, self.side_set).expect("Invalid PIO instruction");
+        // Side-set is applied at the start of the instruction, even if it stalls
+        if let Some(value) = instruction.side_set {
+            let count = self.side_set.bits() - self.side_set.optional() as u8;
+            if self.side_set.pindirs() {
+                self.pindirs = write_pins(self.pindirs, self.side_set_base, count, value as u32);
+            } else {
+                self.pins = write_pins(self.pins, self.side_set_base, count, value as u32);
+            }
+        }
+
+        match self.execute(&instruction.operands) {
+            Outcome::Stall => {
+                if is_exec {
+                    self.exec = Some(word);
+                }
+                self.stalled = true;
+                return;
+            }
+            Outcome::Next if is_exec => (),
+            Outcome::Next => {
+                self.pc = if self.pc == self.wrap_source { self.wrap_target } else { (self.pc + 1) % 32 };
+            }
+            Outcome::Jump(address) => self.pc = address % 32,
+        }
+        self.stalled = false;
+        self.delay = instruction.delay;
+    }
+
+    fn execute(&mut self, operands: &InstructionOperands) -> Outcome {
+        match *operands {
+            InstructionOperands::JMP { condition, address } => {
+                let jump = match condition {
+                    JmpCondition::Always => true,
+                    JmpCondition::XIsZero => self.x == 0,
+                    JmpCondition::XDecNonZero => {
+                        let jump = self.x != 0;
+                        self.x = self.x.wrapping_sub(1);
+                        jump
+                    }
+                    JmpCondition::YIsZero => self.y == 0,
+                    JmpCondition::YDecNonZero => {
+                        let jump = self.y != 0;
+                        self.y = self.y.wrapping_sub(1);
+                        jump
+                    }
+                    JmpCondition::XNotEqualY => self.x != self.y,
+                    JmpCondition::PinHigh => self.read_pins() & (1 << self.jmp_pin) != 0,
+                    JmpCondition::OutputShiftRegisterNotEmpty => self.osr_count < self.pull_threshold,
+                };
+                if jump {
+                    Outcome::Jump(address)
+                } else {
+                    Outcome::Next
+                }
+            }
+            InstructionOperands::WAIT { polarity, source, index, .. } => {
+                let level = match source {
+                    WaitSource::GPIO => self.read_pins() & (1 << index) != 0,
+                    WaitSource::PIN => self.read_pins().rotate_right(self.in_base as u32) & (1 << index) != 0,
+                    WaitSource::IRQ => self.irq & (1 << (index & 7)) != 0,
+                };
+                if level != (polarity != 0) {
+                    return Outcome::Stall;
+                }
+                if source == WaitSource::IRQ && polarity != 0 {
+                    self.irq &= !(1 << (index & 7));
+                }
+                Outcome::Next
+            }
+            InstructionOperands::IN { source, bit_count } => {
+                let bit_count = if bit_count == 0 { 32 } else { bit_count };
+                let full = (self.isr_count + bit_count).min(32) >= self.push_threshold;
+                if self.autopush && full && self.rx.is_full() {
+                    return Outcome::Stall;
+                }
+                let data = match source {
+                    InSource::PINS => self.read_pins().rotate_right(self.in_base as u32),
+                    InSource::X => self.x,
+                    InSource::Y => self.y,
+                    InSource::NULL => 0,
+                    InSource::ISR => self.isr,
+                    InSource::OSR => self.osr,
+                };
+                self.shift_in(data, bit_count);
+                if self.autopush && self.isr_count >= self.push_threshold {
+                    self.push_isr();
+                }
+                Outcome::Next
+            }
+            InstructionOperands::OUT { destination, bit_count } => {
+                let bit_count = if bit_count == 0 { 32 } else { bit_count };
+                if self.autopull && self.osr_count >= self.pull_threshold {
+                    if self.tx.len == 0 {
+                        return Outcome::Stall;
+                    }
+                    self.pull_osr();
+                }
+                let data = self.shift_out(bit_count);
+                let outcome = match destination {
+                    OutDestination::PINS => {
+                        self.pins = write_pins(self.pins, self.out_base, self.out_count, data);
+                        Outcome::Next
+                    }
+                    OutDestination::X => {
+                        self.x = data;
+                        Outcome::Next
+                    }
+                    OutDestination::Y => {
+                        self.y = data;
+                        Outcome::Next
+                    }
+                    OutDestination::NULL => Outcome::Next,
+                    OutDestination::PINDIRS => {
+                        self.pindirs = write_pins(self.pindirs, self.out_base, self.out_count, data);
+                        Outcome::Next
+                    }
+                    OutDestination::PC => Outcome::Jump(data as u8),
+                    OutDestination::ISR => {
+                        self.isr = data;
+                        self.isr_count = bit_count;
+                        Outcome::Next
+                    }
+                    OutDestination::EXEC => {
+                        self.exec = Some(data as u16);
+                        Outcome::Next
+                    }
+                };
+                // Autopull refills OSR in the same cycle, if there is data
+                if self.autopull && self.osr_count >= self.pull_threshold && self.tx.len > 0 {
+                    self.pull_osr();
+                }
+                outcome
+            }
+            InstructionOperands::PUSH { if_full, block } => {
+                if if_full && self.isr_count < self.push_threshold {
+                    return Outcome::Next;
+                }
+                if self.rx.is_full() {
+                    if block {
+                        return Outcome::Stall;
+                    }
+                    // Data is lost
+                    self.isr = 0;
+                    self.isr_count = 0;
+                    return Outcome::Next;
+                }
+                self.push_isr();
+                Outcome::Next
+            }
+            InstructionOperands::PULL { if_empty, block } => {
+                if if_empty && self.osr_count < self.pull_threshold {
+                    return Outcome::Next;
+                }
+                if self.tx.len == 0 {
+                    if block {
+                        return Outcome::Stall;
+                    }
+                    // Non blocking pull from empty FIFO copies X
+                    self.osr = self.x;
+                    self.osr_count = 0;
+                    return Outcome::Next;
+                }
+                self.pull_osr();
+                Outcome::Next
+            }
+            InstructionOperands::MOV { destination, op, source } => {
+                let data = match source {
+                    MovSource::PINS => self.read_pins().rotate_right(self.in_base as u32),
+                    MovSource::X => self.x,
+                    MovSource::Y => self.y,
+                    MovSource::NULL => 0,
+                    MovSource::STATUS => {
+                        let below = match self.mov_status {
+                            MovStatusConfig::Tx(n) => self.tx.len < n as usize,
+                            MovStatusConfig::Rx(n) => self.rx.len < n as usize,
+                        };
+                        if below {
+                            u32::MAX
+                        } else {
+                            0
+                        }
+                    }
+                    MovSource::ISR => self.isr,
+                    MovSource::OSR => self.osr,
+                };
+                let data = match op {
+                    MovOperation::None => data,
+                    MovOperation::Invert => !data,
+                    MovOperation::BitReverse => data.reverse_bits(),
+                };
+                match destination {
+                    MovDestination::PINS => self.pins = write_pins(self.pins, self.out_base, self.out_count, data),
+                    MovDestination::X => self.x = data,
+                    MovDestination::Y => self.y = data,
+                    MovDestination::EXEC => self.exec = Some(data as u16),
+                    MovDestination::PC => return Outcome::Jump(data as u8),
+                    MovDestination::ISR => {
+                        self.isr = data;
+                        self.isr_count = 0;
+                    }
+                    MovDestination::OSR => {
+                        self.osr = data;
+                        self.osr_count = 0;
+                    }
+                }
+                Outcome::Next
+            }
+            InstructionOperands::IRQ { clear, wait, index, .. } => {
+                let mask = 1 << (index & 7);
+                if clear {
+                    self.irq &= !mask;
+                    return Outcome::Next;
+                }
+                if !wait {
+                    self.irq |= mask;
+                    return Outcome::Next;
+                }
+                // Set the flag once and wait until somebody clears it
+                if !self.irq_wait {
+                    self.irq |= mask;
+                    self.irq_wait = true;
+                }
+                if self.irq & mask != 0 {
+                    return Outcome::Stall;
+                }
+                self.irq_wait = false;
+                Outcome::Next
+            }
+            InstructionOperands::SET { destination, data } => {
+                match destination {
+                    SetDestination::PINS => self.pins = write_pins(self.pins, self.set_base, self.set_count, data as u32),
+                    SetDestination::X => self.x = data as u32,
+                    SetDestination::Y => self.y = data as u32,
+                    SetDestination::PINDIRS => {
+                        self.pindirs = write_pins(self.pindirs, self.set_base, self.set_count, data as u32)
+                    }
+                }
+                Outcome::Next
+            }
+        }
+    }
+
+    /// Output pins read back their own level, the other ones come from `set_inputs()`
+    fn read_pins(&self) -> u32 {
+        (self.pins & self.pindirs) | (self.inputs & !self.pindirs)
+    }
+
+    fn shift_in(&mut self, data: u32, bit_count: u8) {
+        let data = data as u64 & ((1u64 << bit_count) - 1);
+        self.isr = match self.in_shift {
+            ShiftDirection::Left => (((self.isr as u64) << bit_count) | data) as u32,
+            ShiftDirection::Right => (((self.isr as u64) >> bit_count) | (data << (32 - bit_count))) as u32,
+        };
+        self.isr_count = (self.isr_count + bit_count).min(32);
+    }
+
+    fn shift_out(&mut self, bit_count: u8) -> u32 {
+        let osr = self.osr as u64;
+        let (data, osr) = match self.out_shift {
+            ShiftDirection::Left => (osr >> (32 - bit_count), osr << bit_count),
+            ShiftDirection::Right => (osr & ((1u64 << bit_count) - 1), osr >> bit_count),
+        };
+        self.osr = osr as u32;
+        self.osr_count = (self.osr_count + bit_count).min(32);
+        data as u32
+    }
+
+    fn push_isr(&mut self) {
+        self.rx.push(self.isr);
+        self.isr = 0;
+        self.isr_count = 0;
+    }
+
+    fn pull_osr(&mut self) {
+        if let Some(word) = self.tx.pop() {
+            self.osr = word;
+            self.osr_count = 0;
+        }
+    }
+}
+
+
+/// Write `count` consecutive pins (wrapping after 31) starting at `base`
+fn write_pins(pins: u32, base: u8, count: u8, value: u32) -> u32 {
+    let mut pins = pins;
+    for i in 0..count as u32 {
+        let pin = (base as u32 + i) % 32;
+        pins = (pins & !(1 << pin)) | (((value >> i) & 1) << pin);
+    }
+    pins
+}
@@ -0,0 +1,381 @@
+//! UART on PIO
+//!
+//! Extra serial ports besides UART0 and UART1. Every direction takes one state machine.
+//! Baud rate, data bits, parity and stop bits come from the HAL `UartConfig`, and the line
+//! can be inverted by the GPIO overrides, e.g. for SBUS (100000 8E2, inverted) without
+//! an external inverter.
+//!
+//! The PIO programs don't know the frame format:
+//!   * TX gets every frame with start, parity and stop bits already prepared by the CPU,
+//!     the number of bits is in the low 4 bits of the word
+//!   * RX gets the number of bits (data + parity) once at start and pushes every frame
+//!     with its stop bit, parity and framing are checked by the CPU
+//!
+//! Methods follow `UartPeripheral`: `read_raw`, `read_full_blocking`, `write_raw`,
+//! `write_full_blocking`, `core::fmt::Write` and the embedded-hal serial traits.
+//! RX overrun isn't detected, frames are lost while RX FIFO is full.
+//!
+use core::convert::Infallible;
+use core::fmt;
+
+use rp_pico::hal::{
+    pac,
+    pio::{Buffers, PIOBuilder, PIOExt, PinDir, PinState, Running, Rx, ShiftDirection, StateMachine, StateMachineIndex, Tx, UninitStateMachine},
+    uart::{DataBits, Parity, ReadError, ReadErrorType, StopBits, UartConfig},
+};
+
+use crate::pio_manager::{PioError, PioManager};
+
+
+/// Number of PIO cycles in a single bit
+pub const CYCLES_PER_BIT: u32 = 8;
+
+
+/// Frame format taken from `UartConfig`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Frame {
+    pub data_bits: u8,
+    /// Some(true) for odd parity
+    pub odd_parity: Option<bool>,
+    pub stop_bits: u8,
+}
+
+impl Frame {
+    pub fn new(config: &UartConfig) -> Self {
+        let data_bits = match config.data_bits {
+            DataBits::Five => 5,
+            DataBits::Six => 6,
+            DataBits::Seven => 7,
+            DataBits::Eight => 8,
+        };
+        let odd_parity = match config.parity {
+            None => None,
+            Some(Parity::Odd) => Some(true),
+            Some(Parity::Even) => Some(false),
+        };
+        let stop_bits = match config.stop_bits {
+            StopBits::One => 1,
+            StopBits::Two => 2,
+        };
+        Self { data_bits, odd_parity, stop_bits }
+    }
+
+    /// Bits sampled by RX: data, parity and the first stop bit
+    pub const fn rx_bits(&self) -> u8 {
+        self.data_bits + self.odd_parity.is_some() as u8 + 1
+    }
+
+    /// Word for the TX program: bit count - 1 in the low 4 bits, then the frame LSB first
+    pub fn encode(&self, byte: u8) -> u32 {
+        let data = byte as u32 & ((1 << self.data_bits) - 1);
+        // Start bit is 0
+        let mut frame = data << 1;
+        let mut bits = 1 + self.data_bits;
+        if let Some(odd) = self.odd_parity {
+            frame |= ((data.count_ones() & 1) ^ odd as u32) << bits;
+            bits += 1;
+        }
+        frame |= ((1 << self.stop_bits) - 1) << bits;
+        bits += self.stop_bits;
+        (bits as u32 - 1) | frame << 4
+    }
+
+    /// Byte from the word pushed by the RX program
+    pub fn decode(&self, word: u32) -> Result<u8, ReadErrorType> {
+        let bits = self.rx_bits();
+        let frame = word >> (32 - bits);
+        let data = frame & ((1 << self.data_bits) - 1);
+        if frame >> (bits - 1) == 0 {
+            return Err(if frame == 0 { ReadErrorType::Break } else { ReadErrorType::Framing });
+        }
+        if let Some(odd) = self.odd_parity {
+            let parity = frame >> self.data_bits & 1;
+            if parity != (data.count_ones() & 1) ^ odd as u32 {
+                return Err(ReadErrorType::Parity);
+            }
+        }
+        Ok(data as u8)
+    }
+}
+
+
+/// PIO clock divisor for the baud rate, as (int, frac)
+pub fn clock_divisor(sys_clock_hz: u32, baudrate: u32) -> (u16, u8) {
+    let cycles = baudrate as u64 * CYCLES_PER_BIT as u64;
+    let div = (sys_clock_hz as u64 * 256 + cycles / 2) / cycles;
+    ((div >> 8) as u16, (div & 0xff) as u8)
+}
+
+
+/// Transmitter
+pub struct PioUartTx<P: PIOExt, SM: StateMachineIndex> {
+    _sm: StateMachine<(P, SM), Running>,
+    tx: Tx<(P, SM)>,
+    frame: Frame,
+}
+
+impl<P: PIOExt, SM: StateMachineIndex> PioUartTx<P, SM> {
+    /// Install the program and start the state machine. The pin has to be set to the PIO
+    /// function before, it would clear the inversion.
+    ///   * inverted - idle line is low
+    pub fn new(
+        pio: &mut PioManager<P>,
+        sm: UninitStateMachine<(P, SM)>,
+        pin: u8,
+        config: &UartConfig,
+        inverted: bool,
+        sys_clock_hz: u32,
+    ) -> Result<Self, PioError> {
+        let installed = pio.install("uart_tx", &uart_tx_program())?;
+        let (int, frac) = clock_divisor(sys_clock_hz, config.baudrate.to_Hz());
+        let (mut sm, _rx, tx) = PIOBuilder::from_program(installed)
+            .out_pins(pin, 1)
+            .set_pins(pin, 1)
+            .out_shift_direction(ShiftDirection::Right)
+            .buffers(Buffers::OnlyTx)
+            .clock_divisor_fixed_point(int, frac)
+            .build(sm);
+        set_inversion(pin, inverted);
+        // Idle line
+        sm.set_pins([(pin, PinState::High)]);
+        sm.set_pindirs([(pin, PinDir::Output)]);
+        Ok(Self { _sm: sm.start(), tx, frame: Frame::new(config) })
+    }
+
+    /// Write as many bytes as fit into TX FIFO and return the rest
+    pub fn write_raw<'d>(&mut self, data: &'d [u8]) -> nb::Result<&'d [u8], Infallible> {
+        let mut sent = 0;
+        for &byte in data {
+            if !self.tx.write(self.frame.encode(byte)) {
+                break;
+            }
+            sent += 1;
+        }
+        if sent == 0 && !data.is_empty() {
+            Err(nb::Error::WouldBlock)
+        } else {
+            Ok(&data[sent..])
+        }
+    }
+
+    /// Write all bytes, waiting for the space in TX FIFO
+    pub fn write_full_blocking(&mut self, data: &[u8]) {
+        let mut rest = data;
+        while !rest.is_empty() {
+            if let Ok(remaining) = self.write_raw(rest) {
+                rest = remaining;
+            }
+        }
+    }
+
+    /// True when all frames were taken from TX FIFO (the last one can still be sent)
+    pub fn is_empty(&self) -> bool {
+        self.tx.is_empty()
+    }
+}
+
+impl<P: PIOExt, SM: StateMachineIndex> fmt::Write for PioUartTx<P, SM> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_full_blocking(s.as_bytes());
+        Ok(())
+    }
+}
+
+impl<P: PIOExt, SM: StateMachineIndex> embedded_hal::serial::Write<u8> for PioUartTx<P, SM> {
+    type Error = Infallible;
+
+    fn write(&mut self, word: u8) -> nb::Result<(), Infallible> {
+        self.write_raw(&[word]).map(|_| ())
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Infallible> {
+        if self.tx.is_empty() {
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+}
+
+
+/// Receiver
+pub struct PioUartRx<P: PIOExt, SM: StateMachineIndex> {
+    _sm: StateMachine<(P, SM), Running>,
+    rx: Rx<(P, SM)>,
+    frame: Frame,
+}
+
+impl<P: PIOExt, SM: StateMachineIndex> PioUartRx<P, SM> {
+    /// Install the program and start the state machine. The pin has to be set to the PIO
+    /// function before, it would clear the inversion.
+    ///   * inverted - idle line is low
+    pub fn new(
+        pio: &mut PioManager<P>,
+        sm: UninitStateMachine<(P, SM)>,
+        pin: u8,
+        config: &UartConfig,
+        inverted: bool,
+        sys_clock_hz: u32,
+    ) -> Result<Self, PioError> {
+        let installed = pio.install("uart_rx", &uart_rx_program())?;
+        let (int, frac) = clock_divisor(sys_clock_hz, config.baudrate.to_Hz());
+        let (mut sm, rx, mut tx) = PIOBuilder::from_program(installed)
+            .in_pin_base(pin)
+            .jmp_pin(pin)
+            .in_shift_direction(ShiftDirection::Right)
+            .clock_divisor_fixed_point(int, frac)
+            .build(sm);
+        set_inversion(pin, inverted);
+        sm.set_pindirs([(pin, PinDir::Input)]);
+        let frame = Frame::new(config);
+        // Loop counter for the data and parity bits
+        tx.write(frame.rx_bits() as u32 - 2);
+        Ok(Self { _sm: sm.start(), rx, frame })
+    }
+
+    /// Read the received bytes into the buffer and return their count.
+    /// On error the bytes before the wrong one are returned as `discarded`.
+    pub fn read_raw<'b>(&mut self, buffer: &'b mut [u8]) -> nb::Result<usize, ReadError<'b>> {
+        let mut count = 0;
+        while count < buffer.len() {
+            let Some(word) = self.rx.read() else {
+                break;
+            };
+            match self.frame.decode(word) {
+                Ok(byte) => {
+                    buffer[count] = byte;
+                    count += 1;
+                }
+                Err(err_type) => {
+                    return Err(nb::Error::Other(ReadError { err_type, discarded: &buffer[..count] }));
+                }
+            }
+        }
+        if count == 0 && !buffer.is_empty() {
+            Err(nb::Error::WouldBlock)
+        } else {
+            Ok(count)
+        }
+    }
+
+    /// Fill the whole buffer
+    pub fn read_full_blocking(&mut self, buffer: &mut [u8]) -> Result<(), ReadErrorType> {
+        for byte in buffer.iter_mut() {
+            *byte = nb::block!(embedded_hal::serial::Read::read(self))?;
+        }
+        Ok(())
+    }
+}
+
+impl<P: PIOExt, SM: StateMachineIndex> embedded_hal::serial::Read<u8> for PioUartRx<P, SM> {
+    type Error = ReadErrorType;
+
+    fn read(&mut self) -> nb::Result<u8, ReadErrorType> {
+        let word = self.rx.read().ok_or(nb::Error::WouldBlock)?;
+        self.frame.decode(word).map_err(nb::Error::Other)
+    }
+}
+
+
+/// Both directions
+pub struct PioUart<P: PIOExt, TX: StateMachineIndex, RX: StateMachineIndex> {
+    pub tx: PioUartTx<P, TX>,
+    pub rx: PioUartRx<P, RX>,
+}
+
+impl<P: PIOExt, TX: StateMachineIndex, RX: StateMachineIndex> PioUart<P, TX, RX> {
+    /// Both state machines on the same PIO block with the same configuration
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        pio: &mut PioManager<P>,
+        tx_sm: UninitStateMachine<(P, TX)>,
+        rx_sm: UninitStateMachine<(P, RX)>,
+        tx_pin: u8,
+        rx_pin: u8,
+        config: &UartConfig,
+        inverted: bool,
+        sys_clock_hz: u32,
+    ) -> Result<Self, PioError> {
+        Ok(Self {
+            tx: PioUartTx::new(pio, tx_sm, tx_pin, config, inverted, sys_clock_hz)?,
+            rx: PioUartRx::new(pio, rx_sm, rx_pin, config, inverted, sys_clock_hz)?,
+        })
+    }
+
+    pub fn write_raw<'d>(&mut self, data: &'d [u8]) -> nb::Result<&'d [u8], Infallible> {
+        self.tx.write_raw(data)
+    }
+
+    pub fn write_full_blocking(&mut self, data: &[u8]) {
+        self.tx.write_full_blocking(data)
+    }
+
+    pub fn read_raw<'b>(&mut self, buffer: &'b mut [u8]) -> nb::Result<usize, ReadError<'b>> {
+        self.rx.read_raw(buffer)
+    }
+
+    pub fn read_full_blocking(&mut self, buffer: &mut [u8]) -> Result<(), ReadErrorType> {
+        self.rx.read_full_blocking(buffer)
+    }
+
+    /// Separate halves, e.g. for the interrupt handler
+    pub fn split(self) -> (PioUartTx<P, TX>, PioUartRx<P, RX>) {
+        (self.tx, self.rx)
+    }
+}
+
+impl<P: PIOExt, TX: StateMachineIndex, RX: StateMachineIndex> fmt::Write for PioUart<P, TX, RX> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.tx.write_str(s)
+    }
+}
+
+
+/// Sends the frames prepared by `Frame::encode()`, LSB first, 8 cycles per bit.
+/// Use with the output shift to the right.
+pub fn uart_tx_program() -> pio::Program<32> {
+    pio_proc::pio_asm!("
+        .wrap_target
+            pull block
+            out x, 4            ; number of bits - 1
+        bitloop:
+            out pins, 1 [6]
+            jmp x-- bitloop
+        .wrap
+    ").program
+}
+
+/// Receives frames with any number of bits, 8 cycles per bit. The number of data
+/// and parity bits - 1 has to be written to TX FIFO once at start.
+/// Use with the input shift to the right, `jmp_pin` is the input.
+pub fn uart_rx_program() -> pio::Program<32> {
+    pio_proc::pio_asm!("
+            pull block          ; bit count stays in OSR
+        .wrap_target
+        start:
+            wait 0 pin 0        ; start bit
+            mov x, osr [10]     ; middle of the first data bit
+        bitloop:
+            in pins, 1
+            jmp x-- bitloop [6]
+            in pins, 1          ; stop bit
+            push block
+            jmp pin start
+            wait 1 pin 0        ; framing error or break, wait for the idle line
+        .wrap
+    ").program
+}
+
+
+/// Invert the pin in the GPIO block
+fn set_inversion(pin: u8, inverted: bool) {
+    // Safety: only the overrides of the pin given to the driver are changed
+    let gpio = unsafe { &(*pac::IO_BANK0::ptr()).gpio[pin as usize] };
+    gpio.gpio_ctrl.modify(|_, w| {
+        if inverted {
+            w.outover().invert().inover().invert()
+        } else {
+            w.outover().normal().inover().normal()
+        }
+    });
+}
@@ -0,0 +1,221 @@
+//! Quadrature (rotary) encoder on PIO
+//!
+//! The state machine samples both phases in a loop and keeps the signed position in Y,
+//! so no transition is missed even when the CPU is busy. The position is pushed to RX FIFO
+//! after every sample, the driver reads the latest one.
+//!
+//! Based on the `quadrature_encoder` program from pico-examples: the previous and the current
+//! state of the pins form a 4 bit index into the jump table at the start of the instruction
+//! memory, so the program has to be installed at offset 0.
+//!
+//! Every detent of a mechanical encoder is usually 4 transitions. `Velocity` estimates
+//! the speed from the position and `Button` handles the push-button of the encoder.
+//!
+use rp_pico::hal::pio::{
+    Buffers, PIOBuilder, PIOExt, Running, Rx, ShiftDirection, StateMachine, StateMachineIndex, UninitStateMachine,
+};
+
+use crate::pio_manager::{PioError, PioManager};
+
+
+/// Number of PIO cycles of the slowest loop of the program
+pub const CYCLES_PER_SAMPLE: u32 = 10;
+/// Depth of the joined RX FIFO
+const FIFO_DEPTH: usize = 8;
+
+
+/// Position counter on two consecutive pins
+pub struct QuadratureEncoder<P: PIOExt, SM: StateMachineIndex> {
+    _sm: StateMachine<(P, SM), Running>,
+    rx: Rx<(P, SM)>,
+}
+
+impl<P: PIOExt, SM: StateMachineIndex> QuadratureEncoder<P, SM> {
+    /// Install the program and start counting from 0
+    ///   * pin_a - phase A, phase B is on the next pin. Pins need pull-ups for mechanical encoders.
+    ///   * max_step_rate - highest expected rate of transitions per second. The state machine
+    ///     is slowed down to filter the noise. 0 runs it at full speed.
+    pub fn new(
+        pio: &mut PioManager<P>,
+        sm: UninitStateMachine<(P, SM)>,
+        pin_a: u8,
+        max_step_rate: u32,
+        sys_clock_hz: u32,
+    ) -> Result<Self, PioError> {
+        let installed = pio.install("quadrature_encoder", &quadrature_program())?;
+        let (int, frac) = if max_step_rate == 0 {
+            (1, 0)
+        } else {
+            let div = (sys_clock_hz as u64 * 256 / (max_step_rate as u64 * CYCLES_PER_SAMPLE as u64)).clamp(256, 0xff_ffff);
+            ((div >> 8) as u16, (div & 0xff) as u8)
+        };
+        let (sm, rx, _tx) = PIOBuilder::from_program(installed)
+            .in_pin_base(pin_a)
+            .in_shift_direction(ShiftDirection::Left)
+            .out_shift_direction(ShiftDirection::Right)
+            .buffers(Buffers::OnlyRx)
+            .clock_divisor_fixed_point(int, frac)
+            .build(sm);
+        Ok(Self { _sm: sm.start(), rx })
+    }
+
+    /// Current position in transitions. Increases when B leads A.
+    pub fn position(&mut self) -> i32 {
+        // Skip the old values and wait for the next sample
+        for _ in 0..FIFO_DEPTH {
+            if self.rx.read().is_none() {
+                break;
+            }
+        }
+        loop {
+            if let Some(position) = self.rx.read() {
+                return position as i32;
+            }
+        }
+    }
+}
+
+
+/// Speed from the position sampled at any rate
+#[derive(Clone, Copy, Debug)]
+pub struct Velocity {
+    window_us: u64,
+    start: Option<(i32, u64)>,
+    velocity: f32,
+}
+
+impl Velocity {
+    /// The speed is updated when the window has passed, longer window gives better
+    /// resolution at low speeds
+    pub const fn new(window_us: u64) -> Self {
+        Self { window_us, start: None, velocity: 0.0 }
+    }
+
+    /// Add the sample and return the speed in transitions per second
+    ///   * time_us - e.g. from `Timer::get_counter()`
+    pub fn update(&mut self, position: i32, time_us: u64) -> f32 {
+        match self.start {
+            None => self.start = Some((position, time_us)),
+            Some((start_position, start_time)) => {
+                let elapsed = time_us - start_time;
+                if elapsed >= self.window_us {
+                    let delta = position.wrapping_sub(start_position);
+                    self.velocity = delta as f32 * 1_000_000.0 / elapsed as f32;
+                    self.start = Some((position, time_us));
+                }
+            }
+        }
+        self.velocity
+    }
+
+    /// Last computed speed in transitions per second
+    pub fn velocity(&self) -> f32 {
+        self.velocity
+    }
+}
+
+
+/// What happened with the button
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum ButtonEvent {
+    Pressed,
+    /// Released before the long press time
+    Click,
+    /// Held for the long press time. Sent once, the release after it gives no event.
+    LongPress,
+}
+
+
+/// Debounced push-button
+#[derive(Clone, Copy, Debug)]
+pub struct Button {
+    debounce_us: u64,
+    long_press_us: u64,
+    pressed: bool,
+    // Time of the last change of the raw input
+    changed: u64,
+    raw: bool,
+    pressed_at: u64,
+    long: bool,
+}
+
+impl Button {
+    ///   * debounce_us - the input has to be stable for this time
+    ///   * long_press_us - hold time for `LongPress`
+    pub const fn new(debounce_us: u64, long_press_us: u64) -> Self {
+        Self { debounce_us, long_press_us, pressed: false, changed: 0, raw: false, pressed_at: 0, long: false }
+    }
+
+    /// Sample the input, call it at least once per debounce time
+    ///   * pressed - raw state of the button
+    ///   * time_us - e.g. from `Timer::get_counter()`
+    pub fn update(&mut self, pressed: bool, time_us: u64) -> Option<ButtonEvent> {
+        if pressed != self.raw {
+            self.raw = pressed;
+            self.changed = time_us;
+        }
+        let stable = time_us - self.changed >= self.debounce_us;
+        if stable && self.raw != self.pressed {
+            self.pressed = self.raw;
+            if self.pressed {
+                self.pressed_at = time_us;
+                self.long = false;
+                return Some(ButtonEvent::Pressed);
+            } else if !self.long {
+                return Some(ButtonEvent::Click);
+            }
+        } else if self.pressed && !self.long && time_us - self.pressed_at >= self.long_press_us {
+            self.long = true;
+            return Some(ButtonEvent::LongPress);
+        }
+        None
+    }
+
+    /// Debounced state
+    pub fn is_pressed(&self) -> bool {
+        self.pressed
+    }
+}
+
+
+/// Quadrature decoder with the jump table at address 0. Position is in Y and pushed
+/// to RX FIFO after every change (and in every loop when idle).
+/// Use with the input shift to the left, output shift to the right and no autopush/autopull.
+pub fn quadrature_program() -> pio::Program<32> {
+    pio_proc::pio_asm!("
+        .origin 0
+            ; index = previous state << 2 | current state
+            jmp update      ; 00 -> 00
+            jmp decrement   ; 00 -> 01
+            jmp increment   ; 00 -> 10
+            jmp update      ; 00 -> 11
+            jmp increment   ; 01 -> 00
+            jmp update      ; 01 -> 01
+            jmp update      ; 01 -> 10
+            jmp decrement   ; 01 -> 11
+            jmp decrement   ; 10 -> 00
+            jmp update      ; 10 -> 01
+            jmp update      ; 10 -> 10
+            jmp increment   ; 10 -> 11
+            ; the last entries are the code itself
+            jmp update      ; 11 -> 00
+            jmp increment   ; 11 -> 01
+        decrement:
+            jmp y--, update ; 11 -> 10
+        .wrap_target
+        update:
+            mov isr, y      ; 11 -> 11
+            push noblock
+        sample_pins:
+            out isr, 2      ; previous state
+            in pins, 2
+            mov osr, isr
+            mov pc, isr
+        increment:
+            mov y, ~y       ; y + 1 = ~(~y - 1)
+            jmp y--, increment_cont
+        increment_cont:
+            mov y, ~y
+        .wrap
+    ").program
+}
@@ -0,0 +1,117 @@
+//! RC receiver protocols
+//!
+//! Streaming decoders for the serial receivers. Bytes from the UART are pushed one by one
+//! and every complete frame is returned as `RcFrame`, so all the protocols look the same
+//! to the application. Decoders find the frame start again after garbage or lost bytes.
+//!
+//!   * `sbus` - Futaba SBUS, 100000 baud 8E2 inverted
+//!   * `crsf` - Crossfire / ExpressLRS, 420000 baud 8N1
+//!   * `ibus` - FlySky iBus, 115200 baud 8N1
+//!
+//! Nothing here touches the hardware, so the decoders can be tested on the host.
+//!
+pub mod crsf;
+pub mod ibus;
+pub mod sbus;
+
+
+/// Most channels in the frame
+pub const MAX_CHANNELS: usize = 18;
+
+
+/// Channel values and the link state in one frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct RcFrame {
+    /// Servo pulses in us, 1500 in the center
+    pub channels: [u16; MAX_CHANNELS],
+    pub count: usize,
+    /// Receiver lost the transmitter, channels are the failsafe positions (or the last ones)
+    pub failsafe: bool,
+    /// Percent of the frames received, if the protocol tells it
+    pub link_quality: Option<u8>,
+}
+
+impl RcFrame {
+    pub const fn new() -> Self {
+        Self { channels: [1500; MAX_CHANNELS], count: 0, failsafe: false, link_quality: None }
+    }
+
+    /// Servo pulse of the channel (0 is the first one)
+    pub fn channel(&self, channel: usize) -> Option<u16> {
+        (channel < self.count).then(|| self.channels[channel])
+    }
+}
+
+impl Default for RcFrame {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+/// Streaming decoder of the protocol
+pub trait Decoder {
+    /// Add the received byte and return the frame when it is complete
+    fn push(&mut self, byte: u8) -> Option<RcFrame>;
+
+    /// Forget the partial frame, e.g. after a gap on the line
+    fn reset(&mut self);
+}
+
+
+/// 16 channels of 11 bits, LSB first (SBUS and CRSF)
+fn unpack_11bit(data: &[u8]) -> [u16; 16] {
+    let mut channels = [0; 16];
+    let mut bits = 0u32;
+    let mut count = 0;
+    let mut bytes = data.iter();
+    for channel in channels.iter_mut() {
+        while count < 11 {
+            bits |= (*bytes.next().unwrap_or(&0) as u32) << count;
+            count += 8;
+        }
+        *channel = (bits & 0x07FF) as u16;
+        bits >>= 11;
+        count -= 11;
+    }
+    channels
+}
+
+/// 11 bit value of SBUS and CRSF to us: 172 is 988us, 992 is 1500us and 1811 is 2012us
+fn ticks_to_us(value: u16) -> u16 {
+    (1500 + (value as i32 - 992) * 5 / 8) as u16
+}
+
+
+/// Buffer of the frame with the resynchronisation
+#[derive(Clone, Debug)]
+struct FrameBuffer<const N: usize> {
+    buffer: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> FrameBuffer<N> {
+    const fn new() -> Self {
+        Self { buffer: [0; N], len: 0 }
+    }
+
+    fn push(&mut self, byte: u8) {
+        if self.len == N {
+            self.len = 0;
+        }
+        self.buffer[self.len] = byte;
+        self.len += 1;
+    }
+
+    /// Drop the first byte and the following ones until the start byte
+    fn resync(&mut self, is_start: impl Fn(u8) -> bool) {
+        let start = self.buffer[1..self.len].iter().position(|&b| is_start(b)).map_or(self.len, |i| i + 1);
+        self.consume(start);
+    }
+
+    /// Drop the bytes from the front and keep the rest
+    fn consume(&mut self, count: usize) {
+        self.buffer.copy_within(count..self.len, 0);
+        self.len -= count;
+    }
+}
@@ -0,0 +1,156 @@
+//! Crossfire (CRSF) as used by TBS Crossfire and ExpressLRS receivers
+//!
+//! 420000 baud, 8N1. Every frame is:
+//!
+//!   address, length, type, payload, CRC-8
+//!
+//! The length counts the type, payload and CRC. The CRC is CRC-8/DVB-S2 (polynomial 0xD5)
+//! of the type and payload. Channels come in the RC channels frame (16 channels of 11 bits),
+//! the link quality in the link statistics frame. CRSF has no failsafe flag: the receiver
+//! stops sending the channels or reports 0% link quality.
+//!
+use super::{ticks_to_us, unpack_11bit, Decoder, FrameBuffer, RcFrame};
+
+
+const MAX_FRAME: usize = 64;
+/// Addresses: flight controller, radio, TX module, receiver
+const ADDRESSES: [u8; 4] = [0xC8, 0xEA, 0xEE, 0xEC];
+const RC_CHANNELS: u8 = 0x16;
+const LINK_STATISTICS: u8 = 0x14;
+
+
+/// CRC-8/DVB-S2
+pub fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0xD5 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+
+/// Link statistics frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct LinkStatistics {
+    /// dBm
+    pub uplink_rssi: [i16; 2],
+    /// Percent of the received packets
+    pub uplink_link_quality: u8,
+    /// dB
+    pub uplink_snr: i8,
+    pub active_antenna: u8,
+    pub rf_mode: u8,
+    pub tx_power: u8,
+    pub downlink_rssi: i16,
+    pub downlink_link_quality: u8,
+    pub downlink_snr: i8,
+}
+
+impl LinkStatistics {
+    fn decode(payload: &[u8]) -> Self {
+        Self {
+            uplink_rssi: [-(payload[0] as i16), -(payload[1] as i16)],
+            uplink_link_quality: payload[2],
+            uplink_snr: payload[3] as i8,
+            active_antenna: payload[4],
+            rf_mode: payload[5],
+            tx_power: payload[6],
+            downlink_rssi: -(payload[7] as i16),
+            downlink_link_quality: payload[8],
+            downlink_snr: payload[9] as i8,
+        }
+    }
+}
+
+
+/// CRSF decoder. Frames of the other types are checked and skipped.
+#[derive(Clone, Debug)]
+pub struct CrsfDecoder {
+    frame: FrameBuffer<MAX_FRAME>,
+    link: Option<LinkStatistics>,
+    crc_errors: u32,
+}
+
+impl CrsfDecoder {
+    pub const fn new() -> Self {
+        Self { frame: FrameBuffer::new(), link: None, crc_errors: 0 }
+    }
+
+    /// Last link statistics from the receiver
+    pub fn link_statistics(&self) -> Option<LinkStatistics> {
+        self.link
+    }
+
+    pub fn crc_errors(&self) -> u32 {
+        self.crc_errors
+    }
+
+    /// Handle the frame with the valid CRC
+    fn decode(&mut self, frame_type: u8, payload: &[u8]) -> Option<RcFrame> {
+        match frame_type {
+            RC_CHANNELS if payload.len() == 22 => {
+                let mut frame = RcFrame::new();
+                for (us, value) in frame.channels.iter_mut().zip(unpack_11bit(payload)) {
+                    *us = ticks_to_us(value);
+                }
+                frame.count = 16;
+                frame.link_quality = self.link.map(|link| link.uplink_link_quality);
+                frame.failsafe = frame.link_quality == Some(0);
+                Some(frame)
+            }
+            LINK_STATISTICS if payload.len() == 10 => {
+                self.link = Some(LinkStatistics::decode(payload));
+                None
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Default for CrsfDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder for CrsfDecoder {
+    fn push(&mut self, byte: u8) -> Option<RcFrame> {
+        self.frame.push(byte);
+        while self.frame.len > 0 {
+            if !ADDRESSES.contains(&self.frame.buffer[0]) {
+                self.frame.resync(|b| ADDRESSES.contains(&b));
+                continue;
+            }
+            if self.frame.len < 2 {
+                return None;
+            }
+            let len = self.frame.buffer[1] as usize + 2;
+            if !(4..=MAX_FRAME).contains(&len) {
+                self.frame.resync(|b| ADDRESSES.contains(&b));
+                continue;
+            }
+            if self.frame.len < len {
+                return None;
+            }
+            if crc8(&self.frame.buffer[2..len - 1]) != self.frame.buffer[len - 1] {
+                self.crc_errors += 1;
+                self.frame.resync(|b| ADDRESSES.contains(&b));
+                continue;
+            }
+            let mut data = [0; MAX_FRAME];
+            data[..len].copy_from_slice(&self.frame.buffer[..len]);
+            self.frame.consume(len);
+            if let Some(frame) = self.decode(data[2], &data[3..len - 1]) {
+                return Some(frame);
+            }
+        }
+        None
+    }
+
+    fn reset(&mut self) {
+        self.frame.len = 0;
+    }
+}
@@ -0,0 +1,81 @@
+//! FlySky iBus
+//!
+//! 32 byte frame every 7ms at 115200 baud, 8N1:
+//!
+//!   0x20, 0x40, 14 channels in us (u16, LSB first), checksum (u16, LSB first)
+//!
+//! The checksum is 0xFFFF minus the sum of all the bytes before it. iBus has no failsafe
+//! flag: depending on the setting the receiver sends the failsafe positions or stops
+//! sending, so use a timeout.
+//!
+use super::{Decoder, FrameBuffer, RcFrame};
+
+
+const FRAME: usize = 32;
+const HEADER: [u8; 2] = [0x20, 0x40];
+const CHANNELS: usize = 14;
+
+
+/// iBus decoder
+#[derive(Clone, Debug)]
+pub struct IbusDecoder {
+    frame: FrameBuffer<FRAME>,
+    checksum_errors: u32,
+}
+
+impl IbusDecoder {
+    pub const fn new() -> Self {
+        Self { frame: FrameBuffer::new(), checksum_errors: 0 }
+    }
+
+    pub fn checksum_errors(&self) -> u32 {
+        self.checksum_errors
+    }
+
+    fn decode(&self) -> RcFrame {
+        let data = &self.frame.buffer;
+        let mut frame = RcFrame::new();
+        for (us, pair) in frame.channels.iter_mut().zip(data[2..2 + 2 * CHANNELS].chunks_exact(2)) {
+            // Upper nibble carries the extra channels of some receivers
+            *us = u16::from_le_bytes([pair[0], pair[1]]) & 0x0FFF;
+        }
+        frame.count = CHANNELS;
+        frame
+    }
+}
+
+impl Default for IbusDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder for IbusDecoder {
+    fn push(&mut self, byte: u8) -> Option<RcFrame> {
+        self.frame.push(byte);
+        while self.frame.len > 0 {
+            let data = &self.frame.buffer;
+            if data[0] != HEADER[0] || (self.frame.len > 1 && data[1] != HEADER[1]) {
+                self.frame.resync(|b| b == HEADER[0]);
+                continue;
+            }
+            if self.frame.len < FRAME {
+                return None;
+            }
+            let sum = data[..FRAME - 2].iter().fold(0u16, |sum, &b| sum.wrapping_add(b as u16));
+            if 0xFFFF - sum != u16::from_le_bytes([data[FRAME - 2], data[FRAME - 1]]) {
+                self.checksum_errors += 1;
+                self.frame.resync(|b| b == HEADER[0]);
+                continue;
+            }
+            let frame = self.decode();
+            self.frame.consume(FRAME);
+            return Some(frame);
+        }
+        None
+    }
+
+    fn reset(&mut self) {
+        self.frame.len = 0;
+    }
+}
@@ -0,0 +1,86 @@
+//! Futaba SBUS
+//!
+//! 25 byte frame every 7 or 14ms at 100000 baud, 8E2 with the inverted line:
+//!
+//!   0x0F, 16 channels of 11 bits (LSB first), flags, 0x00 (SBUS2: 0x04, 0x14, 0x24 or 0x34)
+//!
+//! Flags are the digital channels 17 and 18, the lost frame and the failsafe bits.
+//! There is no checksum, so the header, the unused flag bits and the end byte are used to find
+//! the frame.
+//!
+use super::{ticks_to_us, unpack_11bit, Decoder, FrameBuffer, RcFrame};
+
+
+const FRAME: usize = 25;
+const HEADER: u8 = 0x0F;
+const CH17: u8 = 0x01;
+const CH18: u8 = 0x02;
+const FRAME_LOST: u8 = 0x04;
+const FAILSAFE: u8 = 0x08;
+
+
+/// SBUS decoder. Link quality is the share of the last 32 frames without the lost frame flag.
+#[derive(Clone, Debug)]
+pub struct SbusDecoder {
+    frame: FrameBuffer<FRAME>,
+    /// One bit per frame, set when it was received
+    history: u32,
+}
+
+impl SbusDecoder {
+    pub const fn new() -> Self {
+        Self { frame: FrameBuffer::new(), history: u32::MAX }
+    }
+
+    fn decode(&mut self) -> RcFrame {
+        let data = &self.frame.buffer;
+        let flags = data[23];
+        let mut frame = RcFrame::new();
+        for (us, value) in frame.channels.iter_mut().zip(unpack_11bit(&data[1..23])) {
+            *us = ticks_to_us(value);
+        }
+        frame.channels[16] = if flags & CH17 != 0 { 2012 } else { 988 };
+        frame.channels[17] = if flags & CH18 != 0 { 2012 } else { 988 };
+        frame.count = 18;
+        self.history = self.history << 1 | (flags & FRAME_LOST == 0) as u32;
+        frame.link_quality = Some((self.history.count_ones() * 100 / 32) as u8);
+        frame.failsafe = flags & FAILSAFE != 0;
+        frame
+    }
+}
+
+impl Default for SbusDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder for SbusDecoder {
+    fn push(&mut self, byte: u8) -> Option<RcFrame> {
+        self.frame.push(byte);
+        while self.frame.len > 0 {
+            if self.frame.buffer[0] != HEADER {
+                self.frame.resync(|b| b == HEADER);
+                continue;
+            }
+            if self.frame.len < FRAME {
+                return None;
+            }
+            // Upper bits of the flags are never set
+            let flags = self.frame.buffer[FRAME - 2];
+            let end = self.frame.buffer[FRAME - 1];
+            if flags & 0xF0 != 0 || (end != 0x00 && end & 0x0F != 0x04) {
+                self.frame.resync(|b| b == HEADER);
+                continue;
+            }
+            let frame = self.decode();
+            self.frame.consume(FRAME);
+            return Some(frame);
+        }
+        None
+    }
+
+    fn reset(&mut self) {
+        self.frame.len = 0;
+    }
+}
@@ -0,0 +1,324 @@
+//! Interactive command shell for a serial terminal
+//!
+//! Bytes from the terminal are pushed into the `Shell`, which edits the line and runs
+//! the command on Enter. The output goes to any `fmt::Write`, e.g. the `UartPeripheral`.
+//!
+//!   * backspace deletes the last character, Ctrl-C drops the line
+//!   * up and down arrows go through the history
+//!   * tab completes the command name, or lists the candidates
+//!
+//! Commands are registered in a table. The first word of the line selects the command,
+//! the rest is given to it as `Args`, which parses the words into the typed values:
+//!
+//! ```ignore
+//! const COMMANDS: [Command<Board>; 1] = [
+//!     Command { name: "servo", help: "servo <us>", run: servo },
+//! ];
+//!
+//! fn servo(board: &mut Board, args: &Args, out: &mut dyn Write) -> Result<(), ArgError> {
+//!     let pulse: u16 = args.get(0)?;
+//!     ...
+//! }
+//! ```
+//!
+use core::fmt::{self, Write};
+
+
+/// Longest line
+pub const MAX_LINE: usize = 64;
+/// Most words after the command name
+pub const MAX_ARGS: usize = 8;
+/// Remembered lines
+const HISTORY: usize = 8;
+const PROMPT: &str = "> ";
+
+
+/// Why the arguments don't fit the command
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum ArgError {
+    Missing { index: usize },
+    Invalid { index: usize, expected: &'static str },
+    /// Something else, e.g. an unknown subcommand
+    Usage,
+}
+
+
+/// Value which can be parsed from the word of the command line
+pub trait FromArg<'a>: Sized {
+    /// Shown in the error message
+    const EXPECTED: &'static str;
+
+    fn from_arg(word: &'a str) -> Option<Self>;
+}
+
+macro_rules! from_arg_parse {
+    ($($t:ty => $name:expr),*) => {
+        $(
+            impl FromArg<'_> for $t {
+                const EXPECTED: &'static str = $name;
+
+                fn from_arg(word: &str) -> Option<Self> {
+                    word.parse().ok()
+                }
+            }
+        )*
+    };
+}
+
+from_arg_parse!(u8 => "0-255", u16 => "0-65535", u32 => "number", i32 => "number", f32 => "number");
+
+impl FromArg<'_> for bool {
+    const EXPECTED: &'static str = "on or off";
+
+    fn from_arg(word: &str) -> Option<Self> {
+        match word {
+            "on" | "1" | "true" => Some(true),
+            "off" | "0" | "false" => Some(false),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> FromArg<'a> for &'a str {
+    const EXPECTED: &'static str = "text";
+
+    fn from_arg(word: &'a str) -> Option<Self> {
+        Some(word)
+    }
+}
+
+
+/// Words after the command name
+#[derive(Clone, Copy, Debug)]
+pub struct Args<'a> {
+    words: [&'a str; MAX_ARGS],
+    len: usize,
+}
+
+impl<'a> Args<'a> {
+    /// Split the text into the words, the ones over `MAX_ARGS` are dropped
+    pub fn new(text: &'a str) -> Self {
+        let mut args = Self { words: [""; MAX_ARGS], len: 0 };
+        for word in text.split_whitespace().take(MAX_ARGS) {
+            args.words[args.len] = word;
+            args.len += 1;
+        }
+        args
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Parse the word (0 is the first one after the command)
+    pub fn get<T: FromArg<'a>>(&self, index: usize) -> Result<T, ArgError> {
+        let word = self.words[..self.len].get(index).ok_or(ArgError::Missing { index })?;
+        T::from_arg(word).ok_or(ArgError::Invalid { index, expected: T::EXPECTED })
+    }
+
+    /// Parse the word, or use the default when it is missing
+    pub fn get_or<T: FromArg<'a>>(&self, index: usize, default: T) -> Result<T, ArgError> {
+        match self.get(index) {
+            Err(ArgError::Missing { .. }) => Ok(default),
+            result => result,
+        }
+    }
+}
+
+
+/// Handler of the command
+pub type Run<C> = fn(&mut C, &Args, &mut dyn Write) -> Result<(), ArgError>;
+
+
+/// Command of the shell
+pub struct Command<C> {
+    pub name: &'static str,
+    /// Usage shown by `help` and after the wrong arguments
+    pub help: &'static str,
+    pub run: Run<C>,
+}
+
+
+/// Line of the terminal
+#[derive(Clone, Copy)]
+struct Line {
+    buffer: [u8; MAX_LINE],
+    len: usize,
+}
+
+impl Line {
+    const EMPTY: Line = Line { buffer: [0; MAX_LINE], len: 0 };
+
+    fn as_str(&self) -> &str {
+        // Only printable ASCII is added
+        core::str::from_utf8(&self.buffer[..self.len]).unwrap_or("")
+    }
+}
+
+
+/// State of the escape sequence from the terminal
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Escape {
+    None,
+    Esc,
+    Csi,
+}
+
+
+/// Line editor which runs the commands
+pub struct Shell<C: 'static> {
+    commands: &'static [Command<C>],
+    line: Line,
+    history: [Line; HISTORY],
+    /// Lines in the history
+    history_len: usize,
+    /// Position when going through the history, 0 is the edited line
+    history_pos: usize,
+    escape: Escape,
+    last_cr: bool,
+}
+
+impl<C> Shell<C> {
+    pub const fn new(commands: &'static [Command<C>]) -> Self {
+        Self {
+            commands,
+            line: Line::EMPTY,
+            history: [Line::EMPTY; HISTORY],
+            history_len: 0,
+            history_pos: 0,
+            escape: Escape::None,
+            last_cr: false,
+        }
+    }
+
+    /// Print the prompt, e.g. after the start
+    pub fn prompt(&self, out: &mut dyn Write) -> fmt::Result {
+        out.write_str(PROMPT)
+    }
+
+    /// Handle the byte from the terminal
+    ///   * context - given to the commands, e.g. the peripherals
+    pub fn push(&mut self, byte: u8, context: &mut C, out: &mut dyn Write) -> fmt::Result {
+        let last_cr = core::mem::replace(&mut self.last_cr, byte == b'\r');
+        match (self.escape, byte) {
+            (Escape::Esc, b'[') => self.escape = Escape::Csi,
+            (Escape::Csi, b'A') => {
+                self.escape = Escape::None;
+                self.recall(self.history_pos + 1, out)?;
+            }
+            (Escape::Csi, b'B') => {
+                self.escape = Escape::None;
+                self.recall(self.history_pos.saturating_sub(1), out)?;
+            }
+            // Parameters of the sequence
+            (Escape::Csi, b'0'..=b'9' | b';') => {}
+            (Escape::Esc | Escape::Csi, _) => self.escape = Escape::None,
+            (_, 0x1B) => self.escape = Escape::Esc,
+            // Windows terminals send CR LF
+            (_, b'\n') if last_cr => {}
+            (_, b'\r' | b'\n') => {
+                out.write_str("\r\n")?;
+                self.execute(context, out)?;
+                out.write_str(PROMPT)?;
+            }
+            (_, 0x08 | 0x7F) if self.line.len > 0 => {
+                self.line.len -= 1;
+                out.write_str("\x08 \x08")?;
+            }
+            // Ctrl-C
+            (_, 0x03) => {
+                self.line.len = 0;
+                self.history_pos = 0;
+                out.write_str("^C\r\n")?;
+                out.write_str(PROMPT)?;
+            }
+            (_, b'\t') => self.complete(out)?,
+            (_, 0x20..=0x7E) if self.line.len < MAX_LINE => {
+                self.line.buffer[self.line.len] = byte;
+                self.line.len += 1;
+                out.write_char(byte as char)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Run the edited line and remember it
+    fn execute(&mut self, context: &mut C, out: &mut dyn Write) -> fmt::Result {
+        let line = self.line;
+        self.line.len = 0;
+        self.history_pos = 0;
+        let text = line.as_str().trim();
+        if text.is_empty() {
+            return Ok(());
+        }
+        if self.history_len == 0 || self.history[0].as_str() != line.as_str() {
+            self.history.copy_within(..HISTORY - 1, 1);
+            self.history[0] = line;
+            self.history_len = (self.history_len + 1).min(HISTORY);
+        }
+
+        let (name, rest) = text.split_once(' ').unwrap_or((text, ""));
+        if name == "help" {
+            for command in self.commands {
+                writeln!(out, "{:<8} {}\r", command.name, command.help)?;
+            }
+            return Ok(());
+        }
+        let Some(command) = self.commands.iter().find(|c| c.name == name) else {
+            return write!(out, "Unknown command: {name}, try help\r\n");
+        };
+        match (command.run)(context, &Args::new(rest), out) {
+            Ok(()) => Ok(()),
+            Err(ArgError::Missing { index }) => write!(out, "Missing argument {}\r\nUsage: {}\r\n", index + 1, command.help),
+            Err(ArgError::Invalid { index, expected }) => {
+                write!(out, "Argument {} should be {}\r\nUsage: {}\r\n", index + 1, expected, command.help)
+            }
+            Err(ArgError::Usage) => write!(out, "Usage: {}\r\n", command.help),
+        }
+    }
+
+    /// Replace the line with the one from the history
+    fn recall(&mut self, pos: usize, out: &mut dyn Write) -> fmt::Result {
+        if pos > self.history_len || pos == self.history_pos {
+            return Ok(());
+        }
+        self.line = if pos == 0 { Line::EMPTY } else { self.history[pos - 1] };
+        self.history_pos = pos;
+        write!(out, "\r\x1B[K{}{}", PROMPT, self.line.as_str())
+    }
+
+    /// Complete the command name
+    fn complete(&mut self, out: &mut dyn Write) -> fmt::Result {
+        let line = self.line;
+        let prefix = line.as_str();
+        if prefix.contains(' ') {
+            return Ok(());
+        }
+        let mut candidates = self.commands.iter().map(|c| c.name).chain(["help"]).filter(|name| name.starts_with(prefix));
+        match (candidates.next(), candidates.next()) {
+            (None, _) => Ok(()),
+            (Some(name), None) => {
+                let rest = &name[prefix.len()..];
+                for &byte in rest.as_bytes().iter().chain(b" ") {
+                    if self.line.len < MAX_LINE {
+                        self.line.buffer[self.line.len] = byte;
+                        self.line.len += 1;
+                    }
+                }
+                write!(out, "{rest} ")
+            }
+            (Some(first), Some(second)) => {
+                write!(out, "\r\n{first}  {second}")?;
+                for name in candidates {
+                    write!(out, "  {name}")?;
+                }
+                write!(out, "\r\n{}{}", PROMPT, prefix)
+            }
+        }
+    }
+}
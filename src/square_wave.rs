@@ -0,0 +1,170 @@
+//! Square wave generator on PIO
+//!
+//! Frequency and duty cycle are set by two 16 bit counters, so the state machine doesn't
+//! have to run from the slowest clock. With the divisor the range is from about 0.015Hz
+//! (at 50% duty, the longer part of the period has to fit its counter) up to sys_clock/8.
+//!
+//! The counters are sent through TX FIFO as a single word and the program takes them at the
+//! start of every period, so the frequency can be changed while it is running without glitches.
+//! Only the change of the clock divisor (large frequency jumps) affects the current period.
+//!
+#[allow(unused_imports)]
+use num_traits::float::Float;
+use rp_pico::hal::pio::{
+    Buffers, PIOBuilder, PIOExt, PinDir, Running, ShiftDirection, StateMachine, StateMachineIndex, Tx,
+    UninitStateMachine,
+};
+
+use crate::pio_manager::{PioError, PioManager};
+
+
+/// Extra state machine cycles in the high part of the period
+const HIGH_OVERHEAD: u32 = 3;
+/// Extra state machine cycles in the low part of the period
+const LOW_OVERHEAD: u32 = 5;
+/// Longest high part for a single divisor
+const MAX_HIGH_CYCLES: u32 = u16::MAX as u32 + HIGH_OVERHEAD;
+/// Longest low part for a single divisor
+const MAX_LOW_CYCLES: u32 = u16::MAX as u32 + LOW_OVERHEAD;
+
+
+/// Why the frequency can't be generated
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum FrequencyError {
+    TooHigh,
+    TooLow,
+}
+
+
+/// Divisor and counters for the requested frequency
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Timing {
+    /// Integer PIO clock divisor. 0 is 65536.
+    pub divisor: u16,
+    /// Counter for the high part
+    pub high: u16,
+    /// Counter for the low part
+    pub low: u16,
+}
+
+impl Timing {
+    /// Find the smallest integer divisor (no jitter) where both parts of the period fit into the counters
+    ///   * sys_clock_hz - system clock
+    ///   * freq - output frequency in Hz
+    ///   * duty - fraction of the period with high output (0.0 - 1.0)
+    pub fn new(sys_clock_hz: u32, freq: f32, duty: f32) -> Result<Self, FrequencyError> {
+        let period = sys_clock_hz as f64 / freq as f64;
+        let duty = duty.clamp(0.0, 1.0) as f64;
+        // The longer part decides
+        let first = (period * duty / MAX_HIGH_CYCLES as f64)
+            .max(period * (1.0 - duty) / MAX_LOW_CYCLES as f64)
+            .ceil()
+            .max(1.0);
+        if first > 65_536.0 {
+            return Err(FrequencyError::TooLow);
+        }
+        // Rounding can overflow the counter by a cycle, the next divisor always fits
+        for divisor in first as u32..=first as u32 + 1 {
+            let cycles = (period / divisor as f64 + 0.5) as u32;
+            if cycles < HIGH_OVERHEAD + LOW_OVERHEAD {
+                return Err(FrequencyError::TooHigh);
+            }
+            let high = ((cycles as f64 * duty + 0.5) as u32).clamp(HIGH_OVERHEAD, cycles - LOW_OVERHEAD);
+            let low = cycles - high;
+            if let (Ok(high), Ok(low)) = (u16::try_from(high - HIGH_OVERHEAD), u16::try_from(low - LOW_OVERHEAD)) {
+                // 65536 is written as 0
+                return Ok(Self { divisor: divisor as u16, high, low });
+            }
+        }
+        Err(FrequencyError::TooLow)
+    }
+
+    /// Number of state machine cycles in one period
+    pub const fn cycles(&self) -> u32 {
+        self.high as u32 + self.low as u32 + HIGH_OVERHEAD + LOW_OVERHEAD
+    }
+
+    /// Generated frequency
+    pub fn frequency(&self, sys_clock_hz: u32) -> f32 {
+        let divisor = if self.divisor == 0 { 65_536 } else { self.divisor as u32 };
+        (sys_clock_hz as f64 / (divisor as f64 * self.cycles() as f64)) as f32
+    }
+
+    /// Generated duty cycle
+    pub fn duty(&self) -> f32 {
+        (self.high as u32 + HIGH_OVERHEAD) as f32 / self.cycles() as f32
+    }
+
+    /// Word for TX FIFO: high counter in the lower half
+    pub const fn word(&self) -> u32 {
+        self.high as u32 | (self.low as u32) << 16
+    }
+}
+
+
+/// Running square wave generator
+pub struct SquareWave<P: PIOExt, SM: StateMachineIndex> {
+    sm: StateMachine<(P, SM), Running>,
+    tx: Tx<(P, SM)>,
+    timing: Timing,
+}
+
+impl<P: PIOExt, SM: StateMachineIndex> SquareWave<P, SM> {
+    /// Install the program and start the state machine
+    ///   * pin - output
+    ///   * timing - from `Timing::new()`
+    pub fn start(
+        pio: &mut PioManager<P>,
+        sm: UninitStateMachine<(P, SM)>,
+        pin: u8,
+        timing: Timing,
+    ) -> Result<Self, PioError> {
+        let installed = pio.install("square_wave", &square_wave_program())?;
+        let (mut sm, _rx, mut tx) = PIOBuilder::from_program(installed)
+            .set_pins(pin, 1)
+            .out_shift_direction(ShiftDirection::Right) // high counter first
+            .buffers(Buffers::OnlyTx)
+            .clock_divisor_fixed_point(timing.divisor, 0)
+            .build(sm);
+        sm.set_pindirs([(pin, PinDir::Output)]);
+        tx.write(timing.word());
+        Ok(Self { sm: sm.start(), tx, timing })
+    }
+
+    /// Change frequency and duty cycle. The new values are used from the next period.
+    pub fn retune(&mut self, timing: Timing) {
+        if timing.divisor != self.timing.divisor {
+            self.sm.clock_divisor_fixed_point(timing.divisor, 0);
+        }
+        // Only the last setting matters
+        self.sm.clear_fifos();
+        self.tx.write(timing.word());
+        self.timing = timing;
+    }
+
+    /// Current setting
+    pub fn timing(&self) -> Timing {
+        self.timing
+    }
+}
+
+
+/// Square wave with the counters from TX FIFO. If there is no new word,
+/// the previous one is used again (it is kept in X).
+/// Period is high + low + 8 cycles.
+pub fn square_wave_program() -> pio::Program<32> {
+    pio_proc::pio_asm!("
+        .wrap_target
+            pull noblock        ; OSR = X if there is no new setting
+            mov x, osr
+            out y, 16
+            set pins, 1
+        high:
+            jmp y-- high
+            out y, 16
+            set pins, 0
+        low:
+            jmp y-- low
+        .wrap
+    ").program
+}
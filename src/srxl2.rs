@@ -0,0 +1,470 @@
+//! Spektrum SRXL2 serial receiver protocol
+//!
+//! Half-duplex bus (115200 or 400000 baud, 8N1) between the receiver (bus master) and
+//! devices like flight controllers. Every packet is:
+//!
+//!   0xA6, packet type, length of the whole packet, payload, CRC-16 (MSB first)
+//!
+//! The CRC is CRC-16/XMODEM (polynomial 0x1021, initial value 0) over everything before it.
+//!
+//! `Parser` finds the packets in the byte stream and `Device` implements a bus device:
+//! it answers the handshake of the receiver, takes the baud rate from its broadcast,
+//! keeps the channel values with the failsafe state and replies with telemetry when
+//! the receiver gives it the slot.
+//!
+//! Nothing here touches the hardware, so the protocol can be tested on the host.
+//!
+/// First byte of every packet
+pub const SYNC: u8 = 0xA6;
+/// Longest packet on the bus
+pub const MAX_PACKET: usize = 80;
+/// Shortest packet: header and CRC
+const MIN_PACKET: usize = 5;
+/// Device ID for the broadcast
+pub const BROADCAST: u8 = 0xFF;
+/// Usual ID of the main receiver
+pub const RECEIVER_ID: u8 = 0x21;
+/// Usual ID of the flight controller
+pub const FLIGHT_CONTROLLER_ID: u8 = 0x30;
+/// Length of the telemetry payload
+pub const TELEMETRY_PAYLOAD: usize = 16;
+/// Channel value in the center position
+pub const CHANNEL_CENTER: u16 = 0x8000;
+
+const HANDSHAKE: u8 = 0x21;
+const TELEMETRY: u8 = 0x80;
+const CONTROL: u8 = 0xCD;
+const HANDSHAKE_LEN: usize = 14;
+const TELEMETRY_LEN: usize = 22;
+/// Header of the control packet before the channel values
+const CONTROL_HEADER: usize = 12;
+/// Bit in `baud_supported` of the handshake
+const BAUD_400000: u8 = 0x01;
+/// Devices wait this long for the receiver before they send the handshake themselves
+const HANDSHAKE_TIMEOUT_US: u64 = 50_000;
+/// Without control data for this long the channels are in failsafe
+const FAILSAFE_TIMEOUT_US: u64 = 100_000;
+
+
+/// CRC-16/XMODEM
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+
+/// Handshake packet, sent to negotiate the device IDs and the baud rate
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Handshake {
+    pub src: u8,
+    pub dest: u8,
+    pub priority: u8,
+    /// Bit 0: 400000 baud. In the broadcast from the receiver it is the baud rate to use.
+    pub baud_supported: u8,
+    pub info: u8,
+    pub uid: u32,
+}
+
+impl Handshake {
+    /// Write the packet and return its length
+    pub fn encode(&self, out: &mut [u8]) -> usize {
+        let uid = self.uid.to_le_bytes();
+        let payload = [self.src, self.dest, self.priority, self.baud_supported, self.info, uid[0], uid[1], uid[2], uid[3]];
+        encode(HANDSHAKE, &payload, out)
+    }
+}
+
+
+/// Why the channel values were sent
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Command {
+    Channels,
+    /// Receiver lost the transmitter, values are the failsafe positions
+    Failsafe,
+    /// VTX or forward programming data, not decoded
+    Other(u8),
+}
+
+
+/// Values of up to 32 channels. Only the channels in the mask were sent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Channels {
+    pub mask: u32,
+    pub values: [u16; 32],
+}
+
+impl Channels {
+    pub const fn new() -> Self {
+        Self { mask: 0, values: [CHANNEL_CENTER; 32] }
+    }
+
+    /// Value of the channel (0 is the first one), if it was ever received
+    pub fn get(&self, channel: usize) -> Option<u16> {
+        (channel < 32 && self.mask & (1 << channel) != 0).then(|| self.values[channel])
+    }
+
+    /// Take the channels present in the other update
+    pub fn update(&mut self, other: &Channels) {
+        for channel in 0..32 {
+            if other.mask & (1 << channel) != 0 {
+                self.values[channel] = other.values[channel];
+            }
+        }
+        self.mask |= other.mask;
+    }
+}
+
+impl Default for Channels {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+/// Servo pulse for the channel value: 903us - 2097us, 1500us in the center
+pub const fn channel_to_us(value: u16) -> u16 {
+    (903 + (value as u32 * 1194 + 32768) / 65536) as u16
+}
+
+
+/// Control data packet from the receiver
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct ControlData {
+    pub command: Command,
+    /// Device which can send telemetry after this packet
+    pub reply_id: u8,
+    /// Positive in %, negative in dBm
+    pub rssi: i8,
+    pub frame_losses: u16,
+    pub channels: Channels,
+}
+
+
+/// Decoded packet
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Packet {
+    Handshake(Handshake),
+    Control(ControlData),
+    Telemetry { dest: u8, payload: [u8; TELEMETRY_PAYLOAD] },
+    /// Valid packet of the other type (bind info, parameters, signal quality)
+    Other { packet_type: u8 },
+}
+
+impl Packet {
+    /// Decode the packet with the valid CRC
+    fn decode(packet: &[u8]) -> Option<Packet> {
+        let payload = &packet[3..packet.len() - 2];
+        match packet[1] {
+            HANDSHAKE if packet.len() == HANDSHAKE_LEN => Some(Packet::Handshake(Handshake {
+                src: payload[0],
+                dest: payload[1],
+                priority: payload[2],
+                baud_supported: payload[3],
+                info: payload[4],
+                uid: u32::from_le_bytes([payload[5], payload[6], payload[7], payload[8]]),
+            })),
+            TELEMETRY if packet.len() == TELEMETRY_LEN => {
+                let mut data = [0; TELEMETRY_PAYLOAD];
+                data.copy_from_slice(&payload[1..]);
+                Some(Packet::Telemetry { dest: payload[0], payload: data })
+            }
+            CONTROL if packet.len() >= CONTROL_HEADER + 2 => {
+                let command = match payload[0] {
+                    0x00 => Command::Channels,
+                    0x01 => Command::Failsafe,
+                    other => Command::Other(other),
+                };
+                let reply_id = payload[1];
+                if let Command::Other(_) = command {
+                    return Some(Packet::Control(ControlData {
+                        command,
+                        reply_id,
+                        rssi: 0,
+                        frame_losses: 0,
+                        channels: Channels::new(),
+                    }));
+                }
+                let mask = u32::from_le_bytes([payload[5], payload[6], payload[7], payload[8]]);
+                let values = &payload[9..];
+                if values.len() != 2 * mask.count_ones() as usize {
+                    return None;
+                }
+                let mut channels = Channels { mask, values: [CHANNEL_CENTER; 32] };
+                let mut pairs = values.chunks_exact(2);
+                for channel in (0..32).filter(|ch| mask & (1 << ch) != 0) {
+                    let pair = pairs.next()?;
+                    channels.values[channel] = u16::from_le_bytes([pair[0], pair[1]]);
+                }
+                Some(Packet::Control(ControlData {
+                    command,
+                    reply_id,
+                    rssi: payload[2] as i8,
+                    frame_losses: u16::from_le_bytes([payload[3], payload[4]]),
+                    channels,
+                }))
+            }
+            HANDSHAKE | TELEMETRY | CONTROL => None,
+            packet_type => Some(Packet::Other { packet_type }),
+        }
+    }
+}
+
+
+/// Write the packet with the header and CRC, return its length
+pub fn encode(packet_type: u8, payload: &[u8], out: &mut [u8]) -> usize {
+    let len = payload.len() + MIN_PACKET;
+    out[0] = SYNC;
+    out[1] = packet_type;
+    out[2] = len as u8;
+    out[3..len - 2].copy_from_slice(payload);
+    let crc = crc16(&out[..len - 2]);
+    out[len - 2..len].copy_from_slice(&crc.to_be_bytes());
+    len
+}
+
+/// Write the telemetry packet, return its length
+pub fn encode_telemetry(dest: u8, payload: &[u8; TELEMETRY_PAYLOAD], out: &mut [u8]) -> usize {
+    let mut data = [0; TELEMETRY_PAYLOAD + 1];
+    data[0] = dest;
+    data[1..].copy_from_slice(payload);
+    encode(TELEMETRY, &data, out)
+}
+
+
+/// Statistics of the parser
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub struct ParserStats {
+    pub packets: u32,
+    pub crc_errors: u32,
+    /// Bytes skipped while looking for the start of the packet
+    pub skipped: u32,
+}
+
+
+/// Finds packets in the byte stream
+#[derive(Clone, Debug)]
+pub struct Parser {
+    buffer: [u8; MAX_PACKET],
+    len: usize,
+    stats: ParserStats,
+}
+
+impl Parser {
+    pub const fn new() -> Self {
+        Self { buffer: [0; MAX_PACKET], len: 0, stats: ParserStats { packets: 0, crc_errors: 0, skipped: 0 } }
+    }
+
+    /// Add the received byte and return the packet when it is complete
+    pub fn push(&mut self, byte: u8) -> Option<Packet> {
+        self.buffer[self.len] = byte;
+        self.len += 1;
+        while self.len > 0 {
+            if self.buffer[0] != SYNC {
+                self.resync();
+                continue;
+            }
+            if self.len < 3 {
+                return None;
+            }
+            let expected = self.buffer[2] as usize;
+            if !(MIN_PACKET..=MAX_PACKET).contains(&expected) {
+                self.resync();
+                continue;
+            }
+            if self.len < expected {
+                return None;
+            }
+            let crc = u16::from_be_bytes([self.buffer[expected - 2], self.buffer[expected - 1]]);
+            if crc != crc16(&self.buffer[..expected - 2]) {
+                self.stats.crc_errors += 1;
+                self.resync();
+                continue;
+            }
+            let packet = Packet::decode(&self.buffer[..expected]);
+            // Bytes after the packet are kept, they can be the start of the next one
+            self.buffer.copy_within(expected..self.len, 0);
+            self.len -= expected;
+            if packet.is_some() {
+                self.stats.packets += 1;
+                return packet;
+            }
+        }
+        None
+    }
+
+    /// Forget the partial packet, e.g. after a gap on the line
+    pub fn reset(&mut self) {
+        self.len = 0;
+    }
+
+    pub fn stats(&self) -> ParserStats {
+        self.stats
+    }
+
+    /// Drop the first byte and look for the next sync byte in the rest
+    fn resync(&mut self) {
+        let rest = self.buffer[1..self.len].iter().position(|&b| b == SYNC);
+        match rest {
+            Some(start) => {
+                self.stats.skipped += start as u32 + 1;
+                self.buffer.copy_within(start + 1..self.len, 0);
+                self.len -= start + 1;
+            }
+            None => {
+                self.stats.skipped += self.len as u32;
+                self.len = 0;
+            }
+        }
+    }
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+/// Connection to the receiver
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum State {
+    /// Waiting for the handshake from the receiver
+    Handshake,
+    /// Replied to the receiver, waiting for the broadcast with the baud rate
+    Negotiated { master: u8 },
+    /// Receiving the channel data
+    Running { master: u8 },
+}
+
+
+/// Device on the SRXL2 bus, e.g. a flight controller
+#[derive(Clone, Debug)]
+pub struct Device {
+    handshake: Handshake,
+    state: State,
+    baud_rate: u32,
+    channels: Channels,
+    command: Command,
+    rssi: i8,
+    frame_losses: u16,
+    last_control_us: Option<u64>,
+    start_us: Option<u64>,
+    telemetry: Option<[u8; TELEMETRY_PAYLOAD]>,
+}
+
+impl Device {
+    /// Device which supports 400000 baud
+    ///   * id - device type in the high nibble, unit in the low one, e.g. `FLIGHT_CONTROLLER_ID`
+    ///   * uid - random number which identifies the device
+    pub const fn new(id: u8, uid: u32) -> Self {
+        Self {
+            handshake: Handshake { src: id, dest: RECEIVER_ID, priority: 10, baud_supported: BAUD_400000, info: 0, uid },
+            state: State::Handshake,
+            baud_rate: 115_200,
+            channels: Channels::new(),
+            command: Command::Channels,
+            rssi: 0,
+            frame_losses: 0,
+            last_control_us: None,
+            start_us: None,
+            telemetry: None,
+        }
+    }
+
+    /// Handle the received packet
+    ///   * time_us - e.g. from `Timer::get_counter()`
+    ///   * reply - packet which has to be sent now
+    ///
+    /// Returns the length of the reply, 0 if there is nothing to send.
+    pub fn handle(&mut self, packet: &Packet, time_us: u64, reply: &mut [u8]) -> usize {
+        match packet {
+            // Own packets come back on the half-duplex line
+            Packet::Handshake(h) if h.src == self.handshake.src => 0,
+            Packet::Handshake(h) if h.dest == self.handshake.src => {
+                self.state = State::Negotiated { master: h.src };
+                let answer = Handshake { dest: h.src, ..self.handshake };
+                answer.encode(reply)
+            }
+            Packet::Handshake(h) if h.dest == BROADCAST => {
+                self.baud_rate = if h.baud_supported & self.handshake.baud_supported & BAUD_400000 != 0 {
+                    400_000
+                } else {
+                    115_200
+                };
+                self.state = State::Running { master: h.src };
+                0
+            }
+            Packet::Control(control) => {
+                if let Command::Other(_) = control.command {
+                    return 0;
+                }
+                if let State::Handshake = self.state {
+                    // Receiver was already running (e.g. after our reset)
+                    self.state = State::Running { master: RECEIVER_ID };
+                }
+                self.channels.update(&control.channels);
+                self.command = control.command;
+                self.rssi = control.rssi;
+                self.frame_losses = control.frame_losses;
+                self.last_control_us = Some(time_us);
+                match (control.reply_id == self.handshake.src, self.telemetry, self.state) {
+                    (true, Some(payload), State::Running { master }) => encode_telemetry(master, &payload, reply),
+                    _ => 0,
+                }
+            }
+            _ => 0,
+        }
+    }
+
+    /// Call it periodically. Returns the handshake which has to be sent when the receiver
+    /// didn't start the negotiation in time.
+    pub fn poll(&mut self, time_us: u64, reply: &mut [u8]) -> usize {
+        let start = *self.start_us.get_or_insert(time_us);
+        if self.state == State::Handshake && time_us.saturating_sub(start) >= HANDSHAKE_TIMEOUT_US {
+            self.start_us = Some(time_us);
+            return self.handshake.encode(reply);
+        }
+        0
+    }
+
+    /// Telemetry sent in the next slot given to this device
+    pub fn set_telemetry(&mut self, payload: [u8; TELEMETRY_PAYLOAD]) {
+        self.telemetry = Some(payload);
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// Baud rate agreed with the receiver
+    pub fn baud_rate(&self) -> u32 {
+        self.baud_rate
+    }
+
+    /// Last values of all received channels
+    pub fn channels(&self) -> &Channels {
+        &self.channels
+    }
+
+    /// True when the receiver sends the failsafe positions or nothing came for 100ms
+    pub fn is_failsafe(&self, time_us: u64) -> bool {
+        match self.last_control_us {
+            Some(last) => self.command == Command::Failsafe || time_us.saturating_sub(last) > FAILSAFE_TIMEOUT_US,
+            None => true,
+        }
+    }
+
+    pub fn rssi(&self) -> i8 {
+        self.rssi
+    }
+
+    pub fn frame_losses(&self) -> u16 {
+        self.frame_losses
+    }
+}
@@ -0,0 +1,365 @@
+//! Binary telemetry frames
+//!
+//! Every record is sent in its own frame:
+//!
+//! ```text
+//! COBS( seq: u16 | record id: u8 | record fields | CRC-16 ) 0x00
+//! ```
+//!
+//! The numbers are little-endian. COBS removes the zero bytes from the frame, so the 0x00
+//! delimiter marks the frame boundary and the receiver finds the next frame after any error.
+//! The CRC-16/CCITT-FALSE covers the sequence number, the id and the fields. The sequence
+//! number goes up by one with every frame, the gaps show the lost frames.
+//!
+//! The same `Record` type is used by the firmware (`Encoder`) and by the host tool
+//! (`Decoder`, see `tools/telemetry-decode`).
+//!
+use core::fmt;
+
+
+/// Longest record fields
+pub const MAX_RECORD: usize = 32;
+/// Longest frame with the COBS overhead and the delimiter
+pub const MAX_FRAME: usize = MAX_RECORD + 8;
+/// Sequence number, id and CRC
+const HEADER_LEN: usize = 3;
+const CRC_LEN: usize = 2;
+
+
+/// CRC-16/CCITT-FALSE
+pub fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0xFFFF, |crc, &byte| {
+        let mut crc = crc ^ ((byte as u16) << 8);
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+        crc
+    })
+}
+
+
+/// COBS encoding without the delimiter, returns the length.
+/// The output has to be 1 byte longer than the data (plus 1 per 254 bytes).
+pub fn cobs_encode(data: &[u8], out: &mut [u8]) -> usize {
+    let mut code_pos = 0;
+    let mut len = 1;
+    let mut code = 1u8;
+    for &byte in data {
+        if byte != 0 {
+            out[len] = byte;
+            len += 1;
+            code += 1;
+        }
+        if byte == 0 || code == 0xFF {
+            out[code_pos] = code;
+            code_pos = len;
+            len += 1;
+            code = 1;
+        }
+    }
+    out[code_pos] = code;
+    len
+}
+
+
+/// COBS decoding of the frame without the delimiter, returns the length
+/// or None when the frame is invalid or doesn't fit the output
+pub fn cobs_decode(data: &[u8], out: &mut [u8]) -> Option<usize> {
+    let mut pos = 0;
+    let mut len = 0;
+    while pos < data.len() {
+        let code = data[pos] as usize;
+        let end = pos + code;
+        if code == 0 || end > data.len() {
+            return None;
+        }
+        for &byte in &data[pos + 1..end] {
+            if byte == 0 {
+                return None;
+            }
+            *out.get_mut(len)? = byte;
+            len += 1;
+        }
+        pos = end;
+        // Group shorter than the maximum ends with the zero, except the last one
+        if code < 0xFF && pos < data.len() {
+            *out.get_mut(len)? = 0;
+            len += 1;
+        }
+    }
+    Some(len)
+}
+
+
+/// Field of the record for printing
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Value<'a> {
+    U8(u8),
+    U16(u16),
+    U32(u32),
+    I16(i16),
+    Bytes(&'a [u8]),
+}
+
+impl fmt::Display for Value<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::U8(v) => write!(f, "{v}"),
+            Value::U16(v) => write!(f, "{v}"),
+            Value::U32(v) => write!(f, "{v}"),
+            Value::I16(v) => write!(f, "{v}"),
+            Value::Bytes(bytes) => bytes.iter().try_for_each(|b| write!(f, "{b:02x}")),
+        }
+    }
+}
+
+
+/// Telemetry record
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Record {
+    Counter { value: u32 },
+    /// ADC input
+    Analog { channel: u8, raw: u16 },
+    Status { uptime_ms: u32, vsys_mv: u16, temperature_cdeg: i16 },
+    /// Record of the application, the fields are the raw bytes
+    Other { id: u8, len: u8, data: [u8; MAX_RECORD] },
+}
+
+impl Record {
+    pub const COUNTER: u8 = 1;
+    pub const ANALOG: u8 = 2;
+    pub const STATUS: u8 = 3;
+
+    /// Record of the application, ids below 16 are reserved
+    pub fn other(id: u8, data: &[u8]) -> Option<Self> {
+        if id < 16 || data.len() > MAX_RECORD {
+            return None;
+        }
+        let mut record = [0; MAX_RECORD];
+        record[..data.len()].copy_from_slice(data);
+        Some(Record::Other { id, len: data.len() as u8, data: record })
+    }
+
+    pub fn id(&self) -> u8 {
+        match self {
+            Record::Counter { .. } => Self::COUNTER,
+            Record::Analog { .. } => Self::ANALOG,
+            Record::Status { .. } => Self::STATUS,
+            Record::Other { id, .. } => *id,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Record::Counter { .. } => "counter",
+            Record::Analog { .. } => "analog",
+            Record::Status { .. } => "status",
+            Record::Other { .. } => "other",
+        }
+    }
+
+    /// Give the fields with their names to the callback
+    pub fn fields(&self, mut field: impl FnMut(&'static str, Value)) {
+        match self {
+            Record::Counter { value } => field("value", Value::U32(*value)),
+            Record::Analog { channel, raw } => {
+                field("channel", Value::U8(*channel));
+                field("raw", Value::U16(*raw));
+            }
+            Record::Status { uptime_ms, vsys_mv, temperature_cdeg } => {
+                field("uptime_ms", Value::U32(*uptime_ms));
+                field("vsys_mv", Value::U16(*vsys_mv));
+                field("temperature_cdeg", Value::I16(*temperature_cdeg));
+            }
+            Record::Other { id, len, data } => {
+                field("id", Value::U8(*id));
+                field("data", Value::Bytes(&data[..*len as usize]));
+            }
+        }
+    }
+
+    /// Write the fields, returns their length
+    fn encode(&self, out: &mut [u8; MAX_RECORD]) -> usize {
+        match self {
+            Record::Counter { value } => {
+                out[..4].copy_from_slice(&value.to_le_bytes());
+                4
+            }
+            Record::Analog { channel, raw } => {
+                out[0] = *channel;
+                out[1..3].copy_from_slice(&raw.to_le_bytes());
+                3
+            }
+            Record::Status { uptime_ms, vsys_mv, temperature_cdeg } => {
+                out[..4].copy_from_slice(&uptime_ms.to_le_bytes());
+                out[4..6].copy_from_slice(&vsys_mv.to_le_bytes());
+                out[6..8].copy_from_slice(&temperature_cdeg.to_le_bytes());
+                8
+            }
+            Record::Other { len, data, .. } => {
+                out.copy_from_slice(data);
+                *len as usize
+            }
+        }
+    }
+
+    /// Read the fields, None when the length doesn't fit the record
+    fn decode(id: u8, data: &[u8]) -> Option<Self> {
+        let u16_at = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+        let u32_at = |i: usize| u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+        match (id, data.len()) {
+            (Self::COUNTER, 4) => Some(Record::Counter { value: u32_at(0) }),
+            (Self::ANALOG, 3) => Some(Record::Analog { channel: data[0], raw: u16_at(1) }),
+            (Self::STATUS, 8) => Some(Record::Status {
+                uptime_ms: u32_at(0),
+                vsys_mv: u16_at(4),
+                temperature_cdeg: u16_at(6) as i16,
+            }),
+            _ => Self::other(id, data),
+        }
+    }
+}
+
+
+/// Builds the frames with the sequence numbers
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub struct Encoder {
+    seq: u16,
+}
+
+impl Encoder {
+    pub const fn new() -> Self {
+        Self { seq: 0 }
+    }
+
+    /// Frame the record, returns the length with the delimiter
+    pub fn encode(&mut self, record: &Record, out: &mut [u8; MAX_FRAME]) -> usize {
+        let mut frame = [0u8; HEADER_LEN + MAX_RECORD + CRC_LEN];
+        frame[..2].copy_from_slice(&self.seq.to_le_bytes());
+        frame[2] = record.id();
+        let mut fields = [0u8; MAX_RECORD];
+        let len = record.encode(&mut fields);
+        frame[HEADER_LEN..HEADER_LEN + len].copy_from_slice(&fields[..len]);
+        let len = HEADER_LEN + len;
+        let crc = crc16(&frame[..len]);
+        frame[len..len + CRC_LEN].copy_from_slice(&crc.to_le_bytes());
+        self.seq = self.seq.wrapping_add(1);
+
+        let len = cobs_encode(&frame[..len + CRC_LEN], out);
+        out[len] = 0;
+        len + 1
+    }
+}
+
+
+/// Decoded frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Frame {
+    pub seq: u16,
+    pub record: Record,
+}
+
+
+/// Counters of the decoder
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub struct DecoderStats {
+    pub frames: u32,
+    pub crc_errors: u32,
+    /// Wrong COBS encoding or length
+    pub invalid: u32,
+    /// Frames missing in the sequence numbers
+    pub lost: u32,
+}
+
+
+/// Finds the frames in the byte stream
+#[derive(Clone, Debug)]
+pub struct Decoder {
+    buffer: [u8; MAX_FRAME],
+    len: usize,
+    /// The frame didn't fit into the buffer
+    overflow: bool,
+    last_seq: Option<u16>,
+    stats: DecoderStats,
+}
+
+impl Decoder {
+    pub const fn new() -> Self {
+        Self { buffer: [0; MAX_FRAME], len: 0, overflow: false, last_seq: None, stats: DecoderStats { frames: 0, crc_errors: 0, invalid: 0, lost: 0 } }
+    }
+
+    /// Add the received byte, returns the frame after its delimiter
+    pub fn push(&mut self, byte: u8) -> Option<Frame> {
+        if byte != 0 {
+            if self.len < MAX_FRAME {
+                self.buffer[self.len] = byte;
+                self.len += 1;
+            } else {
+                self.overflow = true;
+            }
+            return None;
+        }
+        let len = core::mem::replace(&mut self.len, 0);
+        if core::mem::replace(&mut self.overflow, false) {
+            self.stats.invalid += 1;
+            return None;
+        }
+        // Delimiters between the frames
+        if len == 0 {
+            return None;
+        }
+        let frame = self.decode(len);
+        if let Some(frame) = frame {
+            self.count_lost(frame.seq);
+            self.stats.frames += 1;
+        }
+        frame
+    }
+
+    pub fn stats(&self) -> DecoderStats {
+        self.stats
+    }
+
+    /// Drop the partial frame, e.g. after the serial port was opened
+    pub fn reset(&mut self) {
+        self.len = 0;
+        self.overflow = false;
+        self.last_seq = None;
+    }
+
+    fn decode(&mut self, len: usize) -> Option<Frame> {
+        let mut frame = [0u8; MAX_FRAME];
+        let Some(len) = cobs_decode(&self.buffer[..len], &mut frame).filter(|&len| len >= HEADER_LEN + CRC_LEN) else {
+            self.stats.invalid += 1;
+            return None;
+        };
+        let (data, crc) = frame[..len].split_at(len - CRC_LEN);
+        if crc16(data) != u16::from_le_bytes([crc[0], crc[1]]) {
+            self.stats.crc_errors += 1;
+            return None;
+        }
+        let Some(record) = Record::decode(data[2], &data[HEADER_LEN..]) else {
+            self.stats.invalid += 1;
+            return None;
+        };
+        Some(Frame { seq: u16::from_le_bytes([data[0], data[1]]), record })
+    }
+
+    /// Gap in the sequence numbers. A jump back (more than half of the range)
+    /// is the restart of the sender, not a loss.
+    fn count_lost(&mut self, seq: u16) {
+        if let Some(last) = self.last_seq {
+            let gap = seq.wrapping_sub(last).wrapping_sub(1);
+            if gap < 0x8000 {
+                self.stats.lost += gap as u32;
+            }
+        }
+        self.last_seq = Some(seq);
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,131 @@
+//! Value Change Dump (.vcd) export of pin traces
+//!
+//! Writes waveforms which can be opened in GTKWave or PulseView. Pins are passed as 32 bit
+//! words where bit n is GPIOn, so the source can be `pio_sim::Simulator::pins()` or
+//! a buffer of raw GPIO samples (e.g. `SIO.gpio_in` read at a fixed rate).
+//!
+//! Time is stored in picoseconds, so system clocks like 125MHz (8000ps) are exact.
+//!
+use core::fmt::{self, Write};
+
+
+/// Named pin in the waveform
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Signal<'a> {
+    pub name: &'a str,
+    pub pin: u8,
+}
+
+impl<'a> Signal<'a> {
+    pub const fn new(name: &'a str, pin: u8) -> Self {
+        Self { name, pin }
+    }
+}
+
+
+/// Sample period in picoseconds
+pub const fn period_ps(freq_hz: u32) -> u64 {
+    1_000_000_000_000 / freq_hz as u64
+}
+
+
+/// VCD file writer
+pub struct VcdWriter<'a, W: Write> {
+    out: W,
+    signals: &'a [Signal<'a>],
+    period_ps: u64,
+    time_ps: u64,
+    last: Option<u32>,
+}
+
+impl<'a, W: Write> VcdWriter<'a, W> {
+    /// Write the header
+    ///   * out - destination, e.g. `String` on the host
+    ///   * module - name of the scope which groups the signals
+    ///   * signals - pins to export, at most 94
+    ///   * period_ps - time between samples passed to `sample()`
+    pub fn new(mut out: W, module: &str, signals: &'a [Signal<'a>], period_ps: u64) -> Result<Self, fmt::Error> {
+        assert!(signals.len() <= 94);
+        writeln!(out, "$version rp2040-sandbox $end")?;
+        writeln!(out, "$timescale 1ps $end")?;
+        writeln!(out, "$scope module {} $end", module)?;
+        for (i, signal) in signals.iter().enumerate() {
+            writeln!(out, "$var wire 1 {} {} $end", identifier(i), signal.name)?;
+        }
+        writeln!(out, "$upscope $end")?;
+        writeln!(out, "$enddefinitions $end")?;
+        Ok(Self { out, signals, period_ps, time_ps: 0, last: None })
+    }
+
+    /// Add the sample and advance time by one period
+    pub fn sample(&mut self, pins: u32) -> fmt::Result {
+        self.sample_at(self.time_ps, pins)?;
+        self.time_ps += self.period_ps;
+        Ok(())
+    }
+
+    /// Add the sample at the given time. Time can't go backwards.
+    pub fn sample_at(&mut self, time_ps: u64, pins: u32) -> fmt::Result {
+        match self.last {
+            None => {
+                writeln!(self.out, "#{}", time_ps)?;
+                writeln!(self.out, "$dumpvars")?;
+                for i in 0..self.signals.len() {
+                    self.write_value(i, pins)?;
+                }
+                writeln!(self.out, "$end")?;
+            }
+            Some(last) => {
+                let changed = self.mask() & (pins ^ last);
+                if changed != 0 {
+                    writeln!(self.out, "#{}", time_ps)?;
+                    for (i, signal) in self.signals.iter().enumerate() {
+                        if changed & (1 << signal.pin) != 0 {
+                            self.write_value(i, pins)?;
+                        }
+                    }
+                }
+            }
+        }
+        self.last = Some(pins);
+        self.time_ps = time_ps;
+        Ok(())
+    }
+
+    /// Mark the end of the trace and return the destination
+    pub fn finish(mut self) -> Result<W, fmt::Error> {
+        writeln!(self.out, "#{}", self.time_ps)?;
+        Ok(self.out)
+    }
+
+    fn write_value(&mut self, index: usize, pins: u32) -> fmt::Result {
+        let value = (pins >> self.signals[index].pin) & 1;
+        writeln!(self.out, "{}{}", value, identifier(index))
+    }
+
+    fn mask(&self) -> u32 {
+        self.signals.iter().fold(0, |mask, s| mask | (1 << s.pin))
+    }
+}
+
+
+/// Export buffer of GPIO samples taken every `period_ps`
+pub fn write_samples<W: Write>(
+    out: W,
+    module: &str,
+    signals: &[Signal],
+    period_ps: u64,
+    samples: &[u32],
+) -> Result<W, fmt::Error> {
+    let mut vcd = VcdWriter::new(out, module, signals, period_ps)?;
+    for &pins in samples {
+        vcd.sample(pins)?;
+    }
+    vcd.finish()
+}
+
+
+/// Short identifier code of the signal: printable characters starting with '!'
+fn identifier(index: usize) -> char {
+    (b'!' + index as u8) as char
+}
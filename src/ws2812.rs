@@ -0,0 +1,270 @@
+//! WS2812 / SK6812 addressable LEDs (NeoPixel) on PIO
+//!
+//! The frame is encoded into a buffer of words (one per LED) and sent to the TX FIFO by DMA,
+//! so the CPU is free while the strip is updated.
+//!
+//!   * WS2812 (RGB) takes 24 bits per LED in GRB order, SK6812 RGBW 32 bits in GRBW order
+//!   * colors are scaled by the global brightness and then gamma corrected
+//!   * every bit is 10 PIO cycles long (800kHz): 2 high, 5 data, 3 low
+//!   * the strip latches the frame after at least 280us with low data line
+//!
+//! `effects` renders animations from the time of the `Timer`.
+//!
+pub mod effects;
+
+use rp_pico::hal::{
+    dma::{single_buffer, SingleChannel},
+    pio::{PIOBuilder, PIOExt, PinDir, ShiftDirection, StateMachine, StateMachineIndex, Running, Tx, UninitStateMachine},
+    Timer,
+};
+
+use crate::pio_manager::{PioError, PioManager};
+
+
+/// Bit rate of the data line
+pub const BIT_RATE: u32 = 800_000;
+/// Number of PIO cycles in a single bit
+pub const CYCLES_PER_BIT: u32 = 10;
+/// Low time which latches the frame (WS2812B needs 280us, older parts 50us)
+const RESET_US: u64 = 300;
+/// Time of the last word in the output shift register after the FIFO is empty
+const LAST_WORD_US: u64 = 32 * 1_000_000 / BIT_RATE as u64;
+
+/// Gamma 2.8 correction
+const GAMMA: [u8; 256] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2,
+    2, 3, 3, 3, 3, 3, 3, 3, 4, 4, 4, 4, 4, 5, 5, 5,
+    5, 6, 6, 6, 6, 7, 7, 7, 7, 8, 8, 8, 9, 9, 9, 10,
+    10, 10, 11, 11, 11, 12, 12, 13, 13, 13, 14, 14, 15, 15, 16, 16,
+    17, 17, 18, 18, 19, 19, 20, 20, 21, 21, 22, 22, 23, 24, 24, 25,
+    25, 26, 27, 27, 28, 29, 29, 30, 31, 32, 32, 33, 34, 35, 35, 36,
+    37, 38, 39, 39, 40, 41, 42, 43, 44, 45, 46, 47, 48, 49, 50, 50,
+    51, 52, 54, 55, 56, 57, 58, 59, 60, 61, 62, 63, 64, 66, 67, 68,
+    69, 70, 72, 73, 74, 75, 77, 78, 79, 81, 82, 83, 85, 86, 87, 89,
+    90, 92, 93, 95, 96, 98, 99, 101, 102, 104, 105, 107, 109, 110, 112, 114,
+    115, 117, 119, 120, 122, 124, 126, 127, 129, 131, 133, 135, 137, 138, 140, 142,
+    144, 146, 148, 150, 152, 154, 156, 158, 160, 162, 164, 167, 169, 171, 173, 175,
+    177, 180, 182, 184, 186, 189, 191, 193, 196, 198, 200, 203, 205, 208, 210, 213,
+    215, 218, 220, 223, 225, 228, 231, 233, 236, 239, 241, 244, 247, 249, 252, 255,
+];
+
+
+/// Color layout of the LEDs
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum LedType {
+    /// WS2812, SK6812 RGB: 24 bits in GRB order
+    Rgb,
+    /// SK6812 RGBW: 32 bits in GRBW order
+    Rgbw,
+}
+
+impl LedType {
+    pub const fn bits(&self) -> u8 {
+        match self {
+            LedType::Rgb => 24,
+            LedType::Rgbw => 32,
+        }
+    }
+}
+
+
+/// Color of a single LED. White is ignored by RGB LEDs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub w: u8,
+}
+
+impl Color {
+    pub const BLACK: Color = Color::rgb(0, 0, 0);
+
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b, w: 0 }
+    }
+
+    pub const fn rgbw(r: u8, g: u8, b: u8, w: u8) -> Self {
+        Self { r, g, b, w }
+    }
+
+    /// Color from the color wheel
+    ///   * hue - 0..=255 is the full circle starting from red
+    ///   * sat - saturation, 0 is white
+    ///   * val - value (brightness)
+    pub const fn hsv(hue: u8, sat: u8, val: u8) -> Self {
+        if sat == 0 {
+            return Self::rgb(val, val, val);
+        }
+        let region = hue / 43;
+        let rem = (hue - region * 43) as u32 * 6;
+        let (s, v) = (sat as u32, val as u32);
+        let p = ((v * (255 - s)) >> 8) as u8;
+        let q = ((v * (255 - ((s * rem) >> 8))) >> 8) as u8;
+        let t = ((v * (255 - ((s * (255 - rem)) >> 8))) >> 8) as u8;
+        match region {
+            0 => Self::rgb(val, t, p),
+            1 => Self::rgb(q, val, p),
+            2 => Self::rgb(p, val, t),
+            3 => Self::rgb(p, q, val),
+            4 => Self::rgb(t, p, val),
+            _ => Self::rgb(val, p, q),
+        }
+    }
+
+    /// Mix of two colors
+    ///   * amount - 0 is this color, 255 the other one
+    pub const fn lerp(&self, other: &Color, amount: u8) -> Self {
+        const fn mix(a: u8, b: u8, amount: u8) -> u8 {
+            ((a as u32 * (255 - amount as u32) + b as u32 * amount as u32) / 255) as u8
+        }
+        Self {
+            r: mix(self.r, other.r, amount),
+            g: mix(self.g, other.g, amount),
+            b: mix(self.b, other.b, amount),
+            w: mix(self.w, other.w, amount),
+        }
+    }
+}
+
+
+/// Word for TX FIFO: color scaled by brightness, gamma corrected, MSB first
+///   * brightness - 255 is full brightness
+pub const fn encode(color: Color, brightness: u8, led_type: LedType) -> u32 {
+    const fn channel(value: u8, brightness: u8) -> u32 {
+        GAMMA[((value as u32 * (brightness as u32 + 1)) >> 8) as usize] as u32
+    }
+    let grb = channel(color.g, brightness) << 24 | channel(color.r, brightness) << 16 | channel(color.b, brightness) << 8;
+    match led_type {
+        LedType::Rgb => grb,
+        LedType::Rgbw => grb | channel(color.w, brightness),
+    }
+}
+
+
+/// PIO clock divisor for 800kHz bit rate, as (int, frac)
+pub const fn clock_divisor(sys_clock_hz: u32) -> (u16, u8) {
+    let div = (sys_clock_hz as u64 * 256 + (BIT_RATE * CYCLES_PER_BIT) as u64 / 2) / (BIT_RATE * CYCLES_PER_BIT) as u64;
+    ((div >> 8) as u16, (div & 0xff) as u8)
+}
+
+
+/// DMA channel, frame buffer and TX FIFO, either idle or moved into the transfer
+enum State<P: PIOExt, SM: StateMachineIndex, CH: SingleChannel, const N: usize> {
+    Idle(CH, &'static mut [u32; N], Tx<(P, SM)>),
+    Sending(single_buffer::Transfer<CH, &'static mut [u32; N], Tx<(P, SM)>>),
+    Empty,
+}
+
+
+/// Strip of N LEDs
+pub struct Ws2812<P: PIOExt, SM: StateMachineIndex, CH: SingleChannel, const N: usize> {
+    _sm: StateMachine<(P, SM), Running>,
+    state: State<P, SM, CH, N>,
+    timer: Timer,
+    led_type: LedType,
+    brightness: u8,
+}
+
+impl<P, SM, CH, const N: usize> Ws2812<P, SM, CH, N>
+where
+    P: PIOExt,
+    SM: StateMachineIndex,
+    CH: SingleChannel,
+{
+    /// Install the program and start the state machine
+    ///   * ch - DMA channel feeding TX FIFO
+    ///   * pin - data line
+    ///   * buffer - one word per LED
+    ///   * timer - keeps the reset time between frames
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        pio: &mut PioManager<P>,
+        sm: UninitStateMachine<(P, SM)>,
+        ch: CH,
+        pin: u8,
+        led_type: LedType,
+        sys_clock_hz: u32,
+        buffer: &'static mut [u32; N],
+        timer: Timer,
+    ) -> Result<Self, PioError> {
+        let installed = pio.install("ws2812", &ws2812_program())?;
+        let (int, frac) = clock_divisor(sys_clock_hz);
+        let (mut sm, _rx, tx) = PIOBuilder::from_program(installed)
+            .side_set_pin_base(pin)
+            .out_shift_direction(ShiftDirection::Left)
+            .autopull(true)
+            .pull_threshold(led_type.bits())
+            .clock_divisor_fixed_point(int, frac)
+            .build(sm);
+        sm.set_pindirs([(pin, PinDir::Output)]);
+        Ok(Self {
+            _sm: sm.start(),
+            state: State::Idle(ch, buffer, tx),
+            timer,
+            led_type,
+            brightness: 255,
+        })
+    }
+
+    /// Global brightness, 255 is full
+    pub fn set_brightness(&mut self, brightness: u8) {
+        self.brightness = brightness;
+    }
+
+    pub fn brightness(&self) -> u8 {
+        self.brightness
+    }
+
+    /// True while the previous frame is being sent by DMA
+    pub fn is_busy(&self) -> bool {
+        matches!(&self.state, State::Sending(transfer) if !transfer.is_done())
+    }
+
+    /// Send the frame. LEDs without color are switched off.
+    /// Waits until the previous frame is latched by the strip.
+    pub fn write<I: IntoIterator<Item = Color>>(&mut self, colors: I) {
+        let (ch, buffer, tx) = self.wait();
+        let mut colors = colors.into_iter();
+        for word in buffer.iter_mut() {
+            let color = colors.next().unwrap_or(Color::BLACK);
+            *word = encode(color, self.brightness, self.led_type);
+        }
+        self.state = State::Sending(single_buffer::Config::new(ch, buffer, tx).start());
+    }
+
+    /// Wait for the end of the transfer and the reset time
+    fn wait(&mut self) -> (CH, &'static mut [u32; N], Tx<(P, SM)>) {
+        match core::mem::replace(&mut self.state, State::Empty) {
+            State::Idle(ch, buffer, tx) => (ch, buffer, tx),
+            State::Sending(transfer) => {
+                let (ch, buffer, tx) = transfer.wait();
+                while !tx.is_empty() {}
+                let latched = self.timer.get_counter().ticks() + LAST_WORD_US + RESET_US;
+                while self.timer.get_counter().ticks() < latched {}
+                (ch, buffer, tx)
+            }
+            State::Empty => unreachable!(),
+        }
+    }
+}
+
+
+/// WS2812 protocol: 10 cycles per bit, the output is on the side-set pin.
+/// Use with autopull, 24 or 32 bits and shift to the left.
+pub fn ws2812_program() -> pio::Program<32> {
+    pio_proc::pio_asm!("
+        .side_set 1
+        .wrap_target
+        bitloop:
+            out x, 1        side 0 [2]  ; low part of the previous bit
+            jmp !x do_zero  side 1 [1]  ; start of the bit
+        do_one:
+            jmp bitloop     side 1 [4]  ; data high
+        do_zero:
+            nop             side 0 [4]  ; data low
+        .wrap
+    ").program
+}
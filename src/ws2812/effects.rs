@@ -0,0 +1,57 @@
+//! Animations for LED strips
+//!
+//! Every effect is a function of time, so the frame can be rendered at any rate
+//! (e.g. from `Timer::get_counter()`) and the speed doesn't depend on it.
+//!
+use super::Color;
+
+
+/// Animation of the whole strip
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Effect {
+    /// Color wheel spread over the strip, rotating once per period
+    Rainbow { period_ms: u32 },
+    /// Every `spacing`-th LED is lit, the pattern moves by one LED every step
+    Chase { color: Color, spacing: u8, step_ms: u32 },
+    /// Whole strip goes from one color to the other and back once per period
+    Fade { from: Color, to: Color, period_ms: u32 },
+}
+
+impl Effect {
+    /// Render the frame
+    ///   * time_us - time since the start of the animation
+    ///   * frame - colors of the LEDs
+    pub fn render(&self, time_us: u64, frame: &mut [Color]) {
+        let time_ms = time_us / 1_000;
+        match *self {
+            Effect::Rainbow { period_ms } => {
+                let offset = phase(time_ms, period_ms);
+                let len = frame.len().max(1);
+                for (i, led) in frame.iter_mut().enumerate() {
+                    let hue = offset.wrapping_add((i * 256 / len) as u8);
+                    *led = Color::hsv(hue, 255, 255);
+                }
+            }
+            Effect::Chase { color, spacing, step_ms } => {
+                let spacing = spacing.max(1) as u64;
+                let position = time_ms / step_ms.max(1) as u64 % spacing;
+                for (i, led) in frame.iter_mut().enumerate() {
+                    *led = if i as u64 % spacing == position { color } else { Color::BLACK };
+                }
+            }
+            Effect::Fade { from, to, period_ms } => {
+                // Triangle: 0 -> 255 -> 0
+                let phase = phase(time_ms, period_ms);
+                let amount = if phase < 128 { phase * 2 } else { (255 - phase) * 2 + 1 };
+                frame.fill(from.lerp(&to, amount));
+            }
+        }
+    }
+}
+
+
+/// Position in the period as 0..=255
+fn phase(time_ms: u64, period_ms: u32) -> u8 {
+    let period_ms = period_ms.max(1) as u64;
+    (time_ms % period_ms * 256 / period_ms) as u8
+}
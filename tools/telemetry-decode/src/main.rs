@@ -0,0 +1,118 @@
+//! Decode the telemetry frames from the file, serial port or stdin
+//!
+//! ```sh
+//! cargo run -- /dev/ttyUSB0
+//! cargo run -- --csv capture.bin > capture.csv
+//! ```
+//!
+//! Without `--csv` every record is printed on its own line. The CSV has one row per field
+//! (`seq,record,field,value`), which is easy to filter or pivot in a spreadsheet.
+//! The frame counters are printed to stderr at the end.
+//!
+use std::env;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::process::ExitCode;
+
+use rp2040_sandbox::telemetry::{Decoder, Frame};
+
+
+const USAGE: &str = "Usage: telemetry-decode [--csv] [FILE]\nReads stdin when FILE is missing or -";
+
+
+fn main() -> ExitCode {
+    let mut csv = false;
+    let mut path = None;
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--csv" => csv = true,
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return ExitCode::SUCCESS;
+            }
+            _ if path.is_none() => path = Some(arg),
+            _ => {
+                eprintln!("{USAGE}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    let input: Box<dyn Read> = match path.as_deref() {
+        None | Some("-") => Box::new(io::stdin()),
+        Some(path) => match File::open(path) {
+            Ok(file) => Box::new(file),
+            Err(error) => {
+                eprintln!("Can't open {path}: {error}");
+                return ExitCode::FAILURE;
+            }
+        },
+    };
+
+    match decode(input, csv) {
+        Ok(decoder) => {
+            let stats = decoder.stats();
+            eprintln!(
+                "{} frames, {} lost, {} CRC errors, {} invalid",
+                stats.frames, stats.lost, stats.crc_errors, stats.invalid
+            );
+            ExitCode::SUCCESS
+        }
+        Err(error) => {
+            eprintln!("{error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+
+/// Print the frames as they come, so it works with the serial port too
+fn decode(mut input: impl Read, csv: bool) -> io::Result<Decoder> {
+    let mut out = BufWriter::new(io::stdout().lock());
+    let mut decoder = Decoder::new();
+    let mut buffer = [0u8; 256];
+    if csv {
+        writeln!(out, "seq,record,field,value")?;
+    }
+    loop {
+        let count = match input.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(count) => count,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+            Err(error) => return Err(error),
+        };
+        for &byte in &buffer[..count] {
+            if let Some(frame) = decoder.push(byte) {
+                if csv {
+                    write_csv(&mut out, &frame)?;
+                } else {
+                    write_text(&mut out, &frame)?;
+                }
+            }
+        }
+        out.flush()?;
+    }
+    Ok(decoder)
+}
+
+fn write_text(out: &mut impl Write, frame: &Frame) -> io::Result<()> {
+    write!(out, "#{} {}", frame.seq, frame.record.name())?;
+    let mut result = Ok(());
+    frame.record.fields(|name, value| {
+        if result.is_ok() {
+            result = write!(out, " {name}={value}");
+        }
+    });
+    result?;
+    writeln!(out)
+}
+
+fn write_csv(out: &mut impl Write, frame: &Frame) -> io::Result<()> {
+    let mut result = Ok(());
+    frame.record.fields(|name, value| {
+        if result.is_ok() {
+            result = writeln!(out, "{},{},{},{}", frame.seq, frame.record.name(), name, value);
+        }
+    });
+    result
+}